@@ -2,18 +2,109 @@ const zmq = require('zeromq')
 const serializeError = require('serialize-error')
 const deserializeError = require('deserialize-error')
 const uuid = require('uuid/v4')
+const fs = require('fs')
+const http = require('http')
+const crypto = require('crypto')
 
 interface ZMQSocket {
   identity: string,
   connect: (uri: string) => void,
   close: () => void,
   on: (type: string, callback: (...args: Buffer[]) => void) => void,
-  send: (message: Array<string | Buffer>) => void
+  send: (message: Array<string | Buffer>) => void,
+  setsockopt: (option: number, value: number | string) => void,
 }
 
-const create = (name = '', uri: string, isWorker = false) => {
+// control messages the broker sends instead of a response when it refuses a task (followed by its response topic),
+// a registration (followed by its topic), or any message of the peer (`@@IDENTITY_CONFLICT`, alone)
+const REJECTIONS = ['@@NO_TOPIC', '@@FORBIDDEN', '@@QUEUE_FULL', '@@STOPPING', '@@ERROR', '@@DEPENDENCY_FAILED', '@@BAD_SIGNATURE', '@@IDENTITY_CONFLICT']
+
+// brokers started with `BLOB_STORE` replace large payloads by a reference to where they are written
+const BLOB_PREFIX = '@@BLOB '
+
+const httpGet = (url: string) => new Promise<string>((resolve, reject) => {
+  http.get(url, (res: any) => {
+    let body = ''
+    res.setEncoding('utf8')
+    res.on('data', (chunk: string) => { body += chunk })
+    res.on('end', () => {
+      if (res.statusCode >= 200 && res.statusCode < 300) resolve(body)
+      else reject(new Error(`${url} responded ${res.statusCode}`))
+    })
+  }).on('error', reject)
+})
+
+// the payload behind a reference, other payloads are given back as they are
+const fetchBlob = async (payload: string): Promise<string> => {
+  if (!payload.startsWith(BLOB_PREFIX)) return payload
+
+  const reference = payload.slice(BLOB_PREFIX.length)
+  if (reference.startsWith('file://')) return fs.promises.readFile(reference.slice('file://'.length), 'utf8')
+  return httpGet(reference)
+}
+
+interface RetryPolicy {
+  // how many times an unanswered request is sent again after a reconnection, before failing
+  maxResends: number,
+}
+
+interface GracefulShutdown {
+  // milliseconds to wait for running tasks to be answered, once SIGTERM is received
+  drainTimeout: number,
+}
+
+// options of the socket to the broker, the zmq defaults block the process on exit while a message is unsent
+// (infinite linger), and drop messages after 1000 waiting ones
+interface SocketOptions {
+  sndhwm: number,
+  rcvhwm: number,
+  // milliseconds
+  linger: number,
+  tcpKeepalive: boolean,
+  // seconds
+  tcpKeepaliveIdle: number,
+  // milliseconds
+  reconnectIvl: number,
+  reconnectIvlMax: number,
+  // messages are only queued once connected to the broker
+  immediate: boolean,
+  // `host:port` of a SOCKS5 proxy, HTTP proxies go through `tiny-broke tunnel`
+  socksProxy?: string,
+}
+
+interface Options {
+  retryPolicy?: Partial<RetryPolicy>,
+  socket?: Partial<SocketOptions>,
+  // workers only, `false` to not handle SIGTERM
+  gracefulShutdown?: Partial<GracefulShutdown> | false,
+  // workers only, unique to the worker but the same for its processes (like the name of its pod)
+  workerName?: string,
+  // workers only, a worker of weight 2 gets twice the tasks of a worker of weight 1 (a faster machine)
+  workerWeight?: number,
+  // workers only, the `CONTROL_SECRET` of the broker, to sign the registrations and unregistrations
+  controlSecret?: string,
+}
+
+const create = (name = '', uri: string, isWorker = false, options: Options = {}) => {
   let sock: ZMQSocket
 
+  const retryPolicy: RetryPolicy = {
+    maxResends: 3,
+    ...options.retryPolicy,
+  }
+
+  const socketOptions: SocketOptions = {
+    sndhwm: 100000,
+    rcvhwm: 100000,
+    linger: 1000,
+    tcpKeepalive: true,
+    tcpKeepaliveIdle: 60,
+    reconnectIvl: 100,
+    reconnectIvlMax: 5000,
+    immediate: false,
+    ...options.socket,
+  }
+
   const sendResponse = (action: { type: string }) => {
     sock.send([action.type, '', JSON.stringify(action)])
   }
@@ -24,25 +115,107 @@ const create = (name = '', uri: string, isWorker = false) => {
     log: Function,
   }
   const registrations = new Map<string, Registration>()
+  // a restarted worker supersedes its previous registrations, even with the same identity
+  const epoch = Date.now()
+  // the features the worker declares, the broker doesn't use the other ones with it
+  const registrationOptions = [`epoch=${epoch}`, 'features=restart', ...(options.workerName ? [`name=${options.workerName}`] : []), ...(options.workerWeight ? [`weight=${options.workerWeight}`] : [])].join(' ')
+  // the features of the broker, once a registration is answered
+  let brokerFeatures: string[] = []
+
+  // `@@REGISTER`, `@@UNREGISTER`, `@@UNSUBSCRIBE_TOPIC`, signed when there is a secret: the headers are the hexadecimal
+  // HMAC-SHA256 of `<identity>\n<control>\n<worker topic>\n<options>\n<timestamp>` and its timestamp (milliseconds)
+  const sendControl = (control: string, topics = '', controlOptions = '') => {
+    if (!options.controlSecret) {
+      sock.send([control, topics, controlOptions])
+      return
+    }
+
+    const timestamp = String(Date.now())
+    const signature = crypto
+      .createHmac('sha256', options.controlSecret)
+      .update([sock.identity, control, topics, controlOptions, timestamp].join('\n'))
+      .digest('hex')
+    sock.send([control, topics, controlOptions, '', '', JSON.stringify({ 'x-signature': signature, 'x-signed-at': timestamp })])
+  }
 
+  // in one message, the worker is never registered to only part of its topics
   const sendRegistrations = () => {
-    if (!isWorker) return
+    if (!isWorker || registrations.size === 0) return
+
+    const topics = Array.from(registrations.keys()).map(type => `@@ASKED>${type}`)
+    sendControl('@@REGISTER', topics.join('\n'), registrationOptions)
+  }
+
+  // requests waiting for a response, by their (unique) returns type
+  // the returns type is kept when a request is sent again, so the broker answers it only once
+  interface PendingRequest {
+    message: string[],
+    resends: number,
+    // the broker sent `@@ACCEPTED` (with `ACCEPTED_ACKS`), it got the request
+    accepted: boolean,
+  }
+  const pendingRequests = new Map<string, PendingRequest>()
+
+  // clients waiting for a worker are told their position, and the milliseconds it may take (`unknown` when the
+  // broker can't tell yet)
+  const queuedListeners = new Map<string, (position: number, eta?: number) => void>()
+
+  const fail = (returnsType: string, message: string) => {
+    pendingRequests.delete(returnsType)
+    queuedListeners.delete(returnsType)
+
+    const registration = registrations.get(returnsType)
+    if (!registration) return
+
+    registrations.delete(returnsType)
+    registration.callback({ error: { name: 'Error', message }, from: returnsType })
+  }
+
+  // clients re-subscribe to the responses they are waiting for, and send again their unanswered requests
+  const sendSubscriptions = () => {
+    if (isWorker) return
 
     registrations.forEach((_, type) => {
-      sock.send(['@@REGISTER', `@@ASKED>${type}`])
+      if (!pendingRequests.has(type)) sock.send(['@@SUBSCRIBE', type])
+    })
+
+    pendingRequests.forEach((request, returnsType) => {
+      if (request.resends >= retryPolicy.maxResends) {
+        fail(returnsType, `no response after ${request.resends} resends, ${request.accepted ? 'accepted' : 'never accepted'} by the broker`)
+        return
+      }
+
+      request.resends += 1
+      request.accepted = false
+      sock.send(request.message)
     })
   }
 
   const start = () => {
+    const reconnecting = !!sock
+
     if (sock) sock.close()
     sock = zmq.socket('dealer')
     sock.identity = `${isWorker ? 'worker' : 'client'}-${name}-${process.pid}` // FIXME: in a container all process id would be same ?? use uuid
+    sock.setsockopt(zmq.ZMQ_SNDHWM, socketOptions.sndhwm)
+    sock.setsockopt(zmq.ZMQ_RCVHWM, socketOptions.rcvhwm)
+    sock.setsockopt(zmq.ZMQ_LINGER, socketOptions.linger)
+    sock.setsockopt(zmq.ZMQ_TCP_KEEPALIVE, socketOptions.tcpKeepalive ? 1 : 0)
+    sock.setsockopt(zmq.ZMQ_TCP_KEEPALIVE_IDLE, socketOptions.tcpKeepaliveIdle)
+    sock.setsockopt(zmq.ZMQ_RECONNECT_IVL, socketOptions.reconnectIvl)
+    sock.setsockopt(zmq.ZMQ_RECONNECT_IVL_MAX, socketOptions.reconnectIvlMax)
+    sock.setsockopt(zmq.ZMQ_IMMEDIATE, socketOptions.immediate ? 1 : 0)
+    if (socketOptions.socksProxy) {
+      if (zmq.ZMQ_SOCKS_PROXY === undefined) throw new Error('this version of zeromq has no SOCKS proxy support')
+      sock.setsockopt(zmq.ZMQ_SOCKS_PROXY, socketOptions.socksProxy.replace(/^socks5:\/\//, ''))
+    }
     sock.connect(uri)
 
     sendRegistrations()
+    if (reconnecting) sendSubscriptions()
     ping()
 
-    sock.on('message', async (_, messageBuffer) => {
+    sock.on('message', async (_, messageBuffer, returnsTypeBuffer, ...detailBuffers) => {
       const message = messageBuffer.toString()
 
       // heart beating
@@ -51,13 +224,53 @@ const create = (name = '', uri: string, isWorker = false) => {
         ping()
         return
       } else if (message === '@@REGISTER') {
+        if (draining) return
         sendRegistrations()
         ping()
         return
+      } else if (message === '@@FEATURES') {
+        brokerFeatures = (returnsTypeBuffer ? returnsTypeBuffer.toString() : '').split(' ').filter(Boolean)
+        return
+      } else if (message === '@@SHUTDOWN' || message === '@@RESTART') {
+        // an admin asked the worker to stop, or to restart, from the broker
+        stop(message === '@@RESTART')
+        return
+      } else if (message === '@@RESUBSCRIBE') {
+        sendSubscriptions()
+        ping()
+        return
+      } else if (message === '@@ACCEPTED') {
+        const request = pendingRequests.get(returnsTypeBuffer ? returnsTypeBuffer.toString() : '')
+        if (request) request.accepted = true
+        ping()
+        return
+      } else if (message === '@@QUEUED') {
+        const [position, eta] = detailBuffers.map((buffer: Buffer) => buffer.toString())
+        const listener = queuedListeners.get(returnsTypeBuffer ? returnsTypeBuffer.toString() : '')
+        if (listener) listener(Number(position), eta === 'unknown' ? undefined : Number(eta))
+        ping()
+        return
+      } else if (REJECTIONS.includes(message)) {
+        // the broker refused the task, the client waiting for it fails
+        // `@@ERROR` is followed by the code and the detail of the error
+        const reason = [message, ...detailBuffers.map((buffer: Buffer) => buffer.toString())].join(' ')
+        if (message === '@@BAD_SIGNATURE') console.error(`[${sock.identity}] the broker refused a registration: ${reason}`)
+        // a rejection without topic is the one of every message of the peer
+        if (returnsTypeBuffer) fail(returnsTypeBuffer.toString(), reason)
+        else Array.from(pendingRequests.keys()).forEach(returnsType => fail(returnsType, reason))
+        ping()
+        return
       }
 
       // find the associated registrations
-      const action = JSON.parse(message)
+      let action
+      try {
+        action = JSON.parse(await fetchBlob(message))
+      } catch (ex) {
+        console.error(`can't read message ${message}`, ex)
+        ping()
+        return
+      }
       const registration = registrations.get(action.type)
       if (!registration) {
         ping()
@@ -68,12 +281,15 @@ const create = (name = '', uri: string, isWorker = false) => {
       const { log, callback } = registration
       let error
       let payload
+      runningTasks += 1
       try {
         if (log) log(action)
         payload = await callback(action)
       } catch (ex) {
         console.error(`error while responding to ${action.type}`, ex)
         error = serializeError(ex)
+      } finally {
+        runningTasks -= 1
       }
 
       // send reponse to broker
@@ -131,17 +347,29 @@ const create = (name = '', uri: string, isWorker = false) => {
       },
     )
 
-    if (isWorker) sock.send(['@@REGISTER', `@@ASKED>${type}`])
+    if (isWorker) sendControl('@@REGISTER', `@@ASKED>${type}`, registrationOptions)
   }
 
-  const wait = (action: { type: string, returnsType: string }) => {
+  // the worker stops serving the type, without registering again
+  const unregister = (type: string) => {
+    registrations.delete(type)
+    if (isWorker) sendControl('@@UNSUBSCRIBE_TOPIC', `@@ASKED>${type}`)
+  }
+
+  const wait = (action: { type: string, returnsType: string }, onQueued?: (position: number, eta?: number) => void) => {
     const wrappedReturnsType = `${action.returnsType}@@${uuid()}`
-    sock.send([`@@ASKED>${action.type}`, wrappedReturnsType, JSON.stringify({ ...action, returnsType: wrappedReturnsType })])
+    const message = [`@@ASKED>${action.type}`, wrappedReturnsType, JSON.stringify({ ...action, returnsType: wrappedReturnsType })]
+    pendingRequests.set(wrappedReturnsType, { message, resends: 0, accepted: false })
+    if (onQueued) queuedListeners.set(wrappedReturnsType, onQueued)
+    sock.send(message)
 
     return new Promise((resolve, reject) => {
       register(
         wrappedReturnsType,
         ({ payload, error, from }) => {
+          registrations.delete(wrappedReturnsType)
+          pendingRequests.delete(wrappedReturnsType)
+          queuedListeners.delete(wrappedReturnsType)
           if (!error) return resolve(payload)
 
           const thrownError = deserializeError(error)
@@ -161,12 +389,52 @@ const create = (name = '', uri: string, isWorker = false) => {
     sock.close()
   }
 
+  // workers leave the broker, then answer their running tasks before exiting
+  let draining = false
+  let runningTasks = 0
+
+  // restarting workers connect and register again instead of exiting
+  const shutdown = (drainTimeout: number, restart = false) => {
+    if (draining) return
+    draining = true
+
+    console.log(`[${sock.identity}] ${restart ? 'restarting' : 'shutting down'}, waiting for ${runningTasks} running tasks...`)
+    sendControl('@@UNREGISTER')
+
+    const startedAt = Date.now()
+    const waitForRunningTasks = () => {
+      if (runningTasks > 0 && Date.now() - startedAt < drainTimeout) {
+        setTimeout(waitForRunningTasks, 100)
+        return
+      }
+
+      if (runningTasks > 0) console.error(`[${sock.identity}] ${runningTasks} tasks still running, ${restart ? 'restarting' : 'exiting'} anyway`)
+      if (restart) {
+        draining = false
+        start()
+        return
+      }
+      close()
+      process.exit(0)
+    }
+    waitForRunningTasks()
+  }
+
+  const { drainTimeout } = { drainTimeout: 10000, ...(options.gracefulShutdown || {}) }
+  const stop = (restart: boolean) => shutdown(drainTimeout, restart)
+
+  if (isWorker && options.gracefulShutdown !== false) {
+    process.once('SIGTERM', () => shutdown(drainTimeout))
+  }
+
   start()
 
   return {
     register,
+    unregister,
     wait,
     close,
+    features: () => brokerFeatures,
   }
 }
 
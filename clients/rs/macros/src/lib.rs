@@ -0,0 +1,86 @@
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, TokenStream, TokenTree};
+
+fn error(message: &str) -> TokenStream {
+    format!("compile_error!({:?});", message).parse().unwrap()
+}
+
+// `#[handler("USER>GET")] fn get_user(request: GetUser) -> User { ... }`
+// is turned into a `get_user` struct implementing `tiny_broke_client::Handler`, that can be given to `Broke::handle`:
+// the task payload is deserialized into the function argument, and the returned value is serialized as the response payload
+#[proc_macro_attribute]
+pub fn handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let topic = match attr.into_iter().collect::<Vec<_>>().as_slice() {
+        [TokenTree::Literal(topic)] if topic.to_string().starts_with('"') => topic.to_string(),
+        _ => return error("expected a topic: #[handler(\"TOPIC\")]"),
+    };
+
+    // attributes and visibility come before `fn`, then the function name
+    let mut visibility = TokenStream::new();
+    let mut tokens = item.clone().into_iter().peekable();
+    let mut name = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(ref punct) if punct.as_char() == '#' => {
+                tokens.next();
+            }
+            TokenTree::Ident(ref ident) if ident.to_string() == "pub" => {
+                visibility.extend(Some(token.clone()));
+                if let Some(TokenTree::Group(group)) = tokens.peek() {
+                    if group.delimiter() == Delimiter::Parenthesis {
+                        visibility.extend(tokens.next());
+                    }
+                }
+            }
+            TokenTree::Ident(ref ident) if ident.to_string() == "fn" => {
+                if let Some(TokenTree::Ident(ident)) = tokens.next() {
+                    name = Some(ident.to_string());
+                }
+                break;
+            }
+            _ => return error("#[handler] only supports plain functions"),
+        }
+    }
+    let name = match name {
+        Some(name) => name,
+        None => return error("#[handler] only supports plain functions"),
+    };
+
+    // the function is kept as is, inside `Handler::handle`, where it shadows the struct
+    let mut handle_body = item;
+    handle_body.extend(
+        format!("::tiny_broke_client::__private::handle(message, {})", name)
+            .parse::<TokenStream>()
+            .unwrap(),
+    );
+
+    let mut impl_body: TokenStream = format!(
+        "fn topic(&self) -> &'static str {{ {} }}
+        fn handle(&self, message: &str) -> ::std::result::Result<::tiny_broke_client::__private::serde_json::Value, ::std::string::String>",
+        topic
+    )
+    .parse()
+    .unwrap();
+    impl_body.extend(Some(TokenTree::Group(Group::new(
+        Delimiter::Brace,
+        handle_body,
+    ))));
+
+    let mut output: TokenStream = "#[allow(non_camel_case_types)]".parse().unwrap();
+    output.extend(visibility);
+    output.extend(
+        format!(
+            "struct {name}; impl ::tiny_broke_client::Handler for {name}",
+            name = name
+        )
+        .parse::<TokenStream>()
+        .unwrap(),
+    );
+    output.extend(Some(TokenTree::Group(Group::new(
+        Delimiter::Brace,
+        impl_body,
+    ))));
+
+    output
+}
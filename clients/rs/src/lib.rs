@@ -1,10 +1,56 @@
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::error;
 use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpStream, UdpSocket};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 use zmq;
 
+pub use tiny_broke_client_macros::handler;
+
+pub mod mock;
+
+// implemented by the functions annotated with `#[handler("TOPIC")]`, see `Broke::handle`
+pub trait Handler {
+    fn topic(&self) -> &'static str;
+    // the whole message in (JSON), the response payload out
+    fn handle(&self, message: &str) -> Result<serde_json::Value, String>;
+}
+
+// used by the code generated by `#[handler]`
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    pub fn handle<Req: DeserializeOwned, Resp: Serialize>(
+        message: &str,
+        function: fn(Req) -> Resp,
+    ) -> Result<serde_json::Value, String> {
+        let message: serde_json::Value =
+            serde_json::from_str(message).map_err(|error| error.to_string())?;
+        let payload = message
+            .get("payload")
+            .cloned()
+            .unwrap_or(serde_json::Value::Null);
+        let request = serde_json::from_value(payload).map_err(|error| error.to_string())?;
+
+        serde_json::to_value(function(request)).map_err(|error| error.to_string())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Message {
@@ -12,9 +58,378 @@ struct Message {
     returns_type: String,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request<'a, Req> {
+    r#type: &'a str,
+    returns_type: &'a str,
+    payload: &'a Req,
+}
+
+#[derive(Debug, Serialize)]
+struct Reply<'a> {
+    r#type: &'a str,
+    payload: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    r#type: String,
+    #[serde(default)]
+    payload: serde_json::Value,
+    error: Option<serde_json::Value>,
+}
+
+// control messages the broker sends instead of a response when it refuses a task (followed by its response topic),
+// a registration (followed by its topic), or any message of the peer (`@@IDENTITY_CONFLICT`, alone)
+const REJECTIONS: [&str; 8] = [
+    "@@NO_TOPIC",
+    "@@FORBIDDEN",
+    "@@QUEUE_FULL",
+    "@@STOPPING",
+    "@@ERROR",
+    "@@DEPENDENCY_FAILED",
+    "@@BAD_SIGNATURE",
+    "@@IDENTITY_CONFLICT",
+];
+
+// why `run` returned: an admin asked the worker to stop (`@@SHUTDOWN`) or to restart (`@@RESTART`), a worker
+// draining on SIGTERM stops as on `@@SHUTDOWN`, see `graceful_shutdown`
+// the worker unregistered, a restarting worker calls `register_again` (or exits and is started again)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Shutdown,
+    Restart,
+}
+
+impl Stop {
+    fn parse(control: &str) -> Option<Stop> {
+        match control {
+            "@@SHUTDOWN" => Some(Stop::Shutdown),
+            "@@RESTART" => Some(Stop::Restart),
+            _ => None,
+        }
+    }
+
+    pub fn control(self) -> &'static str {
+        match self {
+            Stop::Shutdown => "@@SHUTDOWN",
+            Stop::Restart => "@@RESTART",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CallError {
+    // the request can't be serialized, or the response can't be deserialized
+    Serialization(serde_json::Error),
+    Transport(zmq::Error),
+    // the broker refused the task (`@@NO_TOPIC`, `@@FORBIDDEN`, `@@QUEUE_FULL`, `@@STOPPING`, `@@DEPENDENCY_FAILED`),
+    // its payload (`@@ERROR schema_violation <detail>`), or the identity of the client (`@@IDENTITY_CONFLICT`)
+    Rejected(String),
+    // the worker failed, with the error it sent back
+    Worker(serde_json::Value),
+    // no response before the timeout of the calls, see `Broke::call_timeout`
+    Timeout(Duration),
+    // no response after sending the request again this many times, see `RetryPolicy`
+    Unanswered(u32),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::Serialization(error) => write!(f, "serialization error: {}", error),
+            CallError::Transport(error) => write!(f, "transport error: {}", error),
+            CallError::Rejected(reason) => write!(f, "task rejected by the broker: {}", reason),
+            CallError::Worker(error) => write!(f, "worker error: {}", error),
+            CallError::Timeout(timeout) => write!(f, "no response after {:?}", timeout),
+            CallError::Unanswered(resends) => write!(f, "no response after {} resends", resends),
+        }
+    }
+}
+
+impl error::Error for CallError {}
+
+impl From<serde_json::Error> for CallError {
+    fn from(error: serde_json::Error) -> CallError {
+        CallError::Serialization(error)
+    }
+}
+
+impl From<zmq::Error> for CallError {
+    fn from(error: zmq::Error) -> CallError {
+        CallError::Transport(error)
+    }
+}
+
+#[derive(Debug)]
+pub enum DispatchError {
+    // a control message of the broker (`@@BAD_SIGNATURE`, `@@FORBIDDEN`...), not a task
+    Control(String),
+    // the task is not JSON
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DispatchError::Control(control) => write!(f, "not a task: {}", control),
+            DispatchError::Serialization(error) => write!(f, "serialization error: {}", error),
+        }
+    }
+}
+
+impl error::Error for DispatchError {}
+
+impl From<serde_json::Error> for DispatchError {
+    fn from(error: serde_json::Error) -> DispatchError {
+        DispatchError::Serialization(error)
+    }
+}
+
+// brokers started with `BLOB_STORE` replace large payloads by a reference to where they are written
+const BLOB_PREFIX: &str = "@@BLOB ";
+
+// `GET` on a plain `http://host[:port]/path` url
+fn http_get(url: &str) -> std::io::Result<String> {
+    let rest = url.trim_start_matches("http://");
+    let (host, path) = match rest.find('/') {
+        Some(position) => (&rest[..position], &rest[position..]),
+        None => (rest, "/"),
+    };
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = TcpStream::connect(&host)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.split_whitespace().nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        return Err(std::io::Error::other(format!(
+            "{} responded {}",
+            url, status
+        )));
+    }
+    Ok(body.to_string())
+}
+
+// the payload behind a reference, other payloads are given back as they are
+// a blob that can't be read is given back as its reference
+fn fetch_blob(payload: &str) -> String {
+    let reference = match payload.strip_prefix(BLOB_PREFIX) {
+        Some(reference) => reference,
+        None => return payload.to_string(),
+    };
+
+    let fetched = match reference.strip_prefix("file://") {
+        Some(path) => fs::read_to_string(path),
+        None => http_get(reference),
+    };
+    fetched.unwrap_or_else(|_| payload.to_string())
+}
+
+// brokers started with `MDNS=true` advertise a `_tiny-broke._tcp.local` service, its TXT record gives the endpoint
+const SERVICE: &str = "_tiny-broke._tcp.local";
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+
+fn mdns_query() -> Vec<u8> {
+    // one question, no record
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in SERVICE.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet
+}
+
+fn read_u16(packet: &[u8], position: usize) -> Option<usize> {
+    Some(u16::from_be_bytes([*packet.get(position)?, *packet.get(position + 1)?]) as usize)
+}
+
+// moves after a name, compressed or not
+fn skip_name(packet: &[u8], position: usize) -> Option<usize> {
+    let mut position = position;
+    loop {
+        let length = *packet.get(position)? as usize;
+        if length == 0 {
+            return Some(position + 1);
+        }
+        if length & 0xc0 == 0xc0 {
+            return Some(position + 2);
+        }
+        position += 1 + length;
+    }
+}
+
+// the `endpoint=` entries of the TXT records of a response
+fn mdns_endpoints(packet: &[u8]) -> Option<Vec<String>> {
+    let is_response = read_u16(packet, 2)? & 0x8000 != 0;
+    if !is_response {
+        return Some(vec![]);
+    }
+
+    let mut position = 12;
+    for _ in 0..read_u16(packet, 4)? {
+        position = skip_name(packet, position)? + 4;
+    }
+
+    let records = read_u16(packet, 6)? + read_u16(packet, 8)? + read_u16(packet, 10)?;
+    let mut endpoints = vec![];
+    for _ in 0..records {
+        position = skip_name(packet, position)?;
+        let kind = read_u16(packet, position)?;
+        let length = read_u16(packet, position + 8)?;
+        let data = packet.get(position + 10..position + 10 + length)?;
+        position += 10 + length;
+
+        if kind != TYPE_TXT as usize {
+            continue;
+        }
+        let mut cursor = 0;
+        while let Some(&entry_length) = data.get(cursor) {
+            let entry = data.get(cursor + 1..cursor + 1 + entry_length as usize)?;
+            if let Some(endpoint) = String::from_utf8_lossy(entry).strip_prefix("endpoint=") {
+                endpoints.push(endpoint.to_string());
+            }
+            cursor += 1 + entry_length as usize;
+        }
+    }
+
+    Some(endpoints)
+}
+
+// the endpoints of the brokers answering on the LAN before the timeout
+pub fn discover(timeout: Duration) -> Vec<String> {
+    let mut endpoints: Vec<String> = vec![];
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+        Ok(socket) => socket,
+        Err(_) => return endpoints,
+    };
+    if socket
+        .send_to(&mdns_query(), (MDNS_GROUP, MDNS_PORT))
+        .is_err()
+    {
+        return endpoints;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0; 9000];
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if remaining.as_millis() == 0 || socket.set_read_timeout(Some(remaining)).is_err() {
+            break;
+        }
+        let length = match socket.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(_) => break,
+        };
+
+        mdns_endpoints(&buffer[..length])
+            .unwrap_or_default()
+            .into_iter()
+            .for_each(|endpoint| {
+                if !endpoints.contains(&endpoint) {
+                    endpoints.push(endpoint);
+                }
+            });
+    }
+
+    endpoints
+}
+
+// the first message the broker sends back before the timeout
+fn ask(
+    context: &zmq::Context,
+    endpoint: &str,
+    topic: &str,
+    timeout: Duration,
+) -> Option<Vec<String>> {
+    let ask = || -> Result<Option<Vec<String>>, zmq::Error> {
+        let socket = context.socket(zmq::SocketType::DEALER)?;
+        // the broker only accepts UTF-8 identities
+        socket.set_identity(format!("probe-{}", Uuid::new_v4()).as_bytes())?;
+        socket.set_linger(0)?;
+        socket.connect(endpoint)?;
+        socket.send(topic, 0)?;
+        if socket.poll(zmq::POLLIN, timeout.as_millis() as i64)? == 0 {
+            return Ok(None);
+        }
+
+        let frames = socket.recv_multipart(0)?;
+        Ok(Some(
+            frames
+                .iter()
+                .map(|frame| String::from_utf8_lossy(frame).into_owned())
+                .collect(),
+        ))
+    };
+
+    ask().ok().flatten()
+}
+
+// true when the broker answers a ping before the timeout
+fn is_alive(context: &zmq::Context, endpoint: &str, timeout: Duration) -> bool {
+    ask(context, endpoint, "@@PING", timeout).is_some()
+}
+
+// the endpoints of the brokers of the cluster, given by the first seed answering
+// a broker outside a cluster gives no endpoint, it is the only one
+pub fn members(seeds: &[&str], timeout: Duration) -> Vec<String> {
+    let context = zmq::Context::new();
+
+    seeds
+        .iter()
+        .find_map(|seed| {
+            let frames = ask(&context, seed, "@@MEMBERS", timeout)?;
+            if frames.get(1).map(String::as_str) != Some("@@MEMBERS") {
+                return None;
+            }
+
+            let endpoints: Vec<String> = frames
+                .get(2)
+                .map(|endpoints| {
+                    endpoints
+                        .split(',')
+                        .filter(|endpoint| !endpoint.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            if endpoints.is_empty() {
+                Some(vec![seed.to_string()])
+            } else {
+                Some(endpoints)
+            }
+        })
+        .unwrap_or_default()
+}
+
+enum Callback {
+    // the raw message in, the payload (a string) out
+    Raw(Rc<RefCell<Fn(String) -> String>>),
+    Handler(Box<dyn Handler>),
+}
+
 struct Registration {
     topic: String,
-    callback: Rc<RefCell<Fn(String) -> String>>,
+    callback: Callback,
 }
 
 impl fmt::Debug for Registration {
@@ -27,97 +442,801 @@ impl Registration {
     pub fn new(topic: &str, registration: &'static Fn(String) -> String) -> Registration {
         Registration {
             topic: topic.to_string(),
-            callback: Rc::new(RefCell::new(registration.clone())),
+            callback: Callback::Raw(Rc::new(RefCell::new(registration.clone()))),
+        }
+    }
+
+    pub fn from_handler<H: Handler + 'static>(handler: H) -> Registration {
+        Registration {
+            topic: handler.topic().to_string(),
+            callback: Callback::Handler(Box::new(handler)),
+        }
+    }
+
+    fn call(&self, raw: &str) -> Result<serde_json::Value, String> {
+        match &self.callback {
+            Callback::Raw(callback) => {
+                let callback = callback.borrow_mut();
+                Ok(serde_json::Value::String(callback(raw.to_string())))
+            }
+            Callback::Handler(handler) => handler.handle(raw),
+        }
+    }
+}
+
+// the last responses sent, by response topic: on topics declared with `delivery=exactly_once`, the broker sends a
+// timed out task again to the same worker, which sends back the response it already computed
+struct Deduplication {
+    capacity: usize,
+    keys: VecDeque<String>,
+    replies: HashMap<String, String>,
+}
+
+impl Deduplication {
+    fn new(capacity: usize) -> Deduplication {
+        Deduplication {
+            capacity,
+            keys: VecDeque::new(),
+            replies: HashMap::new(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&String> {
+        self.replies.get(key)
+    }
+
+    // the oldest response is forgotten once the capacity is reached
+    fn insert(&mut self, key: &str, reply: String) {
+        if self.replies.insert(key.to_string(), reply).is_some() {
+            return;
+        }
+        self.keys.push_back(key.to_string());
+        if self.keys.len() > self.capacity {
+            if let Some(oldest) = self.keys.pop_front() {
+                self.replies.remove(&oldest);
+            }
         }
     }
 }
 
+// workers stop gracefully on SIGTERM: they unregister (`@@UNREGISTER`), answer the tasks the broker already sent them,
+// for at most their drain timeout, then `run` returns, see `Broke::graceful_shutdown`
+const SIGTERM: i32 = 15;
+
+static TERMINATING: AtomicBool = AtomicBool::new(false);
+static HANDLE_SIGTERM: Once = Once::new();
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+// only an atomic store is safe in a signal handler, `run_once` reads it when it wakes up
+extern "C" fn terminate(_: i32) {
+    TERMINATING.store(true, Ordering::Relaxed);
+}
+
+// options of the socket to the broker, the zmq defaults block the process on exit while a message is unsent
+// (infinite linger), and drop messages after 1000 waiting ones
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub sndhwm: i32,
+    pub rcvhwm: i32,
+    pub linger: Duration,
+    pub tcp_keepalive: bool,
+    pub tcp_keepalive_idle: Duration,
+    pub reconnect_ivl: Duration,
+    pub reconnect_ivl_max: Duration,
+    // messages are only queued once connected to the broker, `send` blocks before
+    pub immediate: bool,
+    // `socks5://host:port` or `host:port`, HTTP proxies go through `tiny-broke tunnel`
+    pub socks_proxy: Option<String>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            sndhwm: 100_000,
+            rcvhwm: 100_000,
+            linger: Duration::from_secs(1),
+            tcp_keepalive: true,
+            tcp_keepalive_idle: Duration::from_secs(60),
+            reconnect_ivl: Duration::from_millis(100),
+            reconnect_ivl_max: Duration::from_secs(5),
+            immediate: false,
+            socks_proxy: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    fn apply(&self, socket: &zmq::Socket) -> Result<(), zmq::Error> {
+        socket.set_sndhwm(self.sndhwm)?;
+        socket.set_rcvhwm(self.rcvhwm)?;
+        socket.set_linger(self.linger.as_millis() as i32)?;
+        socket.set_tcp_keepalive(if self.tcp_keepalive { 1 } else { 0 })?;
+        socket.set_tcp_keepalive_idle(self.tcp_keepalive_idle.as_secs() as i32)?;
+        socket.set_reconnect_ivl(self.reconnect_ivl.as_millis() as i32)?;
+        socket.set_reconnect_ivl_max(self.reconnect_ivl_max.as_millis() as i32)?;
+        socket.set_immediate(self.immediate)?;
+        match &self.socks_proxy {
+            // libzmq wants the address without the scheme
+            Some(proxy) => socket.set_socks_proxy(Some(proxy.trim_start_matches("socks5://"))),
+            None => Ok(()),
+        }
+    }
+}
+
+// a request waiting for its response is sent again after a reconnection (missed pongs), or when the restarted broker
+// asks the client to re-subscribe (`@@RESUBSCRIBE`)
+// its response topic is kept, so the broker answers it only once
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // how many times a request is sent again, before `call` fails with `CallError::Unanswered`
+    pub max_resends: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy { max_resends: 3 }
+    }
+}
+
+// the DEALER socket of the peer, connected to the broker
+fn connect(
+    context: &zmq::Context,
+    identity: &str,
+    uri: &str,
+    options: &SocketOptions,
+) -> Result<zmq::Socket, zmq::Error> {
+    let socket = context.socket(zmq::SocketType::DEALER)?;
+    socket.set_identity(identity.as_bytes())?;
+    options.apply(&socket)?;
+    socket.connect(uri)?;
+    Ok(socket)
+}
+
 pub struct Broke {
-    socket: zmq::Socket,
+    // replaced on reconnection, see `reconnect`
+    socket: RefCell<zmq::Socket>,
+    context: zmq::Context,
+    uri: String,
+    options: SocketOptions,
     registrations: Vec<Registration>,
+    deduplication: RefCell<Option<Deduplication>>,
+    // a restarted worker supersedes its previous registrations
+    epoch: u128,
+    // the logical name of the worker, the same for all its processes
+    worker_name: Option<String>,
+    // share of the tasks of its topics, 1 for the broker when not given
+    worker_weight: Option<u32>,
+    // what the broker answered to the registrations (`@@FEATURES`), empty until then
+    broker_features: RefCell<Vec<String>>,
+    identity: String,
+    // the `CONTROL_SECRET` of the broker, see `sign_controls`
+    control_secret: Option<String>,
+    // how long `call` waits for a response, forever when none
+    call_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    // how long a worker answers its tasks once SIGTERM is received, SIGTERM is left to the application when none (the
+    // default)
+    drain_timeout: Option<Duration>,
+}
+
+// the features the worker declares when it registers, the broker doesn't use the other ones with it
+const FEATURES: &str = "restart";
+
+// twice the default `TASK_TIMEOUT` of the broker, so a task timing out on a worker can be answered by another one
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+// while waiting for a response, the client pings the broker when nothing came for `PING_INTERVAL`, and reconnects
+// when nothing comes back within `PING_TIMEOUT`
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+// the headers of a control message signed with the `CONTROL_SECRET` of the broker: the hexadecimal HMAC-SHA256 of
+// `<identity>\n<control>\n<worker topic>\n<options>\n<timestamp>`, and its timestamp (milliseconds since the epoch)
+fn signature_headers(secret: &str, message: &str, timestamp: u128) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}\n{}", message, timestamp).as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    serde_json::json!({
+        "x-signature": signature,
+        "x-signed-at": timestamp.to_string(),
+    })
+    .to_string()
 }
 
 impl Broke {
     pub fn new(name: &str, uri: &str, worker: bool) -> Broke {
-        let context = zmq::Context::new();
-        let socket = context.socket(zmq::SocketType::DEALER).unwrap();
+        Broke::with_options(name, uri, worker, SocketOptions::default())
+    }
+
+    pub fn with_options(name: &str, uri: &str, worker: bool, options: SocketOptions) -> Broke {
+        Broke::with_context(&zmq::Context::new(), name, uri, worker, options)
+    }
+
+    // `inproc://` endpoints are only reached from the context they are bound in, see `mock::MockBroker`
+    fn with_context(
+        context: &zmq::Context,
+        name: &str,
+        uri: &str,
+        worker: bool,
+        options: SocketOptions,
+    ) -> Broke {
         let entity = format!(
             "{}-{}-{}",
             if worker { "worker" } else { "client" },
             name,
             Uuid::new_v4()
         );
-
-        socket.set_identity(entity.as_bytes()).expect("Can't set zmq identity");
-        socket.connect(uri).expect("Can't connect");
+        let socket = connect(context, &entity, uri, &options).expect("Can't connect");
 
         Broke {
-            socket,
+            socket: RefCell::new(socket),
+            context: context.clone(),
+            uri: uri.to_string(),
+            options,
             registrations: vec![],
+            deduplication: RefCell::new(None),
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            worker_name: None,
+            worker_weight: None,
+            broker_features: RefCell::new(vec![]),
+            identity: entity,
+            control_secret: None,
+            call_timeout: Some(CALL_TIMEOUT),
+            retry_policy: RetryPolicy::default(),
+            drain_timeout: None,
         }
     }
 
-    pub fn register(&mut self, topic: &str, callback: &'static Fn(String) -> String) {
-        self.registrations.push(Registration::new(topic, callback));
+    // once SIGTERM is received, the worker answers the tasks the broker already sent it for at most this long, then
+    // `run` returns `Stop::Shutdown`, `None` (the default) leaves SIGTERM to the application
+    pub fn graceful_shutdown(&mut self, drain_timeout: Option<Duration>) {
+        self.drain_timeout = drain_timeout;
+    }
+
+    // how many times `call` sends a request again after a reconnection, 3 by default
+    pub fn retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
 
+    // a new socket, with the same identity: the broker still knows the response topics the client waits for
+    // the previous socket is kept when the new one can't be created
+    fn reconnect(&self) {
+        if let Ok(socket) = connect(&self.context, &self.identity, &self.uri, &self.options) {
+            *self.socket.borrow_mut() = socket;
+        }
+    }
+
+    // how long `call` waits for a response before failing with `CallError::Timeout`, two minutes by default, `None`
+    // waits forever
+    pub fn call_timeout(&mut self, timeout: Option<Duration>) {
+        self.call_timeout = timeout;
+    }
+
+    // a broker started with `CONTROL_SECRET` only accepts the registrations and unregistrations signed with it, given
+    // before registering
+    pub fn sign_controls(&mut self, secret: &str) {
+        self.control_secret = Some(secret.to_string());
+    }
+
+    // `@@REGISTER`, `@@UNREGISTER`, `@@UNSUBSCRIBE_TOPIC`, signed when there is a secret
+    fn send_control(&self, control: &str, topics: &str, options: &str) {
+        let mut frames = vec![control.to_string(), topics.to_string(), options.to_string()];
+        if let Some(secret) = &self.control_secret {
+            let message = format!("{}\n{}\n{}\n{}", self.identity, control, topics, options);
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            frames.extend(vec![
+                String::new(),
+                String::new(),
+                signature_headers(secret, &message, timestamp),
+            ]);
+        }
+        let frames: Vec<Vec<u8>> = frames.into_iter().map(String::into_bytes).collect();
         self.socket
-            .send("@@REGISTER", zmq::SNDMORE | zmq::DONTWAIT)
-            .and_then(|_| {
-                self.socket
-                    .send(&format!("@@ASKED>{}", topic), zmq::DONTWAIT)
+            .borrow()
+            .send_multipart(frames, zmq::DONTWAIT)
+            .ok();
+    }
+
+    // the features of the broker (`headers`, `retry`, `blobs`...), once a registration is answered
+    pub fn broker_features(&self) -> Vec<String> {
+        self.broker_features.borrow().clone()
+    }
+
+    // connects to the first broker found on the LAN (mDNS), otherwise to the first of `fallbacks` answering a ping
+    // `None` when no broker is found
+    pub fn discover(name: &str, worker: bool, fallbacks: &[&str]) -> Option<Broke> {
+        let timeout = Duration::from_secs(1);
+        let discovered = discover(timeout);
+        let context = zmq::Context::new();
+
+        discovered
+            .first()
+            .map(String::as_str)
+            .or_else(|| {
+                fallbacks
+                    .iter()
+                    .copied()
+                    .find(|endpoint| is_alive(&context, endpoint, timeout))
             })
+            .map(|endpoint| Broke::new(name, endpoint, worker))
+    }
+
+    // keeps the last `capacity` responses, a task received again is answered without calling its handler
+    // the responses are kept in memory: a task received again after a restart of the worker is processed again
+    pub fn deduplicate(&mut self, capacity: usize) {
+        *self.deduplication.borrow_mut() = Some(Deduplication::new(capacity));
+    }
+
+    pub fn register(&mut self, topic: &str, callback: &'static Fn(String) -> String) {
+        self.registrations.push(Registration::new(topic, callback));
+        self.send_registration(&format!("@@ASKED>{}", topic));
+    }
+
+    // registers a function annotated with `#[handler("TOPIC")]`
+    pub fn handle<H: Handler + 'static>(&mut self, handler: H) {
+        let topic = handler.topic();
+        self.registrations.push(Registration::from_handler(handler));
+        self.send_registration(&format!("@@ASKED>{}", topic));
+    }
+
+    // the worker stops serving the topic (`@@UNSUBSCRIBE_TOPIC`), without registering again, the tasks it is running
+    // can still be answered
+    pub fn stop_handling(&mut self, topic: &str) {
+        self.registrations
+            .retain(|registration| registration.topic != topic);
+        self.send_control("@@UNSUBSCRIBE_TOPIC", &format!("@@ASKED>{}", topic), "");
+    }
+
+    // the broker aggregates the stats of the worker under this name, and a new process registering with it supersedes
+    // the previous one: it has to be unique to the worker (like the name of its pod), and given before registering
+    pub fn name_worker(&mut self, name: &str) {
+        self.worker_name = Some(name.to_string());
+    }
+
+    // a worker with a weight of 2 gets twice the tasks of a worker of weight 1 (a faster machine), given before
+    // registering
+    pub fn weigh_worker(&mut self, weight: u32) {
+        self.worker_weight = Some(weight);
+    }
+
+    // registers the handlers again, after a `Stop::Restart`
+    // in one message, the worker is never registered to only part of its topics
+    pub fn register_again(&self) {
+        let topics: Vec<String> = self
+            .registrations
+            .iter()
+            .map(|registration| format!("@@ASKED>{}", registration.topic))
+            .collect();
+        self.send_registration(&topics.join("\n"));
+    }
+
+    // `topics` has a line per topic
+    fn send_registration(&self, topics: &str) {
+        let mut options = format!("epoch={} features={}", self.epoch, FEATURES);
+        if let Some(name) = &self.worker_name {
+            options.push_str(&format!(" name={}", name));
+        }
+        if let Some(weight) = self.worker_weight {
+            options.push_str(&format!(" weight={}", weight));
+        }
+        self.send_control("@@REGISTER", topics, &options);
+    }
+
+    // sends a task to the workers of `topic` and waits for its response, until the timeout of the calls
+    // this is meant for clients (`worker` set to `false`): messages that are not the response are ignored
+    // the task is sent again after a reconnection, see `RetryPolicy`
+    pub fn call<Req: Serialize, Resp: DeserializeOwned>(
+        &self,
+        topic: &str,
+        request: &Req,
+    ) -> Result<Resp, CallError> {
+        let returns_type = format!("{}>RESPONSE@@{}", topic, Uuid::new_v4());
+        let content = serde_json::to_string(&Request {
+            r#type: topic,
+            returns_type: &returns_type,
+            payload: request,
+        })?;
+
+        let request = [format!("@@ASKED>{}", topic), returns_type.clone(), content];
+        self.socket.borrow().send_multipart(&request, 0)?;
+
+        let deadline = self.call_timeout.map(|timeout| Instant::now() + timeout);
+        let mut resends = 0;
+        let mut pinged = false;
+        loop {
+            let heartbeat = if pinged { PING_TIMEOUT } else { PING_INTERVAL };
+            let wait = match deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(heartbeat),
+                None => heartbeat,
+            };
+            if self
+                .socket
+                .borrow()
+                .poll(zmq::POLLIN, wait.as_millis() as i64)?
+                == 0
+            {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(CallError::Timeout(self.call_timeout.unwrap_or_default()));
+                }
+                // the wait was cut short by the deadline, not by the heart beat
+                if wait < heartbeat {
+                    continue;
+                }
+                if !pinged {
+                    self.socket.borrow().send("@@PING", zmq::DONTWAIT).ok();
+                    pinged = true;
+                    continue;
+                }
+                // no pong: the broker is gone, or the connection is
+                self.reconnect();
+                self.resend(&request, &mut resends)?;
+                pinged = false;
+                continue;
+            }
+            pinged = false;
+            let parts = self.socket.borrow().recv_multipart(0)?;
+            let message = match parts.get(1).map(|part| String::from_utf8_lossy(part)) {
+                Some(message) => fetch_blob(&message),
+                None => continue,
+            };
+
+            if REJECTIONS.contains(&message.as_str()) {
+                // a rejection without topic is the one of the message of the client
+                let rejected_type = parts.get(2).map(|part| String::from_utf8_lossy(part));
+                if rejected_type.is_none()
+                    || rejected_type.as_deref() == Some(returns_type.as_str())
+                {
+                    // `@@ERROR` is followed by the code and the detail of the error
+                    let details = parts
+                        .iter()
+                        .skip(3)
+                        .map(|part| String::from_utf8_lossy(part));
+                    let reason = std::iter::once(message.clone())
+                        .chain(details.map(|detail| detail.into_owned()))
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    return Err(CallError::Rejected(reason));
+                }
+                continue;
+            }
+            // the broker restarted, without the request
+            if message == "@@RESUBSCRIBE" {
+                self.resend(&request, &mut resends)?;
+                continue;
+            }
+            if message.starts_with("@@") {
+                continue;
+            }
+
+            let response: Response = match serde_json::from_str(&message) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            if response.r#type != returns_type {
+                continue;
+            }
+
+            if let Some(error) = response.error.filter(|error| !error.is_null()) {
+                return Err(CallError::Worker(error));
+            }
+            return Ok(serde_json::from_value(response.payload)?);
+        }
+    }
+
+    // the request of `call` again, unless it was sent again too many times
+    fn resend(&self, request: &[String], resends: &mut u32) -> Result<(), CallError> {
+        if *resends >= self.retry_policy.max_resends {
+            return Err(CallError::Unanswered(*resends));
+        }
+        *resends += 1;
+        self.socket.borrow().send_multipart(request, 0)?;
+        Ok(())
+    }
+
+    fn reply(&self, returns_type: &str, content: &str) {
+        let socket = self.socket.borrow();
+        socket
+            .send(returns_type, zmq::SNDMORE | zmq::DONTWAIT)
+            .and_then(|_| socket.send("", zmq::SNDMORE | zmq::DONTWAIT))
+            .and_then(|_| socket.send(content, zmq::DONTWAIT))
             .ok();
     }
 
-    pub fn dispatch(&self, raw: &str) {
-        let message: Message = serde_json::from_str(raw).unwrap();
+    // the task is handled by the registrations of its topic, and answered
+    pub fn dispatch(&self, raw: &str) -> Result<(), DispatchError> {
+        if raw.starts_with("@@") && !raw.starts_with(BLOB_PREFIX) {
+            return Err(DispatchError::Control(raw.to_string()));
+        }
+        let raw = &fetch_blob(raw);
+        let message: Message = serde_json::from_str(raw)?;
+
+        let sent = self
+            .deduplication
+            .borrow()
+            .as_ref()
+            .and_then(|deduplication| deduplication.get(&message.returns_type).cloned());
+        if let Some(content) = sent {
+            self.reply(&message.returns_type, &content);
+            return Ok(());
+        }
 
         self.registrations
             .iter()
             .filter(|registration| registration.topic == message.r#type)
             .for_each(|registration| {
-                let callback = registration.callback.borrow_mut();
-                let payload = callback(raw.to_string());
-
-                self.socket
-                    .send(&message.returns_type, zmq::SNDMORE | zmq::DONTWAIT)
-                    .and_then(|_| self.socket.send("", zmq::SNDMORE | zmq::DONTWAIT))
-                    .and_then(|_| {
-                        self.socket.send(
-                            &format!(
-                                "{{ \"type\": \"{}\", \"payload\": \"{}\" }}",
-                                message.returns_type, payload
-                            ),
-                            zmq::DONTWAIT,
-                        )
-                    })
-                    .ok();
+                let reply = match registration.call(raw) {
+                    Ok(payload) => Reply {
+                        r#type: &message.returns_type,
+                        payload,
+                        error: None,
+                    },
+                    Err(error) => Reply {
+                        r#type: &message.returns_type,
+                        payload: serde_json::Value::Null,
+                        error: Some(error),
+                    },
+                };
+                let content = serde_json::to_string(&reply).unwrap();
+
+                self.reply(&message.returns_type, &content);
+                if let Some(deduplication) = self.deduplication.borrow_mut().as_mut() {
+                    deduplication.insert(&message.returns_type, content);
+                }
             });
+        Ok(())
     }
 
-    pub fn run(&self) {
-        let mut zmq_message = zmq::Message::new();
-        let mut index = 0;
-        let mut identity = String::from("");
-        let mut raw = String::from("");
+    // handles the tasks until the broker asks the worker to stop
+    pub fn run(&self) -> Result<Stop, zmq::Error> {
+        loop {
+            if let Some(stop) = self.run_once()? {
+                return Ok(stop);
+            }
+        }
+    }
 
+    // the worker unregisters, and answers the tasks the broker sent before getting the unregistration: the broker
+    // answers a ping after them
+    fn drain(&self, timeout: Duration) {
+        self.send_control("@@UNREGISTER", "", "");
+        self.socket.borrow().send("@@PING", zmq::DONTWAIT).ok();
+
+        let deadline = Instant::now() + timeout;
         loop {
-            self.socket.recv(&mut zmq_message, 0).unwrap();
-            let part = zmq_message.as_str().unwrap().to_owned();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // a second signal interrupts the poll
+            if self
+                .socket
+                .borrow()
+                .poll(zmq::POLLIN, remaining.as_millis() as i64)
+                .unwrap_or(0)
+                == 0
+            {
+                return;
+            }
+            let parts = match self.socket.borrow().recv_multipart(0) {
+                Ok(parts) => parts,
+                Err(_) => return,
+            };
+            match parts.get(1).map(|part| String::from_utf8_lossy(part)) {
+                Some(pong) if pong == "@@PONG" => return,
+                // `@@REGISTER` is not answered, the worker is leaving
+                Some(raw) => {
+                    self.dispatch(&raw).ok();
+                }
+                None => {}
+            }
+        }
+    }
 
-            match index {
-                0 => identity = part,
-                1 => raw = part,
-                _ => panic!(format!("Unknown index for message: {}", index)),
+    // waits for the next task and handles it, or unregisters when the broker asks the worker to stop
+    // on SIGTERM, the worker drains then stops, see `graceful_shutdown`
+    pub fn run_once(&self) -> Result<Option<Stop>, zmq::Error> {
+        if self.drain_timeout.is_some() {
+            // the handler only touches an atomic
+            HANDLE_SIGTERM.call_once(|| unsafe {
+                signal(SIGTERM, terminate);
+            });
+        }
+        // a signal interrupts the poll and the receive, they are tried again
+        loop {
+            match self.socket.borrow().poll(zmq::POLLIN, 100) {
+                Ok(0) | Err(zmq::Error::EINTR) => {}
+                Ok(_) => break,
+                Err(error) => return Err(error),
             }
+            if let (true, Some(timeout)) = (TERMINATING.load(Ordering::Relaxed), self.drain_timeout)
+            {
+                self.drain(timeout);
+                return Ok(Some(Stop::Shutdown));
+            }
+        }
+        let parts = loop {
+            match self.socket.borrow().recv_multipart(0) {
+                Err(zmq::Error::EINTR) => {}
+                parts => break parts?,
+            }
+        };
+        // the delimiter, the task, then the direct endpoint and the headers of the task, unused here
+        let raw = match parts.get(1) {
+            Some(raw) => String::from_utf8_lossy(raw),
+            None => return Ok(None),
+        };
+        if raw == "@@FEATURES" {
+            let features = parts
+                .get(2)
+                .map(|frame| String::from_utf8_lossy(frame).into_owned());
+            *self.broker_features.borrow_mut() = features
+                .unwrap_or_default()
+                .split(' ')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect();
+            return Ok(None);
+        }
+        Ok(match Stop::parse(&raw) {
+            Some(stop) => {
+                self.send_control("@@UNREGISTER", "", "");
+                Some(stop)
+            }
+            // the broker lost the registrations of the worker
+            None if raw == "@@REGISTER" => {
+                self.register_again();
+                None
+            }
+            // the other control messages (`@@BAD_SIGNATURE`, `@@IDENTITY_CONFLICT`...) are not tasks, they are skipped
+            None => {
+                self.dispatch(&raw).ok();
+                None
+            }
+        })
+    }
+}
 
-            if zmq_message.get_more() {
-                index += 1;
-            } else {
-                index = 0;
+#[cfg(test)]
+mod tests {
+    use super::{signature_headers, CallError, DispatchError, RetryPolicy, REJECTIONS};
+    use crate::mock::{Behavior, MockBroker};
+    use serde_json::json;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn controls_are_signed_with_their_timestamp() {
+        let headers: serde_json::Value = serde_json::from_str(&signature_headers(
+            "secret",
+            "worker-1\n@@REGISTER\nADD\n",
+            1_500_000_000_000,
+        ))
+        .unwrap();
+        assert_eq!(
+            headers,
+            serde_json::json!({
+                "x-signature": "4beab7eb9efd029108398c38e014c084c3ab0f13feaebb5aea763518d11017b0",
+                "x-signed-at": "1500000000000",
+            })
+        );
+    }
 
-                self.dispatch(&raw);
+    #[test]
+    fn control_messages_are_not_dispatched() {
+        let mock = MockBroker::start();
+        let worker = mock.worker("service-users");
+        for control in REJECTIONS.iter().chain(&["@@PONG"]) {
+            match worker.dispatch(control) {
+                Err(DispatchError::Control(message)) => assert_eq!(&message, control),
+                result => panic!("{} was dispatched: {:?}", control, result),
             }
         }
+        match worker.dispatch("{") {
+            Err(DispatchError::Serialization(_)) => {}
+            result => panic!("a task that is not JSON was dispatched: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejected_tasks_fail() {
+        let mock = MockBroker::start();
+        mock.on(
+            "INVOICES>GET",
+            Behavior::Reject("@@DEPENDENCY_FAILED".to_string()),
+        );
+        let client = mock.client("graphql-api");
+        match client.call::<_, serde_json::Value>("INVOICES>GET", &10) {
+            Err(CallError::Rejected(reason)) => assert_eq!(reason, "@@DEPENDENCY_FAILED"),
+            result => panic!("the task wasn't rejected: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn calls_time_out() {
+        let mock = MockBroker::start();
+        mock.on("INVOICES>GET", Behavior::Drop);
+        let mut client = mock.client("graphql-api");
+        client.call_timeout(Some(Duration::from_millis(50)));
+        match client.call::<_, serde_json::Value>("INVOICES>GET", &10) {
+            Err(CallError::Timeout(timeout)) => assert_eq!(timeout, Duration::from_millis(50)),
+            result => panic!("the call didn't time out: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn draining_workers_answer_the_tasks_they_were_sent() {
+        let mock = MockBroker::start();
+        let mut worker = mock.worker("service-math");
+        worker.register("DOUBLE", &|raw: String| raw);
+        let returns_type = mock.send_task("DOUBLE", &21);
+
+        worker.drain(Duration::from_secs(1));
+        assert!(mock
+            .response(&returns_type, Duration::from_secs(1))
+            .is_some());
+        // unregistered, the next task waits for another worker
+        let returns_type = mock.send_task("DOUBLE", &21);
+        assert!(mock
+            .response(&returns_type, Duration::from_millis(50))
+            .is_none());
+    }
+
+    #[test]
+    fn requests_are_sent_again_to_a_restarted_broker() {
+        let mock = MockBroker::start();
+        mock.on("INVOICES>GET", Behavior::Drop);
+        let client = mock.client("graphql-api");
+
+        let invoice: serde_json::Value = thread::scope(|scope| {
+            scope.spawn(|| {
+                while mock.tasks("INVOICES>GET").is_empty() {
+                    thread::yield_now();
+                }
+                mock.on("INVOICES>GET", Behavior::Respond(json!({ "price": 100 })));
+                mock.restart();
+            });
+            client.call("INVOICES>GET", &10).unwrap()
+        });
+        assert_eq!(invoice, json!({ "price": 100 }));
+        assert_eq!(mock.tasks("INVOICES>GET"), vec![json!(10), json!(10)]);
+        // the response topic is kept, the broker answers the request once
+        let tasks = mock.all_tasks();
+        assert_eq!(tasks[0].returns_type, tasks[1].returns_type);
+    }
+
+    #[test]
+    fn requests_are_sent_again_a_limited_number_of_times() {
+        let mock = MockBroker::start();
+        mock.on("INVOICES>GET", Behavior::Drop);
+        let mut client = mock.client("graphql-api");
+        client.retry_policy(RetryPolicy { max_resends: 0 });
+
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                while mock.tasks("INVOICES>GET").is_empty() {
+                    thread::yield_now();
+                }
+                mock.restart();
+            });
+            client.call::<_, serde_json::Value>("INVOICES>GET", &10)
+        });
+        match result {
+            Err(CallError::Unanswered(resends)) => assert_eq!(resends, 0),
+            result => panic!("the request was answered: {:?}", result),
+        }
     }
 }
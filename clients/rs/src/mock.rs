@@ -0,0 +1,466 @@
+use crate::{Broke, SocketOptions, Stop};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use zmq;
+
+// a broker speaking the wire protocol on an `inproc://` socket, in a thread of the test, so the code using `Broke`
+// can be tested without running tiny-broke
+// tasks sent to a topic get the behavior scripted for it, otherwise they go to the workers registered to it:
+//
+// ```
+// let mock = MockBroker::start();
+// mock.on("INVOICES>GET", Behavior::Respond(json!({ "id": 10, "price": 100 })));
+// let broke = mock.client("graphql-api");
+// let invoice: Invoice = broke.call("INVOICES>GET", &GetInvoice { id: 10 }).unwrap();
+// assert_eq!(mock.tasks("INVOICES>GET"), vec![json!({ "id": 10 })]);
+// ```
+
+// what the mock does with the tasks of a topic
+#[derive(Debug, Clone)]
+pub enum Behavior {
+    // the worker responds this payload
+    Respond(Value),
+    // the worker responds this error, `call` fails with `CallError::Worker`
+    Error(Value),
+    // the behavior happens after this delay
+    Delay(Duration, Box<Behavior>),
+    // nobody answers
+    Drop,
+    // the broker refuses the task (`@@NO_TOPIC`, `@@FORBIDDEN`, `@@QUEUE_FULL`, `@@STOPPING`, `@@DEPENDENCY_FAILED`),
+    // `call` fails with `CallError::Rejected`
+    Reject(String),
+}
+
+// a task the mock received
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub topic: String,
+    pub returns_type: String,
+    pub payload: Value,
+}
+
+#[derive(Default)]
+struct State {
+    behaviors: HashMap<String, Behavior>,
+    tasks: Vec<Task>,
+    // by topic
+    workers: HashMap<String, VecDeque<String>>,
+    // tasks without behavior nor worker yet: identity of the client (none for `send_task`), topic, raw task
+    waiting: Vec<(Option<String>, String, String)>,
+    // client waiting for each response, the mock itself for the tasks of `send_task`
+    clients: HashMap<String, Option<String>>,
+    responses: HashMap<String, Value>,
+    // messages sent once their date is reached, by identity
+    scheduled: Vec<(Instant, String, Vec<String>)>,
+    // identities of the clients that sent tasks
+    peers: Vec<String>,
+    stopped: bool,
+}
+
+impl State {
+    // the messages to send now
+    fn take_due(&mut self, now: Instant) -> Vec<(String, Vec<String>)> {
+        let (due, later) = self
+            .scheduled
+            .drain(..)
+            .partition(|(date, _, _)| *date <= now);
+        self.scheduled = later;
+        due.into_iter()
+            .map(|(_, identity, frames)| (identity, frames))
+            .collect()
+    }
+
+    fn schedule(&mut self, delay: Duration, identity: &str, frames: Vec<String>) {
+        self.scheduled
+            .push((Instant::now() + delay, identity.to_string(), frames));
+    }
+
+    fn apply(&mut self, behavior: Behavior, delay: Duration, client: &str, returns_type: &str) {
+        let response = |payload: Value, error: Value| {
+            json!({ "type": returns_type, "payload": payload, "error": error }).to_string()
+        };
+        match behavior {
+            Behavior::Respond(payload) => self.schedule(
+                delay,
+                client,
+                vec![String::new(), response(payload, Value::Null)],
+            ),
+            Behavior::Error(error) => self.schedule(
+                delay,
+                client,
+                vec![String::new(), response(Value::Null, error)],
+            ),
+            Behavior::Delay(more, behavior) => {
+                self.apply(*behavior, delay + more, client, returns_type)
+            }
+            Behavior::Drop => {}
+            Behavior::Reject(rejection) => self.schedule(
+                delay,
+                client,
+                vec![String::new(), rejection, returns_type.to_string()],
+            ),
+        }
+    }
+
+    // to the next worker of the topic, round robin
+    fn forward(&mut self, client: Option<&str>, topic: &str, raw: &str) -> bool {
+        let worker = match self.workers.get_mut(topic) {
+            Some(workers) if !workers.is_empty() => {
+                let worker = workers.pop_front().unwrap();
+                workers.push_back(worker.clone());
+                worker
+            }
+            _ => return false,
+        };
+        if let Ok(task) = serde_json::from_str::<Value>(raw) {
+            if let Some(returns_type) = task.get("returnsType").and_then(Value::as_str) {
+                self.clients
+                    .insert(returns_type.to_string(), client.map(String::from));
+            }
+        }
+        self.schedule(
+            Duration::from_secs(0),
+            &worker,
+            vec![String::new(), raw.to_string()],
+        );
+        true
+    }
+
+    // to a worker, or once one registers to the topic
+    fn dispatch(&mut self, client: Option<&str>, topic: &str, raw: &str) {
+        if !self.forward(client, topic, raw) {
+            self.waiting
+                .push((client.map(String::from), topic.to_string(), raw.to_string()));
+        }
+    }
+
+    fn handle(&mut self, identity: &str, frames: &[String]) {
+        match frames {
+            [ping, ..] if ping == "@@PING" => self.schedule(
+                Duration::from_secs(0),
+                identity,
+                vec![String::new(), "@@PONG".to_string()],
+            ),
+            // a line per topic
+            [register, topics, ..] if register == "@@REGISTER" => {
+                for topic in topics.lines() {
+                    let topic = topic.trim_start_matches("@@ASKED>").to_string();
+                    self.workers
+                        .entry(topic.clone())
+                        .or_default()
+                        .push_back(identity.to_string());
+                    for (client, waiting_topic, raw) in std::mem::take(&mut self.waiting) {
+                        if waiting_topic != topic || !self.forward(client.as_deref(), &topic, &raw)
+                        {
+                            self.waiting.push((client, waiting_topic, raw));
+                        }
+                    }
+                }
+            }
+            [unregister, ..] if unregister == "@@UNREGISTER" => {
+                for workers in self.workers.values_mut() {
+                    workers.retain(|worker| worker != identity);
+                }
+            }
+            // a worker response
+            [returns_type, empty, content, ..] if empty.is_empty() => {
+                match self.clients.remove(returns_type) {
+                    Some(Some(client)) => self.schedule(
+                        Duration::from_secs(0),
+                        &client,
+                        vec![String::new(), content.to_string()],
+                    ),
+                    _ => {
+                        let response = serde_json::from_str(content).unwrap_or(Value::Null);
+                        self.responses.insert(returns_type.to_string(), response);
+                    }
+                }
+            }
+            // a task
+            [topic, returns_type, raw, ..] => {
+                let topic = topic.trim_start_matches("@@ASKED>").to_string();
+                let payload = serde_json::from_str::<Value>(raw)
+                    .ok()
+                    .and_then(|task| task.get("payload").cloned())
+                    .unwrap_or(Value::Null);
+                if !self.peers.iter().any(|peer| peer == identity) {
+                    self.peers.push(identity.to_string());
+                }
+                self.tasks.push(Task {
+                    topic: topic.clone(),
+                    returns_type: returns_type.to_string(),
+                    payload,
+                });
+
+                match self.behaviors.get(&topic).cloned() {
+                    Some(behavior) => {
+                        self.apply(behavior, Duration::from_secs(0), identity, returns_type)
+                    }
+                    None => self.dispatch(Some(identity), &topic, raw),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct MockBroker {
+    context: zmq::Context,
+    endpoint: String,
+    state: Arc<Mutex<State>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockBroker {
+    pub fn start() -> MockBroker {
+        let context = zmq::Context::new();
+        let endpoint = format!("inproc://tiny-broke-mock-{}", Uuid::new_v4());
+        let router = context.socket(zmq::SocketType::ROUTER).unwrap();
+        router.bind(&endpoint).expect("Can't bind the mock broker");
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let thread_state = state.clone();
+        let thread = thread::spawn(move || serve(router, thread_state));
+
+        MockBroker {
+            context,
+            endpoint,
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    // a client connected to the mock
+    pub fn client(&self, name: &str) -> Broke {
+        Broke::with_context(
+            &self.context,
+            name,
+            &self.endpoint,
+            false,
+            SocketOptions::default(),
+        )
+    }
+
+    // a worker connected to the mock, it gets the tasks of the topics it registers to that have no behavior
+    pub fn worker(&self, name: &str) -> Broke {
+        Broke::with_context(
+            &self.context,
+            name,
+            &self.endpoint,
+            true,
+            SocketOptions::default(),
+        )
+    }
+
+    // the behavior of the tasks sent to the topic from now on
+    pub fn on(&self, topic: &str, behavior: Behavior) {
+        self.state
+            .lock()
+            .unwrap()
+            .behaviors
+            .insert(topic.to_string(), behavior);
+    }
+
+    // the payloads of the tasks received on the topic, in order
+    pub fn tasks(&self, topic: &str) -> Vec<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .tasks
+            .iter()
+            .filter(|task| task.topic == topic)
+            .map(|task| task.payload.clone())
+            .collect()
+    }
+
+    pub fn all_tasks(&self) -> Vec<Task> {
+        self.state.lock().unwrap().tasks.clone()
+    }
+
+    // sends a task to a worker registered to the topic (once there is one), its response is read with `response`
+    // the returned value is the response topic of the task
+    pub fn send_task<Req: Serialize>(&self, topic: &str, payload: &Req) -> String {
+        let returns_type = format!("{}>RESPONSE@@{}", topic, Uuid::new_v4());
+        let raw = json!({
+            "type": topic,
+            "returnsType": returns_type,
+            "payload": payload,
+        })
+        .to_string();
+
+        self.state.lock().unwrap().dispatch(None, topic, &raw);
+        returns_type
+    }
+
+    // asks the registered workers of this name (given to `worker`) to stop, as the `SHUTDOWN` and `RESTART` admin
+    // commands of the broker do
+    pub fn stop_worker(&self, name: &str, stop: Stop) {
+        let mut state = self.state.lock().unwrap();
+        let prefix = format!("worker-{}-", name);
+        let mut identities: Vec<String> = state
+            .workers
+            .values()
+            .flatten()
+            .filter(|identity| identity.starts_with(&prefix))
+            .cloned()
+            .collect();
+        identities.dedup();
+        for identity in identities {
+            state.schedule(
+                Duration::from_secs(0),
+                &identity,
+                vec![String::new(), stop.control().to_string()],
+            );
+        }
+    }
+
+    // asks the clients that sent tasks to re-subscribe (`@@RESUBSCRIBE`), as a restarted broker does: they send again
+    // the requests they are waiting for
+    pub fn restart(&self) {
+        let mut state = self.state.lock().unwrap();
+        for peer in state.peers.clone() {
+            state.schedule(
+                Duration::from_secs(0),
+                &peer,
+                vec![String::new(), "@@RESUBSCRIBE".to_string()],
+            );
+        }
+    }
+
+    // the response of a task sent with `send_task`: `{ "type": ..., "payload": ..., "error": ... }`
+    pub fn response(&self, returns_type: &str, timeout: Duration) -> Option<Value> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(response) = self.state.lock().unwrap().responses.remove(returns_type) {
+                return Some(response);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        // the state is poisoned when a test failed while holding it
+        match self.state.lock() {
+            Ok(mut state) => state.stopped = true,
+            Err(poisoned) => poisoned.into_inner().stopped = true,
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn serve(router: zmq::Socket, state: Arc<Mutex<State>>) {
+    router.set_linger(0).ok();
+
+    loop {
+        // scheduled messages are sent within a millisecond
+        let readable = router.poll(zmq::POLLIN, 1).unwrap_or(0) > 0;
+        let received = match readable {
+            true => router.recv_multipart(zmq::DONTWAIT).ok(),
+            false => None,
+        };
+
+        let mut state = state.lock().unwrap();
+        if state.stopped {
+            return;
+        }
+        if let Some(parts) = received {
+            let parts: Vec<String> = parts
+                .iter()
+                .map(|part| String::from_utf8_lossy(part).into_owned())
+                .collect();
+            if let Some((identity, frames)) = parts.split_first() {
+                state.handle(identity, frames);
+            }
+        }
+
+        for (identity, frames) in state.take_due(Instant::now()) {
+            router
+                .send(&identity, zmq::SNDMORE)
+                .and_then(|_| router.send_multipart(&frames, 0))
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Behavior, MockBroker};
+    use crate::{CallError, Stop};
+    use serde_json::{json, Value};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn behaviors_answer_the_calls() {
+        let mock = MockBroker::start();
+        mock.on("INVOICES>GET", Behavior::Respond(json!({ "price": 100 })));
+        mock.on("INVOICES>PAY", Behavior::Error(json!("no money")));
+        mock.on(
+            "INVOICES>LATE",
+            Behavior::Delay(
+                Duration::from_millis(20),
+                Box::new(Behavior::Respond(json!(1))),
+            ),
+        );
+        let client = mock.client("graphql-api");
+
+        let invoice: Value = client.call("INVOICES>GET", &json!({ "id": 10 })).unwrap();
+        assert_eq!(invoice, json!({ "price": 100 }));
+        match client.call::<_, Value>("INVOICES>PAY", &10) {
+            Err(CallError::Worker(error)) => assert_eq!(error, json!("no money")),
+            result => panic!("the worker didn't fail: {:?}", result),
+        }
+        assert_eq!(client.call::<_, u32>("INVOICES>LATE", &10).unwrap(), 1);
+        assert_eq!(mock.tasks("INVOICES>GET"), vec![json!({ "id": 10 })]);
+    }
+
+    #[test]
+    fn tasks_go_to_the_workers() {
+        let mock = MockBroker::start();
+        let mut worker = mock.worker("service-math");
+        worker.register("DOUBLE", &|raw: String| {
+            let task: Value = serde_json::from_str(&raw).unwrap();
+            (task["payload"].as_u64().unwrap() * 2).to_string()
+        });
+
+        let returns_type = mock.send_task("DOUBLE", &21);
+        assert_eq!(worker.run_once().unwrap(), None);
+        let response = mock
+            .response(&returns_type, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(response["payload"], json!("42"));
+    }
+
+    #[test]
+    fn stopped_workers_unregister() {
+        let mock = MockBroker::start();
+        let mut worker = mock.worker("service-math");
+        worker.register("DOUBLE", &|raw: String| raw);
+        // the registration reaches the mock before the stop
+        while mock.state.lock().unwrap().workers.is_empty() {
+            thread::yield_now();
+        }
+
+        mock.stop_worker("service-math", Stop::Restart);
+        assert_eq!(worker.run_once().unwrap(), Some(Stop::Restart));
+        while !mock.state.lock().unwrap().workers["DOUBLE"].is_empty() {
+            thread::yield_now();
+        }
+    }
+}
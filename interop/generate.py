@@ -0,0 +1,119 @@
+#!/usr/bin/env python3
+# generates the protocol modules of the reference clients from the broker spec:
+#   tiny-broke protocol describe --format json | interop/generate.py
+import json
+import os
+import sys
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+HEADER = "generated by interop/generate.py from `tiny-broke protocol describe --format json`, do not edit"
+
+
+def free_frames(message):
+    return [frame["name"] for frame in message["frames"] if frame["value"] is None]
+
+
+def control(message):
+    # the fixed topic frame of a broker message, if any
+    return next((frame["value"] for frame in message["frames"] if frame["name"] == "topic"), None)
+
+
+def python(spec):
+    lines = [f"# {HEADER}", "", f"VERSION = {spec['version']}", ""]
+
+    lines += [
+        "",
+        "def _frame(value):",
+        "    return value if isinstance(value, bytes) else str(value).encode()",
+        "",
+    ]
+
+    for message in spec["messages"]:
+        if message["direction"] != "peer>broker":
+            continue
+        frames = [
+            repr(frame["value"].encode()) if frame["value"] is not None else f"_frame({frame['name']})"
+            for frame in message["frames"]
+        ]
+        lines += [
+            "",
+            f"def {message['name']}({', '.join(free_frames(message))}):",
+            f"    \"\"\"{message['description']}\"\"\"",
+            f"    return [{', '.join(frames)}]",
+            "",
+        ]
+
+    controls = {control(m): m for m in spec["messages"] if m["direction"] == "broker>peer" and control(m)}
+    lines += ["", "# broker messages, by their fixed topic: (name, free frames)", "CONTROLS = {"]
+    lines += [f"    {k!r}: ({m['name']!r}, {free_frames(m)!r})," for k, m in controls.items()]
+    lines += [
+        "}",
+        "",
+        "",
+        "def parse(frames):",
+        "    \"\"\"returns the name of a message received from the broker, and its free frames\"\"\"",
+        "    frames = [frame.decode() for frame in frames[1:]]",
+        "    if frames and frames[0] in CONTROLS:",
+        "        name, names = CONTROLS[frames[0]]",
+        "        return name, dict(zip(names, frames[1:]))",
+        "    return 'delivery', {'payload': frames[0] if frames else ''}",
+        "",
+    ]
+    return "\n".join(lines)
+
+
+def node(spec):
+    lines = [f"// {HEADER}", "", f"const VERSION = {spec['version']}", ""]
+
+    names = []
+    for message in spec["messages"]:
+        if message["direction"] != "peer>broker":
+            continue
+        name = "".join(part if i == 0 else part.capitalize() for i, part in enumerate(message["name"].split("_")))
+        names.append(name)
+        frames = [
+            json.dumps(frame["value"]) if frame["value"] is not None else f"String({frame['name']})"
+            for frame in message["frames"]
+        ]
+        lines += [
+            f"// {message['description']}",
+            f"const {name} = ({', '.join(free_frames(message))}) => [{', '.join(frames)}]",
+            "",
+        ]
+
+    controls = {control(m): m for m in spec["messages"] if m["direction"] == "broker>peer" and control(m)}
+    lines += ["// broker messages, by their fixed topic: [name, free frames]", "const CONTROLS = {"]
+    lines += [f"  {json.dumps(k)}: [{json.dumps(m['name'])}, {json.dumps(free_frames(m))}]," for k, m in controls.items()]
+    lines += [
+        "}",
+        "",
+        "// returns the name of a message received from the broker, and its free frames",
+        "const parse = (frames) => {",
+        "  const [first, ...rest] = frames.slice(1).map(frame => frame.toString())",
+        "  if (first in CONTROLS) {",
+        "    const [name, names] = CONTROLS[first]",
+        "    return [name, Object.fromEntries(names.map((field, i) => [field, rest[i]]))]",
+        "  }",
+        "  return ['delivery', { payload: first || '' }]",
+        "}",
+        "",
+        f"module.exports = {{ VERSION, CONTROLS, parse, {', '.join(names)} }}",
+        "",
+    ]
+    return "\n".join(lines)
+
+
+def main():
+    # interop/generate.py [spec.json|-] [output directory]
+    source = sys.argv[1] if len(sys.argv) > 1 else "-"
+    spec = json.load(sys.stdin if source == "-" else open(source))
+    output = sys.argv[2] if len(sys.argv) > 2 else HERE
+
+    with open(os.path.join(output, "python", "tiny_broke_protocol.py"), "w") as file:
+        file.write(python(spec))
+    with open(os.path.join(output, "node", "tiny_broke_protocol.js"), "w") as file:
+        file.write(node(spec))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,75 @@
+// reference client, speaking the frame protocol through the generated module
+//   client.js worker <topic>             answers the tasks of <topic> until killed
+//   client.js request <topic> <payload>  sends a task and prints the response payload
+// payloads follow the JS client convention: JSON with the response topic in `returnsType`
+const zmq = require('zeromq')
+const crypto = require('crypto')
+const protocol = require('./tiny_broke_protocol')
+
+const URI = process.env.BROKER_URI || 'tcp://localhost:3000'
+const TIMEOUT = 5000
+
+const connect = (kind) => {
+  const socket = zmq.socket('dealer')
+  socket.identity = `${kind}-node-${process.pid}`
+  socket.connect(URI)
+  return socket
+}
+
+const worker = (topic) => {
+  const socket = connect('worker')
+  socket.send(protocol.register(topic))
+  setInterval(() => socket.send(protocol.ping()), 1000)
+
+  socket.on('message', (...frames) => {
+    const [name, fields] = protocol.parse(frames)
+    if (name === 'register_again') {
+      socket.send(protocol.register(topic))
+    } else if (name === 'delivery') {
+      const action = JSON.parse(fields.payload)
+      const response = {
+        type: action.returnsType,
+        from: action.type,
+        payload: `node:${action.payload}`,
+      }
+      socket.send(protocol.response(action.returnsType, JSON.stringify(response)))
+    }
+  })
+}
+
+const request = (topic, payload) => {
+  const socket = connect('client')
+  const responseTopic = `${topic}>RESPONSE@@${crypto.randomBytes(16).toString('hex')}`
+  const action = { type: topic, returnsType: responseTopic, payload }
+  socket.send(protocol.task(topic, responseTopic, JSON.stringify(action)))
+
+  const timer = setTimeout(() => {
+    console.error('timeout')
+    process.exit(1)
+  }, TIMEOUT)
+
+  socket.on('message', (...frames) => {
+    const [name, fields] = protocol.parse(frames)
+    if (name === 'delivery') {
+      console.log(JSON.parse(fields.payload).payload)
+    } else if (fields.response_topic === responseTopic) {
+      console.error(`rejected: ${name}`)
+      process.exitCode = 1
+    } else {
+      return
+    }
+
+    clearTimeout(timer)
+    socket.close()
+  })
+}
+
+const [command, ...args] = process.argv.slice(2)
+if (command === 'worker' && args.length === 1) {
+  worker(args[0])
+} else if (command === 'request' && args.length === 2) {
+  request(args[0], args[1])
+} else {
+  console.error('usage: client.js worker <topic> | request <topic> <payload>')
+  process.exit(2)
+}
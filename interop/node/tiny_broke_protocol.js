@@ -0,0 +1,128 @@
+// generated by interop/generate.py from `tiny-broke protocol describe --format json`, do not edit
+
+const VERSION = 1
+
+// sent regularly by every peer, the broker answers with a pong
+const ping = () => ["@@PING"]
+
+// a worker registers to a topic
+const register = (worker_topic) => ["@@REGISTER", String(worker_topic)]
+
+// a worker registers to all its topics at once, the registration is taken or refused as a whole
+const registerTopics = (worker_topics, options) => ["@@REGISTER", String(worker_topics), String(options)]
+
+// a worker leaves, its running tasks can still be answered
+const unregister = () => ["@@UNREGISTER"]
+
+// a registered worker serves more topics, without registering again
+const subscribeTopic = (worker_topics, options) => ["@@SUBSCRIBE_TOPIC", String(worker_topics), String(options)]
+
+// a worker stops serving topics (and their labelled topics), its running tasks can still be answered
+const unsubscribeTopic = (worker_topics) => ["@@UNSUBSCRIBE_TOPIC", String(worker_topics)]
+
+// a worker registers to a topic, on a broker with CONTROL_SECRET
+const signedRegister = (worker_topic, options, headers) => ["@@REGISTER", String(worker_topic), String(options), "", "", String(headers)]
+
+// a worker leaves, on a broker with CONTROL_SECRET
+const signedUnregister = (headers) => ["@@UNREGISTER", "", "", "", "", String(headers)]
+
+// a client waits for responses on a topic without sending a task
+const subscribe = (response_topic) => ["@@SUBSCRIBE", String(response_topic)]
+
+// a client waits for the tasks of a group to be answered or failed
+const group = (group, size) => ["@@GROUP", String(group), String(size)]
+
+// a client confirms a response, which isn't sent again
+const received = (response_topic) => ["@@RECEIVED", String(response_topic)]
+
+// a client stops waiting for responses on a topic
+const unsubscribe = (response_topic) => ["@@UNSUBSCRIBE", String(response_topic)]
+
+// a client asks for something
+const task = (worker_topic, response_topic, payload) => [String(worker_topic), String(response_topic), String(payload)]
+
+// a client asks for something, after the previous tasks of the partition on an ordered topic
+const orderedTask = (worker_topic, response_topic, payload, partition_key) => [String(worker_topic), String(response_topic), String(payload), String(partition_key)]
+
+// a client asks for something, once other tasks are answered
+const dependentTask = (worker_topic, response_topic, payload, partition_key, dependencies) => [String(worker_topic), String(response_topic), String(payload), String(partition_key), String(dependencies)]
+
+// a client asks for something, with headers (auth tokens, trace ids, ...)
+const taskWithHeaders = (worker_topic, response_topic, payload, partition_key, dependencies, headers) => [String(worker_topic), String(response_topic), String(payload), String(partition_key), String(dependencies), String(headers)]
+
+// a worker answers a task
+const response = (response_topic, payload) => [String(response_topic), "", String(payload)]
+
+// a worker answers a task, with headers
+const responseWithHeaders = (response_topic, payload, headers) => [String(response_topic), "", String(payload), "", "", String(headers)]
+
+// a worker registers to a topic, and gets the direct endpoint of the clients with their tasks
+const registerDirect = (worker_topic) => ["@@REGISTER", String(worker_topic), "direct"]
+
+// a worker registers to a topic, with labels the routing rules of the topic can send tasks to, the epoch of its process and its logical name
+const registerWithOptions = (worker_topic, options) => ["@@REGISTER", String(worker_topic), String(options)]
+
+// a client asks for the responses of its next tasks to be sent by the workers straight to this endpoint
+const direct = (endpoint) => ["@@DIRECT", String(endpoint)]
+
+// a worker sent the response of a task straight to its client, the task is done
+const done = (response_topic) => ["@@DONE", String(response_topic)]
+
+// a worker can't process a task now, it is sent again after the backoff of its topic
+const retry = (response_topic, reason) => ["@@RETRY", String(response_topic), String(reason)]
+
+// asks for the brokers of the cluster, to bootstrap from any of them
+const members = () => ["@@MEMBERS"]
+
+// a client sends a DAG of tasks, a node is sent once the nodes it waits for are answered
+const workflow = (workflow_id, dag) => ["@@WORKFLOW", String(workflow_id), String(dag)]
+
+// asks for the status of a workflow
+const workflowQuery = (workflow_id) => ["@@WORKFLOW", String(workflow_id)]
+
+// asks for the response of a task answered in the last RESULTS_TTL seconds
+const result = (response_topic) => ["@@RESULT", String(response_topic)]
+
+// asks for the response of a task, answered as soon as it comes or once the timeout expires
+const wait = (response_topic, timeout) => ["@@WAIT", String(response_topic), String(timeout)]
+
+// any message of protocol 1 in an envelope, the broker then answers the peer in envelopes
+const envelope = (envelope) => ["@@ENVELOPE", String(envelope)]
+
+// broker messages, by their fixed topic: [name, free frames]
+const CONTROLS = {
+  "@@GROUP_DONE": ["group_done", ["group", "succeeded", "failed"]],
+  "@@PONG": ["pong", []],
+  "@@REGISTER": ["register_again", []],
+  "@@SHUTDOWN": ["shutdown", []],
+  "@@RESTART": ["restart", []],
+  "@@RESUBSCRIBE": ["resubscribe", []],
+  "@@NO_TOPIC": ["no_topic", ["response_topic"]],
+  "@@FORBIDDEN": ["forbidden", ["response_topic"]],
+  "@@ERROR": ["unsupported_protocol", ["detail"]],
+  "@@STOPPING": ["stopping", ["response_topic"]],
+  "@@IDENTITY_CONFLICT": ["identity_conflict", []],
+  "@@BAD_SIGNATURE": ["bad_signature", ["worker_topic"]],
+  "@@ACCEPTED": ["accepted", ["response_topic"]],
+  "@@QUEUED": ["queued", ["response_topic", "position", "eta"]],
+  "@@ENVELOPE": ["envelope_answer", ["envelope"]],
+  "@@FEATURES": ["features", ["features"]],
+  "@@QUEUE_FULL": ["queue_full", ["response_topic"]],
+  "@@DEPENDENCY_FAILED": ["dependency_failed", ["response_topic"]],
+  "@@MEMBERS": ["members_list", ["endpoints"]],
+  "@@WORKFLOW": ["workflow_status", ["workflow_id", "status"]],
+  "@@RESULT": ["result_found", ["response_topic", "payload"]],
+  "@@NO_RESULT": ["no_result", ["response_topic"]],
+}
+
+// returns the name of a message received from the broker, and its free frames
+const parse = (frames) => {
+  const [first, ...rest] = frames.slice(1).map(frame => frame.toString())
+  if (first in CONTROLS) {
+    const [name, names] = CONTROLS[first]
+    return [name, Object.fromEntries(names.map((field, i) => [field, rest[i]]))]
+  }
+  return ['delivery', { payload: first || '' }]
+}
+
+module.exports = { VERSION, CONTROLS, parse, ping, register, registerTopics, unregister, subscribeTopic, unsubscribeTopic, signedRegister, signedUnregister, subscribe, group, received, unsubscribe, task, orderedTask, dependentTask, taskWithHeaders, response, responseWithHeaders, registerDirect, registerWithOptions, direct, done, retry, members, workflow, workflowQuery, result, wait, envelope }
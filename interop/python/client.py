@@ -0,0 +1,73 @@
+#!/usr/bin/env python3
+# reference client, speaking the frame protocol through the generated module
+#   client.py worker <topic>             answers the tasks of <topic> until killed
+#   client.py request <topic> <payload>  sends a task and prints the response payload
+# payloads follow the JS client convention: JSON with the response topic in `returnsType`
+import json
+import os
+import sys
+import uuid
+
+import zmq
+
+import tiny_broke_protocol as protocol
+
+URI = os.environ.get("BROKER_URI", "tcp://localhost:3000")
+TIMEOUT = 5000
+
+
+def connect(kind):
+    socket = zmq.Context.instance().socket(zmq.DEALER)
+    socket.setsockopt(zmq.IDENTITY, f"{kind}-python-{os.getpid()}".encode())
+    socket.connect(URI)
+    return socket
+
+
+def worker(topic):
+    socket = connect("worker")
+    socket.send_multipart(protocol.register(topic))
+
+    while True:
+        if not socket.poll(1000):
+            socket.send_multipart(protocol.ping())
+            continue
+
+        name, fields = protocol.parse(socket.recv_multipart())
+        if name == "register_again":
+            socket.send_multipart(protocol.register(topic))
+        elif name == "delivery":
+            action = json.loads(fields["payload"])
+            response = {
+                "type": action["returnsType"],
+                "from": action["type"],
+                "payload": f"python:{action['payload']}",
+            }
+            socket.send_multipart(protocol.response(action["returnsType"], json.dumps(response)))
+
+
+def request(topic, payload):
+    socket = connect("client")
+    response_topic = f"{topic}>RESPONSE@@{uuid.uuid4()}"
+    action = {"type": topic, "returnsType": response_topic, "payload": payload}
+    socket.send_multipart(protocol.task(topic, response_topic, json.dumps(action)))
+
+    while socket.poll(TIMEOUT):
+        name, fields = protocol.parse(socket.recv_multipart())
+        if name == "delivery":
+            print(json.loads(fields["payload"])["payload"])
+            return 0
+        if fields.get("response_topic") == response_topic:
+            print(f"rejected: {name}", file=sys.stderr)
+            return 1
+
+    print("timeout", file=sys.stderr)
+    return 1
+
+
+if __name__ == "__main__":
+    if sys.argv[1:2] == ["worker"] and len(sys.argv) == 3:
+        worker(sys.argv[2])
+    elif sys.argv[1:2] == ["request"] and len(sys.argv) == 4:
+        sys.exit(request(sys.argv[2], sys.argv[3]))
+    else:
+        sys.exit("usage: client.py worker <topic> | request <topic> <payload>")
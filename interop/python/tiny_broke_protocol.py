@@ -0,0 +1,188 @@
+# generated by interop/generate.py from `tiny-broke protocol describe --format json`, do not edit
+
+VERSION = 1
+
+
+def _frame(value):
+    return value if isinstance(value, bytes) else str(value).encode()
+
+
+def ping():
+    """sent regularly by every peer, the broker answers with a pong"""
+    return [b'@@PING']
+
+
+def register(worker_topic):
+    """a worker registers to a topic"""
+    return [b'@@REGISTER', _frame(worker_topic)]
+
+
+def register_topics(worker_topics, options):
+    """a worker registers to all its topics at once, the registration is taken or refused as a whole"""
+    return [b'@@REGISTER', _frame(worker_topics), _frame(options)]
+
+
+def unregister():
+    """a worker leaves, its running tasks can still be answered"""
+    return [b'@@UNREGISTER']
+
+
+def subscribe_topic(worker_topics, options):
+    """a registered worker serves more topics, without registering again"""
+    return [b'@@SUBSCRIBE_TOPIC', _frame(worker_topics), _frame(options)]
+
+
+def unsubscribe_topic(worker_topics):
+    """a worker stops serving topics (and their labelled topics), its running tasks can still be answered"""
+    return [b'@@UNSUBSCRIBE_TOPIC', _frame(worker_topics)]
+
+
+def signed_register(worker_topic, options, headers):
+    """a worker registers to a topic, on a broker with CONTROL_SECRET"""
+    return [b'@@REGISTER', _frame(worker_topic), _frame(options), b'', b'', _frame(headers)]
+
+
+def signed_unregister(headers):
+    """a worker leaves, on a broker with CONTROL_SECRET"""
+    return [b'@@UNREGISTER', b'', b'', b'', b'', _frame(headers)]
+
+
+def subscribe(response_topic):
+    """a client waits for responses on a topic without sending a task"""
+    return [b'@@SUBSCRIBE', _frame(response_topic)]
+
+
+def group(group, size):
+    """a client waits for the tasks of a group to be answered or failed"""
+    return [b'@@GROUP', _frame(group), _frame(size)]
+
+
+def received(response_topic):
+    """a client confirms a response, which isn't sent again"""
+    return [b'@@RECEIVED', _frame(response_topic)]
+
+
+def unsubscribe(response_topic):
+    """a client stops waiting for responses on a topic"""
+    return [b'@@UNSUBSCRIBE', _frame(response_topic)]
+
+
+def task(worker_topic, response_topic, payload):
+    """a client asks for something"""
+    return [_frame(worker_topic), _frame(response_topic), _frame(payload)]
+
+
+def ordered_task(worker_topic, response_topic, payload, partition_key):
+    """a client asks for something, after the previous tasks of the partition on an ordered topic"""
+    return [_frame(worker_topic), _frame(response_topic), _frame(payload), _frame(partition_key)]
+
+
+def dependent_task(worker_topic, response_topic, payload, partition_key, dependencies):
+    """a client asks for something, once other tasks are answered"""
+    return [_frame(worker_topic), _frame(response_topic), _frame(payload), _frame(partition_key), _frame(dependencies)]
+
+
+def task_with_headers(worker_topic, response_topic, payload, partition_key, dependencies, headers):
+    """a client asks for something, with headers (auth tokens, trace ids, ...)"""
+    return [_frame(worker_topic), _frame(response_topic), _frame(payload), _frame(partition_key), _frame(dependencies), _frame(headers)]
+
+
+def response(response_topic, payload):
+    """a worker answers a task"""
+    return [_frame(response_topic), b'', _frame(payload)]
+
+
+def response_with_headers(response_topic, payload, headers):
+    """a worker answers a task, with headers"""
+    return [_frame(response_topic), b'', _frame(payload), b'', b'', _frame(headers)]
+
+
+def register_direct(worker_topic):
+    """a worker registers to a topic, and gets the direct endpoint of the clients with their tasks"""
+    return [b'@@REGISTER', _frame(worker_topic), b'direct']
+
+
+def register_with_options(worker_topic, options):
+    """a worker registers to a topic, with labels the routing rules of the topic can send tasks to, the epoch of its process and its logical name"""
+    return [b'@@REGISTER', _frame(worker_topic), _frame(options)]
+
+
+def direct(endpoint):
+    """a client asks for the responses of its next tasks to be sent by the workers straight to this endpoint"""
+    return [b'@@DIRECT', _frame(endpoint)]
+
+
+def done(response_topic):
+    """a worker sent the response of a task straight to its client, the task is done"""
+    return [b'@@DONE', _frame(response_topic)]
+
+
+def retry(response_topic, reason):
+    """a worker can't process a task now, it is sent again after the backoff of its topic"""
+    return [b'@@RETRY', _frame(response_topic), _frame(reason)]
+
+
+def members():
+    """asks for the brokers of the cluster, to bootstrap from any of them"""
+    return [b'@@MEMBERS']
+
+
+def workflow(workflow_id, dag):
+    """a client sends a DAG of tasks, a node is sent once the nodes it waits for are answered"""
+    return [b'@@WORKFLOW', _frame(workflow_id), _frame(dag)]
+
+
+def workflow_query(workflow_id):
+    """asks for the status of a workflow"""
+    return [b'@@WORKFLOW', _frame(workflow_id)]
+
+
+def result(response_topic):
+    """asks for the response of a task answered in the last RESULTS_TTL seconds"""
+    return [b'@@RESULT', _frame(response_topic)]
+
+
+def wait(response_topic, timeout):
+    """asks for the response of a task, answered as soon as it comes or once the timeout expires"""
+    return [b'@@WAIT', _frame(response_topic), _frame(timeout)]
+
+
+def envelope(envelope):
+    """any message of protocol 1 in an envelope, the broker then answers the peer in envelopes"""
+    return [b'@@ENVELOPE', _frame(envelope)]
+
+
+# broker messages, by their fixed topic: (name, free frames)
+CONTROLS = {
+    '@@GROUP_DONE': ('group_done', ['group', 'succeeded', 'failed']),
+    '@@PONG': ('pong', []),
+    '@@REGISTER': ('register_again', []),
+    '@@SHUTDOWN': ('shutdown', []),
+    '@@RESTART': ('restart', []),
+    '@@RESUBSCRIBE': ('resubscribe', []),
+    '@@NO_TOPIC': ('no_topic', ['response_topic']),
+    '@@FORBIDDEN': ('forbidden', ['response_topic']),
+    '@@ERROR': ('unsupported_protocol', ['detail']),
+    '@@STOPPING': ('stopping', ['response_topic']),
+    '@@IDENTITY_CONFLICT': ('identity_conflict', []),
+    '@@BAD_SIGNATURE': ('bad_signature', ['worker_topic']),
+    '@@ACCEPTED': ('accepted', ['response_topic']),
+    '@@QUEUED': ('queued', ['response_topic', 'position', 'eta']),
+    '@@ENVELOPE': ('envelope_answer', ['envelope']),
+    '@@FEATURES': ('features', ['features']),
+    '@@QUEUE_FULL': ('queue_full', ['response_topic']),
+    '@@DEPENDENCY_FAILED': ('dependency_failed', ['response_topic']),
+    '@@MEMBERS': ('members_list', ['endpoints']),
+    '@@WORKFLOW': ('workflow_status', ['workflow_id', 'status']),
+    '@@RESULT': ('result_found', ['response_topic', 'payload']),
+    '@@NO_RESULT': ('no_result', ['response_topic']),
+}
+
+
+def parse(frames):
+    """returns the name of a message received from the broker, and its free frames"""
+    frames = [frame.decode() for frame in frames[1:]]
+    if frames and frames[0] in CONTROLS:
+        name, names = CONTROLS[frames[0]]
+        return name, dict(zip(names, frames[1:]))
+    return 'delivery', {'payload': frames[0] if frames else ''}
@@ -0,0 +1,39 @@
+#!/bin/sh
+# cross-language integration tests: the generated modules match the broker spec,
+# and python/node reference clients talk to each other through a fresh broker
+# needs pyzmq, and `npm install` in interop/node
+set -e
+cd "$(dirname "$0")/.."
+
+cargo build --quiet
+BROKER=target/debug/tiny-broke
+
+# the generated modules are up to date with the spec
+OUTPUT=$(mktemp -d)
+mkdir "$OUTPUT/python" "$OUTPUT/node"
+$BROKER protocol describe --format json | python3 interop/generate.py - "$OUTPUT"
+diff "$OUTPUT/python/tiny_broke_protocol.py" interop/python/tiny_broke_protocol.py
+diff "$OUTPUT/node/tiny_broke_protocol.js" interop/node/tiny_broke_protocol.js
+rm -r "$OUTPUT"
+
+$BROKER > /dev/null &
+BROKER_PID=$!
+python3 interop/python/client.py worker 'INTEROP>PYTHON' &
+PYTHON_PID=$!
+node interop/node/client.js worker 'INTEROP>NODE' &
+NODE_PID=$!
+trap 'kill $BROKER_PID $PYTHON_PID $NODE_PID 2> /dev/null' EXIT
+sleep 1
+
+check() {
+  if [ "$2" != "$3" ]; then
+    echo "$1: expected '$3', got '$2'"
+    exit 1
+  fi
+  echo "$1: ok"
+}
+
+check "node client -> python worker" "$(node interop/node/client.js request 'INTEROP>PYTHON' hello)" "python:hello"
+check "python client -> node worker" "$(python3 interop/python/client.py request 'INTEROP>NODE' hello)" "node:hello"
+check "python client -> python worker" "$(python3 interop/python/client.py request 'INTEROP>PYTHON' hi)" "python:hi"
+check "node client -> node worker" "$(node interop/node/client.js request 'INTEROP>NODE' hi)" "node:hi"
@@ -0,0 +1,163 @@
+use crate::json::{self, Value};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// protocol 2 wraps a message in an envelope: `@@ENVELOPE` followed by a single JSON frame, with a SemVer version
+// `{"version": "2.0", "topic": ..., "response_topic": ..., "payload": ..., "partition_key": ..., "dependencies": ...,
+// "headers": {...}}` (missing fields are empty), the broker answers with `{"version": "2.0", "frames": [...]}`
+
+pub const ENVELOPE: &str = "@@ENVELOPE";
+pub const VERSION: &str = "2.0";
+
+// the frames of protocol 1, as fields
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Envelope {
+    pub topic: String,
+    pub response_topic: String,
+    pub payload: String,
+    pub partition_key: String,
+    pub dependencies: String,
+    // a JSON object, as in its frame
+    pub headers: String,
+}
+
+// `2`, `2.1` and `2.1.3` are all major version 2, the minor versions only add fields
+pub fn major(version: &str) -> Result<u64, String> {
+    version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse().ok())
+        .ok_or_else(|| format!("{} is not a version", version))
+}
+
+fn version(envelope: &Value) -> Result<u64, String> {
+    match envelope.get("version") {
+        Some(Value::String(version)) => major(version),
+        Some(Value::Number(version)) => Ok(*version as u64),
+        _ => Err("envelopes need a version".to_string()),
+    }
+}
+
+fn field(envelope: &Value, name: &str) -> Result<String, String> {
+    match envelope.get(name) {
+        None | Some(Value::Null) => Ok(String::new()),
+        Some(Value::String(value)) => Ok(value.clone()),
+        // the headers are a JSON object, as in their frame
+        Some(value @ Value::Object(_)) if name == "headers" => Ok(value.to_string()),
+        Some(_) => Err(format!("{} is expected to be a string", name)),
+    }
+}
+
+impl Envelope {
+    // the major version, to be checked by the reader, and the envelope
+    pub fn decode(envelope: &str) -> Result<(u64, Envelope), String> {
+        let envelope = json::parse(envelope)?;
+        let version = version(&envelope)?;
+        Ok((
+            version,
+            Envelope {
+                topic: field(&envelope, "topic")?,
+                response_topic: field(&envelope, "response_topic")?,
+                payload: field(&envelope, "payload")?,
+                partition_key: field(&envelope, "partition_key")?,
+                dependencies: field(&envelope, "dependencies")?,
+                headers: field(&envelope, "headers")?,
+            },
+        ))
+    }
+
+    // empty fields are left out, headers that are not a JSON object are a string
+    pub fn encode(&self) -> String {
+        let mut fields = Vec::new();
+        fields.push(format!("\"version\":{}", json::string(VERSION)));
+        for (name, value) in [
+            ("topic", &self.topic),
+            ("response_topic", &self.response_topic),
+            ("payload", &self.payload),
+            ("partition_key", &self.partition_key),
+            ("dependencies", &self.dependencies),
+        ] {
+            if !value.is_empty() {
+                fields.push(format!("{}:{}", json::string(name), json::string(value)));
+            }
+        }
+        match json::parse(&self.headers) {
+            Ok(headers @ Value::Object(_)) => fields.push(format!("\"headers\":{}", headers)),
+            _ if self.headers.is_empty() => {}
+            _ => fields.push(format!("\"headers\":{}", json::string(&self.headers))),
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+// the frames sent to a peer speaking protocol 2
+pub fn wrap(frames: &[&str]) -> String {
+    let frames: Vec<String> = frames.iter().map(|frame| json::string(frame)).collect();
+    format!(
+        "{{\"version\":{},\"frames\":[{}]}}",
+        json::string(VERSION),
+        frames.join(",")
+    )
+}
+
+// the frames the broker sent, with the major version of their envelope
+pub fn unwrap(envelope: &str) -> Result<(u64, Vec<String>), String> {
+    let envelope = json::parse(envelope)?;
+    let version = version(&envelope)?;
+    let frames = envelope
+        .get("frames")
+        .and_then(Value::as_array)
+        .and_then(|frames| {
+            frames
+                .iter()
+                .map(|frame| frame.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+        })
+        .ok_or_else(|| "frames are expected to be strings".to_string())?;
+    Ok((version, frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{major, unwrap, wrap, Envelope};
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn versions_are_semver() {
+        assert_eq!(major("2"), Ok(2));
+        assert_eq!(major("2.1.3"), Ok(2));
+        assert!(major("v2").is_err());
+    }
+
+    #[test]
+    fn envelopes_are_encoded_and_decoded() {
+        let envelope = Envelope {
+            topic: "ADD".to_string(),
+            response_topic: "ADD>1".to_string(),
+            payload: "1+1".to_string(),
+            headers: r#"{"a":"b"}"#.to_string(),
+            ..Envelope::default()
+        };
+        let encoded = envelope.encode();
+        assert_eq!(
+            encoded,
+            r#"{"version":"2.0","topic":"ADD","response_topic":"ADD>1","payload":"1+1","headers":{"a":"b"}}"#
+        );
+        assert_eq!(Envelope::decode(&encoded), Ok((2, envelope)));
+        assert!(Envelope::decode(r#"{"topic":"ADD"}"#).is_err());
+        assert!(Envelope::decode(r#"{"version":"2.0","topic":1}"#).is_err());
+    }
+
+    #[test]
+    fn frames_are_wrapped_and_unwrapped() {
+        let wrapped = wrap(&["", "a\"b"]);
+        assert_eq!(wrapped, r#"{"version":"2.0","frames":["","a\"b"]}"#);
+        assert_eq!(
+            unwrap(&wrapped),
+            Ok((2, vec!["".to_string(), "a\"b".to_string()]))
+        );
+        assert!(unwrap(r#"{"version":"2.0","frames":[1]}"#).is_err());
+    }
+}
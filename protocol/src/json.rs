@@ -0,0 +1,419 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
+
+// JSON string literal, with its quotes
+pub fn string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+// just enough JSON to read back what the broker writes (envelopes, recordings, ...)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+// compact JSON, to write back what was read
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", string(value)),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", string(name), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// arrays and objects nested deeper are refused: the parser recurses, and takes untrusted content (headers, envelopes)
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    // arrays and objects being parsed
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespaces(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespaces();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}', found '{}'", expected, c)),
+            None => Err(format!("expected '{}', found the end", expected)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, String> {
+        for expected in keyword.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("expected {}", keyword));
+            }
+        }
+        Ok(value)
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err("too deeply nested".to_string()),
+            false => Ok(()),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespaces();
+        match self.chars.peek() {
+            Some('n') => self.keyword("null", Value::Null),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => {
+                self.nest()?;
+                let array = self.array();
+                self.depth -= 1;
+                array
+            }
+            Some('{') => {
+                self.nest()?;
+                let object = self.object();
+                self.depth -= 1;
+                object
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let mut number = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+        {
+            number.push(c);
+        }
+        number
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| format!("bad number: {}", number))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut value = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => value.push('\u{8}'),
+                    Some('f') => value.push('\u{c}'),
+                    Some('u') => value.push(self.unicode_escape()?),
+                    Some(c) => value.push(c),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some(c) => value.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    // the 4 hexadecimal digits of `\u`
+    fn code_unit(&mut self) -> Result<u32, String> {
+        let code: String = self.chars.by_ref().take(4).collect();
+        // `from_str_radix` takes a sign
+        match code.len() == 4 && code.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Ok(u32::from_str_radix(&code, 16).unwrap_or_default()),
+            false => Err(format!("bad unicode escape: {}", code)),
+        }
+    }
+
+    // characters out of the BMP are escaped as a surrogate pair (`\ud83d\ude00`), as JSON.stringify and json.dumps do
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let code = match self.code_unit()? {
+            high @ 0xd800..=0xdbff => {
+                if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                    return Err("lone surrogate in unicode escape".to_string());
+                }
+                match self.code_unit()? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => return Err("lone surrogate in unicode escape".to_string()),
+                }
+            }
+            0xdc00..=0xdfff => return Err("lone surrogate in unicode escape".to_string()),
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| format!("bad unicode escape: {:x}", code))
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = vec![];
+
+        self.skip_whitespaces();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespaces();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err("expected ',' or ']'".to_string()),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut fields = vec![];
+
+        self.skip_whitespaces();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespaces();
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+            self.skip_whitespaces();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => return Err("expected ',' or '}'".to_string()),
+            }
+        }
+    }
+}
+
+pub fn parse(content: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: content.chars().peekable(),
+        depth: 0,
+    };
+    let value = parser.value()?;
+
+    parser.skip_whitespaces();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected '{}' after the value", c)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, string, Value, MAX_DEPTH};
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    #[test]
+    fn nesting_is_bounded() {
+        let nested = |depth: usize| {
+            let mut content = String::new();
+            (0..depth).for_each(|_| content.push_str("[{\"a\":"));
+            content.push('1');
+            (0..depth).for_each(|_| content.push_str("}]"));
+            content
+        };
+        // an array and an object per level
+        assert!(parse(&nested(MAX_DEPTH / 2)).is_ok());
+        assert_eq!(
+            parse(&nested(MAX_DEPTH / 2 + 1)),
+            Err("too deeply nested".into())
+        );
+        assert_eq!(parse(&"[".repeat(100_000)), Err("too deeply nested".into()));
+        assert_eq!(
+            parse("[[1],[[2]]]"),
+            Ok(Value::Array(vec![
+                Value::Array(vec![Value::Number(1.0)]),
+                Value::Array(vec![Value::Array(vec![Value::Number(2.0)])]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn strings_are_unescaped() {
+        let parsed = |content: &str| parse(content).map(|value| value.as_str().map(str::to_string));
+        assert_eq!(
+            parsed(r#""a\n\r\t\b\f\"\\\/\u00e9""#),
+            Ok(Some("a\n\r\t\u{8}\u{c}\"\\/\u{e9}".to_string()))
+        );
+        // surrogate pairs, from JSON.stringify or json.dumps
+        assert_eq!(
+            parsed(r#""\ud83d\ude00!""#),
+            Ok(Some("\u{1f600}!".to_string()))
+        );
+        assert_eq!(
+            parsed(r#""\uD83D\uDE00""#),
+            Ok(Some("\u{1f600}".to_string()))
+        );
+        assert!(parsed(r#""\ud83d""#).is_err());
+        assert!(parsed(r#""\ud83dx""#).is_err());
+        assert!(parsed(r#""\ud83d\u0041""#).is_err());
+        assert!(parsed(r#""\ude00""#).is_err());
+        assert!(parsed(r#""\u12""#).is_err());
+        assert!(parsed(r#""\u+123""#).is_err());
+
+        // what the broker writes is read back
+        let written = "\u{1f600} \"quoted\" \\ \n\u{1}";
+        assert_eq!(parsed(&string(written)), Ok(Some(written.to_string())));
+    }
+
+    #[test]
+    fn numbers_are_parsed() {
+        let number = |content: &str| parse(content).map(|value| value.as_f64());
+        assert_eq!(number("0"), Ok(Some(0.0)));
+        assert_eq!(number("-12"), Ok(Some(-12.0)));
+        assert_eq!(number("1.5e3"), Ok(Some(1500.0)));
+        assert_eq!(number("2E-2"), Ok(Some(0.02)));
+        assert!(number("+1").is_err());
+        assert!(number(".5").is_err());
+        assert!(number("1e").is_err());
+        assert!(number("--1").is_err());
+        assert!(number("1-2").is_err());
+        assert!(number("NaN").is_err());
+        assert!(number("Infinity").is_err());
+    }
+
+    #[test]
+    fn values_are_read_back() {
+        let content = r#" {"a": [1, true, null, "x"], "b": {"c": false}, "d": [], "e": {}} "#;
+        let value = parse(content).unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&Value::Array(vec![
+                Value::Number(1.0),
+                Value::Bool(true),
+                Value::Null,
+                Value::String("x".to_string()),
+            ]))
+        );
+        assert_eq!(
+            value.get("b").and_then(|b| b.get("c")),
+            Some(&Value::Bool(false))
+        );
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(
+            value.to_string(),
+            r#"{"a":[1,true,null,"x"],"b":{"c":false},"d":[],"e":{}}"#
+        );
+        assert_eq!(parse(&value.to_string()), Ok(value));
+    }
+
+    #[test]
+    fn malformed_content_is_refused() {
+        [
+            "",
+            " ",
+            "nul",
+            "tru",
+            "True",
+            "[1,]",
+            "[1 2]",
+            "[1",
+            "{\"a\" 1}",
+            "{\"a\":}",
+            "{a: 1}",
+            "{,}",
+            "{\"a\": 1,}",
+            "\"unterminated",
+            "\"escape\\",
+            "true false",
+            "{} x",
+            "'single'",
+        ]
+        .iter()
+        .for_each(|content| assert!(parse(content).is_err(), "{} is parsed", content));
+    }
+}
@@ -0,0 +1,12 @@
+// the wire format of tiny-broke that doesn't need the standard library, only an allocator, so workers on
+// microcontrollers (behind a TCP shim speaking ZeroMQ for them) can read and write the messages of the broker:
+// - `envelope`: the envelopes of protocol 2, and the frames the broker answers with
+// - `json`: the JSON they are written in
+// the broker uses it for its own envelopes, see `src/envelope.rs` of tiny-broke
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod envelope;
+pub mod json;
@@ -0,0 +1,29 @@
+use crate::transport::Transport;
+use crate::Broker;
+use std::env;
+
+// with `ACCEPTED_ACKS`, a client gets `@@ACCEPTED <response topic>` once its task is validated and queued, before a
+// worker gets it: a client without it can tell the broker never got the task, instead of waiting for its response
+// refused tasks get their rejection instead, and tasks merged with a copy already sent are accepted too
+
+pub fn accepted_acks() -> bool {
+    env::var("ACCEPTED_ACKS").is_ok_and(|v| v == "true")
+}
+
+impl Broker {
+    pub fn acknowledge(&self, transport: &dyn Transport, identity: &str, response_topic: &str) {
+        // the task may have been dropped when it was submitted (a full queue)
+        if !self.accepted_acks
+            || !self
+                .registry
+                .clients_of(response_topic)
+                .iter()
+                .any(|client| client == identity)
+        {
+            return;
+        }
+        transport
+            .send(identity, &["", "@@ACCEPTED", response_topic])
+            .ok();
+    }
+}
@@ -0,0 +1,406 @@
+use crate::flags;
+use crate::graph;
+use crate::jsonrpc;
+use crate::log;
+use crate::roles;
+use crate::topics::TopicSettings;
+use crate::transport::Transport;
+use crate::Broker;
+
+// admin requests are plain text: the command name followed by its arguments, separated by spaces
+// the response is a single frame starting with `OK` or `ERROR`
+// with `CONTROL_SECRET`, requests come with their timestamp and signature (see signature.rs)
+// with `ADMIN_USERS`, requests start with the token of a user whose role allows the command (see roles.rs)
+// requests are written to the audit log without their token, with the identity of the admin client (see audit.rs)
+// requests starting with `{` are JSON-RPC (see jsonrpc.rs)
+pub fn handle(
+    broker: &mut Broker,
+    transport: &dyn Transport,
+    identity: &str,
+    request: &str,
+    signature: Option<&str>,
+) -> String {
+    match jsonrpc::is_request(request) {
+        true => jsonrpc::handle(broker, transport, identity, request, signature),
+        false => handle_text(broker, transport, identity, request, signature).1,
+    }
+}
+
+// answers the next request of the admin socket, its frames are the request and, with `CONTROL_SECRET`, the signature
+// every frame is read, so the REP socket can answer, and requests with another count of frames are answered `ERROR`
+// errors are logged: an admin client must not stop the broker
+pub fn serve(broker: &mut Broker, transport: &dyn Transport, socket: &zmq::Socket) {
+    let response = match receive(socket) {
+        Ok((identity, frames)) => match frames.as_slice() {
+            [request] => handle(broker, transport, &identity, request, None),
+            [request, signature] => handle(
+                broker,
+                transport,
+                &identity,
+                request,
+                Some(signature.as_str()),
+            ),
+            _ => format!(
+                "ERROR an admin request has 1 or 2 frames, not {}",
+                frames.len()
+            ),
+        },
+        Err(error) => {
+            log::warn(&format!("Can't receive an admin request: {}", error));
+            return;
+        }
+    };
+    if let Err(error) = socket.send(&response, 0) {
+        log::warn(&format!("Can't answer an admin request: {}", error));
+    }
+}
+
+// the address of the admin client, and the frames of its request
+fn receive(socket: &zmq::Socket) -> Result<(String, Vec<String>), zmq::Error> {
+    let mut message = zmq::Message::new();
+    socket.recv(&mut message, 0)?;
+    let identity = message.gets("Peer-Address").unwrap_or("").to_owned();
+    let mut frames = vec![message.as_str().unwrap_or("").to_owned()];
+    if message.get_more() {
+        frames.extend(
+            socket
+                .recv_multipart(0)?
+                .into_iter()
+                .map(|frame| String::from_utf8(frame).unwrap_or_default()),
+        );
+    }
+    Ok((identity, frames))
+}
+
+// whether the request is signed and allowed to its user, and the response
+pub fn handle_text(
+    broker: &mut Broker,
+    transport: &dyn Transport,
+    identity: &str,
+    request: &str,
+    signature: Option<&str>,
+) -> (bool, String) {
+    let authorized = broker
+        .signed_request(request, signature)
+        .and_then(|_| broker.authorized_request(request));
+    let (authorized, user, request, response) = match authorized {
+        Ok((_, request)) if request.split_whitespace().next() == Some("AUDIT") => {
+            return (true, broker.audit.query(request.split_whitespace().skip(1)));
+        }
+        Ok((user, request)) => (true, user, request, run(broker, transport, request)),
+        Err(error) => (
+            false,
+            None,
+            roles::without_token(request),
+            format!("ERROR {}", error),
+        ),
+    };
+
+    let identity = match user {
+        Some(user) => format!("{}@{}", user, identity),
+        None => identity.to_string(),
+    };
+    broker.audit(&identity, request, &response);
+    (authorized, response)
+}
+
+fn run(broker: &mut Broker, transport: &dyn Transport, request: &str) -> String {
+    let mut args = request.split_whitespace();
+    let command = args.next().unwrap_or("");
+    if let Err(error) = flags::command_available(command) {
+        return format!("ERROR {}", error);
+    }
+
+    match (command, args.next()) {
+        #[cfg(feature = "persistence")]
+        ("EXPORT", Some(name)) => match broker
+            .state_path(name)
+            .and_then(|path| broker.export_state(path).map_err(|error| error.to_string()))
+        {
+            Ok(count) => format!("OK {} tasks exported to {}", count, name),
+            Err(error) => format!("ERROR can't export to {}: {}", name, error),
+        },
+        #[cfg(feature = "persistence")]
+        ("IMPORT", Some(name)) => match broker.state_path(name).and_then(|path| {
+            broker
+                .import_state(transport, path)
+                .map_err(|error| error.to_string())
+        }) {
+            Ok(count) => format!("OK {} tasks imported from {}", count, name),
+            Err(error) => format!("ERROR can't import from {}: {}", name, error),
+        },
+        ("EXPORT", None) | ("IMPORT", None) => format!("ERROR usage: {} <file>", command),
+        ("PEEK", Some(topic)) => peek(broker, topic, args.next()),
+        ("DRAIN", Some(topic)) => {
+            let count = broker.drain(transport, topic);
+            format!("OK {} tasks drained from {}", count, topic)
+        }
+        ("PAUSE", Some(topic)) => match broker.pause_topic(topic) {
+            Ok(()) => format!("OK {} paused", topic),
+            Err(error) => format!("ERROR {}", error),
+        },
+        ("RESUME", Some(topic)) => match broker.resume_topic(transport, topic) {
+            Ok(()) => format!("OK {} resumed", topic),
+            Err(error) => format!("ERROR {}", error),
+        },
+        ("PEEK", None) | ("DRAIN", None) | ("PAUSE", None) | ("RESUME", None) => {
+            format!("ERROR usage: {} <topic>", command)
+        }
+        ("REPLAY", topic) => {
+            let count = broker.replay_dead_letters(transport, topic);
+            format!("OK {} dead tasks replayed", count)
+        }
+        ("DRAIN_WORKER", Some(worker)) => {
+            match broker.drain_worker(transport, &broker.identity_of(worker)) {
+                Ok(count) => format!("OK draining {}, {} tasks in flight", worker, count),
+                Err(error) => format!("ERROR {}", error),
+            }
+        }
+        ("DRAIN_WORKER", None) => "ERROR usage: DRAIN_WORKER <worker>".to_string(),
+        ("SHUTDOWN", Some(target)) | ("RESTART", Some(target)) => {
+            let control = format!("@@{}", command);
+            match broker.control_workers(transport, target, &control) {
+                Ok(workers) => format!("OK {} sent to {} workers", control, workers.len()),
+                Err(error) => format!("ERROR {}", error),
+            }
+        }
+        ("SHUTDOWN", None) | ("RESTART", None) => {
+            format!("ERROR usage: {} <worker|all>", command)
+        }
+        ("CREATE_TOPIC", Some(topic)) => match TopicSettings::parse(args).and_then(|settings| {
+            broker.allows_scripts(settings.split.as_ref(), &settings.merge)?;
+            Ok(settings)
+        }) {
+            Ok(settings) => {
+                broker.declared_topics.insert(topic.to_string(), settings);
+                format!("OK topic {} declared", topic)
+            }
+            Err(error) => format!("ERROR {}", error),
+        },
+        ("CREATE_TOPIC", None) => concat!(
+            "ERROR usage: CREATE_TOPIC <topic> [ttl=<seconds>] [max_queue=<count>]",
+            " [max_in_flight=<count>] [weight=<count>] [acl=<uid>,...] [delivery=<mode>]",
+            " [ordered=<true|false>] [headers=<name>,...] [split=<splitter>] [merge=<merger>]"
+        )
+        .to_string(),
+        ("WORKER_WEIGHT", Some(worker)) => {
+            match broker.set_worker_weight(worker, args.next().unwrap_or("")) {
+                Ok(weight) => format!("OK worker {} weighs {}", worker, weight),
+                Err(error) => format!("ERROR {}", error),
+            }
+        }
+        ("TOPIC_WEIGHT", Some(topic)) => {
+            match broker.set_topic_weight(topic, args.next().unwrap_or("")) {
+                Ok(weight) => format!("OK topic {} weighs {}", topic, weight),
+                Err(error) => format!("ERROR {}", error),
+            }
+        }
+        ("WORKER_WEIGHT", None) => "ERROR usage: WORKER_WEIGHT <worker> <weight>".to_string(),
+        ("TOPIC_WEIGHT", None) => "ERROR usage: TOPIC_WEIGHT <topic> <weight>".to_string(),
+        ("PEER", Some(identity)) => match broker.peer(identity) {
+            Ok(line) => format!("OK {}", line),
+            Err(error) => format!("ERROR {}", error),
+        },
+        ("PEER", None) => "ERROR usage: PEER <identity>".to_string(),
+        ("DLQ", topic) => dead_letters(broker, topic),
+        ("WORKERS", None) => workers(broker),
+        ("TOPICS", None) => topics(broker),
+        ("GRAPH", format) => match graph::Format::parse(format.unwrap_or("dot")) {
+            Ok(format) => format!("OK {}", broker.graph_lines(format)),
+            Err(error) => format!("ERROR {}", error),
+        },
+        #[cfg(feature = "persistence")]
+        ("RECOVERY", None) => match broker.recovery_lines() {
+            Ok(lines) => format!("OK {}", lines),
+            Err(error) => format!("ERROR {}", error),
+        },
+        ("SLOW_WORKERS", None) => slow_workers(broker),
+        ("STATS", None) => format!("OK {}", broker.debug_line()),
+        ("ENDPOINTS", None) => format!("OK {}", broker.endpoints_line()),
+        ("UNDELIVERED", None) => format!("OK {}", broker.undelivered_line()),
+        ("HISTORY_DEPTH", Some(topic)) => {
+            match broker.history_depth(topic, args.next().unwrap_or("1h")) {
+                Ok(lines) => format!("OK {} snapshots\n{}", lines.len(), lines.join("\n")),
+                Err(error) => format!("ERROR {}", error),
+            }
+        }
+        ("HISTORY_DEPTH", None) => "ERROR usage: HISTORY_DEPTH <topic> [window]".to_string(),
+        ("DEBUG", Some(output)) => match broker.debug.set(std::iter::once(output).chain(args)) {
+            Ok(()) => format!(
+                "OK debug output {}, every {}ms",
+                broker.debug.output.name(),
+                broker.debug.interval.as_millis()
+            ),
+            Err(error) => format!("ERROR {}", error),
+        },
+        ("DEBUG", None) => {
+            "ERROR usage: DEBUG <off|stdout|events> [interval=<milliseconds>]".to_string()
+        }
+        _ => format!("ERROR unknown command: {}", command),
+    }
+}
+
+// one line per worker: name (the logical one if given), processed tasks, failures, average processing time
+fn workers(broker: &Broker) -> String {
+    let mut lines: Vec<String> = broker
+        .registry
+        .clients
+        .values()
+        .filter(|client| client.is_worker)
+        .map(|worker| {
+            let name = broker.logical_name(&worker.name);
+            let stats = broker.worker_stats.get(name).cloned().unwrap_or_default();
+            let weight = match broker.worker_weight(&worker.name) {
+                1 => String::new(),
+                weight => format!(" weight={}", weight),
+            };
+            format!(
+                "{} processed={} failures={} average={}us{}",
+                name,
+                stats.processed,
+                stats.failures,
+                stats.average_as_micros(),
+                weight
+            )
+        })
+        .collect();
+    lines.sort();
+    lines.dedup();
+
+    format!("OK {} workers\n{}", lines.len(), lines.join("\n"))
+        .trim_end()
+        .to_string()
+}
+
+// one line per topic served by workers or with waiting tasks: name, waiting tasks, tasks in flight, workers, processed
+// tasks, and whether it is paused
+fn topics(broker: &Broker) -> String {
+    let lines: Vec<String> = broker
+        .topic_snapshots()
+        .into_iter()
+        .map(|(topic, snapshot)| {
+            format!(
+                "{} depth={} in_flight={} workers={} processed={}{}",
+                topic,
+                snapshot.depth,
+                snapshot.in_flight,
+                snapshot.workers,
+                snapshot.processed,
+                match broker.is_paused(&topic) {
+                    true => " paused",
+                    false => "",
+                }
+            )
+        })
+        .collect();
+
+    format!("OK {} topics\n{}", lines.len(), lines.join("\n"))
+        .trim_end()
+        .to_string()
+}
+
+// one line per slow worker: topic, name, average processing time and the median of its topic
+fn slow_workers(broker: &Broker) -> String {
+    let mut lines: Vec<String> = broker
+        .registry
+        .topics
+        .keys()
+        .flat_map(|topic_name| {
+            broker
+                .slow_workers(topic_name)
+                .into_iter()
+                .map(move |slow| {
+                    format!(
+                        "{} {} average={}us median={}us",
+                        topic_name, slow.name, slow.average_as_micros, slow.median_as_micros
+                    )
+                })
+        })
+        .collect();
+    lines.sort();
+
+    format!("OK {} slow workers\n{}", lines.len(), lines.join("\n"))
+        .trim_end()
+        .to_string()
+}
+
+// one line per waiting task payload (redacted), in the order they will be sent
+fn peek(broker: &Broker, topic: &str, count: Option<&str>) -> String {
+    let count = match count.map(|count| count.parse::<usize>()) {
+        None => 10,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => return "ERROR usage: PEEK <topic> [count]".to_string(),
+    };
+
+    let payloads: Vec<String> = broker
+        .dispatcher
+        .tasks_to_retry
+        .iter()
+        .filter(|task| &*task.worker_topic == topic)
+        .take(count)
+        .map(|task| broker.redaction.redact(&task.payload).replace('\n', "\\n"))
+        .collect();
+
+    format!("OK {} tasks\n{}", payloads.len(), payloads.join("\n"))
+        .trim_end()
+        .to_string()
+}
+
+// one line per dead task (optionally filtered by topic): topic, response topic, failures and payload (redacted)
+fn dead_letters(broker: &Broker, topic: Option<&str>) -> String {
+    let lines: Vec<String> = broker
+        .dispatcher
+        .dead_letters
+        .iter()
+        .filter(|task| topic.is_none_or(|topic| &*task.worker_topic == topic))
+        .map(|task| {
+            let failures: Vec<String> = task.failures.iter().map(|f| f.to_string()).collect();
+            let history = match task.history.is_empty() {
+                true => String::new(),
+                false => format!(" history={}", task.history.join("; ")),
+            };
+            format!(
+                "{} {} failures={}{} payload={}",
+                task.worker_topic,
+                task.response_topic,
+                failures.join(", "),
+                history,
+                broker.redaction.redact(&task.payload).replace('\n', "\\n")
+            )
+        })
+        .collect();
+
+    format!("OK {} dead tasks\n{}", lines.len(), lines.join("\n"))
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::embedded::BrokerHandle;
+
+    #[test]
+    fn admin_requests_with_too_many_frames_are_answered() {
+        let mut handle = BrokerHandle::new();
+        let context = zmq::Context::new();
+        let admin_socket = context.socket(zmq::REP).unwrap();
+        admin_socket.bind("inproc://admin-frames").unwrap();
+        let client = context.socket(zmq::REQ).unwrap();
+        client.connect("inproc://admin-frames").unwrap();
+
+        client
+            .send_multipart(["STATS", "1 ab", "extra"], 0)
+            .unwrap();
+        serve(&mut handle.broker, &handle.transport, &admin_socket);
+        assert_eq!(
+            client.recv_string(0).unwrap().unwrap(),
+            "ERROR an admin request has 1 or 2 frames, not 3"
+        );
+
+        // the socket still answers the next request
+        client.send("STATS", 0).unwrap();
+        serve(&mut handle.broker, &handle.transport, &admin_socket);
+        assert!(client.recv_string(0).unwrap().unwrap().starts_with("OK"));
+    }
+}
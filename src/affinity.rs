@@ -0,0 +1,78 @@
+use crate::intern::intern;
+use crate::log;
+use crate::{Broker, Task};
+use std::rc::Rc;
+
+// clients hint where their task should go with headers, for data locality or after a partial failure:
+// - `prefer_worker`: the worker (identity or name) gets the task when it is registered to its topic
+// - `avoid_worker`: the workers (comma separated) only get the task when there is no other worker
+// the hints are best effort, the task goes to the next worker otherwise, and the fallback is written to its history:
+// the `x-broker-history` response header, the dead letter queue, and the `task.affinity_fallback` event
+
+fn hint<'a>(task: &'a Task, name: &str) -> Option<&'a str> {
+    task.headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+impl Broker {
+    fn is_worker_of(&self, worker_topic: &str, identity: &str) -> bool {
+        self.registry
+            .topics
+            .get(worker_topic)
+            .is_some_and(|topic| topic.workers.iter().any(|name| name == identity))
+    }
+
+    // the worker for the task, following its hints when it can
+    pub fn select_worker(&mut self, task: &mut Task, avoided: &[String]) -> Option<Rc<str>> {
+        let avoid_hint: Vec<String> = hint(task, "avoid_worker")
+            .map(|names| {
+                names
+                    .split(',')
+                    .map(|name| self.identity_of(name.trim()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut avoided = avoided.to_vec();
+        avoided.extend(avoid_hint.iter().cloned());
+
+        let preferred = hint(task, "prefer_worker").map(str::to_string);
+        if let Some(preferred) = &preferred {
+            let identity = self.identity_of(preferred);
+            if self.is_worker_of(&task.worker_topic, &identity) && !avoided.contains(&identity) {
+                return Some(intern(&identity));
+            }
+        }
+
+        let worker_name = self.get_next_worker_name(&task.worker_topic, &avoided)?;
+        if let Some(preferred) = preferred {
+            self.record_fallback(task, &format!("prefer_worker={}", preferred), &worker_name);
+        }
+        if avoid_hint.contains(&worker_name) {
+            let name = self.logical_name(&worker_name).to_string();
+            self.record_fallback(task, &format!("avoid_worker={}", name), &worker_name);
+        }
+        Some(intern(&worker_name))
+    }
+
+    fn record_fallback(&self, task: &mut Task, hint: &str, worker_name: &str) {
+        let worker_name = self.logical_name(worker_name);
+        log::info(&format!(
+            "Task {} can't follow {}, sending it to {}",
+            task.response_topic, hint, worker_name
+        ));
+        self.emit(
+            "task.affinity_fallback",
+            &[
+                ("topic", &task.worker_topic),
+                ("responseTopic", &task.response_topic),
+                ("hint", hint),
+                ("worker", worker_name),
+            ],
+        );
+        task.history
+            .push(format!("{} fell back to {}", hint, worker_name));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::log;
+use crate::{webhook, Broker};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::time::{Duration, SystemTime};
+
+pub struct Alerts {
+    queue_depth: Option<usize>,
+    queue_depth_as_secs: u64,
+    no_workers: bool,
+    dlq_growth: Option<usize>,
+    dlq_growth_window_as_secs: u64,
+    webhook: Option<String>,
+    deep_queues_since: HashMap<String, SystemTime>,
+    // dead tasks by topic, at each evaluation of the window, and the last one before it
+    dead_letters: VecDeque<(SystemTime, HashMap<String, usize>)>,
+    firing: HashSet<(&'static str, String)>,
+}
+
+impl Alerts {
+    pub fn new() -> Alerts {
+        Alerts {
+            queue_depth: env::var("ALERT_QUEUE_DEPTH")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok()),
+            queue_depth_as_secs: env::var("ALERT_QUEUE_DEPTH_DURATION")
+                .map(|v| v.parse::<u64>().unwrap_or(0))
+                .unwrap_or(0),
+            no_workers: env::var("ALERT_NO_WORKERS").is_ok_and(|v| v == "true"),
+            dlq_growth: env::var("ALERT_DLQ_GROWTH")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok()),
+            dlq_growth_window_as_secs: env::var("ALERT_DLQ_GROWTH_WINDOW")
+                .map(|v| v.parse::<u64>().unwrap_or(60))
+                .unwrap_or(60),
+            webhook: env::var("ALERT_WEBHOOK").ok(),
+            deep_queues_since: HashMap::new(),
+            dead_letters: VecDeque::new(),
+            firing: HashSet::new(),
+        }
+    }
+}
+
+impl Broker {
+    // rules are evaluated on waiting tasks, and on the dead letter queue, per topic
+    // an alert fires once when its rule starts to match, and is resolved once it stops matching
+    pub fn check_alerts(&mut self) {
+        let mut depths: HashMap<&str, usize> = HashMap::new();
+        self.dispatcher.tasks_to_retry.iter().for_each(|task| {
+            *depths.entry(&task.worker_topic).or_insert(0) += 1;
+        });
+
+        let mut matching: HashSet<(&'static str, String)> = HashSet::new();
+
+        if let Some(max_depth) = self.alerts.queue_depth {
+            let queue_depth_as_secs = self.alerts.queue_depth_as_secs;
+            let now = self.now();
+            let deep_queues_since = &mut self.alerts.deep_queues_since;
+            deep_queues_since.retain(|topic, _| depths.get(topic.as_str()) > Some(&max_depth));
+
+            depths
+                .iter()
+                .filter(|(_, &depth)| depth > max_depth)
+                .for_each(|(&topic, _)| {
+                    let since = deep_queues_since.entry(topic.to_string()).or_insert(now);
+                    let elapsed = now.duration_since(*since).map_or(0, |e| e.as_secs());
+                    if elapsed >= queue_depth_as_secs {
+                        matching.insert(("queue_depth", topic.to_string()));
+                    }
+                });
+        }
+
+        if self.alerts.no_workers {
+            depths
+                .keys()
+                .filter(|&&topic| {
+                    self.registry
+                        .topics
+                        .get(topic)
+                        .is_none_or(|topic| topic.workers.is_empty())
+                })
+                .for_each(|&topic| {
+                    matching.insert(("no_workers", topic.to_string()));
+                });
+        }
+
+        if let Some(max_growth) = self.alerts.dlq_growth {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            self.dispatcher.dead_letters.iter().for_each(|task| {
+                *counts.entry(task.worker_topic.to_string()).or_insert(0) += 1;
+            });
+
+            let now = self.now();
+            let window = Duration::from_secs(self.alerts.dlq_growth_window_as_secs);
+            let samples = &mut self.alerts.dead_letters;
+            samples.push_back((now, counts));
+            while samples.len() > 1 && samples[1].0 + window <= now {
+                samples.pop_front();
+            }
+
+            // replayed tasks leave the queue, it only grows by the tasks quarantined in the window
+            let before = &samples[0].1;
+            samples[samples.len() - 1]
+                .1
+                .iter()
+                .filter(|(topic, &count)| {
+                    count.saturating_sub(before.get(*topic).cloned().unwrap_or(0)) > max_growth
+                })
+                .for_each(|(topic, _)| {
+                    matching.insert(("dlq_growth", topic.to_string()));
+                });
+        }
+
+        let fired: Vec<_> = matching.difference(&self.alerts.firing).cloned().collect();
+        let resolved: Vec<_> = self.alerts.firing.difference(&matching).cloned().collect();
+
+        fired.iter().for_each(|(rule, topic)| {
+            let depth = depths.get(topic.as_str()).cloned().unwrap_or(0);
+            self.notify("alert.fired", rule, topic, depth);
+        });
+        resolved.iter().for_each(|(rule, topic)| {
+            let depth = depths.get(topic.as_str()).cloned().unwrap_or(0);
+            self.notify("alert.resolved", rule, topic, depth);
+        });
+
+        self.alerts.firing = matching;
+    }
+
+    fn notify(&self, event: &'static str, rule: &str, topic: &str, depth: usize) {
+        log::warn(&format!(
+            "[{}] {} on {} ({} waiting tasks)",
+            event, rule, topic, depth
+        ));
+
+        let fields = [
+            ("rule", rule),
+            ("topic", topic),
+            ("depth", &depth.to_string()),
+        ];
+        self.emit(event, &fields);
+
+        if let Some(url) = &self.alerts.webhook {
+            webhook::post_in_background(url, Broker::event_content(event, &fields));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedded::BrokerHandle;
+    use crate::Task;
+    use std::time::Duration;
+
+    fn quarantine(broker: &mut BrokerHandle, topic: &str, count: usize) {
+        for index in 0..count {
+            let response_topic = format!("{}>{}", topic, index);
+            broker
+                .broker
+                .quarantine(Task::new(topic, &response_topic, "1+1"));
+        }
+    }
+
+    #[test]
+    fn dead_letter_queue_growth_alerts() {
+        let mut broker = BrokerHandle::new();
+        broker.broker.alerts.dlq_growth = Some(2);
+        broker.broker.alerts.dlq_growth_window_as_secs = 60;
+        let firing = |broker: &BrokerHandle| {
+            broker
+                .broker
+                .alerts
+                .firing
+                .contains(&("dlq_growth", "ADD".to_string()))
+        };
+
+        broker.advance(Duration::from_secs(1));
+        quarantine(&mut broker, "ADD", 2);
+        broker.advance(Duration::from_secs(1));
+        assert!(!firing(&broker));
+
+        quarantine(&mut broker, "ADD", 1);
+        broker.advance(Duration::from_secs(1));
+        assert!(firing(&broker));
+
+        // the tasks quarantined before the window are not counted
+        broker.advance(Duration::from_secs(60));
+        assert!(!firing(&broker));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::json::{self, Value};
+use crate::log;
+use crate::signature::{hex, sha256};
+use crate::Broker;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+// with `AUDIT_LOG`, admin requests are appended to this file, one JSON line each:
+// `{"date": ..., "identity": ..., "request": ..., "response": ..., "previous": ..., "hash": ...}`
+// the date is in milliseconds since the epoch, the identity is the address of the admin client, `<user>@<address>`
+// with `ADMIN_USERS`
+// the hash is the SHA-256 of the line without it, and `previous` the hash of the line before: changing or removing
+// a line breaks the chain, `AUDIT VERIFY` tells where
+// `AUDIT [count]` gives the last lines, these requests are not logged
+// requests and responses are redacted (see redaction.rs)
+
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// `,"hash":"<64 hexadecimal digits>"}`
+const HASH_SUFFIX_LEN: usize = 75;
+
+pub struct Audit {
+    path: Option<PathBuf>,
+    last_hash: String,
+}
+
+// the line without its hash, and its hash
+fn split_hash(line: &str) -> Option<(String, &str)> {
+    let start = line.len().checked_sub(HASH_SUFFIX_LEN)?;
+    let suffix = line.get(start..)?;
+    let hash = suffix.strip_prefix(",\"hash\":\"")?.strip_suffix("\"}")?;
+    Some((format!("{}}}", &line[..start]), hash))
+}
+
+// the number of lines, or the first one breaking the chain
+fn verify(content: &str) -> Result<usize, String> {
+    let mut previous = GENESIS.to_string();
+    for (index, line) in content.lines().enumerate() {
+        let tampered = || format!("line {} is tampered", index + 1);
+        let (without_hash, hash) = split_hash(line).ok_or_else(tampered)?;
+        let linked = json::parse(line)
+            .ok()
+            .and_then(|entry| {
+                entry
+                    .get("previous")
+                    .and_then(Value::as_str)
+                    .map(String::from)
+            })
+            .is_some_and(|hash| hash == previous);
+        if !linked || hex(&sha256(without_hash.as_bytes())) != hash {
+            return Err(tampered());
+        }
+        previous = hash.to_string();
+    }
+    Ok(content.lines().count())
+}
+
+impl Audit {
+    pub fn from_env() -> Audit {
+        let path = env::var("AUDIT_LOG").ok().map(PathBuf::from);
+        // the chain goes on from the last line
+        let last_hash = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| {
+                content
+                    .lines()
+                    .last()
+                    .and_then(split_hash)
+                    .map(|(_, hash)| hash.to_string())
+            })
+            .unwrap_or_else(|| GENESIS.to_string());
+        Audit { path, last_hash }
+    }
+
+    fn append(
+        &mut self,
+        date: u128,
+        identity: &str,
+        request: &str,
+        response: &str,
+    ) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let entry = format!(
+            "{{\"date\":{},\"identity\":{},\"request\":{},\"response\":{},\"previous\":{}}}",
+            date,
+            json::string(identity),
+            json::string(request),
+            json::string(response),
+            json::string(&self.last_hash)
+        );
+        let hash = hex(&sha256(entry.as_bytes()));
+        let line = format!("{},\"hash\":\"{}\"}}\n", &entry[..entry.len() - 1], hash);
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())?;
+        self.last_hash = hash;
+        Ok(())
+    }
+
+    // `AUDIT [count]` or `AUDIT VERIFY`
+    pub fn query<'a>(&self, mut args: impl Iterator<Item = &'a str>) -> String {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return "ERROR the audit log is disabled, see AUDIT_LOG".to_string(),
+        };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return format!("ERROR can't read the audit log: {}", error),
+        };
+
+        let count = match args.next() {
+            Some("VERIFY") => {
+                return match verify(&content) {
+                    Ok(count) => format!("OK {} entries verified", count),
+                    Err(error) => format!("ERROR {}", error),
+                }
+            }
+            None => 10,
+            Some(count) => match count.parse::<usize>() {
+                Ok(count) => count,
+                Err(_) => return "ERROR usage: AUDIT [count|VERIFY]".to_string(),
+            },
+        };
+
+        let lines: Vec<&str> = content.lines().collect();
+        let lines = &lines[lines.len().saturating_sub(count)..];
+        format!("OK {} entries\n{}", lines.len(), lines.join("\n"))
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl Broker {
+    pub fn audit(&mut self, identity: &str, request: &str, response: &str) {
+        let date = self
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let request = self.redaction.redact(request);
+        let response = self.redaction.redact(response);
+        if let Err(error) = self.audit.append(date, identity, &request, &response) {
+            log::warn(&format!("Can't write the audit log: {}", error));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, Audit};
+    use std::fs;
+
+    #[test]
+    fn audit_lines_are_chained() {
+        let path = std::env::temp_dir().join("tiny-broke-audit-test.log");
+        fs::remove_file(&path).ok();
+        let mut audit = Audit {
+            path: Some(path.clone()),
+            last_hash: super::GENESIS.to_string(),
+        };
+        audit.append(1, "127.0.0.1", "DRAIN ADD", "OK").unwrap();
+        audit.append(2, "127.0.0.1", "DLQ", "OK").unwrap();
+        audit.append(3, "127.0.0.1", "STATS", "OK").unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(verify(&content), Ok(3));
+        assert_eq!(
+            verify(&content.replace("DRAIN ADD", "DRAIN SUB")),
+            Err("line 1 is tampered".to_string())
+        );
+        let without_second: Vec<&str> = content
+            .lines()
+            .enumerate()
+            .filter(|(index, _)| *index != 1)
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(
+            verify(&without_second.join("\n")),
+            Err("line 2 is tampered".to_string())
+        );
+        assert!(audit
+            .query(std::iter::once("1"))
+            .starts_with("OK 1 entries\n{\"date\":3,"));
+        fs::remove_file(&path).ok();
+    }
+}
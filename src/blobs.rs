@@ -0,0 +1,132 @@
+use crate::log;
+use crate::{results, webhook, Broker};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// claim check: payloads (tasks and responses) above `BLOB_THRESHOLD` bytes are written to `BLOB_STORE` and replaced
+// by a reference, `@@BLOB <url>`, so the broker and the peers' sockets only carry the reference
+// the SDKs fetch the payload behind the reference before giving it to the handlers and the callers
+// - a directory is shared by the broker and its peers (a volume), references are `file://` urls
+// - an `http://` url is a S3-compatible bucket, objects are `PUT` under it, references are their urls
+// the broker doesn't remove the blobs, the store's own lifecycle rules (or a cron for a directory) do
+// a payload that can't be written is sent as is
+
+pub const PREFIX: &str = "@@BLOB ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlobStore {
+    Directory(PathBuf),
+    Bucket(String),
+}
+
+impl BlobStore {
+    pub fn parse(value: &str) -> BlobStore {
+        if value.starts_with("http://") {
+            BlobStore::Bucket(value.trim_end_matches('/').to_string())
+        } else {
+            BlobStore::Directory(PathBuf::from(value))
+        }
+    }
+
+    // the reference of the written payload
+    fn write(&self, key: &str, payload: &str) -> io::Result<String> {
+        match self {
+            BlobStore::Directory(dir) => {
+                fs::create_dir_all(dir)?;
+                let path = dir.join(key);
+                fs::write(&path, payload)?;
+                Ok(format!("file://{}", fs::canonicalize(path)?.display()))
+            }
+            BlobStore::Bucket(url) => {
+                let url = format!("{}/{}", url, key);
+                webhook::put(&url, payload)?;
+                Ok(url)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Blobs {
+    threshold: usize,
+    store: Option<BlobStore>,
+}
+
+impl Blobs {
+    pub fn from_env() -> Blobs {
+        Blobs {
+            threshold: env::var("BLOB_THRESHOLD")
+                .map(|v| v.parse::<usize>().unwrap_or(0))
+                .unwrap_or(0),
+            store: env::var("BLOB_STORE").ok().map(|v| BlobStore::parse(&v)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.store.is_some() && self.threshold > 0
+    }
+
+    // `None` when the payload stays in the message
+    fn offload(&self, key: &str, payload: &str) -> Option<io::Result<String>> {
+        let store = self.store.as_ref()?;
+        if self.threshold == 0 || payload.len() <= self.threshold || payload.starts_with(PREFIX) {
+            return None;
+        }
+
+        Some(
+            store
+                .write(key, payload)
+                .map(|reference| format!("{}{}", PREFIX, reference)),
+        )
+    }
+}
+
+impl Broker {
+    // the payload to send, a reference when it is too large
+    // the key is the hexadecimal response topic, a task and its response have their own blob
+    pub fn claim_check(&self, response_topic: &str, kind: &str, payload: &str) -> String {
+        let key = format!("{}.{}", results::file_name(response_topic), kind);
+        match self.blobs.offload(&key, payload) {
+            Some(Ok(reference)) => reference,
+            Some(Err(error)) => {
+                log::warn(&format!(
+                    "Can't write the blob of {}: {}",
+                    response_topic, error
+                ));
+                payload.to_string()
+            }
+            None => payload.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlobStore, Blobs};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn large_payloads_are_replaced_by_a_reference() {
+        let dir = env::temp_dir().join("tiny-broke-blobs-test");
+        let blobs = Blobs {
+            threshold: 4,
+            store: Some(BlobStore::Directory(dir.clone())),
+        };
+
+        assert!(blobs.offload("small", "1234").is_none());
+        let reference = blobs.offload("large", "12345").unwrap().unwrap();
+        let path = reference.strip_prefix("@@BLOB file://").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "12345");
+        // a reference is not offloaded again
+        assert!(blobs.offload("again", &reference).is_none());
+        fs::remove_dir_all(dir).ok();
+
+        assert_eq!(
+            BlobStore::parse("http://s3:9000/bucket/"),
+            BlobStore::Bucket("http://s3:9000/bucket".to_string())
+        );
+    }
+}
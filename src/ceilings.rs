@@ -0,0 +1,58 @@
+use crate::transport::Transport;
+use crate::Broker;
+use std::env;
+
+// concurrency ceilings: at most `MAX_IN_FLIGHT` tasks sent and waiting for their response, and at most
+// `max_in_flight=<count>` of a declared topic, whatever the number of workers (for workers calling a service with its
+// own rate limits)
+// a task over a ceiling waits for a worker (and counts in the `max_queue` of its topic), it is sent once an other task
+// is answered or given up, the topics taking turns (see fairness.rs)
+// at most once tasks are not waited for, they are not counted
+
+pub fn max_in_flight() -> Option<usize> {
+    env::var("MAX_IN_FLIGHT")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+}
+
+impl Broker {
+    fn topic_ceiling(&self, topic_name: &str) -> Option<usize> {
+        self.declared_topics
+            .get(topic_name)
+            .and_then(|settings| settings.max_in_flight)
+    }
+
+    fn has_ceilings(&self) -> bool {
+        self.max_in_flight.is_some()
+            || self
+                .declared_topics
+                .values()
+                .any(|settings| settings.max_in_flight.is_some())
+    }
+
+    pub fn at_ceiling(&self, topic_name: &str) -> bool {
+        self.max_in_flight
+            .is_some_and(|max| self.dispatcher.tasks.len() >= max)
+            || self
+                .topic_ceiling(topic_name)
+                .is_some_and(|max| self.dispatcher.tasks.count_on(topic_name) >= max)
+    }
+
+    // the tasks kept by a ceiling are sent as long as they fit, see fairness.rs for their order
+    pub fn release_ceilings(&mut self, transport: &dyn Transport) {
+        if self.dispatcher.tasks_to_retry.is_empty() || !self.has_ceilings() {
+            return;
+        }
+
+        self.send_waiting(transport, |broker, task| {
+            let has_workers = broker
+                .registry
+                .topics
+                .get(&*task.worker_topic)
+                .is_some_and(|topic| !topic.workers.is_empty());
+            has_workers
+                && !broker.at_ceiling(&task.worker_topic)
+                && !broker.is_paused(&task.worker_topic)
+        });
+    }
+}
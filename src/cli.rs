@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+// a subcommand gets the arguments following its name, and returns what to print
+pub type Subcommand = fn(&[String]) -> Result<String, String>;
+
+// subcommand arguments: `--name value` options, anything else is positional
+// only the given option names are accepted
+pub fn options(
+    args: &[String],
+    names: &[&str],
+) -> Result<(Vec<String>, HashMap<String, String>), String> {
+    let mut positionals = vec![];
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(name) if names.contains(&name) => match args.next() {
+                Some(value) => {
+                    options.insert(name.to_string(), value.clone());
+                }
+                None => return Err(format!("missing value for --{}", name)),
+            },
+            Some(name) => return Err(format!("unknown option --{}", name)),
+            None => positionals.push(arg.clone()),
+        }
+    }
+
+    Ok((positionals, options))
+}
+
+// `500ms`, `60s`, `2m`, `1h`, seconds without unit
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    if let Some(millis) = duration.strip_suffix("ms") {
+        return millis.parse().ok().map(Duration::from_millis);
+    }
+    let (number, unit) = match duration.chars().last() {
+        Some('s') => (&duration[..duration.len() - 1], 1),
+        Some('m') => (&duration[..duration.len() - 1], 60),
+        Some('h') => (&duration[..duration.len() - 1], 3600),
+        _ => (duration, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .map(|number| Duration::from_secs(number * unit))
+}
@@ -0,0 +1,51 @@
+use crate::Broker;
+use std::cell::Cell;
+use std::time::{Duration, SystemTime};
+
+// every time based rule of the broker (timeouts, alerts, garbage collection, ...) reads the time from its clock,
+// so a simulation can drive it with a virtual time
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// only moves when it is told to
+pub struct VirtualClock {
+    now: Cell<SystemTime>,
+}
+
+impl VirtualClock {
+    pub fn new(now: SystemTime) -> VirtualClock {
+        VirtualClock {
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+}
+
+impl Broker {
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    // zero if the date is in the future
+    pub fn elapsed(&self, since: SystemTime) -> Duration {
+        self.now().duration_since(since).unwrap_or_default()
+    }
+}
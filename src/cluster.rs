@@ -0,0 +1,439 @@
+use crate::container;
+use crate::discovery;
+use crate::dispatcher::Task;
+use crate::endpoints;
+use crate::gossip::{Member, Membership};
+use crate::log;
+use crate::transport::Transport;
+use crate::tuning::SocketOptions;
+use crate::Broker;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// federated brokers: a task without local worker is forwarded to a peer broker that has workers for its topic
+// - every broker connects to the peering port (`CLUSTER_PORT`) of the members of the cluster, and sends there, every
+//   round, the members it knows (`@@MEMBERS <member>...`, see gossip.rs) and the topics its workers handle
+//   (`@@ROUTES <topic>...`); the members it doesn't know are answered with its own `@@MEMBERS`
+// - the first members are the seeds (`CLUSTER_PEERS`), the others are learned from them
+// - a broker forwards tasks to the peers announcing the topic, back on their connection:
+//   `@@FORWARD <topic> <response topic> <payload>`
+// - the response goes back on the same connection: `@@RESPONSE <response topic> <payload>`
+// forwarded tasks are only sent to local workers, so they can't loop between brokers
+
+const ROUND_INTERVAL: Duration = Duration::from_secs(1);
+// a peer not announcing its routes anymore is down
+const ROUTES_TTL: Duration = Duration::from_secs(5);
+
+struct Route {
+    topics: Vec<String>,
+    date: SystemTime,
+}
+
+// a connection to the peering socket of another broker
+struct Peer {
+    // unique by connection, so the peer can tell two connections of this broker apart
+    identity: String,
+    // known once it gossiped
+    name: Option<String>,
+    seed: bool,
+    socket: zmq::Socket,
+}
+
+pub struct Cluster {
+    context: zmq::Context,
+    options: SocketOptions,
+    router: zmq::Socket,
+    peers: Vec<Peer>,
+    connections: usize,
+    membership: Membership,
+    // by identity of the connection of the peer
+    routes: HashMap<String, Route>,
+    // the connection (its identity) each forwarded task came from, by response topic
+    forwarded: HashMap<String, String>,
+    last_round: SystemTime,
+}
+
+fn zmq_error(error: zmq::Error) -> String {
+    error.to_string()
+}
+
+// connections are named after their broker: `<name>#<index>`
+fn broker_of(identity: &str) -> &str {
+    identity.rsplit_once('#').map_or(identity, |(name, _)| name)
+}
+
+impl Cluster {
+    // `None` when `CLUSTER_PORT` is not set
+    pub fn from_env(
+        context: &zmq::Context,
+        port: u16,
+        options: &SocketOptions,
+    ) -> Result<Option<Cluster>, String> {
+        let peering_port = match env::var("CLUSTER_PORT") {
+            Ok(peering_port) => peering_port,
+            Err(_) => return Ok(None),
+        };
+        let name = env::var("CLUSTER_NAME").unwrap_or_else(|_| discovery::hostname());
+        let address = env::var("CLUSTER_ADDRESS").unwrap_or_else(|_| discovery::hostname());
+
+        let router = context.socket(zmq::ROUTER).map_err(zmq_error)?;
+        options.apply(&router).map_err(zmq_error)?;
+        router.set_router_mandatory(true).map_err(zmq_error)?;
+        router
+            .bind(&container::endpoint(&peering_port)?)
+            .map_err(zmq_error)?;
+        // with port 0, the one the OS picked
+        let peering_port = endpoints::port_of(&endpoints::bound_endpoint(&router))
+            .map_or(peering_port, |port| port.to_string());
+
+        let mut cluster = Cluster {
+            context: context.clone(),
+            options: *options,
+            router,
+            peers: vec![],
+            connections: 0,
+            membership: Membership::new(Member {
+                name,
+                peering_endpoint: format!("tcp://{}:{}", address, peering_port),
+                endpoint: format!("tcp://{}:{}", address, port),
+                // a restarted broker has a higher heartbeat than before
+                heartbeat: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_millis() as u64)
+                    .unwrap_or(0),
+            }),
+            routes: HashMap::new(),
+            forwarded: HashMap::new(),
+            last_round: UNIX_EPOCH,
+        };
+
+        let seeds = env::var("CLUSTER_PEERS").unwrap_or_default();
+        for seed in seeds.split(',').filter(|seed| !seed.is_empty()) {
+            cluster.connect(seed, None).map_err(zmq_error)?;
+        }
+
+        Ok(Some(cluster))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.membership.myself.name
+    }
+
+    // where the peering socket is bound, members reach it through `CLUSTER_ADDRESS`
+    pub fn peering_endpoint(&self) -> String {
+        endpoints::bound_endpoint(&self.router)
+    }
+
+    // the endpoints of the live brokers, this one first, for the peers to bootstrap from any of them
+    pub fn endpoints(&self) -> Vec<String> {
+        let mut endpoints = vec![self.membership.myself.endpoint.clone()];
+        endpoints.extend(
+            self.membership
+                .members()
+                .iter()
+                .map(|member| member.endpoint.clone()),
+        );
+        endpoints
+    }
+
+    // to be polled along other sockets
+    pub fn sockets(&self) -> Vec<&zmq::Socket> {
+        let mut sockets = vec![&self.router];
+        sockets.extend(self.peers.iter().map(|peer| &peer.socket));
+        sockets
+    }
+
+    // seeds have no name until they gossip
+    fn connect(&mut self, endpoint: &str, name: Option<String>) -> Result<(), zmq::Error> {
+        let identity = format!("{}#{}", self.name(), self.connections);
+        self.connections += 1;
+
+        let socket = self.context.socket(zmq::DEALER)?;
+        socket.set_identity(identity.as_bytes())?;
+        self.options.apply(&socket)?;
+        // a peer may be gone for good, nothing waits for it on shutdown
+        socket.set_linger(0)?;
+        socket.connect(endpoint)?;
+
+        self.peers.push(Peer {
+            identity,
+            seed: name.is_none(),
+            name,
+            socket,
+        });
+        Ok(())
+    }
+
+    // one connection per member: to the members learned, and not twice to the same one nor to this broker
+    fn connect_members(&mut self) {
+        let myself = self.name().to_string();
+        let mut connected = HashSet::new();
+        // seeds first, so they are kept
+        self.peers.sort_by_key(|peer| !peer.seed);
+        self.peers.retain(|peer| match &peer.name {
+            Some(name) => name != &myself && connected.insert(name.clone()),
+            None => true,
+        });
+
+        let missing: Vec<Member> = self
+            .membership
+            .members()
+            .into_iter()
+            .filter(|member| !connected.contains(&member.name))
+            .cloned()
+            .collect();
+        for member in missing {
+            if let Err(error) = self.connect(&member.peering_endpoint, Some(member.name.clone())) {
+                log::warn(&format!(
+                    "Can't connect to the broker {}: {}",
+                    member.name, error
+                ));
+            }
+        }
+    }
+
+    // the first peer connection announcing the topic
+    fn peer_for(&self, topic_name: &str) -> Option<String> {
+        let mut peers: Vec<&String> = self
+            .routes
+            .iter()
+            .filter(|(_, route)| route.topics.iter().any(|topic| topic == topic_name))
+            .map(|(peer, _)| peer)
+            .collect();
+        peers.sort();
+        peers.first().map(|peer| peer.to_string())
+    }
+}
+
+fn receive(socket: &zmq::Socket) -> Option<Vec<String>> {
+    socket.recv_multipart(zmq::DONTWAIT).ok().map(|frames| {
+        frames
+            .iter()
+            .map(|frame| String::from_utf8_lossy(frame).into_owned())
+            .collect()
+    })
+}
+
+impl Broker {
+    // the peer the task is forwarded to, when there is no local worker
+    pub fn forward(&mut self, task: &mut Task) -> Option<String> {
+        let cluster = self.cluster.as_mut()?;
+        if cluster.forwarded.contains_key(&task.response_topic) {
+            return None;
+        }
+        let peer = cluster.peer_for(&task.worker_topic)?;
+
+        task.sent = cluster
+            .router
+            .send_multipart(
+                [
+                    peer.as_str(),
+                    "@@FORWARD",
+                    &task.worker_topic,
+                    &task.response_topic,
+                    &task.payload,
+                ],
+                zmq::DONTWAIT,
+            )
+            .is_ok();
+
+        if !task.sent {
+            // the peer is gone, its routes too
+            cluster.routes.remove(&peer);
+            return Some(peer);
+        }
+
+        self.emit(
+            "task.forwarded",
+            &[
+                ("topic", &task.worker_topic),
+                ("responseTopic", &task.response_topic),
+                ("broker", broker_of(&peer)),
+            ],
+        );
+
+        Some(peer)
+    }
+
+    // the response of a task forwarded by a peer goes back to it
+    pub fn return_forwarded(&mut self, response_topic: &str, payload: &str) {
+        let cluster = match self.cluster.as_mut() {
+            Some(cluster) => cluster,
+            None => return,
+        };
+
+        let identity = match cluster.forwarded.remove(response_topic) {
+            Some(identity) => identity,
+            None => return,
+        };
+        if let Some(peer) = cluster.peers.iter().find(|peer| peer.identity == identity) {
+            peer.socket
+                .send_multipart(["@@RESPONSE", response_topic, payload], zmq::DONTWAIT)
+                .ok();
+        }
+    }
+
+    // the endpoints to give to a peer asking for the members of the cluster, none outside a cluster
+    pub fn cluster_endpoints(&self) -> Vec<String> {
+        self.cluster
+            .as_ref()
+            .map(|cluster| cluster.endpoints())
+            .unwrap_or_default()
+    }
+
+    // a gossip round: the members and the topics of the local workers to all the peers, then the silent members
+    // and routes are forgotten
+    pub fn cluster_round(&mut self) {
+        let now = self.now();
+        let topics: Vec<&str> = self
+            .registry
+            .topics
+            .values()
+            .filter(|topic| !topic.workers.is_empty())
+            .map(|topic| topic.name.as_str())
+            .collect();
+        let tasks = &self.dispatcher;
+        let cluster = match self.cluster.as_mut() {
+            Some(cluster) => cluster,
+            None => return,
+        };
+        if now.duration_since(cluster.last_round).unwrap_or_default() < ROUND_INTERVAL {
+            return;
+        }
+        cluster.last_round = now;
+
+        cluster.membership.beat();
+        let mut members = vec!["@@MEMBERS".to_string()];
+        members.extend(cluster.membership.entries());
+        let mut routes = vec!["@@ROUTES"];
+        routes.extend(topics);
+        cluster.peers.iter().for_each(|peer| {
+            peer.socket
+                .send_multipart(&members, zmq::DONTWAIT)
+                .and_then(|_| peer.socket.send_multipart(&routes, zmq::DONTWAIT))
+                .ok();
+        });
+
+        let lost = cluster.membership.expire(now);
+        lost.iter().for_each(|member| {
+            let name = Some(&member.name);
+            cluster
+                .peers
+                .retain(|peer| peer.seed || peer.name.as_ref() != name);
+            // a seed may come back as another broker
+            cluster.peers.iter_mut().for_each(|peer| {
+                if peer.name.as_ref() == name {
+                    peer.name = None;
+                }
+            });
+        });
+        cluster.connect_members();
+
+        cluster
+            .routes
+            .retain(|_, route| now.duration_since(route.date).unwrap_or_default() < ROUTES_TTL);
+        // forwarded tasks that are not there anymore (quarantined, drained) won't be answered
+        cluster.forwarded.retain(|response_topic, _| {
+            tasks
+                .pending()
+                .any(|task| &task.response_topic == response_topic)
+        });
+
+        lost.iter().for_each(|member| {
+            log::warn(&format!("Lost the broker {}", member.name));
+            self.emit("broker.lost", &[("broker", &member.name)]);
+        });
+    }
+
+    // the messages of the peers, without waiting
+    pub fn handle_cluster(&mut self, transport: &dyn Transport) {
+        let mut routes_changed = false;
+        let mut joined = vec![];
+        let mut responses = vec![];
+        let mut forwarded = vec![];
+
+        let now = self.now();
+        if let Some(cluster) = self.cluster.as_mut() {
+            while let Some(frames) = receive(&cluster.router) {
+                match frames.get(1).map(String::as_str) {
+                    Some("@@MEMBERS") => {
+                        joined.extend(cluster.membership.merge(&frames[2..], now));
+
+                        let mut reply = vec![frames[0].clone(), "@@MEMBERS".to_string()];
+                        reply.extend(cluster.membership.entries());
+                        cluster.router.send_multipart(&reply, zmq::DONTWAIT).ok();
+                    }
+                    Some("@@ROUTES") => {
+                        let topics = frames[2..].to_vec();
+                        let previous = cluster.routes.insert(
+                            frames[0].clone(),
+                            Route {
+                                topics: topics.clone(),
+                                date: now,
+                            },
+                        );
+                        routes_changed |= previous.is_none_or(|route| route.topics != topics);
+                    }
+                    Some("@@RESPONSE") if frames.len() == 4 => {
+                        responses.push((frames[2].clone(), frames[3].clone()));
+                    }
+                    _ => log::warn(&format!("Ignoring a message of the peer {}", frames[0])),
+                }
+            }
+
+            for peer in cluster.peers.iter_mut() {
+                while let Some(frames) = receive(&peer.socket) {
+                    match frames.first().map(String::as_str) {
+                        // the gossip of the peer starts with itself
+                        Some("@@MEMBERS") => {
+                            if let Some(member) =
+                                frames.get(1).and_then(|entry| Member::parse(entry))
+                            {
+                                peer.name = Some(member.name);
+                            }
+                            joined.extend(cluster.membership.merge(&frames[1..], now));
+                        }
+                        Some("@@FORWARD") if frames.len() == 4 => {
+                            cluster
+                                .forwarded
+                                .insert(frames[2].clone(), peer.identity.clone());
+                            forwarded.push(Task::new(&frames[1], &frames[2], &frames[3]));
+                        }
+                        _ => log::warn("Ignoring a message of a peer"),
+                    }
+                }
+            }
+
+            if !joined.is_empty() {
+                cluster.connect_members();
+            }
+        }
+
+        joined.iter().for_each(|member| {
+            log::info(&format!("The broker {} joined", member.name));
+            self.emit(
+                "broker.joined",
+                &[("broker", &member.name), ("endpoint", &member.endpoint)],
+            );
+        });
+        for (response_topic, payload) in responses {
+            self.send_response(transport, &response_topic, &payload);
+        }
+        for task in forwarded {
+            self.emit(
+                "task.created",
+                &[
+                    ("topic", &task.worker_topic),
+                    ("responseTopic", &task.response_topic),
+                    ("client", "@@CLUSTER"),
+                ],
+            );
+            self.send_task_and_retry(transport, task);
+        }
+        // the tasks waiting for a worker may have one on a peer now
+        if routes_changed {
+            self.retry_tasks(transport);
+        }
+    }
+}
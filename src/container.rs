@@ -0,0 +1,299 @@
+use crate::log;
+use crate::Broker;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// `tiny-broke --container` is the profile of a broker run by an orchestrator (docker, kubernetes):
+// - logs are JSON lines (`LOG_FORMAT`, see log.rs)
+// - `READY_FILE` is written once the sockets are bound, and removed when the broker stops, for readiness probes
+// - SIGTERM (and SIGINT) stop the broker gracefully: new tasks are refused with `@@STOPPING`, and the broker exits
+//   once the tasks it has are answered, or after `STOP_TIMEOUT`
+// `BIND_ADDRESS` is the address the sockets are bound to, with or without the profile: an IPv4 or IPv6 address, `*`
+// for all the interfaces, or the name of an interface (`eth0`)
+// `DUAL_STACK=true` binds to `::` by default, where IPv4 and IPv6 peers connect, an IPv6 address needs it too:
+// zmq sockets are IPv4 only unless told otherwise
+// the dashboard (`HTTP_PORT`) takes `*`, but not interface names, and is only served on the loopback without
+// `ADMIN_USERS` or `CONTROL_SECRET`
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+const READY_FILE: &str = "/tmp/tiny-broke.ready";
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+// only an atomic store is safe in a signal handler, the main loop reads it when it wakes up
+extern "C" fn request_stop(_: i32) {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_container(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--container")
+}
+
+pub fn handle_stop_signals() {
+    // the handler only touches an atomic, and the broker is single threaded
+    unsafe {
+        signal(SIGTERM, request_stop);
+        signal(SIGINT, request_stop);
+    }
+}
+
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+pub fn dual_stack() -> bool {
+    env::var("DUAL_STACK").is_ok_and(|v| v == "true")
+}
+
+// IPv6 addresses are bracketed in endpoints
+fn parse_bind_address(address: &str) -> Result<String, String> {
+    let unbracketed = address.trim_start_matches('[').trim_end_matches(']');
+    match unbracketed.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Ok(ip.to_string()),
+        Ok(IpAddr::V6(ip)) => Ok(format!("[{}]", ip)),
+        Err(_) if address == "*" => Ok(address.to_string()),
+        // anything looking like an address (`10.0.0.300`, `fe80::1::2`) is a typo, not an interface
+        Err(_)
+            if address.starts_with(|c: char| c.is_ascii_alphabetic())
+                && address
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') =>
+        {
+            Ok(address.to_string())
+        }
+        Err(_) => Err(format!(
+            "BIND_ADDRESS {} is not an IPv4 or IPv6 address, `*`, or the name of an interface",
+            address
+        )),
+    }
+}
+
+pub fn bind_address() -> Result<String, String> {
+    match env::var("BIND_ADDRESS") {
+        Ok(address) => parse_bind_address(&address),
+        Err(_) if dual_stack() => Ok("[::]".to_string()),
+        Err(_) => Ok("0.0.0.0".to_string()),
+    }
+}
+
+// the sockets take IPv6 peers, which `ZMQ_IPV6` enables
+pub fn ipv6() -> bool {
+    dual_stack() || bind_address().is_ok_and(|address| address.starts_with('['))
+}
+
+pub fn endpoint(port: impl std::fmt::Display) -> Result<String, String> {
+    let port = port.to_string();
+    port.parse::<u16>()
+        .map_err(|_| format!("{} is not a port", port))?;
+    Ok(format!("tcp://{}:{}", bind_address()?, port))
+}
+
+// the dashboard and QUIC are served by the standard library, which binds to addresses only: `*` is every address, and
+// an interface has to be given by its address
+// on the loopback, the address of the same family is used
+#[cfg(any(feature = "http", feature = "quic"))]
+fn socket_address_of(
+    bind_address: &str,
+    dual_stack: bool,
+    loopback: bool,
+    port: &str,
+) -> Result<String, String> {
+    let ipv6 = bind_address.starts_with('[') || (bind_address == "*" && dual_stack);
+    let address = match bind_address {
+        _ if loopback && ipv6 => "[::1]",
+        _ if loopback => "127.0.0.1",
+        "*" if dual_stack => "[::]",
+        "*" => "0.0.0.0",
+        address if !ipv6 && address.parse::<IpAddr>().is_err() => {
+            return Err(format!(
+                "the interface {} can't be served, give its address in BIND_ADDRESS",
+                address
+            ))
+        }
+        address => address,
+    };
+    Ok(format!("{}:{}", address, port))
+}
+
+// `<address>:<port>`
+#[cfg(any(feature = "http", feature = "quic"))]
+pub fn socket_address(port: impl std::fmt::Display, loopback: bool) -> Result<String, String> {
+    let port = port.to_string();
+    port.parse::<u16>()
+        .map_err(|_| format!("{} is not a port", port))?;
+    socket_address_of(&bind_address()?, dual_stack(), loopback, &port)
+}
+
+pub fn stop_timeout() -> Duration {
+    Duration::from_secs(
+        env::var("STOP_TIMEOUT")
+            .map(|v| v.parse::<u64>().unwrap_or(10))
+            .unwrap_or(10),
+    )
+}
+
+pub struct ReadyFile {
+    path: Option<PathBuf>,
+}
+
+impl ReadyFile {
+    // an empty `READY_FILE` disables it in the container profile
+    pub fn from_env(container: bool) -> ReadyFile {
+        let path = match env::var("READY_FILE") {
+            Ok(path) => Some(path).filter(|path| !path.is_empty()),
+            Err(_) if container => Some(READY_FILE.to_string()),
+            Err(_) => None,
+        };
+        ReadyFile {
+            path: path.map(PathBuf::from),
+        }
+    }
+
+    pub fn ready(&self) {
+        if let Some(path) = &self.path {
+            if let Err(error) = fs::write(path, "ready\n") {
+                log::warn(&format!(
+                    "Can't write the ready file {}: {}",
+                    path.display(),
+                    error
+                ));
+            }
+        }
+    }
+
+    pub fn unready(&self) {
+        if let Some(path) = &self.path {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+impl Broker {
+    pub fn is_stopping(&self) -> bool {
+        self.stop_deadline.is_some()
+    }
+
+    pub fn stop(&mut self, timeout: Duration) {
+        if self.is_stopping() {
+            return;
+        }
+        self.stop_deadline = Some(self.now() + timeout);
+        self.count_stopping_tasks();
+        log::info(&format!(
+            "Stopping once {} tasks are answered, in {} seconds at most",
+            self.dispatcher.pending().count(),
+            timeout.as_secs()
+        ));
+    }
+
+    // the broker can exit: the tasks it had are answered, or it waited long enough
+    pub fn is_stopped(&self) -> bool {
+        match self.stop_deadline {
+            Some(deadline) => self.dispatcher.pending().next().is_none() || self.now() >= deadline,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bind_address, ReadyFile};
+    use crate::tuning::SocketOptions;
+
+    #[test]
+    fn the_ready_file_is_written_and_removed() {
+        let path = std::env::temp_dir().join("tiny-broke-ready-test");
+        let ready_file = ReadyFile {
+            path: Some(path.clone()),
+        };
+        ready_file.ready();
+        assert!(path.exists());
+        ready_file.unready();
+        assert!(!path.exists());
+        ReadyFile { path: None }.ready();
+    }
+
+    #[test]
+    fn bind_addresses_are_validated() {
+        assert_eq!(parse_bind_address("0.0.0.0").unwrap(), "0.0.0.0");
+        assert_eq!(parse_bind_address("::").unwrap(), "[::]");
+        assert_eq!(parse_bind_address("[fe80::1]").unwrap(), "[fe80::1]");
+        assert_eq!(parse_bind_address("*").unwrap(), "*");
+        assert_eq!(parse_bind_address("eth0").unwrap(), "eth0");
+        assert!(parse_bind_address("10.0.0.300").is_err());
+        assert!(parse_bind_address("fe80::1::2").is_err());
+        assert!(parse_bind_address("tcp://0.0.0.0").is_err());
+        assert!(parse_bind_address("").is_err());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http_is_served_on_addresses() {
+        use super::socket_address_of;
+
+        assert_eq!(
+            socket_address_of("0.0.0.0", false, false, "80").unwrap(),
+            "0.0.0.0:80"
+        );
+        assert_eq!(
+            socket_address_of("[::1]", true, false, "80").unwrap(),
+            "[::1]:80"
+        );
+        assert_eq!(
+            socket_address_of("*", false, false, "80").unwrap(),
+            "0.0.0.0:80"
+        );
+        assert_eq!(
+            socket_address_of("*", true, false, "80").unwrap(),
+            "[::]:80"
+        );
+        assert!(socket_address_of("eth0", false, false, "80").is_err());
+        // without authentication
+        assert_eq!(
+            socket_address_of("0.0.0.0", false, true, "80").unwrap(),
+            "127.0.0.1:80"
+        );
+        assert_eq!(
+            socket_address_of("eth0", false, true, "80").unwrap(),
+            "127.0.0.1:80"
+        );
+        assert_eq!(
+            socket_address_of("[::]", true, true, "80").unwrap(),
+            "[::1]:80"
+        );
+    }
+
+    #[test]
+    fn dual_stack_sockets_take_ipv4_and_ipv6_peers() {
+        let context = zmq::Context::new();
+        let options = SocketOptions {
+            ipv6: true,
+            ..SocketOptions::default()
+        };
+        let router = context.socket(zmq::ROUTER).unwrap();
+        options.apply(&router).unwrap();
+        router.bind("tcp://[::]:0").unwrap();
+        let endpoint = router.get_last_endpoint().unwrap().unwrap();
+        let port = endpoint.rsplit(':').next().unwrap();
+
+        for address in &["127.0.0.1", "[::1]"] {
+            let dealer = context.socket(zmq::DEALER).unwrap();
+            options.apply(&dealer).unwrap();
+            dealer
+                .connect(&format!("tcp://{}:{}", address, port))
+                .unwrap();
+            dealer.send(*address, 0).unwrap();
+            let message = router.recv_multipart(0).unwrap();
+            assert_eq!(message[1], address.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::log;
+use crate::Broker;
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// the debug line gives the size of the broker: workers, clients, topics, sent tasks and tasks waiting for a worker
+// it goes to stdout, to the events socket (`broker.debug`), or nowhere, at most once per interval, so a busy broker
+// doesn't spend its time writing it
+// `DEBUG_OUTPUT` and `DEBUG_INTERVAL` set it on start, the `DEBUG` admin command while running
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Off,
+    Stdout,
+    Events,
+}
+
+impl Output {
+    pub fn parse(value: &str) -> Result<Output, String> {
+        match value {
+            "off" => Ok(Output::Off),
+            "stdout" => Ok(Output::Stdout),
+            "events" => Ok(Output::Events),
+            _ => Err(format!(
+                "unknown debug output {}, expected off, stdout or events",
+                value
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Output::Off => "off",
+            Output::Stdout => "stdout",
+            Output::Events => "events",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Debug {
+    pub output: Output,
+    pub interval: Duration,
+    last: SystemTime,
+}
+
+impl Debug {
+    pub fn from_env() -> Debug {
+        Debug {
+            output: env::var("DEBUG_OUTPUT")
+                .ok()
+                .and_then(|v| Output::parse(&v).ok())
+                .unwrap_or(Output::Stdout),
+            interval: Duration::from_millis(
+                env::var("DEBUG_INTERVAL")
+                    .map(|v| v.parse::<u64>().unwrap_or(1000))
+                    .unwrap_or(1000),
+            ),
+            last: UNIX_EPOCH,
+        }
+    }
+
+    // `<output> [interval=<milliseconds>]`
+    pub fn set<'a>(&mut self, mut args: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let output = Output::parse(args.next().unwrap_or(""))?;
+        let interval = match args.next().map(|arg| arg.split_once('=')) {
+            Some(Some(("interval", value))) => Duration::from_millis(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid interval {}", value))?,
+            ),
+            Some(_) => return Err("expected interval=<milliseconds>".to_string()),
+            None => self.interval,
+        };
+
+        self.output = output;
+        self.interval = interval;
+        Ok(())
+    }
+}
+
+impl Broker {
+    pub fn debug_line(&self) -> String {
+        let workers = self
+            .registry
+            .clients
+            .values()
+            .filter(|client| client.is_worker)
+            .count();
+
+        format!(
+            "[{} workers; {} clients; {} topics; {} tasks, {} waiting]",
+            workers,
+            self.registry.clients.len() - workers,
+            self.registry.topics.len(),
+            self.dispatcher.tasks.len(),
+            self.dispatcher.tasks_to_retry.len(),
+        )
+    }
+
+    pub fn print_debug(&mut self) {
+        if self.debug.output == Output::Off || self.elapsed(self.debug.last) < self.debug.interval {
+            return;
+        }
+        self.debug.last = self.now();
+
+        let line = self.debug_line();
+        match self.debug.output {
+            Output::Stdout => log::info(&line),
+            Output::Events => self.emit("broker.debug", &[("line", &line)]),
+            Output::Off => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debug, Output};
+    use std::time::Duration;
+
+    #[test]
+    fn output_and_interval_are_set_at_runtime() {
+        let mut debug = Debug::from_env();
+        debug.set("events interval=50".split_whitespace()).unwrap();
+        assert_eq!(debug.output, Output::Events);
+        assert_eq!(debug.interval, Duration::from_millis(50));
+
+        debug.set("off".split_whitespace()).unwrap();
+        assert_eq!(debug.output, Output::Off);
+        assert_eq!(debug.interval, Duration::from_millis(50));
+
+        assert!(debug.set("file".split_whitespace()).is_err());
+        assert!(debug.set("stdout every=2".split_whitespace()).is_err());
+    }
+}
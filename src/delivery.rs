@@ -0,0 +1,63 @@
+use crate::{Broker, Task};
+use std::rc::Rc;
+
+// delivery semantics of a topic, set with `delivery=<mode>` when it is declared
+// - at most once: a task is forgotten once sent to a worker, it is neither timed out nor sent again, its response
+//   still goes to its clients if it comes, for events nobody has to acknowledge
+// - at least once (default): a task is kept until its response comes, a task timing out is sent to the next worker,
+//   it may be processed twice
+// - exactly once: the response topic is the idempotency key of the task, a task sent again while its first copy
+//   is not answered is merged with it, and a timed out task goes back to the same worker, whose SDK keeps the
+//   responses it sent (see `Broke::deduplicate`), as long as this worker is registered
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Delivery {
+    AtMost,
+    #[default]
+    AtLeast,
+    Exactly,
+}
+
+impl Delivery {
+    pub fn parse(value: &str) -> Result<Delivery, String> {
+        match value {
+            "at_most_once" => Ok(Delivery::AtMost),
+            "at_least_once" => Ok(Delivery::AtLeast),
+            "exactly_once" => Ok(Delivery::Exactly),
+            _ => Err(format!(
+                "unknown delivery {}, expected at_most_once, at_least_once or exactly_once",
+                value
+            )),
+        }
+    }
+}
+
+impl Broker {
+    pub fn delivery(&self, topic_name: &str) -> Delivery {
+        self.declared_topics
+            .get(topic_name)
+            .map(|settings| settings.delivery)
+            .unwrap_or_default()
+    }
+
+    // a task with the same idempotency key is sent or waiting for a worker
+    pub fn is_duplicate(&self, worker_topic: &str, response_topic: &str) -> bool {
+        self.delivery(worker_topic) == Delivery::Exactly
+            && self.dispatcher.pending().any(|task| {
+                &*task.worker_topic == worker_topic && task.response_topic == response_topic
+            })
+    }
+
+    // the worker that already got the task, when it has to get it again
+    pub fn previous_worker(&self, task: &Task) -> Option<Rc<str>> {
+        if self.delivery(&task.worker_topic) != Delivery::Exactly {
+            return None;
+        }
+
+        task.worker_name.clone().filter(|worker_name| {
+            self.registry
+                .topics
+                .get(&*task.worker_topic)
+                .is_some_and(|topic| topic.workers.iter().any(|name| **name == **worker_name))
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use crate::log;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+
+// a task can wait for other tasks, given by their response topic: it is sent once none of them is pending anymore
+// (sent, waiting for a worker or for their own dependencies), so once they are answered
+// a dependency the broker doesn't know is answered, or was never sent
+// a dependency that fails (quarantined, dropped or drained) fails its dependents, their clients get
+// `@@DEPENDENCY_FAILED`
+
+pub fn parse_dependencies(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|dependency| !dependency.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl Broker {
+    pub fn is_pending(&self, response_topic: &str) -> bool {
+        self.dispatcher
+            .pending()
+            .any(|task| task.response_topic == response_topic)
+    }
+
+    fn has_failed(&self, response_topic: &str) -> bool {
+        self.dispatcher
+            .dead_letters
+            .iter()
+            .any(|task| task.response_topic == response_topic)
+    }
+
+    // the task is sent, or waits for its dependencies
+    pub fn submit(&mut self, transport: &dyn Transport, mut task: Task) {
+        // a task can't wait for itself
+        let response_topic = task.response_topic.clone();
+        task.dependencies
+            .retain(|dependency| *dependency != response_topic);
+        task.created = self.now();
+
+        if task
+            .dependencies
+            .iter()
+            .any(|dependency| self.has_failed(dependency))
+        {
+            self.fail_dependent(transport, task);
+        } else if task
+            .dependencies
+            .iter()
+            .any(|dependency| self.is_pending(dependency))
+        {
+            log::info(&format!(
+                "Task {} waiting for {}",
+                task.worker_topic,
+                task.dependencies.join(",")
+            ));
+            self.dispatcher.blocked.push(task);
+        } else {
+            self.send_in_order(transport, task);
+        }
+    }
+
+    // sends the tasks whose dependencies are all answered
+    pub fn release_dependents(&mut self, transport: &dyn Transport) {
+        loop {
+            let ready = self.dispatcher.blocked.iter().position(|task| {
+                !task
+                    .dependencies
+                    .iter()
+                    .any(|dependency| self.is_pending(dependency))
+            });
+            match ready {
+                Some(index) => {
+                    let mut task = self.dispatcher.blocked.remove(index);
+                    self.fill_results(&mut task);
+                    self.send_in_order(transport, task);
+                }
+                None => break,
+            }
+        }
+    }
+
+    // the task won't be answered, nor the tasks depending on it
+    pub fn fail_dependents(&mut self, transport: &dyn Transport, response_topic: &str) {
+        // and it fails its group
+        self.count_in_group(transport, response_topic, false);
+        // and the task it is a part of
+        self.fail_part(transport, response_topic);
+        let (failed, blocked): (Vec<Task>, Vec<Task>) =
+            self.dispatcher.blocked.drain(..).partition(|task| {
+                task.dependencies
+                    .iter()
+                    .any(|dependency| dependency == response_topic)
+            });
+        self.dispatcher.blocked = blocked;
+
+        failed
+            .into_iter()
+            .for_each(|task| self.fail_dependent(transport, task));
+    }
+
+    fn fail_dependent(&mut self, transport: &dyn Transport, task: Task) {
+        log::warn(&format!(
+            "A dependency of task {} failed, dropping it",
+            task.worker_topic
+        ));
+        self.registry
+            .clients_of(&task.response_topic)
+            .iter()
+            .for_each(|identity| {
+                transport
+                    .send(identity, &["", "@@DEPENDENCY_FAILED", &task.response_topic])
+                    .ok();
+            });
+        self.abandon_response_topic(&task.response_topic);
+        self.fail_dependents(transport, &task.response_topic);
+    }
+}
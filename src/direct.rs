@@ -0,0 +1,53 @@
+use crate::headers::headers_frame;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+
+// large responses can skip the broker: a client binds a ROUTER socket and gives its endpoint (`@@DIRECT`), workers
+// registered with `direct` get it with the task, send the response there, and only tell the broker the task is done
+// (`@@DONE`), so the broker tracks the task without its response going through it
+// the response only goes to the client that sent the task, not to the other clients waiting on its response topic
+
+impl Broker {
+    // an empty endpoint stops the direct responses
+    pub fn set_direct_endpoint(&mut self, identity: &str, endpoint: &str) {
+        if endpoint.is_empty() {
+            self.direct_endpoints.remove(identity);
+        } else {
+            self.direct_endpoints
+                .insert(identity.to_string(), endpoint.to_string());
+        }
+    }
+
+    pub fn direct_endpoint(&self, identity: &str) -> Option<String> {
+        self.direct_endpoints.get(identity).cloned()
+    }
+
+    // the endpoint is only given to workers that can use it, the others get the task as usual
+    // headers come last, after an empty endpoint if there is none
+    pub fn send_to_worker(
+        &self,
+        transport: &dyn Transport,
+        worker_name: &str,
+        task: &Task,
+    ) -> bool {
+        let endpoint = task
+            .direct_endpoint
+            .as_deref()
+            .filter(|_| self.direct_workers.contains(worker_name));
+        // workers that declared their features without `headers` don't expect the frame
+        let headers = match self.supports(worker_name, "headers") {
+            true => self.task_headers(task),
+            false => vec![],
+        };
+        let frame = headers_frame(&headers);
+        match (endpoint, headers.is_empty()) {
+            (Some(endpoint), true) => transport.send(worker_name, &["", &task.payload, endpoint]),
+            (endpoint, false) => transport.send(
+                worker_name,
+                &["", &task.payload, endpoint.unwrap_or(""), &frame],
+            ),
+            (None, true) => transport.send(worker_name, &["", &task.payload]),
+        }
+        .is_ok()
+    }
+}
@@ -0,0 +1,288 @@
+use crate::protocol;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// the broker advertises itself on the LAN with mDNS (DNS-SD), so workers and clients find it without endpoint:
+// a `_tiny-broke._tcp.local` service, its TXT record gives the endpoint to connect to (`endpoint=tcp://<ip>:<port>`)
+// queries from another port than 5353 (one-shot queries, like the clients do) are answered to their sender
+
+const SERVICE: &str = "_tiny-broke._tcp.local";
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const TTL_AS_SECS: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// the record is the only one with this name and type, caches replace theirs
+const CACHE_FLUSH: u16 = 0x8000;
+
+pub fn mdns() -> bool {
+    env::var("MDNS").is_ok_and(|v| v == "true")
+}
+
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|name| name.trim().replace('.', "-"))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "tiny-broke".to_string())
+}
+
+// the address other hosts of the LAN reach us on: the one of the interface routing the multicast group
+fn lan_address() -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((MDNS_GROUP, MDNS_PORT))?;
+    match socket.local_addr()? {
+        SocketAddr::V4(address) => Ok(*address.ip()),
+        SocketAddr::V6(_) => Err(io::Error::other("no IPv4 address")),
+    }
+}
+
+fn push_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+fn push_record(packet: &mut Vec<u8>, name: &str, kind: u16, class: u16, data: &[u8]) {
+    push_name(packet, name);
+    packet.extend_from_slice(&kind.to_be_bytes());
+    packet.extend_from_slice(&class.to_be_bytes());
+    packet.extend_from_slice(&TTL_AS_SECS.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+}
+
+fn read_u16(packet: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *packet.get(position)?,
+        *packet.get(position + 1)?,
+    ]))
+}
+
+// names may point to an earlier name of the packet (compression), the position moves after the name
+fn read_name(packet: &[u8], position: &mut usize) -> Option<String> {
+    let mut labels = vec![];
+    let mut cursor = *position;
+    let mut jumped = false;
+
+    // a bound on the pointers followed, so a malicious packet can't loop
+    for _ in 0..packet.len() {
+        let length = *packet.get(cursor)? as usize;
+        if length == 0 {
+            if !jumped {
+                *position = cursor + 1;
+            }
+            return Some(labels.join("."));
+        }
+
+        if length & 0xc0 == 0xc0 {
+            if !jumped {
+                *position = cursor + 2;
+            }
+            jumped = true;
+            cursor = (read_u16(packet, cursor)? & 0x3fff) as usize;
+            continue;
+        }
+
+        let label = packet.get(cursor + 1..cursor + 1 + length)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        cursor += 1 + length;
+    }
+
+    None
+}
+
+// the message id and the questions (name and type) of a query, none for a response
+fn parse_query(packet: &[u8]) -> Option<(u16, Vec<(String, u16)>)> {
+    let id = read_u16(packet, 0)?;
+    let is_response = read_u16(packet, 2)? & 0x8000 != 0;
+    if is_response {
+        return None;
+    }
+
+    let mut position = 12;
+    let questions = (0..read_u16(packet, 4)?)
+        .map(|_| {
+            let name = read_name(packet, &mut position)?;
+            let kind = read_u16(packet, position)?;
+            position += 4;
+            Some((name, kind))
+        })
+        .collect::<Option<Vec<(String, u16)>>>()?;
+
+    Some((id, questions))
+}
+
+pub struct Advertiser {
+    socket: UdpSocket,
+    instance: String,
+    host: String,
+    address: Ipv4Addr,
+    port: u16,
+}
+
+impl Advertiser {
+    pub fn bind(port: u16) -> io::Result<Advertiser> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, MDNS_PORT))?;
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_nonblocking(true)?;
+        let name = hostname();
+
+        Ok(Advertiser {
+            socket,
+            instance: format!("{}.{}", name, SERVICE),
+            host: format!("{}.local", name),
+            address: lan_address()?,
+            port,
+        })
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("tcp://{}:{}", self.address, self.port)
+    }
+
+    // the whole service in one response: PTR as answer, SRV, TXT and A as additional records
+    // one-shot queries get their id and question back, and records their cache keeps as is
+    fn response(&self, id: u16, question: Option<&(String, u16)>) -> Vec<u8> {
+        let unique = if question.is_some() {
+            CLASS_IN
+        } else {
+            CLASS_IN | CACHE_FLUSH
+        };
+        let mut packet = vec![];
+        packet.extend_from_slice(&id.to_be_bytes());
+        // a response, authoritative
+        packet.extend_from_slice(&0x8400u16.to_be_bytes());
+        packet.extend_from_slice(&(question.is_some() as u16).to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet.extend_from_slice(&0u16.to_be_bytes());
+        packet.extend_from_slice(&3u16.to_be_bytes());
+
+        if let Some((name, kind)) = question {
+            push_name(&mut packet, name);
+            packet.extend_from_slice(&kind.to_be_bytes());
+            packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+
+        let mut instance = vec![];
+        push_name(&mut instance, &self.instance);
+        push_record(&mut packet, SERVICE, TYPE_PTR, CLASS_IN, &instance);
+
+        let mut service = vec![0, 0, 0, 0];
+        service.extend_from_slice(&self.port.to_be_bytes());
+        push_name(&mut service, &self.host);
+        push_record(&mut packet, &self.instance, TYPE_SRV, unique, &service);
+
+        let mut text = vec![];
+        for entry in &[
+            format!("endpoint={}", self.endpoint()),
+            format!("protocol={}", protocol::VERSION),
+        ] {
+            text.push(entry.len() as u8);
+            text.extend_from_slice(entry.as_bytes());
+        }
+        push_record(&mut packet, &self.instance, TYPE_TXT, unique, &text);
+
+        push_record(
+            &mut packet,
+            &self.host,
+            TYPE_A,
+            unique,
+            &self.address.octets(),
+        );
+
+        packet
+    }
+
+    // unsolicited response, for the peers already listening
+    pub fn announce(&self) -> io::Result<()> {
+        self.socket
+            .send_to(&self.response(0, None), (MDNS_GROUP, MDNS_PORT))
+            .map(|_| ())
+    }
+
+    // answers the queries received, without waiting
+    pub fn handle(&self) -> io::Result<()> {
+        let mut buffer = [0; 9000];
+
+        loop {
+            let (length, sender) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(error) => return Err(error),
+            };
+            let (id, questions) = match parse_query(&buffer[..length]) {
+                Some(query) => query,
+                None => continue,
+            };
+
+            let question = questions.into_iter().find(|(name, kind)| {
+                (name.eq_ignore_ascii_case(SERVICE) && (*kind == TYPE_PTR || *kind == TYPE_ANY))
+                    || name.eq_ignore_ascii_case(&self.instance)
+            });
+            let question = match question {
+                Some(question) => question,
+                None => continue,
+            };
+
+            if sender.port() == MDNS_PORT {
+                self.announce()?;
+            } else {
+                self.socket
+                    .send_to(&self.response(id, Some(&question)), sender)?;
+            }
+        }
+    }
+}
+
+impl AsRawFd for Advertiser {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_query, push_name, read_name, SERVICE, TYPE_PTR};
+
+    #[test]
+    fn queries_are_parsed_with_compressed_names() {
+        let mut packet = vec![0, 42, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        push_name(&mut packet, SERVICE);
+        packet.extend_from_slice(&[0, TYPE_PTR as u8, 0, 1]);
+        // the second question points to `_tcp.local` in the first one
+        packet.extend_from_slice(&[3, b'f', b'o', b'o', 0xc0, 12 + 12, 0, 33, 0, 1]);
+
+        let (id, questions) = parse_query(&packet).unwrap();
+        assert_eq!(id, 42);
+        assert_eq!(
+            questions,
+            vec![
+                (SERVICE.to_string(), TYPE_PTR),
+                ("foo._tcp.local".to_string(), 33),
+            ]
+        );
+
+        // responses and truncated packets are not queries
+        packet[2] = 0x84;
+        assert_eq!(parse_query(&packet), None);
+        assert_eq!(parse_query(&packet[..20]), None);
+    }
+
+    #[test]
+    fn pointer_loops_are_refused() {
+        let packet = [0xc0, 0];
+        assert_eq!(read_name(&packet, &mut 0), None);
+    }
+}
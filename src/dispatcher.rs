@@ -0,0 +1,627 @@
+use crate::delivery::Delivery;
+use crate::dlq::Failure;
+use crate::headers::{headers_frame, Headers};
+use crate::intern::intern;
+use crate::log;
+use crate::receipts;
+use crate::transport::Transport;
+use crate::wheel::Wheel;
+use crate::Broker;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// the task queues: sent tasks waiting for their response, tasks waiting for a worker, tasks waiting for the
+// previous task of their partition or for their dependencies, and dead tasks
+// the scheduling (which worker, retries, timeouts) is done by the broker, below
+
+#[derive(Debug, Clone)]
+pub struct Task {
+    // interned, shared by the tasks of the topic and of the worker
+    pub worker_topic: Rc<str>,
+    pub worker_name: Option<Rc<str>>,
+    pub response_topic: String,
+    pub retry: u32,
+    pub payload: String,
+    pub date: SystemTime,
+    pub sent: bool,
+    pub failures: Vec<Failure>,
+    // on ordered topics, tasks with the same key are sent one at a time
+    pub partition_key: Option<String>,
+    // response topics of the tasks to answer before this one is sent
+    pub dependencies: Vec<String>,
+    // where the worker can send the response, instead of the broker
+    pub direct_endpoint: Option<String>,
+    pub headers: Headers,
+    // identity of the client that sent the task
+    pub client: Option<String>,
+    // when the task was submitted
+    pub created: SystemTime,
+    // what the broker couldn't do as asked, see affinity.rs
+    pub history: Vec<String>,
+}
+
+impl Task {
+    pub fn new(worker_topic: &str, response_topic: &str, payload: &str) -> Task {
+        Task {
+            worker_topic: intern(worker_topic),
+            worker_name: None,
+            response_topic: response_topic.to_string(),
+            retry: 0,
+            payload: payload.to_string(),
+            // set when the task is sent
+            date: UNIX_EPOCH,
+            sent: false,
+            failures: vec![],
+            partition_key: None,
+            dependencies: vec![],
+            direct_endpoint: None,
+            headers: vec![],
+            client: None,
+            // set when the task is submitted
+            created: UNIX_EPOCH,
+            history: vec![],
+        }
+    }
+}
+
+// the sent tasks, in a slab (the slot of a task answered is reused by the next one) so they are neither moved nor
+// copied while they wait, with their deadlines in a timing wheel: timed out tasks are found without looking at the
+// others
+// a task keeps the timeout of its topic at the time it was sent
+#[derive(Default)]
+pub struct Sent {
+    slots: Vec<Option<(Task, SystemTime)>>,
+    free: Vec<usize>,
+    deadlines: Wheel,
+    // slots of the tasks, by response topic
+    by_response_topic: HashMap<String, Vec<usize>>,
+    // number of tasks, by worker topic
+    by_worker_topic: HashMap<Rc<str>, usize>,
+}
+
+impl Sent {
+    pub fn insert(&mut self, task: Task, deadline: SystemTime) -> usize {
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.deadlines.insert(id, deadline);
+        self.by_response_topic
+            .entry(task.response_topic.clone())
+            .or_default()
+            .push(id);
+        *self
+            .by_worker_topic
+            .entry(task.worker_topic.clone())
+            .or_default() += 1;
+        self.slots[id] = Some((task, deadline));
+        id
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Task> {
+        let (task, deadline) = self.slots.get_mut(id)?.take()?;
+        self.free.push(id);
+        self.deadlines.remove(id, deadline);
+        if let Some(ids) = self.by_response_topic.get_mut(&task.response_topic) {
+            ids.retain(|other| *other != id);
+            if ids.is_empty() {
+                self.by_response_topic.remove(&task.response_topic);
+            }
+        }
+        if let Some(count) = self.by_worker_topic.get_mut(&task.worker_topic) {
+            *count -= 1;
+            if *count == 0 {
+                self.by_worker_topic.remove(&task.worker_topic);
+            }
+        }
+        Some(task)
+    }
+
+    // the tasks answered on the response topic
+    pub fn complete(&mut self, response_topic: &str) -> Vec<Task> {
+        self.by_response_topic
+            .get(response_topic)
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| self.remove(id))
+            .collect()
+    }
+
+    // the tasks answered on the response topic that were sent to the worker
+    pub fn complete_by(&mut self, response_topic: &str, worker_name: &str) -> Vec<Task> {
+        let ids: Vec<usize> = self
+            .by_response_topic
+            .get(response_topic)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|id| {
+                self.slots[*id]
+                    .as_ref()
+                    .is_some_and(|(task, _)| task.worker_name.as_deref() == Some(worker_name))
+            })
+            .collect();
+        ids.into_iter().filter_map(|id| self.remove(id)).collect()
+    }
+
+    // the tasks whose deadline is passed
+    pub fn take_expired(&mut self, now: SystemTime) -> Vec<Task> {
+        self.deadlines
+            .expire(now)
+            .into_iter()
+            .filter_map(|id| self.remove(id))
+            .collect()
+    }
+
+    // the tasks to be answered on the response topic
+    pub fn sent_on<'a>(&'a self, response_topic: &str) -> impl Iterator<Item = &'a Task> {
+        self.by_response_topic
+            .get(response_topic)
+            .into_iter()
+            .flatten()
+            .filter_map(move |id| self.slots[*id].as_ref().map(|(task, _)| task))
+    }
+
+    // the tasks the worker didn't answer yet
+    pub fn take_sent_to(&mut self, worker_name: &str) -> Vec<Task> {
+        let ids: Vec<usize> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| {
+                slot.as_ref()
+                    .is_some_and(|(task, _)| task.worker_name.as_deref() == Some(worker_name))
+            })
+            .map(|(id, _)| id)
+            .collect();
+        ids.into_iter().filter_map(|id| self.remove(id)).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Task> {
+        self.slots.iter().flatten().map(|(task, _)| task)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn count_on(&self, worker_topic: &str) -> usize {
+        self.by_worker_topic.get(worker_topic).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+pub struct Dispatcher {
+    pub tasks: Sent,
+    pub tasks_to_retry: Vec<Task>,
+    pub dead_letters: Vec<Task>,
+    // by worker topic and partition key, a partition is there as long as one of its tasks is sent or waiting for a
+    // worker, the next ones wait here in arrival order
+    pub partitions: HashMap<(Rc<str>, String), VecDeque<Task>>,
+    pub blocked: Vec<Task>,
+    // failed tasks waiting for the backoff of their topic, with the date they are sent again
+    pub delayed: Vec<(SystemTime, Task)>,
+}
+
+impl Dispatcher {
+    // the sent tasks answered on the response topic
+    pub fn complete(&mut self, response_topic: &str) -> Vec<Task> {
+        self.tasks.complete(response_topic)
+    }
+
+    // only the worker a task was sent to can complete it (`@@DONE`) or ask to retry it (`@@RETRY`)
+    pub fn is_sent_to(&self, response_topic: &str, worker_name: &str) -> bool {
+        self.tasks
+            .sent_on(response_topic)
+            .any(|task| task.worker_name.as_deref() == Some(worker_name))
+    }
+
+    // the tasks not answered yet: sent, waiting for a worker, for their partition, or for their dependencies
+    pub fn pending(&self) -> impl Iterator<Item = &Task> {
+        self.tasks
+            .iter()
+            .chain(self.tasks_to_retry.iter())
+            .chain(self.partitions.values().flatten())
+            .chain(self.blocked.iter())
+            .chain(self.delayed.iter().map(|(_, task)| task))
+    }
+
+    // the task if it can be sent now, otherwise it waits for the previous task of its partition
+    pub fn hold(&mut self, task: Task) -> Option<Task> {
+        let key = match &task.partition_key {
+            Some(partition_key) => (task.worker_topic.clone(), partition_key.clone()),
+            None => return Some(task),
+        };
+
+        match self.partitions.get_mut(&key) {
+            Some(held) => {
+                held.push_back(task);
+                None
+            }
+            None => {
+                self.partitions.insert(key, VecDeque::new());
+                Some(task)
+            }
+        }
+    }
+
+    // the task is done (answered, dropped or quarantined), the next task of its partition can be sent
+    pub fn release(&mut self, task: &Task) -> Option<Task> {
+        let key = (task.worker_topic.clone(), task.partition_key.clone()?);
+        let next = self.partitions.get_mut(&key)?.pop_front();
+        if next.is_none() {
+            self.partitions.remove(&key);
+        }
+        next
+    }
+
+    pub fn take_waiting(&mut self) -> Vec<Task> {
+        self.tasks_to_retry.drain(..).collect()
+    }
+
+    // waiting for a worker, or for their backoff
+    pub fn take_waiting_on(&mut self, worker_topic: &str) -> Vec<Task> {
+        let (mut taken, tasks): (Vec<Task>, Vec<Task>) = self
+            .tasks_to_retry
+            .drain(..)
+            .partition(|task| &*task.worker_topic == worker_topic);
+        self.tasks_to_retry = tasks;
+        let (delayed, others): (Vec<(SystemTime, Task)>, _) = self
+            .delayed
+            .drain(..)
+            .partition(|(_, task)| &*task.worker_topic == worker_topic);
+        self.delayed = others;
+        taken.extend(delayed.into_iter().map(|(_, task)| task));
+        taken
+    }
+
+    pub fn take_blocked_on(&mut self, worker_topic: &str) -> Vec<Task> {
+        let (taken, blocked) = self
+            .blocked
+            .drain(..)
+            .partition(|task| &*task.worker_topic == worker_topic);
+        self.blocked = blocked;
+        taken
+    }
+
+    // the tasks waiting for their partition, the partitions without sent task are closed
+    pub fn take_held_on(&mut self, worker_topic: &str) -> Vec<Task> {
+        let mut taken = vec![];
+        self.partitions
+            .iter_mut()
+            .filter(|((topic, _), _)| &**topic == worker_topic)
+            .for_each(|(_, held)| taken.extend(held.drain(..)));
+
+        let tasks = &self.tasks;
+        self.partitions.retain(|(topic, partition_key), _| {
+            &**topic != worker_topic
+                || tasks.iter().any(|task| {
+                    &task.worker_topic == topic
+                        && task.partition_key.as_ref() == Some(partition_key)
+                })
+        });
+
+        taken
+    }
+}
+
+impl Broker {
+    fn send_task(&mut self, transport: &dyn Transport, task: &mut Task) -> Option<Rc<str>> {
+        task.date = self.now();
+        task.retry = task.retry.saturating_add(1);
+
+        if task.retry > 1 {
+            self.emit(
+                "task.retried",
+                &[
+                    ("topic", &task.worker_topic),
+                    ("responseTopic", &task.response_topic),
+                    ("retry", &task.retry.to_string()),
+                ],
+            );
+        }
+
+        // select a worker, or a peer broker with workers for the topic
+        let avoided = self.avoided_workers(task);
+        task.worker_name = match self.previous_worker(task) {
+            Some(worker_name) => Some(worker_name),
+            None => self.select_worker(task, &avoided),
+        };
+        if task.worker_name.is_none() {
+            task.worker_name = self.forward(task).map(|name| intern(&name));
+            return task.worker_name.clone();
+        }
+        let worker_name = task.worker_name.clone()?;
+
+        // send the task to the worker
+        // if it doesn't works (worker is dead for instance), then we retry
+        // the recursion is done if there is no worker anymore or if the retry is to damn high
+        task.sent = self.send_to_worker(transport, &worker_name, task);
+
+        if task.sent {
+            self.emit(
+                "task.dispatched",
+                &[
+                    ("topic", &task.worker_topic),
+                    ("responseTopic", &task.response_topic),
+                    ("worker", &worker_name),
+                ],
+            );
+        } else {
+            self.record_failure(&worker_name);
+            task.failures
+                .push(Failure::new(&worker_name, "unreachable"));
+            self.remove_worker(&worker_name);
+        }
+
+        Some(worker_name)
+    }
+
+    // tasks of a partition are sent one at a time, in arrival order
+    pub fn send_in_order(&mut self, transport: &dyn Transport, task: Task) {
+        let worker_topic = task.worker_topic.clone();
+        match self.dispatcher.hold(task) {
+            Some(task) => self.send_task_and_retry(transport, task),
+            None => log::info(&format!(
+                "Task {} waiting for the previous task of its partition",
+                worker_topic
+            )),
+        }
+    }
+
+    fn release_partition(&mut self, transport: &dyn Transport, task: &Task) {
+        if let Some(next) = self.dispatcher.release(task) {
+            self.send_task_and_retry(transport, next);
+        }
+    }
+
+    pub fn send_task_and_retry(&mut self, transport: &dyn Transport, mut task: Task) {
+        loop {
+            if self.is_poison(&task) {
+                let response_topic = task.response_topic.clone();
+                self.release_partition(transport, &task);
+                self.quarantine(task);
+                self.fail_dependents(transport, &response_topic);
+                break;
+            }
+
+            // over a ceiling, or paused, the task waits as if there was no worker
+            let at_ceiling = self.at_ceiling(&task.worker_topic);
+            let paused = self.is_paused(&task.worker_topic);
+            let sent_to = if at_ceiling || paused {
+                None
+            } else {
+                self.send_task(transport, &mut task)
+            };
+            match sent_to {
+                Some(_) => {
+                    // the response is only waited for when the task may be sent again
+                    if task.sent && self.delivery(&task.worker_topic) == Delivery::AtMost {
+                        self.release_partition(transport, &task);
+                        break;
+                    }
+                    if task.sent {
+                        let timeout =
+                            Duration::from_secs(self.task_timeout_as_secs(&task.worker_topic));
+                        let deadline = task.date + timeout;
+                        self.dispatcher.tasks.insert(task, deadline);
+                        break;
+                    }
+                    // the worker was unreachable
+                    task = match self.delay_retry(task) {
+                        Some(task) => task,
+                        None => break,
+                    };
+                }
+                None => {
+                    if self.is_queue_full(&task.worker_topic) {
+                        log::warn(&format!(
+                            "Queue of {} is full, dropping task",
+                            task.worker_topic
+                        ));
+                        self.registry
+                            .clients_of(&task.response_topic)
+                            .iter()
+                            .for_each(|identity| {
+                                transport
+                                    .send(identity, &["", "@@QUEUE_FULL", &task.response_topic])
+                                    .ok();
+                            });
+                        self.abandon_response_topic(&task.response_topic);
+                        self.release_partition(transport, &task);
+                        self.fail_dependents(transport, &task.response_topic);
+                        break;
+                    }
+
+                    if paused {
+                        log::info(&format!(
+                            "Topic is paused, storing task {}",
+                            task.worker_topic
+                        ));
+                    } else if at_ceiling {
+                        log::info(&format!(
+                            "Too many tasks in flight, storing task {}",
+                            task.worker_topic
+                        ));
+                    } else {
+                        log::info(&format!(
+                            "Can't find a worker at the moment, storing task {}",
+                            task.worker_topic
+                        ));
+                    }
+                    self.dispatcher.tasks_to_retry.push(task);
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn send_response(&mut self, transport: &dyn Transport, topic_name: &str, payload: &str) {
+        self.complete(transport, topic_name, Some(payload), &[]);
+    }
+
+    // without payload, the response went straight from the worker to the client
+    pub fn complete(
+        &mut self,
+        transport: &dyn Transport,
+        topic_name: &str,
+        payload: Option<&str>,
+        worker_headers: &[(String, String)],
+    ) {
+        // the task is done, even if nobody waits for its response anymore
+        let completed = self.dispatcher.complete(topic_name);
+        completed.iter().for_each(|task| {
+            if let Some(worker_name) = &task.worker_name {
+                let processing_time = self.elapsed(task.date);
+                self.record_processed(worker_name, &task.worker_topic, processing_time);
+            }
+            if let Some(payload) = payload {
+                self.mirror_response(&task.worker_topic, &task.response_topic, payload);
+            }
+            self.emit(
+                "task.completed",
+                &[
+                    ("topic", &task.worker_topic),
+                    ("responseTopic", &task.response_topic),
+                    ("worker", task.worker_name.as_deref().unwrap_or("")),
+                ],
+            );
+        });
+
+        let headers = self.response_headers(completed.first(), worker_headers);
+        let wants_receipt = receipts::wants_receipt(&headers);
+        let headers = headers_frame(&headers);
+        self.registry
+            .clients_of(topic_name)
+            .iter()
+            .for_each(|name| {
+                if let Some(payload) = payload {
+                    let frames: &[&str] = match headers.as_str() {
+                        "{}" => &["", payload],
+                        _ => &["", payload, &headers],
+                    };
+                    self.send_or_keep(transport, name, topic_name, frames);
+                    if wants_receipt {
+                        self.await_receipt(name, topic_name, frames);
+                    }
+                }
+                self.remove_client_from_topic(name, topic_name);
+            });
+        self.registry.remove_if_unused(topic_name);
+        if let Some(payload) = payload {
+            self.return_forwarded(topic_name, payload);
+            if !completed.is_empty() {
+                let now = self.now();
+                self.results.insert(topic_name, payload, now);
+                self.record_workflow_result(topic_name, payload);
+            }
+            self.end_waits(transport, topic_name, payload);
+        }
+        // after the response, at most once tasks are already gone from the dispatcher
+        self.count_in_group(transport, topic_name, true);
+        if let Some(payload) = payload {
+            self.merge_part(transport, topic_name, payload);
+        }
+
+        completed
+            .iter()
+            .for_each(|task| self.release_partition(transport, task));
+        self.release_dependents(transport);
+        self.check_workflows(transport);
+    }
+
+    pub fn retry_tasks(&mut self, transport: &dyn Transport) {
+        self.send_waiting(transport, |_, _| true);
+    }
+
+    // drops the tasks waiting for a worker (or for their partition, or their dependencies) on the topic, their
+    // clients stop waiting for them, and the tasks depending on them fail
+    pub fn drain(&mut self, transport: &dyn Transport, topic_name: &str) -> usize {
+        let mut drained = self.dispatcher.take_waiting_on(topic_name);
+        drained.extend(self.dispatcher.take_held_on(topic_name));
+        drained.extend(self.dispatcher.take_blocked_on(topic_name));
+
+        drained.iter().for_each(|task| {
+            self.abandon_response_topic(&task.response_topic);
+            self.fail_dependents(transport, &task.response_topic);
+        });
+
+        drained.len()
+    }
+
+    // the worker may be dead or the task may be what kills it, so the task is sent to an other worker
+    // until it fails too many times
+    pub fn retry_timeout_tasks(&mut self, transport: &dyn Transport) {
+        let now = self.now();
+        for mut task in self.dispatcher.tasks.take_expired(now) {
+            if let Some(worker_name) = task.worker_name.clone() {
+                self.record_failure(&worker_name);
+                task.failures.push(Failure::new(&worker_name, "timeout"));
+            }
+            if let Some(task) = self.delay_retry(task) {
+                self.send_task_and_retry(transport, task);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dispatcher, Sent, Task};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn task(response_topic: &str, partition_key: Option<&str>) -> Task {
+        let mut task = Task::new("ORDERS", response_topic, "");
+        task.partition_key = partition_key.map(String::from);
+        task
+    }
+
+    #[test]
+    fn tasks_of_a_partition_are_held_until_the_previous_one_is_done() {
+        let mut dispatcher = Dispatcher::default();
+        let first = dispatcher.hold(task("R1", Some("a"))).unwrap();
+        assert!(dispatcher.hold(task("R2", Some("a"))).is_none());
+        assert!(dispatcher.hold(task("R3", Some("a"))).is_none());
+        assert!(dispatcher.hold(task("R4", Some("b"))).is_some());
+        assert!(dispatcher.hold(task("R5", None)).is_some());
+        assert_eq!(dispatcher.pending().count(), 2);
+
+        let second = dispatcher.release(&first).unwrap();
+        assert_eq!(second.response_topic, "R2");
+        assert_eq!(dispatcher.release(&second).unwrap().response_topic, "R3");
+
+        // the partition is closed once its last task is done
+        assert!(dispatcher.release(&task("R3", Some("a"))).is_none());
+        assert!(dispatcher.hold(task("R6", Some("a"))).is_some());
+    }
+
+    #[test]
+    fn sent_tasks_expire_in_deadline_order() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut sent = Sent::default();
+        sent.insert(task("R1", None), at(30));
+        sent.insert(task("R2", None), at(10));
+        sent.insert(task("R3", None), at(20));
+        sent.insert(task("R1", None), at(40));
+
+        let expired: Vec<String> = sent
+            .take_expired(at(20))
+            .into_iter()
+            .map(|task| task.response_topic)
+            .collect();
+        assert_eq!(expired, vec!["R2", "R3"]);
+        assert_eq!(sent.len(), 2);
+
+        // slots are reused
+        sent.insert(task("R4", None), at(50));
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent.complete("R1").len(), 2);
+        assert!(sent.take_expired(at(45)).is_empty());
+        assert_eq!(sent.iter().next().unwrap().response_topic, "R4");
+    }
+}
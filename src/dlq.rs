@@ -0,0 +1,186 @@
+use crate::log;
+use crate::retry::MAX_RETRIES;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub worker_name: String,
+    pub reason: String,
+}
+
+impl Failure {
+    pub fn new(worker_name: &str, reason: &str) -> Failure {
+        Failure {
+            worker_name: worker_name.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.worker_name, self.reason)
+    }
+}
+
+// a task failing on this many workers is a poison message, it is moved to the dead letter queue
+pub fn poison_threshold() -> usize {
+    env::var("POISON_THRESHOLD")
+        .map(|v| v.parse::<usize>().unwrap_or(3))
+        .unwrap_or(3)
+}
+
+// the oldest dead task is dropped once the queue is full
+pub fn dead_letters_capacity() -> usize {
+    env::var("DLQ_SIZE")
+        .map(|v| v.parse::<usize>().unwrap_or(10_000))
+        .unwrap_or(10_000)
+}
+
+fn failed_workers(task: &Task) -> usize {
+    task.failures
+        .iter()
+        .map(|failure| failure.worker_name.as_str())
+        .collect::<HashSet<&str>>()
+        .len()
+}
+
+impl Broker {
+    // the task failed on too many workers, too many times on its topic (`retries=`), or for a reason its topic
+    // doesn't retry (see retry.rs)
+    // a task failing on the same worker is the one of a broken worker, it only is poison once it failed as much as
+    // `retries=` allows
+    pub fn is_poison(&self, task: &Task) -> bool {
+        let policy = self.retry_policy(&task.worker_topic);
+        let retried = task
+            .failures
+            .last()
+            .is_none_or(|failure| policy.retries_on(&failure.reason));
+        let failed = match policy.retries {
+            Some(retries) => task.failures.len() >= retries,
+            None => {
+                failed_workers(task) >= self.poison_threshold || task.failures.len() >= MAX_RETRIES
+            }
+        };
+        failed || !retried
+    }
+
+    // nobody will answer the task anymore, its clients stop waiting for it
+    pub fn quarantine(&mut self, task: Task) {
+        log::warn(&format!(
+            "Task {} failed {} times on {} workers, moving it to the dead letter queue",
+            task.worker_topic,
+            task.failures.len(),
+            failed_workers(&task)
+        ));
+
+        let workers: Vec<&str> = task
+            .failures
+            .iter()
+            .map(|failure| failure.worker_name.as_str())
+            .collect();
+        self.emit(
+            "task.quarantined",
+            &[
+                ("topic", &task.worker_topic),
+                ("responseTopic", &task.response_topic),
+                ("workers", &workers.join(",")),
+            ],
+        );
+
+        self.abandon_response_topic(&task.response_topic);
+        self.dispatcher.dead_letters.push(task);
+        if self.dispatcher.dead_letters.len() > self.dead_letters_capacity {
+            let dropped = self.dispatcher.dead_letters.remove(0);
+            log::warn(&format!(
+                "The dead letter queue is full, dropping the dead task {} of {}",
+                dropped.response_topic, dropped.worker_topic
+            ));
+            self.emit(
+                "task.dropped",
+                &[
+                    ("topic", &dropped.worker_topic),
+                    ("responseTopic", &dropped.response_topic),
+                ],
+            );
+        }
+    }
+
+    // the dead tasks (of the topic) are sent again from scratch, nobody waits for their responses anymore (they can
+    // be kept with `RESULTS_TTL`, or mirrored)
+    pub fn replay_dead_letters(&mut self, transport: &dyn Transport, topic: Option<&str>) -> usize {
+        let (replayed, kept): (Vec<Task>, Vec<Task>) = self
+            .dispatcher
+            .dead_letters
+            .drain(..)
+            .partition(|task| topic.is_none_or(|topic| &*task.worker_topic == topic));
+        self.dispatcher.dead_letters = kept;
+
+        let count = replayed.len();
+        for mut task in replayed {
+            log::info(&format!(
+                "Replaying the dead task {} of {}",
+                task.response_topic, task.worker_topic
+            ));
+            self.emit(
+                "task.replayed",
+                &[
+                    ("topic", &task.worker_topic),
+                    ("responseTopic", &task.response_topic),
+                ],
+            );
+            task.failures.clear();
+            task.retry = 0;
+            task.sent = false;
+            task.worker_name = None;
+            // its dependencies were answered, or it wouldn't have been sent
+            task.dependencies.clear();
+            task.history
+                .push("replayed from the dead letter queue".to_string());
+            self.send_in_order(transport, task);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Failure;
+    use crate::embedded::BrokerHandle;
+    use crate::Task;
+
+    #[test]
+    fn tasks_failing_on_several_workers_are_poison() {
+        let broker = BrokerHandle::new();
+        let mut task = Task::new("ADD", "ADD>1", "1+1");
+        task.failures = vec![Failure::new("worker-1", "timeout"); 5];
+        assert!(!broker.broker.is_poison(&task));
+
+        task.failures.push(Failure::new("worker-2", "timeout"));
+        task.failures.push(Failure::new("worker-3", "unreachable"));
+        assert!(broker.broker.is_poison(&task));
+    }
+
+    #[test]
+    fn the_dead_letter_queue_is_bounded() {
+        let mut broker = BrokerHandle::new();
+        broker.broker.dead_letters_capacity = 2;
+        for response_topic in ["ADD>1", "ADD>2", "ADD>3"] {
+            broker
+                .broker
+                .quarantine(Task::new("ADD", response_topic, "1+1"));
+        }
+        let kept: Vec<&str> = broker
+            .broker
+            .dispatcher
+            .dead_letters
+            .iter()
+            .map(|task| task.response_topic.as_str())
+            .collect();
+        assert_eq!(kept, vec!["ADD>2", "ADD>3"]);
+    }
+}
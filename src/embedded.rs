@@ -0,0 +1,183 @@
+use crate::admin;
+use crate::clock::VirtualClock;
+use crate::envelope::Envelopes;
+use crate::transport::{Memory, Transport};
+use crate::Broker;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// a broker running in the process, for the tests of applications using tiny-broke: peers are identities sending
+// and receiving frames without sockets, and the time only moves when it is told to
+// it is configured by the environment variables, like the broker, and is what simulations run on
+//
+// ```
+// let mut broker = tiny_broke::embedded::BrokerHandle::new();
+// broker.register_worker("worker-1", "ADD");
+// broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+// assert_eq!(broker.receive("worker-1"), Some(vec!["".to_string(), "1+1".to_string()]));
+// broker.respond("worker-1", "ADD>1", "2");
+// assert_eq!(broker.receive("client-1"), Some(vec!["".to_string(), "2".to_string()]));
+// ```
+
+pub struct BrokerHandle {
+    pub(crate) transport: Envelopes<Memory>,
+    clock: Rc<VirtualClock>,
+    pub(crate) broker: Broker,
+}
+
+impl Default for BrokerHandle {
+    fn default() -> BrokerHandle {
+        BrokerHandle::new()
+    }
+}
+
+impl BrokerHandle {
+    // panics when the broker can't start (a storage key it can't read), see `try_new`
+    pub fn new() -> BrokerHandle {
+        BrokerHandle::try_new().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_new() -> Result<BrokerHandle, String> {
+        // a fixed date, so runs are the same
+        let clock = Rc::new(VirtualClock::new(
+            UNIX_EPOCH + Duration::from_secs(1_500_000_000),
+        ));
+        Ok(BrokerHandle {
+            transport: Envelopes::new(Memory::default()),
+            broker: Broker::new(clock.clone())?,
+            clock,
+        })
+    }
+
+    // the peer sends a message (its frames after the identity), the broker handles it
+    pub fn send(&mut self, identity: &str, frames: &[&str]) -> Result<(), String> {
+        if frames.is_empty() || frames.len() > 6 {
+            return Err("a message has 1 to 6 frames".to_string());
+        }
+        let frames: Vec<String> = frames.iter().map(|frame| frame.to_string()).collect();
+        self.transport.push(identity, &frames);
+        if !self.transport.poll(Duration::from_secs(0))? {
+            return Err("the broker didn't receive the message".to_string());
+        }
+
+        let message = self.transport.recv()?;
+        self.broker.handle_message(&self.transport, &message);
+        self.broker.tick(&self.transport);
+
+        Ok(())
+    }
+
+    pub fn register_worker(&mut self, worker: &str, topic: &str) {
+        self.send(worker, &["@@REGISTER", topic]).ok();
+    }
+
+    pub fn send_task(&mut self, client: &str, topic: &str, response_topic: &str, payload: &str) {
+        self.send(client, &[topic, response_topic, payload]).ok();
+    }
+
+    pub fn respond(&mut self, worker: &str, response_topic: &str, payload: &str) {
+        self.send(worker, &[response_topic, "", payload]).ok();
+    }
+
+    // the virtual time moves forward, then the time based rules (timeouts, alerts, ...) run
+    pub fn advance(&mut self, duration: Duration) {
+        self.clock.advance(duration);
+        self.broker.tick(&self.transport);
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.broker.now()
+    }
+
+    // the next message the peer received, its frames after the identity
+    pub fn receive(&mut self, identity: &str) -> Option<Vec<String>> {
+        self.transport.take(identity)
+    }
+
+    // the peer goes away, messages sent to it fail
+    pub fn disconnect(&mut self, identity: &str) {
+        self.transport.disconnect(identity);
+    }
+
+    // the response of an admin command, see the Administration section of the README
+    pub fn admin(&mut self, request: &str) -> String {
+        admin::handle(&mut self.broker, &self.transport, "embedded", request, None)
+    }
+
+    // `signature` is `<timestamp> <signature>`, see signature.rs
+    pub fn signed_admin(&mut self, request: &str, signature: &str) -> String {
+        admin::handle(
+            &mut self.broker,
+            &self.transport,
+            "embedded",
+            request,
+            Some(signature),
+        )
+    }
+
+    pub fn workers(&self, topic: &str) -> Vec<String> {
+        self.broker
+            .registry
+            .topics
+            .get(topic)
+            .map(|topic| topic.workers.clone())
+            .unwrap_or_default()
+    }
+
+    // response topics of the tasks not answered yet: sent, or waiting
+    pub fn pending_tasks(&self) -> Vec<String> {
+        self.broker
+            .dispatcher
+            .pending()
+            .map(|task| task.response_topic.clone())
+            .collect()
+    }
+
+    pub fn dead_letters(&self) -> Vec<String> {
+        self.broker
+            .dispatcher
+            .dead_letters
+            .iter()
+            .map(|task| task.response_topic.clone())
+            .collect()
+    }
+
+    pub fn stop(&mut self, timeout: Duration) {
+        self.broker.stop(timeout);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.broker.is_stopped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BrokerHandle;
+    use std::time::Duration;
+
+    #[test]
+    fn tasks_go_through_the_embedded_broker() {
+        let mut broker = BrokerHandle::new();
+        broker.register_worker("worker-1", "ADD");
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        assert_eq!(broker.workers("ADD"), vec!["worker-1".to_string()]);
+        assert_eq!(broker.pending_tasks(), vec!["ADD>1".to_string()]);
+        assert_eq!(
+            broker.receive("worker-1"),
+            Some(vec!["".to_string(), "1+1".to_string()])
+        );
+
+        broker.respond("worker-1", "ADD>1", "2");
+        assert_eq!(
+            broker.receive("client-1"),
+            Some(vec!["".to_string(), "2".to_string()])
+        );
+        assert!(broker.pending_tasks().is_empty());
+        assert!(broker.admin("STATS").starts_with("OK"));
+
+        let before = broker.now();
+        broker.advance(Duration::from_secs(5));
+        assert_eq!(broker.now(), before + Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::signature::{hex, hmac_sha256};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::process::Command;
+
+// payloads written to disk (exported tasks, `RESULTS_DIR`) are encrypted when a storage key is given:
+// - `STORAGE_KEY`: the key itself
+// - `STORAGE_KEY_FILE`: a file holding the key (a mounted secret)
+// - `STORAGE_KEY_COMMAND`: a command printing the key (a KMS client), run once on start
+// an encrypted payload is `@@ENC <hex>`: a random nonce, then the payload encrypted with ChaCha20-Poly1305 (RFC 8439)
+// and its tag, with a key derived from the storage key, so a payload changed on disk is refused rather than read
+// what the payload is (the kind of record, its topics) is authenticated with it, so a payload moved to another record
+// is refused too
+// with a key, payloads written without key (or before their record was authenticated) are refused, unless
+// `STORAGE_KEY_MIGRATION=true`, to import existing files once: otherwise anyone writing to the disk could put their
+// payloads there
+
+const PREFIX: &str = "@@ENC ";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn nonce() -> Result<[u8; NONCE_LEN], String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut nonce))
+        .map_err(|error| format!("can't get a nonce: {}", error))?;
+    Ok(nonce)
+}
+
+#[derive(Clone)]
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+    // payloads in plaintext or without their record authenticated are read
+    migration: bool,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8]) -> Cipher {
+        let key = hmac_sha256(key, b"tiny-broke encryption");
+        Cipher {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            migration: false,
+        }
+    }
+
+    // the broker doesn't start with a key it can't read, rather than writing payloads in plaintext
+    pub fn from_env() -> Result<Option<Cipher>, String> {
+        let key = if let Ok(key) = env::var("STORAGE_KEY") {
+            key
+        } else if let Ok(path) = env::var("STORAGE_KEY_FILE") {
+            fs::read_to_string(&path).map_err(|error| format!("can't read {}: {}", path, error))?
+        } else if let Ok(command) = env::var("STORAGE_KEY_COMMAND") {
+            let output = Command::new("sh")
+                .args(["-c", &command])
+                .output()
+                .map_err(|error| format!("can't run {}: {}", command, error))?;
+            if !output.status.success() {
+                return Err(format!("{} failed with {}", command, output.status));
+            }
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        } else {
+            return Ok(None);
+        };
+
+        match key.trim() {
+            "" => Err("the storage key is empty".to_string()),
+            key => Ok(Some(Cipher {
+                migration: env::var("STORAGE_KEY_MIGRATION").is_ok_and(|v| v == "true"),
+                ..Cipher::new(key.as_bytes())
+            })),
+        }
+    }
+
+    // `record` says what the payload is, it has to be the same to decrypt it
+    pub fn encrypt(&self, record: &str, payload: &str) -> Result<String, String> {
+        let nonce = nonce()?;
+        let payload = Payload {
+            msg: payload.as_bytes(),
+            aad: record.as_bytes(),
+        };
+        let encrypted = self
+            .aead
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| "the payload can't be encrypted".to_string())?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(encrypted);
+        Ok(format!("{}{}", PREFIX, hex(&sealed)))
+    }
+
+    pub fn decrypt(&self, record: &str, value: &str) -> Result<String, String> {
+        let sealed = match value.strip_prefix(PREFIX) {
+            Some(sealed) => unhex(sealed).ok_or("the payload is not hexadecimal")?,
+            None if self.migration => return Ok(value.to_string()),
+            None => return Err("the payload is not encrypted".to_string()),
+        };
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err("the payload is truncated".to_string());
+        }
+
+        let (nonce, encrypted) = sealed.split_at(NONCE_LEN);
+        let decrypt = |aad: &[u8]| {
+            self.aead.decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: encrypted,
+                    aad,
+                },
+            )
+        };
+        let decrypted = decrypt(record.as_bytes())
+            .or_else(|error| match self.migration {
+                true => decrypt(b""),
+                false => Err(error),
+            })
+            .map_err(|_| "the payload was changed, or encrypted with another key".to_string())?;
+        String::from_utf8(decrypted).map_err(|_| "the payload is not UTF-8".to_string())
+    }
+}
+
+// payloads are written encrypted when there is a key
+pub fn seal(cipher: Option<&Cipher>, record: &str, payload: &str) -> Result<String, String> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(record, payload),
+        None => Ok(payload.to_string()),
+    }
+}
+
+pub fn open(cipher: Option<&Cipher>, record: &str, value: &str) -> Result<String, String> {
+    match cipher {
+        Some(cipher) => cipher.decrypt(record, value),
+        None if value.starts_with(PREFIX) => {
+            Err("the payload is encrypted, a storage key is needed".to_string())
+        }
+        None => Ok(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open, seal, Cipher};
+
+    #[test]
+    fn payloads_are_sealed_and_opened() {
+        let cipher = Cipher::new(b"secret");
+        let sealed = seal(Some(&cipher), "task ADD", "1+1").unwrap();
+        assert!(sealed.starts_with("@@ENC "));
+        assert_ne!(sealed, seal(Some(&cipher), "task ADD", "1+1").unwrap());
+        assert_eq!(open(Some(&cipher), "task ADD", &sealed).unwrap(), "1+1");
+
+        assert!(open(Some(&Cipher::new(b"other")), "task ADD", &sealed).is_err());
+        assert!(open(None, "task ADD", &sealed).is_err());
+        let mut tampered = sealed.clone().into_bytes();
+        tampered[10] = if tampered[10] == b'0' { b'1' } else { b'0' };
+        assert!(open(
+            Some(&cipher),
+            "task ADD",
+            &String::from_utf8(tampered).unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn payloads_are_bound_to_their_record() {
+        let cipher = Cipher::new(b"secret");
+        let sealed = seal(Some(&cipher), "task ADD", "1+1").unwrap();
+        assert!(open(Some(&cipher), "dead ADD", &sealed).is_err());
+        assert!(open(Some(&cipher), "task SUB", &sealed).is_err());
+
+        // plaintext and payloads sealed without their record are only read to migrate
+        let legacy = seal(Some(&cipher), "", "1+1").unwrap();
+        assert!(open(Some(&cipher), "task ADD", "1+1").is_err());
+        assert!(open(Some(&cipher), "task ADD", &legacy).is_err());
+        let migration = Cipher {
+            migration: true,
+            ..cipher
+        };
+        assert_eq!(open(Some(&migration), "task ADD", "1+1").unwrap(), "1+1");
+        assert_eq!(open(Some(&migration), "task ADD", &legacy).unwrap(), "1+1");
+        assert!(open(Some(&migration), "dead ADD", &sealed).is_err());
+    }
+}
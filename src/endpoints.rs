@@ -0,0 +1,116 @@
+use crate::log;
+use crate::Broker;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// with port 0 the OS picks a free port, the sockets tell which one they got: the endpoints are logged once bound,
+// given by the `ENDPOINTS` admin command, and written to `ENDPOINT_FILE`, one `<socket>=<endpoint>` line per socket,
+// for test harnesses and orchestrators
+
+pub fn bound_endpoint(socket: &zmq::Socket) -> String {
+    socket
+        .get_last_endpoint()
+        .ok()
+        .and_then(|endpoint| endpoint.ok())
+        .unwrap_or_default()
+}
+
+pub fn port_of(endpoint: &str) -> Option<u16> {
+    endpoint.rsplit(':').next()?.parse().ok()
+}
+
+pub fn line(endpoints: &[(String, String)]) -> String {
+    endpoints
+        .iter()
+        .map(|(socket, endpoint)| format!("{}={}", socket, endpoint))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct EndpointFile {
+    path: Option<PathBuf>,
+}
+
+impl EndpointFile {
+    pub fn from_env() -> EndpointFile {
+        EndpointFile {
+            path: env::var("ENDPOINT_FILE")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        }
+    }
+
+    // written at once (renamed), a harness polling the file never reads half of it
+    pub fn write(&self, endpoints: &[(String, String)]) {
+        if let Some(path) = &self.path {
+            let content: String = endpoints
+                .iter()
+                .map(|(socket, endpoint)| format!("{}={}\n", socket, endpoint))
+                .collect();
+            let temporary = path.with_extension("tmp");
+            if let Err(error) =
+                fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path))
+            {
+                log::warn(&format!(
+                    "Can't write the endpoint file {}: {}",
+                    path.display(),
+                    error
+                ));
+            }
+        }
+    }
+
+    pub fn remove(&self) {
+        if let Some(path) = &self.path {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+impl Broker {
+    pub fn set_endpoints(&mut self, endpoints: Vec<(String, String)>) {
+        log::info(&format!("Listening on {}", line(&endpoints)));
+        self.endpoints = endpoints;
+    }
+
+    pub fn endpoints_line(&self) -> String {
+        line(&self.endpoints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bound_endpoint, port_of, EndpointFile};
+
+    #[test]
+    fn ephemeral_ports_are_reported() {
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::ROUTER).unwrap();
+        socket.bind("tcp://127.0.0.1:0").unwrap();
+        let endpoint = bound_endpoint(&socket);
+        assert!(endpoint.starts_with("tcp://127.0.0.1:"));
+        assert_ne!(port_of(&endpoint), Some(0));
+        assert!(port_of(&endpoint).is_some());
+        assert_eq!(port_of("tcp://[::]:3000"), Some(3000));
+    }
+
+    #[test]
+    fn endpoint_files_have_a_line_per_socket() {
+        let path = std::env::temp_dir().join("tiny-broke-endpoints-test");
+        let file = EndpointFile {
+            path: Some(path.clone()),
+        };
+        file.write(&[
+            ("broker".to_string(), "tcp://0.0.0.0:41000".to_string()),
+            ("admin".to_string(), "tcp://0.0.0.0:41001".to_string()),
+        ]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "broker=tcp://0.0.0.0:41000\nadmin=tcp://0.0.0.0:41001\n"
+        );
+        file.remove();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::transport::{Control, Incoming, Transport, Unreachable};
+use broker_protocol::envelope::{self, Envelope, ENVELOPE};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::env;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+// protocol 2 wraps a message in an envelope: `@@ENVELOPE` followed by a single JSON frame, with a SemVer version
+// `{"version": "2.0", "topic": ..., "response_topic": ..., "payload": ..., "partition_key": ..., "dependencies": ...,
+// "headers": {...}}` (missing fields are empty), the frames of protocol 1 are the other messages
+// the envelopes are read and written by the `broker-protocol` crate (protocol/), which embedded workers use too
+// both are spoken at once, the protocol of a peer is the one of its last message: a peer sending envelopes gets
+// envelopes back (`{"version": "2.0", "frames": [...]}`), so a fleet migrates one peer at a time
+// `PROTOCOL_VERSIONS` gives the major versions taken (`1,2` by default), `2` once the migration is done: messages
+// of another version are refused with `@@ERROR "" unsupported_protocol <detail>`, in frames
+
+fn protocol_versions() -> Vec<u64> {
+    let versions: Vec<u64> = env::var("PROTOCOL_VERSIONS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|version| version.trim().parse().ok())
+        .collect();
+    match versions.is_empty() {
+        true => vec![1, 2],
+        false => versions,
+    }
+}
+
+// the transport of the broker, speaking protocol 1 or 2 with each peer
+pub struct Envelopes<T> {
+    transport: T,
+    versions: Vec<u64>,
+    // the peers speaking protocol 2
+    peers: RefCell<HashSet<String>>,
+}
+
+impl<T: Transport> Envelopes<T> {
+    pub fn new(transport: T) -> Envelopes<T> {
+        Envelopes {
+            transport,
+            versions: protocol_versions(),
+            peers: RefCell::new(HashSet::new()),
+        }
+    }
+
+    fn check(&self, version: u64) -> Result<(), String> {
+        match self.versions.contains(&version) {
+            true => Ok(()),
+            false => Err(format!(
+                "protocol {} is not supported, this broker speaks {}",
+                version,
+                self.versions
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )),
+        }
+    }
+
+    fn read(&self, message: &Incoming) -> Result<Incoming, String> {
+        let (version, envelope) = Envelope::decode(&message.response_topic)?;
+        self.check(version)?;
+
+        Ok(Incoming {
+            identity: message.identity.clone(),
+            control: Control::parse(envelope.topic.as_bytes()),
+            topic: envelope.topic,
+            response_topic: envelope.response_topic,
+            payload: envelope.payload,
+            partition_key: envelope.partition_key,
+            dependencies: envelope.dependencies,
+            headers: envelope.headers,
+            uid: message.uid,
+            address: message.address.clone(),
+            frame_count: message.frame_count,
+            malformed: None,
+        })
+    }
+
+    // the message in protocol 1, malformed when its protocol isn't taken
+    fn open(&self, message: Incoming) -> Incoming {
+        if message.malformed.is_some() {
+            return message;
+        }
+        let (identity, frame_count) = (message.identity.clone(), message.frame_count);
+        let envelope = message.topic == ENVELOPE;
+        let opened = match envelope {
+            true => self.read(&message),
+            false => self.check(1).map(|_| message),
+        };
+        match opened {
+            Ok(opened) => {
+                let mut peers = self.peers.borrow_mut();
+                match envelope {
+                    true => peers.insert(identity),
+                    false => peers.remove(&identity),
+                };
+                opened
+            }
+            // counted and logged with the stats of the peer
+            Err(error) => {
+                self.peers.borrow_mut().remove(&identity);
+                self.transport
+                    .send(
+                        &identity,
+                        &["", "@@ERROR", "", "unsupported_protocol", &error],
+                    )
+                    .ok();
+                Incoming {
+                    identity,
+                    frame_count,
+                    malformed: Some(error),
+                    ..Incoming::default()
+                }
+            }
+        }
+    }
+}
+
+impl<T: Transport> Transport for Envelopes<T> {
+    fn send(&self, identity: &str, frames: &[&str]) -> Result<(), Unreachable> {
+        match self.peers.borrow().contains(identity) {
+            true => self
+                .transport
+                .send(identity, &[ENVELOPE, &envelope::wrap(frames)]),
+            false => self.transport.send(identity, frames),
+        }
+    }
+
+    fn poll(&self, timeout: Duration) -> Result<bool, String> {
+        self.transport.poll(timeout)
+    }
+
+    fn recv(&self) -> Result<Incoming, String> {
+        self.transport.recv().map(|message| self.open(message))
+    }
+}
+
+// the transport specific methods (binding, flushing, ...)
+impl<T> Deref for Envelopes<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T> DerefMut for Envelopes<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Envelopes;
+    use crate::transport::{Control, Memory, Transport};
+    use std::time::Duration;
+
+    fn frames(frames: &[&str]) -> Vec<String> {
+        frames.iter().map(|frame| frame.to_string()).collect()
+    }
+
+    #[test]
+    fn peers_are_answered_in_their_protocol() {
+        let envelopes = Envelopes::new(Memory::default());
+        let envelope =
+            r#"{"version":"2.1","topic":"@@REGISTER","response_topic":"ADD","headers":{"a":"b"}}"#;
+        envelopes.push("worker-2", &frames(&["@@ENVELOPE", envelope]));
+        envelopes.push("worker-1", &frames(&["@@REGISTER", "ADD"]));
+        assert!(envelopes.poll(Duration::from_secs(0)).unwrap());
+
+        let message = envelopes.recv().unwrap();
+        assert_eq!(message.control, Some(Control::Register));
+        assert_eq!(message.response_topic, "ADD");
+        assert_eq!(message.headers, r#"{"a":"b"}"#);
+        envelopes.recv().unwrap();
+
+        envelopes.send("worker-1", &["", "1+1"]).unwrap();
+        envelopes.send("worker-2", &["", "1+1"]).unwrap();
+        assert_eq!(envelopes.take("worker-1").unwrap(), vec!["", "1+1"]);
+        assert_eq!(
+            envelopes.take("worker-2").unwrap(),
+            vec!["@@ENVELOPE", r#"{"version":"2.0","frames":["","1+1"]}"#]
+        );
+    }
+
+    #[test]
+    fn unknown_major_versions_are_refused() {
+        let envelopes = Envelopes::new(Memory::default());
+        envelopes.push(
+            "worker-3",
+            &frames(&["@@ENVELOPE", r#"{"version":"3.0","topic":"ADD"}"#]),
+        );
+        let message = envelopes.recv().unwrap();
+        assert!(message.malformed.is_some());
+        assert_eq!(
+            envelopes.take("worker-3").unwrap()[3],
+            "unsupported_protocol"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::dlq::Failure;
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+
+// a worker restarting quickly registers again with the same identity (or logical name, see names.rs) before the
+// broker notices it left: the registrations give an epoch (`epoch=<number>` option, the start date of the worker in
+// the SDKs), which only grows
+// - a registration with a greater epoch supersedes the previous one: the tasks sent to the previous process will
+//   never be answered, they are sent again at once (failure `restart`) instead of waiting for their timeout, and the
+//   previous identity of the name is removed
+// - a registration with a smaller epoch comes from the previous process, it is ignored
+// - a response to a task sent again this way, from the worker that restarted, is ignored: the previous process
+//   answered it late, and the task is running elsewhere (a response can't tell its epoch, so the task sent again to
+//   the same worker is answered by the first response)
+
+pub fn parse_epoch(options: &str) -> Option<u64> {
+    options
+        .split(' ')
+        .find_map(|option| option.strip_prefix("epoch="))
+        .and_then(|epoch| epoch.parse().ok())
+}
+
+impl Broker {
+    // false when the registration comes from a previous process of the worker
+    pub fn register_epoch(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        options: &str,
+    ) -> bool {
+        // the epoch is the one of the logical name
+        self.set_logical_name(identity, options);
+        let epoch = match parse_epoch(options) {
+            Some(epoch) => epoch,
+            None => return true,
+        };
+        let worker_name = self.logical_name(identity).to_string();
+        let previous = match self.worker_epochs.insert(worker_name.clone(), epoch) {
+            Some(previous) => previous,
+            None => return true,
+        };
+        if epoch < previous {
+            log::warn(&format!(
+                "Ignoring a registration of worker {} from epoch {}, it is at epoch {}",
+                worker_name, epoch, previous
+            ));
+            self.worker_epochs.insert(worker_name, previous);
+            return false;
+        }
+        if epoch == previous {
+            return true;
+        }
+
+        let mut identities = self.previous_identities(identity);
+        identities.push(identity.to_string());
+        for previous_identity in identities {
+            let tasks = self.dispatcher.tasks.take_sent_to(&previous_identity);
+            log::info(&format!(
+                "Worker {} restarted (epoch {}), sending the {} tasks of {} again",
+                worker_name,
+                epoch,
+                tasks.len(),
+                previous_identity
+            ));
+            if previous_identity != identity {
+                self.remove_worker(&previous_identity);
+            }
+            for mut task in tasks {
+                task.failures
+                    .push(Failure::new(&previous_identity, "restart"));
+                if let Some(task) = self.delay_retry(task) {
+                    self.send_task_and_retry(transport, task);
+                }
+            }
+        }
+        true
+    }
+
+    // the response comes from the process the worker replaced
+    pub fn is_stale_response(&self, identity: &str, response_topic: &str) -> bool {
+        let mut tasks = self.dispatcher.tasks.sent_on(response_topic).peekable();
+        tasks.peek().is_some()
+            && tasks.all(|task| {
+                task.worker_name.as_deref() != Some(identity)
+                    && task.failures.iter().any(|failure| {
+                        failure.reason == "restart" && failure.worker_name == identity
+                    })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_epoch;
+
+    #[test]
+    fn epochs_are_read_from_the_options() {
+        assert_eq!(
+            parse_epoch("direct epoch=1700000000000"),
+            Some(1700000000000)
+        );
+        assert_eq!(parse_epoch("labels=eu"), None);
+        assert_eq!(parse_epoch("epoch=soon"), None);
+    }
+}
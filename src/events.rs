@@ -0,0 +1,100 @@
+use crate::{json, Broker};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// events are formatted and published by a background thread, the broker only hands them over, so publishing them
+// doesn't slow down the dispatch of tasks
+
+// event and field names are literals, only the values are copied
+struct Event {
+    name: &'static str,
+    date: u128,
+    fields: Vec<(&'static str, String)>,
+}
+
+pub struct Events {
+    sender: Sender<Event>,
+}
+
+fn now_as_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0)
+}
+
+// JSON object with the event name, its date, and the given fields
+fn content(event: &str, date: u128, fields: &[(&str, &str)]) -> String {
+    let mut content = format!("{{\"event\":{},\"date\":{}", json::string(event), date);
+    fields.iter().for_each(|(name, value)| {
+        content.push_str(&format!(",{}:{}", json::string(name), json::string(value)));
+    });
+    content.push('}');
+
+    content
+}
+
+impl Events {
+    // events are published in two frames: the event name (so subscribers can filter on it) and a JSON object
+    pub fn start(socket: zmq::Socket) -> Events {
+        let (sender, receiver) = mpsc::channel::<Event>();
+        thread::spawn(move || {
+            // the broker is gone once the channel is closed
+            for event in receiver {
+                let fields: Vec<(&str, &str)> = event
+                    .fields
+                    .iter()
+                    .map(|(name, value)| (*name, value.as_str()))
+                    .collect();
+                socket
+                    .send(event.name, zmq::SNDMORE | zmq::DONTWAIT)
+                    .and_then(|_| {
+                        socket.send(&content(event.name, event.date, &fields), zmq::DONTWAIT)
+                    })
+                    .ok();
+            }
+        });
+
+        Events { sender }
+    }
+}
+
+impl Broker {
+    pub fn event_content(event: &str, fields: &[(&str, &str)]) -> String {
+        content(event, now_as_millis(), fields)
+    }
+
+    // dated now, published later
+    pub fn emit(&self, event: &'static str, fields: &[(&'static str, &str)]) {
+        let events = match &self.events {
+            Some(events) => events,
+            None => return,
+        };
+
+        events
+            .sender
+            .send(Event {
+                name: event,
+                date: now_as_millis(),
+                fields: fields
+                    .iter()
+                    .map(|(name, value)| (*name, value.to_string()))
+                    .collect(),
+            })
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::content;
+
+    #[test]
+    fn events_are_json_objects() {
+        assert_eq!(
+            content("task.created", 12, &[("topic", "ADD"), ("client", "c\"1")]),
+            r#"{"event":"task.created","date":12,"topic":"ADD","client":"c\"1"}"#
+        );
+    }
+}
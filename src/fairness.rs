@@ -0,0 +1,180 @@
+use crate::dispatcher::Task;
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::rc::Rc;
+
+// the tasks waiting for a worker (none registered yet, or kept by a ceiling) are sent with a deficit round robin
+// across their topics, so a busy topic doesn't starve a quiet one on the workers they share: each turn, a topic gets
+// `FAIR_QUANTUM` bytes (times its `weight=<count>`) more to send, and sends its tasks in arrival order while their
+// payloads fit in what it has
+// the deficits are kept from one release to the next, a topic without waiting task loses its deficit
+
+pub fn fair_quantum() -> usize {
+    env::var("FAIR_QUANTUM")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|quantum| *quantum > 0)
+        .unwrap_or(4096)
+}
+
+// the first waiting task of a topic
+pub struct Front {
+    pub topic: Rc<str>,
+    pub cost: usize,
+    pub ready: bool,
+    pub quantum: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Fairness {
+    // topics with waiting tasks, in turn order
+    turns: VecDeque<Rc<str>>,
+    deficits: HashMap<Rc<str>, usize>,
+}
+
+impl Fairness {
+    // the topic that sends its first task, among the ready ones
+    pub fn next(&mut self, fronts: &[Front]) -> Option<Rc<str>> {
+        let front = |topic: &Rc<str>| fronts.iter().find(|front| front.topic == *topic);
+        self.turns.retain(|topic| front(topic).is_some());
+        self.deficits.retain(|topic, _| front(topic).is_some());
+        fronts.iter().for_each(|front| {
+            if !self.turns.contains(&front.topic) {
+                self.turns.push_back(front.topic.clone());
+            }
+        });
+        if !fronts.iter().any(|front| front.ready) {
+            return None;
+        }
+
+        // the ready topics get their quantum until one has enough
+        loop {
+            let topic = self.turns.front()?.clone();
+            let front = front(&topic)?;
+            let deficit = self.deficits.entry(topic).or_default();
+            if front.ready && *deficit >= front.cost {
+                *deficit -= front.cost;
+                return Some(front.topic.clone());
+            }
+            if front.ready {
+                *deficit += front.quantum;
+            }
+            self.turns.rotate_left(1);
+        }
+    }
+}
+
+impl Broker {
+    fn topic_weight(&self, topic_name: &str) -> usize {
+        self.declared_topics
+            .get(topic_name)
+            .and_then(|settings| settings.weight)
+            .unwrap_or(1)
+    }
+
+    // the waiting tasks are sent in turns while they are ready, the others keep waiting
+    pub fn send_waiting(
+        &mut self,
+        transport: &dyn Transport,
+        is_ready: impl Fn(&Broker, &Task) -> bool,
+    ) {
+        // by topic, in arrival order
+        let mut queues: Vec<(Rc<str>, VecDeque<Task>)> = vec![];
+        for task in self.dispatcher.take_waiting() {
+            match queues
+                .iter_mut()
+                .find(|(topic, _)| *topic == task.worker_topic)
+            {
+                Some((_, queue)) => queue.push_back(task),
+                None => queues.push((task.worker_topic.clone(), VecDeque::from([task]))),
+            }
+        }
+
+        loop {
+            let fronts: Vec<Front> = queues
+                .iter()
+                .filter_map(|(topic, queue)| {
+                    queue.front().map(|task| Front {
+                        topic: topic.clone(),
+                        cost: task.payload.len().max(1),
+                        ready: is_ready(self, task),
+                        quantum: self.fair_quantum * self.topic_weight(topic),
+                    })
+                })
+                .collect();
+            let task = self.fairness.next(&fronts).and_then(|topic| {
+                queues
+                    .iter_mut()
+                    .find(|(other, _)| *other == topic)
+                    .and_then(|(_, queue)| queue.pop_front())
+            });
+            match task {
+                Some(task) => self.send_task_and_retry(transport, task),
+                None => break,
+            }
+        }
+
+        // before the tasks stored again meanwhile
+        let mut waiting: Vec<Task> = queues.into_iter().flat_map(|(_, queue)| queue).collect();
+        waiting.append(&mut self.dispatcher.tasks_to_retry);
+        self.dispatcher.tasks_to_retry = waiting;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fairness, Front};
+    use std::rc::Rc;
+
+    fn front(topic: &str, cost: usize, quantum: usize) -> Front {
+        Front {
+            topic: Rc::from(topic),
+            cost,
+            ready: true,
+            quantum,
+        }
+    }
+
+    #[test]
+    fn topics_take_turns_by_bytes() {
+        let mut fairness = Fairness::default();
+        let mut sent = vec![];
+        // a busy topic with big tasks, and a quiet one with small tasks
+        let mut busy = 4;
+        let mut quiet = 4;
+        while busy + quiet > 0 {
+            let mut fronts = vec![];
+            if busy > 0 {
+                fronts.push(front("BUSY", 200, 100));
+            }
+            if quiet > 0 {
+                fronts.push(front("QUIET", 100, 100));
+            }
+            let topic = fairness.next(&fronts).unwrap();
+            match &*topic {
+                "BUSY" => busy -= 1,
+                _ => quiet -= 1,
+            }
+            sent.push(topic);
+        }
+        let sent: Vec<&str> = sent.iter().map(|topic| &**topic).collect();
+        assert_eq!(
+            sent,
+            ["QUIET", "BUSY", "QUIET", "QUIET", "BUSY", "QUIET", "BUSY", "BUSY"]
+        );
+    }
+
+    #[test]
+    fn topics_not_ready_keep_their_turn() {
+        let mut fairness = Fairness::default();
+        let mut fronts = vec![front("A", 1, 1), front("B", 1, 1)];
+        fronts[0].ready = false;
+        assert_eq!(fairness.next(&fronts).as_deref(), Some("B"));
+        fronts[1].ready = false;
+        assert_eq!(fairness.next(&fronts), None);
+        fronts[0].ready = true;
+        assert_eq!(fairness.next(&fronts).as_deref(), Some("A"));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::HashSet;
+
+// workers declare the features they support when they register (`features=headers,restart` in the options), the
+// broker answers with its own ones (`@@FEATURES headers direct ...`), and only uses with a worker what it declared:
+// - `headers`: tasks are sent without their headers frame otherwise
+// - `restart`: `RESTART` leaves the worker alone otherwise
+// workers without `features=` predate the handshake: they get no answer (an unknown frame), and every feature is
+// used with them
+
+pub fn parse_features(options: &str) -> Option<HashSet<String>> {
+    options
+        .split(' ')
+        .find_map(|option| option.strip_prefix("features="))
+        .map(|features| {
+            features
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+}
+
+impl Broker {
+    // the ones depending on the configuration are only given when enabled
+    pub fn features(&self) -> Vec<&'static str> {
+        let mut features = vec![
+            "headers", "direct", "retry", "restart", "topics", "epochs", "names",
+        ];
+        if self.blobs.is_enabled() {
+            features.push("blobs");
+        }
+        if self.accepted_acks {
+            features.push("accepted");
+        }
+        if self.queued_interval.is_some() {
+            features.push("queued");
+        }
+        features
+    }
+
+    pub fn record_features(&mut self, transport: &dyn Transport, identity: &str, options: &str) {
+        if let Some(features) = parse_features(options) {
+            self.worker_features.insert(identity.to_string(), features);
+            transport
+                .send(identity, &["", "@@FEATURES", &self.features().join(" ")])
+                .ok();
+        }
+    }
+
+    pub fn supports(&self, worker_name: &str, feature: &str) -> bool {
+        self.worker_features
+            .get(worker_name)
+            .is_none_or(|features| features.contains(feature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_features;
+
+    #[test]
+    fn features_are_declared_in_the_options() {
+        assert_eq!(parse_features("epoch=1"), None);
+        assert!(parse_features("features=").unwrap().is_empty());
+        let features = parse_features("epoch=1 features=headers,restart").unwrap();
+        assert!(features.contains("headers") && features.contains("restart"));
+        assert_eq!(features.len(), 2);
+    }
+}
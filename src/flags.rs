@@ -0,0 +1,81 @@
+use crate::log;
+use std::env;
+
+// compile-time Cargo features, on by default, so embedded users can build a smaller broker
+// (`cargo build --no-default-features --features http`):
+// - `http`: the dashboard and the admin requests over HTTP (`HTTP_PORT`), see web.rs
+// - `persistence`: the state files (`EXPORT`, `IMPORT`, `STATE_DIR`, `STOP_STATE_FILE` and its recovery) and `RESULTS_DIR`, see
+//   state.rs, recovery.rs and results.rs, and the storage key they are encrypted with, see encryption.rs
+// `quic`, the peers connected over QUIC (`QUIC_PORT`), see quic.rs, is off by default: it brings an async runtime and a
+// TLS stack
+// a broker built without a feature warns about its variables, and refuses its admin commands
+// `make features` runs the tests with each feature alone, without any, and with all of them
+
+pub const FEATURES: &[(&str, bool)] = &[
+    ("http", cfg!(feature = "http")),
+    ("persistence", cfg!(feature = "persistence")),
+    ("quic", cfg!(feature = "quic")),
+];
+
+// the environment variables and the admin commands of each feature
+const VARIABLES: &[(&str, &str)] = &[
+    ("HTTP_PORT", "http"),
+    ("STOP_STATE_FILE", "persistence"),
+    ("STATE_DIR", "persistence"),
+    ("RESULTS_DIR", "persistence"),
+    ("STORAGE_KEY", "persistence"),
+    ("STORAGE_KEY_FILE", "persistence"),
+    ("STORAGE_KEY_COMMAND", "persistence"),
+    ("STORAGE_KEY_MIGRATION", "persistence"),
+    ("QUIC_PORT", "quic"),
+    ("QUIC_CERT", "quic"),
+    ("QUIC_KEY", "quic"),
+];
+const COMMANDS: &[(&str, &str)] = &[
+    ("EXPORT", "persistence"),
+    ("IMPORT", "persistence"),
+    ("RECOVERY", "persistence"),
+];
+
+pub fn is_enabled(feature: &str) -> bool {
+    FEATURES
+        .iter()
+        .any(|(name, enabled)| *name == feature && *enabled)
+}
+
+fn missing(feature: &str) -> String {
+    format!("tiny-broke was built without the {} feature", feature)
+}
+
+// the variables are ignored, the broker runs without their feature
+pub fn warn_ignored_variables() {
+    VARIABLES
+        .iter()
+        .filter(|(variable, feature)| !is_enabled(feature) && env::var(variable).is_ok())
+        .for_each(|(variable, feature)| {
+            log::warn(&format!("Ignoring {}: {}", variable, missing(feature)))
+        });
+}
+
+pub fn command_available(command: &str) -> Result<(), String> {
+    match COMMANDS.iter().find(|(name, _)| *name == command) {
+        Some((_, feature)) if !is_enabled(feature) => Err(missing(feature)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedded::BrokerHandle;
+
+    #[test]
+    fn commands_of_missing_features_are_refused() {
+        let mut broker = BrokerHandle::new();
+        let expected = match cfg!(feature = "persistence") {
+            true => "ERROR no state was recovered at startup",
+            false => "ERROR tiny-broke was built without the persistence feature",
+        };
+        assert_eq!(broker.admin("RECOVERY"), expected);
+        assert!(broker.admin("STATS").starts_with("OK"));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+
+// workers are restarted one at a time from the admin socket: `DRAIN_WORKER <name>` stops sending tasks to the
+// worker, and once the tasks it has are answered (or timed out, and sent to other workers), the broker sends it
+// `@@SHUTDOWN`
+// a draining worker is not asked to register again when it pings
+// `SHUTDOWN <worker|all>` and `RESTART <worker|all>` don't wait: the workers get no new task and are sent
+// `@@SHUTDOWN` or `@@RESTART` at once, the SDKs answer the tasks they are running, then exit or connect again
+
+impl Broker {
+    // the number of tasks the worker still has
+    pub fn drain_worker(
+        &mut self,
+        transport: &dyn Transport,
+        worker_name: &str,
+    ) -> Result<usize, String> {
+        let is_worker = self
+            .registry
+            .clients
+            .get(worker_name)
+            .is_some_and(|client| client.is_worker);
+        if !is_worker {
+            return Err(format!("unknown worker {}", worker_name));
+        }
+
+        self.remove_worker(worker_name);
+        self.draining_workers.insert(worker_name.to_string());
+        let in_flight = self.tasks_of(worker_name);
+        log::info(&format!(
+            "Draining worker {}, {} tasks in flight",
+            worker_name, in_flight
+        ));
+        self.shutdown_drained_workers(transport);
+
+        Ok(in_flight)
+    }
+
+    // the workers sent the control, `all` for every registered worker
+    pub fn control_workers(
+        &mut self,
+        transport: &dyn Transport,
+        target: &str,
+        control: &str,
+    ) -> Result<Vec<String>, String> {
+        let mut worker_names: Vec<String> = self
+            .registry
+            .clients
+            .values()
+            .filter(|client| {
+                client.is_worker
+                    && (target == "all"
+                        || client.name == target
+                        || self.logical_name(&client.name) == target)
+            })
+            .map(|client| client.name.clone())
+            .collect();
+        if worker_names.is_empty() && target != "all" {
+            return Err(format!("unknown worker {}", target));
+        }
+        worker_names.sort();
+        // workers that declared their features without `restart` don't know the control
+        if control == "@@RESTART" {
+            worker_names.retain(|worker_name| {
+                let supported = self.supports(worker_name, "restart");
+                if !supported {
+                    log::warn(&format!("Worker {} can't restart, leaving it", worker_name));
+                }
+                supported
+            });
+        }
+
+        for worker_name in &worker_names {
+            log::info(&format!("Sending {} to worker {}", control, worker_name));
+            self.remove_worker(worker_name);
+            transport.send(worker_name, &["", control]).ok();
+        }
+        Ok(worker_names)
+    }
+
+    fn tasks_of(&self, worker_name: &str) -> usize {
+        self.dispatcher
+            .tasks
+            .iter()
+            .filter(|task| task.worker_name.as_deref() == Some(worker_name))
+            .count()
+    }
+
+    pub fn is_draining(&self, worker_name: &str) -> bool {
+        self.draining_workers.contains(worker_name)
+    }
+
+    // the draining workers without tasks anymore
+    pub fn shutdown_drained_workers(&mut self, transport: &dyn Transport) {
+        let drained: Vec<String> = self
+            .draining_workers
+            .iter()
+            .filter(|worker_name| self.tasks_of(worker_name) == 0)
+            .cloned()
+            .collect();
+
+        for worker_name in drained {
+            log::info(&format!("Worker {} drained, shutting it down", worker_name));
+            transport.send(&worker_name, &["", "@@SHUTDOWN"]).ok();
+            self.draining_workers.remove(&worker_name);
+        }
+    }
+}
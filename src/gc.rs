@@ -0,0 +1,134 @@
+use crate::log;
+use crate::{intern, Broker};
+use std::collections::HashSet;
+use std::env;
+use std::time::Duration;
+
+// the garbage collection doesn't need to run on every message
+const GC_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn idle_ttl_as_secs() -> u64 {
+    env::var("IDLE_TTL")
+        .map(|v| v.parse::<u64>().unwrap_or(60))
+        .unwrap_or(60)
+}
+
+// clients (not workers) that didn't send anything for `CLIENT_TTL` are expired, off by default
+pub fn client_ttl() -> Option<Duration> {
+    env::var("CLIENT_TTL")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+impl Broker {
+    // rules:
+    // - a topic without workers, clients, nor tasks (sent or waiting) is removed once idle for `IDLE_TTL`
+    // - a topic forgets the clients and workers the broker doesn't know anymore
+    // - a client forgets the topics that don't exist anymore, and is removed when it has no topic left
+    // - a client that didn't send anything for `CLIENT_TTL` stops waiting on its response topics and is removed
+    // - a peer that didn't send anything for `IDLE_TTL` is forgotten, with its direct endpoint, its address and its
+    //   protocol counters
+    // - a group without pending tasks is forgotten once idle for `IDLE_TTL`
+    // - a result older than `RESULTS_TTL` is removed
+    // - an interned topic or worker name no task holds anymore is forgotten
+    pub fn collect_garbage(&mut self) {
+        let now = self.now();
+        if self.elapsed(self.last_gc) < GC_INTERVAL {
+            return;
+        }
+        self.last_gc = now;
+        self.expire_clients();
+
+        let clients = &self.registry.clients;
+        self.registry.topics.values_mut().for_each(|topic| {
+            topic.clients.retain(|name| clients.contains_key(name));
+            topic.workers.retain(|name| clients.contains_key(name));
+        });
+
+        let used_topics: HashSet<&str> = self
+            .dispatcher
+            .pending()
+            .flat_map(|task| vec![&*task.worker_topic, task.response_topic.as_str()])
+            .collect();
+        let idle_ttl = Duration::from_secs(self.idle_ttl_as_secs);
+        let idle_topics: Vec<String> = self
+            .registry
+            .topics
+            .values()
+            .filter(|topic| {
+                topic.workers.is_empty()
+                    && topic.clients.is_empty()
+                    && !used_topics.contains(topic.name.as_str())
+                    && now.duration_since(topic.last_activity).unwrap_or_default() >= idle_ttl
+            })
+            .map(|topic| topic.name.clone())
+            .collect();
+        idle_topics.iter().for_each(|name| {
+            self.registry.topics.remove(name);
+            self.topic_stats.remove(name);
+        });
+
+        let topics = &self.registry.topics;
+        self.registry.clients.values_mut().for_each(|client| {
+            client.topics.retain(|name| topics.contains_key(name));
+        });
+        self.registry
+            .clients
+            .retain(|_, client| !client.topics.is_empty());
+
+        // peers that stopped sending messages will be greeted again if they come back
+        self.last_seen
+            .retain(|_, date| now.duration_since(*date).unwrap_or_default() < idle_ttl);
+        let last_seen = &self.last_seen;
+        self.direct_endpoints
+            .retain(|identity, _| last_seen.contains_key(identity));
+        self.identities
+            .retain(|identity, _| last_seen.contains_key(identity));
+        self.peer_stats.retain(|_, stats| {
+            now.duration_since(stats.last_activity).unwrap_or_default() < idle_ttl
+        });
+
+        self.groups.collect(now, idle_ttl);
+        self.results.expire(now);
+        intern::forget_unused();
+    }
+
+    // the tasks of an expired client go on, their responses are kept with `RESULTS_TTL` (see results.rs)
+    fn expire_clients(&mut self) {
+        let client_ttl = match self.client_ttl {
+            Some(client_ttl) => client_ttl,
+            None => return,
+        };
+        let now = self.now();
+        let expired: Vec<(String, Vec<String>)> = self
+            .registry
+            .clients
+            .values()
+            .filter(|client| {
+                !client.is_worker
+                    && now.duration_since(client.last_activity).unwrap_or_default() >= client_ttl
+            })
+            .map(|client| (client.name.clone(), client.topics.clone()))
+            .collect();
+
+        expired.into_iter().for_each(|(identity, topics)| {
+            log::info(&format!(
+                "Client {} idle for {}s, expiring it",
+                identity,
+                client_ttl.as_secs()
+            ));
+            topics
+                .iter()
+                .for_each(|topic| self.remove_client_from_topic(&identity, topic));
+            self.registry.clients.remove(&identity);
+            self.emit(
+                "client.expired",
+                &[
+                    ("client", &identity),
+                    ("responseTopics", &topics.len().to_string()),
+                ],
+            );
+        });
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+// cluster membership by gossip: every broker has a heartbeat counter, incremented every round, and sends the members
+// it knows (with their counters) to its peers, which keep the highest counters
+// a member whose counter doesn't move for `MEMBER_TTL` is lost, its last counter is kept so an older gossip doesn't
+// bring it back; a restarted broker comes back, its counter starts at its start date
+
+pub const MEMBER_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    // where the other brokers connect
+    pub peering_endpoint: String,
+    // where the clients and workers connect
+    pub endpoint: String,
+    pub heartbeat: u64,
+}
+
+impl Member {
+    // `<name> <peering endpoint> <endpoint> <heartbeat>`
+    pub fn parse(entry: &str) -> Option<Member> {
+        let fields: Vec<&str> = entry.split(' ').collect();
+        match fields.as_slice() {
+            [name, peering_endpoint, endpoint, heartbeat] => Some(Member {
+                name: name.to_string(),
+                peering_endpoint: peering_endpoint.to_string(),
+                endpoint: endpoint.to_string(),
+                heartbeat: heartbeat.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn entry(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.name, self.peering_endpoint, self.endpoint, self.heartbeat
+        )
+    }
+}
+
+pub struct Membership {
+    pub myself: Member,
+    // with the date their heartbeat last moved
+    members: HashMap<String, (Member, SystemTime)>,
+    // last heartbeat of the lost members
+    lost: HashMap<String, u64>,
+}
+
+impl Membership {
+    pub fn new(myself: Member) -> Membership {
+        Membership {
+            myself,
+            members: HashMap::new(),
+            lost: HashMap::new(),
+        }
+    }
+
+    pub fn beat(&mut self) {
+        self.myself.heartbeat += 1;
+    }
+
+    // what is gossiped, this broker first
+    pub fn entries(&self) -> Vec<String> {
+        let mut entries = vec![self.myself.entry()];
+        entries.extend(self.members().iter().map(|member| member.entry()));
+        entries
+    }
+
+    // the other live members, by name
+    pub fn members(&self) -> Vec<&Member> {
+        let mut members: Vec<&Member> = self.members.values().map(|(member, _)| member).collect();
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        members
+    }
+
+    // the members that joined
+    pub fn merge(&mut self, entries: &[String], now: SystemTime) -> Vec<Member> {
+        let mut joined = vec![];
+
+        for member in entries.iter().filter_map(|entry| Member::parse(entry)) {
+            if member.name == self.myself.name
+                || self
+                    .lost
+                    .get(&member.name)
+                    .is_some_and(|heartbeat| *heartbeat >= member.heartbeat)
+            {
+                continue;
+            }
+
+            match self.members.get_mut(&member.name) {
+                Some((known, _)) if known.heartbeat >= member.heartbeat => {}
+                Some(known) => *known = (member, now),
+                None => {
+                    self.lost.remove(&member.name);
+                    joined.push(member.clone());
+                    self.members.insert(member.name.clone(), (member, now));
+                }
+            }
+        }
+
+        joined
+    }
+
+    // the members lost
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Member> {
+        let lost: Vec<Member> = self
+            .members
+            .values()
+            .filter(|(_, updated)| now.duration_since(*updated).unwrap_or_default() >= MEMBER_TTL)
+            .map(|(member, _)| member.clone())
+            .collect();
+
+        lost.iter().for_each(|member| {
+            self.members.remove(&member.name);
+            self.lost.insert(member.name.clone(), member.heartbeat);
+        });
+
+        lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Member, Membership, MEMBER_TTL};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(name: &str, heartbeat: u64) -> String {
+        Member {
+            name: name.to_string(),
+            peering_endpoint: format!("tcp://{}:3100", name),
+            endpoint: format!("tcp://{}:3000", name),
+            heartbeat,
+        }
+        .entry()
+    }
+
+    #[test]
+    fn members_are_lost_when_their_heartbeat_stops() {
+        let mut membership = Membership::new(Member::parse(&entry("a", 1)).unwrap());
+        let start = UNIX_EPOCH;
+
+        let joined = membership.merge(&[entry("a", 7), entry("b", 10), entry("c", 5)], start);
+        assert_eq!(joined.len(), 2);
+        assert_eq!(membership.entries()[0], entry("a", 1));
+
+        // b beats, c doesn't
+        let later = start + MEMBER_TTL;
+        assert!(membership
+            .merge(&[entry("b", 11), entry("c", 5)], later)
+            .is_empty());
+        let lost = membership.expire(later);
+        assert_eq!(lost, vec![Member::parse(&entry("c", 5)).unwrap()]);
+        assert_eq!(membership.entries(), vec![entry("a", 1), entry("b", 11)]);
+
+        // an old gossip doesn't bring c back, a restarted c does
+        let restart = later + Duration::from_secs(1);
+        assert!(membership.merge(&[entry("c", 5)], restart).is_empty());
+        assert_eq!(membership.merge(&[entry("c", 900)], restart).len(), 1);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::cli;
+use crate::json;
+use crate::Broker;
+use std::collections::{BTreeMap, BTreeSet};
+
+// the current topology as a graph, for documentation and debugging of routing setups, `GRAPH [dot|mermaid]` admin
+// command, or `tiny-broke graph` against a running broker:
+// - clients to the topics of the tasks they wait on
+// - topics to the topics (or labelled topics) they route to, with the rule
+// - topics to the topics whose tasks wait for theirs (dependencies and workflows)
+// - topics to their workers
+
+const USAGE: &str =
+    "usage: tiny-broke graph --admin <endpoint> [--format dot|mermaid] [--token <token>]";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Dot,
+    Mermaid,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Format, String> {
+        match value {
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            _ => Err(format!("unknown format {}, expected dot or mermaid", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Client,
+    Topic,
+    Worker,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Link {
+    Sends,
+    Routes,
+    Then,
+    Serves,
+}
+
+type Node = (Kind, String);
+
+#[derive(Default)]
+struct Graph {
+    nodes: BTreeSet<Node>,
+    // with their labels, the count of tasks for `Sends`
+    edges: BTreeMap<(Node, Node, Link), BTreeSet<String>>,
+    tasks: BTreeMap<(Node, Node), usize>,
+}
+
+impl Graph {
+    fn edge(&mut self, from: Node, to: Node, link: Link, label: Option<String>) {
+        self.nodes.insert(from.clone());
+        self.nodes.insert(to.clone());
+        if link == Link::Sends {
+            *self.tasks.entry((from.clone(), to.clone())).or_insert(0) += 1;
+        }
+        let labels = self.edges.entry((from, to, link)).or_default();
+        labels.extend(label);
+    }
+
+    fn label(&self, from: &Node, to: &Node, link: Link, labels: &BTreeSet<String>) -> String {
+        match link {
+            Link::Sends => match self.tasks.get(&(from.clone(), to.clone())) {
+                Some(1) => "1 task".to_string(),
+                Some(count) => format!("{} tasks", count),
+                None => String::new(),
+            },
+            _ => labels.iter().cloned().collect::<Vec<String>>().join(", "),
+        }
+    }
+
+    fn dot(&self) -> String {
+        let ids: BTreeMap<&Node, String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node, format!("n{}", index)))
+            .collect();
+        let mut lines = vec![
+            "digraph tiny_broke {".to_string(),
+            "  rankdir=LR;".to_string(),
+        ];
+        for node in &self.nodes {
+            let shape = match node.0 {
+                Kind::Client => "ellipse",
+                Kind::Topic => "box",
+                Kind::Worker => "component",
+            };
+            lines.push(format!(
+                "  {} [label={}, shape={}];",
+                ids[node],
+                json::string(&node.1),
+                shape
+            ));
+        }
+        for ((from, to, link), labels) in &self.edges {
+            let mut attributes = vec![];
+            let label = self.label(from, to, *link, labels);
+            if !label.is_empty() {
+                attributes.push(format!("label={}", json::string(&label)));
+            }
+            match link {
+                Link::Routes => attributes.push("style=dashed".to_string()),
+                Link::Then => attributes.push("style=dotted".to_string()),
+                Link::Sends | Link::Serves => {}
+            }
+            let attributes = match attributes.is_empty() {
+                true => String::new(),
+                false => format!(" [{}]", attributes.join(", ")),
+            };
+            lines.push(format!("  {} -> {}{};", ids[from], ids[to], attributes));
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+
+    fn mermaid(&self) -> String {
+        let ids: BTreeMap<&Node, String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node, format!("n{}", index)))
+            .collect();
+        // mermaid texts are quoted, their own quotes are entities
+        let text = |text: &str| format!("\"{}\"", text.replace('"', "#quot;"));
+        let mut lines = vec!["flowchart LR".to_string()];
+        for node in &self.nodes {
+            let (open, close) = match node.0 {
+                Kind::Client => ("([", "])"),
+                Kind::Topic => ("[", "]"),
+                Kind::Worker => ("[[", "]]"),
+            };
+            lines.push(format!("  {}{}{}{}", ids[node], open, text(&node.1), close));
+        }
+        for ((from, to, link), labels) in &self.edges {
+            let arrow = match link {
+                Link::Routes | Link::Then => "-.->",
+                Link::Sends | Link::Serves => "-->",
+            };
+            let label = match self.label(from, to, *link, labels) {
+                label if label.is_empty() => String::new(),
+                label => format!("|{}|", text(&label)),
+            };
+            lines.push(format!("  {} {}{} {}", ids[from], arrow, label, ids[to]));
+        }
+        lines.join("\n")
+    }
+}
+
+fn topic(name: &str) -> Node {
+    (Kind::Topic, name.to_string())
+}
+
+impl Broker {
+    fn graph(&self) -> Graph {
+        let mut graph = Graph::default();
+
+        // the topic of each pending task, to link the tasks waiting for it
+        let mut topics_of: BTreeMap<&str, &str> = BTreeMap::new();
+        for task in self.dispatcher.pending() {
+            topics_of.insert(&task.response_topic, &task.worker_topic);
+            if let Some(client) = &task.client {
+                graph.edge(
+                    (Kind::Client, client.clone()),
+                    topic(&task.worker_topic),
+                    Link::Sends,
+                    None,
+                );
+            }
+        }
+        for task in self.dispatcher.pending() {
+            for dependency in &task.dependencies {
+                if let Some(dependency_topic) = topics_of.get(dependency.as_str()) {
+                    graph.edge(
+                        topic(dependency_topic),
+                        topic(&task.worker_topic),
+                        Link::Then,
+                        None,
+                    );
+                }
+            }
+        }
+        for workflow in self.workflows.values() {
+            for node in &workflow.nodes {
+                for after in &node.after {
+                    if let Some(previous) = workflow.nodes.iter().find(|other| other.id == *after) {
+                        graph.edge(
+                            topic(&previous.topic),
+                            topic(&node.topic),
+                            Link::Then,
+                            Some(workflow.id.clone()),
+                        );
+                    }
+                }
+            }
+        }
+
+        for (topic_name, settings) in &self.declared_topics {
+            for route in &settings.routes {
+                graph.edge(
+                    topic(topic_name),
+                    topic(&route.target_topic(topic_name)),
+                    Link::Routes,
+                    Some(route.condition()),
+                );
+            }
+        }
+
+        for registered in self.registry.topics.values() {
+            for worker in &registered.workers {
+                graph.edge(
+                    topic(&registered.name),
+                    (Kind::Worker, self.logical_name(worker).to_string()),
+                    Link::Serves,
+                    None,
+                );
+            }
+        }
+        graph
+    }
+
+    // the first line tells the size of the graph, the next ones are the graph
+    pub fn graph_lines(&self, format: Format) -> String {
+        let graph = self.graph();
+        let text = match format {
+            Format::Dot => graph.dot(),
+            Format::Mermaid => graph.mermaid(),
+        };
+        format!(
+            "{} nodes, {} edges\n{}",
+            graph.nodes.len(),
+            graph.edges.len(),
+            text
+        )
+    }
+}
+
+// `tiny-broke graph --admin <endpoint>`: asks a running broker for its graph
+pub fn run(args: &[String]) -> Result<String, String> {
+    let (positionals, options) = cli::options(args, &["admin", "format", "token"])?;
+    let endpoint = match (positionals.is_empty(), options.get("admin")) {
+        (true, Some(endpoint)) => endpoint,
+        _ => return Err(USAGE.to_string()),
+    };
+    let format = options.get("format").map_or("dot", String::as_str);
+    Format::parse(format)?;
+    let request = match options.get("token") {
+        Some(token) => format!("token={} GRAPH {}", token, format),
+        None => format!("GRAPH {}", format),
+    };
+
+    let context = zmq::Context::new();
+    let socket = context
+        .socket(zmq::REQ)
+        .map_err(|error| error.to_string())?;
+    socket
+        .set_rcvtimeo(5000)
+        .and_then(|_| socket.set_linger(0))
+        .and_then(|_| socket.connect(endpoint))
+        .and_then(|_| socket.send(request.as_str(), 0))
+        .map_err(|error| format!("can't ask {}: {}", endpoint, error))?;
+    let response = socket
+        .recv_string(0)
+        .map_err(|error| format!("{} didn't answer: {}", endpoint, error))?
+        .map_err(|_| format!("{} answered with a non UTF-8 response", endpoint))?;
+
+    match response.strip_prefix("OK ") {
+        Some(response) => Ok(response
+            .split_once('\n')
+            .map_or("", |(_, graph)| graph)
+            .to_string()),
+        None => Err(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{topic, Format, Graph, Kind, Link};
+    use crate::embedded::BrokerHandle;
+
+    #[test]
+    fn the_graph_links_clients_topics_and_workers() {
+        let mut broker = BrokerHandle::new();
+        broker.admin("CREATE_TOPIC ORDERS route=$.priority=1:URGENT");
+        broker.register_worker("worker-1", "URGENT");
+        broker.send_task("client-1", "ORDERS", "ORDERS>1", "{\"priority\":1}");
+        broker.send_task("client-1", "ORDERS", "ORDERS>2", "{\"priority\":2}");
+
+        assert_eq!(
+            broker.admin("GRAPH mermaid"),
+            [
+                "OK 4 nodes, 4 edges",
+                "flowchart LR",
+                "  n0([\"client-1\"])",
+                "  n1[\"ORDERS\"]",
+                "  n2[\"URGENT\"]",
+                "  n3[[\"worker-1\"]]",
+                "  n0 -->|\"1 task\"| n1",
+                "  n0 -->|\"1 task\"| n2",
+                "  n1 -.->|\"$.priority=1\"| n2",
+                "  n2 --> n3",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn graphs_are_written_as_dot_and_mermaid() {
+        let mut graph = Graph::default();
+        let client = (Kind::Client, "client-1".to_string());
+        graph.edge(client.clone(), topic("ADD"), Link::Sends, None);
+        graph.edge(client, topic("ADD"), Link::Sends, None);
+        graph.edge(
+            topic("ADD"),
+            topic("ADD@eu"),
+            Link::Routes,
+            Some("$.country=\"de\"".to_string()),
+        );
+        graph.edge(
+            topic("ADD@eu"),
+            (Kind::Worker, "worker-1".to_string()),
+            Link::Serves,
+            None,
+        );
+
+        assert_eq!(
+            graph.dot(),
+            [
+                "digraph tiny_broke {",
+                "  rankdir=LR;",
+                "  n0 [label=\"client-1\", shape=ellipse];",
+                "  n1 [label=\"ADD\", shape=box];",
+                "  n2 [label=\"ADD@eu\", shape=box];",
+                "  n3 [label=\"worker-1\", shape=component];",
+                "  n0 -> n1 [label=\"2 tasks\"];",
+                "  n1 -> n2 [label=\"$.country=\\\"de\\\"\", style=dashed];",
+                "  n2 -> n3;",
+                "}",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            graph.mermaid(),
+            [
+                "flowchart LR",
+                "  n0([\"client-1\"])",
+                "  n1[\"ADD\"]",
+                "  n2[\"ADD@eu\"]",
+                "  n3[[\"worker-1\"]]",
+                "  n0 -->|\"2 tasks\"| n1",
+                "  n1 -.->|\"$.country=#quot;de#quot;\"| n2",
+                "  n2 --> n3",
+            ]
+            .join("\n")
+        );
+        assert!(Format::parse("svg").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::headers;
+use crate::log;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+// clients tag tasks with a group (the `group` header) and subscribe to its completion with `@@GROUP <id> [size]`,
+// the broker answers `@@GROUP_DONE <id> <succeeded> <failed>` once the group is done:
+// - with a size, once that many tasks of the group are answered or failed
+// - without, once the tasks of the group sent so far are: the subscription comes after the tasks
+// a task fails when it won't be answered: refused, quarantined, dropped (queue full, drained), or one of its
+// dependencies failed
+// a done group is forgotten once its subscribers are told, a group nobody subscribes to is forgotten once its tasks
+// are done and it is idle for `IDLE_TTL`
+
+const GROUP_HEADER: &str = "group";
+
+#[derive(Debug)]
+struct Group {
+    // response topics of the tasks not answered yet
+    pending: HashSet<String>,
+    succeeded: u64,
+    failed: u64,
+    size: Option<u64>,
+    subscribers: Vec<String>,
+    last_activity: SystemTime,
+}
+
+impl Group {
+    fn new(now: SystemTime) -> Group {
+        Group {
+            pending: HashSet::new(),
+            succeeded: 0,
+            failed: 0,
+            size: None,
+            subscribers: vec![],
+            last_activity: now,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match self.size {
+            Some(size) => self.succeeded + self.failed >= size,
+            None => self.pending.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Groups {
+    groups: HashMap<String, Group>,
+    // the group of each pending task, by response topic
+    tasks: HashMap<String, String>,
+}
+
+impl Groups {
+    fn group(&mut self, id: &str, now: SystemTime) -> &mut Group {
+        let group = self
+            .groups
+            .entry(id.to_string())
+            .or_insert_with(|| Group::new(now));
+        group.last_activity = now;
+        group
+    }
+
+    pub fn collect(&mut self, now: SystemTime, idle_ttl: Duration) {
+        self.groups.retain(|_, group| {
+            !group.pending.is_empty()
+                || now.duration_since(group.last_activity).unwrap_or_default() < idle_ttl
+        });
+        let groups = &self.groups;
+        self.tasks.retain(|_, id| groups.contains_key(id));
+    }
+}
+
+fn group_of(headers: &[(String, String)]) -> Option<&str> {
+    headers
+        .iter()
+        .find(|(name, _)| name == GROUP_HEADER)
+        .map(|(_, id)| id.as_str())
+        .filter(|id| !id.is_empty())
+}
+
+impl Broker {
+    pub fn join_group(&mut self, task: &Task) {
+        if let Some(id) = group_of(&task.headers) {
+            let now = self.now();
+            self.groups
+                .group(id, now)
+                .pending
+                .insert(task.response_topic.clone());
+            self.groups
+                .tasks
+                .insert(task.response_topic.clone(), id.to_string());
+        }
+    }
+
+    // a task refused when it was sent, from its headers frame
+    pub fn reject_in_group(&mut self, transport: &dyn Transport, headers: &str) {
+        let headers = headers::parse_headers(headers).unwrap_or_default();
+        if let Some(id) = group_of(&headers) {
+            let now = self.now();
+            self.groups.group(id, now).failed += 1;
+            let id = id.to_string();
+            self.finish_group(transport, &id);
+        }
+    }
+
+    // the task is answered, or won't be
+    pub fn count_in_group(
+        &mut self,
+        transport: &dyn Transport,
+        response_topic: &str,
+        succeeded: bool,
+    ) {
+        let id = match self.groups.tasks.remove(response_topic) {
+            Some(id) => id,
+            None => return,
+        };
+        let now = self.now();
+        let group = self.groups.group(&id, now);
+        group.pending.remove(response_topic);
+        match succeeded {
+            true => group.succeeded += 1,
+            false => group.failed += 1,
+        }
+        self.finish_group(transport, &id);
+    }
+
+    pub fn subscribe_group(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        id: &str,
+        size: &str,
+    ) {
+        let size = match size {
+            "" => None,
+            size => match size.parse::<u64>() {
+                Ok(size) => Some(size),
+                Err(_) => {
+                    log::warn(&format!(
+                        "Ignoring the subscription of {} to group {}: {} is not a size",
+                        identity, id, size
+                    ));
+                    return;
+                }
+            },
+        };
+        let now = self.now();
+        let group = self.groups.group(id, now);
+        group.size = size.or(group.size);
+        if !group
+            .subscribers
+            .iter()
+            .any(|subscriber| subscriber == identity)
+        {
+            group.subscribers.push(identity.to_string());
+        }
+        self.finish_group(transport, id);
+    }
+
+    fn finish_group(&mut self, transport: &dyn Transport, id: &str) {
+        let group = match self.groups.groups.get(id) {
+            Some(group) if group.is_done() && !group.subscribers.is_empty() => group,
+            _ => return,
+        };
+        let (succeeded, failed) = (group.succeeded.to_string(), group.failed.to_string());
+        for subscriber in &group.subscribers {
+            transport
+                .send(subscriber, &["", "@@GROUP_DONE", id, &succeeded, &failed])
+                .ok();
+        }
+        self.emit(
+            "group.done",
+            &[
+                ("group", id),
+                ("succeeded", &succeeded),
+                ("failed", &failed),
+            ],
+        );
+        if let Some(group) = self.groups.groups.remove(id) {
+            group.pending.iter().for_each(|response_topic| {
+                self.groups.tasks.remove(response_topic);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_of, Groups};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn groups_are_given_by_a_header() {
+        let headers = vec![
+            ("trace-id".to_string(), "abc".to_string()),
+            ("group".to_string(), "files".to_string()),
+        ];
+        assert_eq!(group_of(&headers), Some("files"));
+        assert_eq!(group_of(&[("group".to_string(), String::new())]), None);
+    }
+
+    #[test]
+    fn idle_groups_without_pending_tasks_are_forgotten() {
+        let mut groups = Groups::default();
+        let now = UNIX_EPOCH + Duration::from_secs(100);
+        groups.group("done", UNIX_EPOCH);
+        groups
+            .group("running", UNIX_EPOCH)
+            .pending
+            .insert("R1".to_string());
+        groups.tasks.insert("R1".to_string(), "running".to_string());
+        groups.collect(now, Duration::from_secs(60));
+        assert!(!groups.groups.contains_key("done"));
+        assert!(groups.groups.contains_key("running"));
+        assert_eq!(groups.tasks.len(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::delivery::Delivery;
+use crate::json::{self, Value};
+use crate::{Broker, Task};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// headers are free key/values (auth tokens, trace ids, ...) sent after the dependencies as a JSON object of strings
+// `{"trace-id": "abc"}`, the broker keeps them with the task: the worker gets them with the task, and the clients get
+// them back with the response, along with the headers of the worker response and the ones added by the broker
+// - x-broker-retry: how many times the task was sent again
+// - x-broker-queue-time: milliseconds between the task submission and its last dispatch
+// tasks without headers are sent and answered as before
+// topics declared with `headers=<name>,...` give their workers broker headers with the tasks, to skip stale work or
+// special-case retries:
+// - retry: x-broker-retry, how many times the task was sent before
+// - enqueued_at: x-broker-enqueued-at, when the task was submitted, milliseconds since the epoch
+// - deadline: x-broker-deadline, when the task times out, milliseconds since the epoch (none for at most once topics)
+// - client: x-broker-client, identity of the client that sent the task
+
+pub type Headers = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrokerHeader {
+    Retry,
+    EnqueuedAt,
+    Deadline,
+    Client,
+}
+
+impl BrokerHeader {
+    pub fn parse(value: &str) -> Result<BrokerHeader, String> {
+        match value {
+            "retry" => Ok(BrokerHeader::Retry),
+            "enqueued_at" => Ok(BrokerHeader::EnqueuedAt),
+            "deadline" => Ok(BrokerHeader::Deadline),
+            "client" => Ok(BrokerHeader::Client),
+            _ => Err(format!(
+                "unknown header {}, expected retry, enqueued_at, deadline or client",
+                value
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            BrokerHeader::Retry => "x-broker-retry",
+            BrokerHeader::EnqueuedAt => "x-broker-enqueued-at",
+            BrokerHeader::Deadline => "x-broker-deadline",
+            BrokerHeader::Client => "x-broker-client",
+        }
+    }
+}
+
+fn millis(date: SystemTime) -> String {
+    date.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .to_string()
+}
+
+pub fn parse_headers(value: &str) -> Result<Headers, String> {
+    if value.is_empty() {
+        return Ok(vec![]);
+    }
+
+    match json::parse(value)? {
+        Value::Object(fields) => fields
+            .into_iter()
+            .map(|(name, value)| match value {
+                Value::String(value) => Ok((name, value)),
+                _ => Err(format!("header {} is not a string", name)),
+            })
+            .collect(),
+        _ => Err("headers are not a JSON object".to_string()),
+    }
+}
+
+pub fn headers_frame(headers: &[(String, String)]) -> String {
+    let fields: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}", json::string(name), json::string(value)))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+// a header given again replaces the previous one
+fn set(headers: &mut Headers, name: &str, value: &str) {
+    match headers.iter_mut().find(|(other, _)| other == name) {
+        Some((_, previous)) => *previous = value.to_string(),
+        None => headers.push((name.to_string(), value.to_string())),
+    }
+}
+
+impl Broker {
+    // the headers of the task, with the broker ones its topic asks for, once it is sent
+    pub fn task_headers(&self, task: &Task) -> Headers {
+        let mut headers = task.headers.clone();
+        let broker_headers = self
+            .declared_topics
+            .get(&*task.worker_topic)
+            .map(|settings| settings.headers.as_slice())
+            .unwrap_or_default();
+
+        for header in broker_headers {
+            let value = match header {
+                BrokerHeader::Retry => task.retry.saturating_sub(1).to_string(),
+                BrokerHeader::EnqueuedAt => millis(task.created),
+                BrokerHeader::Deadline if self.delivery(&task.worker_topic) == Delivery::AtMost => {
+                    continue
+                }
+                BrokerHeader::Deadline => {
+                    let timeout =
+                        Duration::from_secs(self.task_timeout_as_secs(&task.worker_topic));
+                    millis(task.date + timeout)
+                }
+                BrokerHeader::Client => match &task.client {
+                    Some(client) => client.clone(),
+                    None => continue,
+                },
+            };
+            set(&mut headers, header.name(), &value);
+        }
+        headers
+    }
+
+    // the headers of the task, overridden by the worker ones, and the broker ones
+    pub fn response_headers(
+        &self,
+        task: Option<&Task>,
+        worker_headers: &[(String, String)],
+    ) -> Headers {
+        let mut headers = task.map(|task| task.headers.clone()).unwrap_or_default();
+        worker_headers
+            .iter()
+            .for_each(|(name, value)| set(&mut headers, name, value));
+
+        if let Some(task) =
+            task.filter(|task| !task.headers.is_empty() || !worker_headers.is_empty())
+        {
+            let queue_time = task.date.duration_since(task.created).unwrap_or_default();
+            set(
+                &mut headers,
+                "x-broker-retry",
+                &task.retry.saturating_sub(1).to_string(),
+            );
+            set(
+                &mut headers,
+                "x-broker-queue-time",
+                &queue_time.as_millis().to_string(),
+            );
+            if !task.history.is_empty() {
+                set(&mut headers, "x-broker-history", &task.history.join("; "));
+            }
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{headers_frame, parse_headers, BrokerHeader};
+
+    #[test]
+    fn headers_are_a_json_object_of_strings() {
+        let headers = parse_headers(r#"{"trace-id": "abc", "auth": "a \"b\""}"#).unwrap();
+        assert_eq!(
+            headers,
+            vec![
+                ("trace-id".to_string(), "abc".to_string()),
+                ("auth".to_string(), "a \"b\"".to_string())
+            ]
+        );
+        assert_eq!(parse_headers(&headers_frame(&headers)).unwrap(), headers);
+        assert_eq!(parse_headers("").unwrap(), vec![]);
+        assert!(parse_headers(r#"{"retry": 1}"#).is_err());
+        assert!(parse_headers(r#"["abc"]"#).is_err());
+    }
+
+    #[test]
+    fn broker_headers_are_known_names() {
+        assert_eq!(
+            BrokerHeader::parse("enqueued_at").unwrap(),
+            BrokerHeader::EnqueuedAt
+        );
+        assert_eq!(BrokerHeader::Deadline.name(), "x-broker-deadline");
+        assert!(BrokerHeader::parse("x-broker-retry").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::cli;
+use crate::Broker;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// every `HISTORY_INTERVAL` the broker takes a snapshot of each topic served by workers or with waiting tasks, the last
+// `HISTORY_SIZE` ones are kept by topic, for dashboards to draw graphs without an external storage
+// (`HISTORY_DEPTH <topic> <window>` admin command)
+
+pub fn history_interval() -> Duration {
+    Duration::from_secs(
+        env::var("HISTORY_INTERVAL")
+            .map(|v| v.parse::<u64>().unwrap_or(10))
+            .unwrap_or(10),
+    )
+}
+
+pub fn history_size() -> usize {
+    env::var("HISTORY_SIZE")
+        .map(|v| v.parse::<usize>().unwrap_or(360))
+        .unwrap_or(360)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub date: SystemTime,
+    // tasks waiting for a worker
+    pub depth: usize,
+    pub in_flight: usize,
+    pub workers: usize,
+    // tasks answered since the previous snapshot
+    pub processed: u64,
+}
+
+pub struct History {
+    interval: Duration,
+    size: usize,
+    last: SystemTime,
+    by_topic: HashMap<String, VecDeque<Snapshot>>,
+    // processed tasks by topic at the previous snapshot
+    processed: HashMap<String, u64>,
+}
+
+impl History {
+    pub fn new(now: SystemTime) -> History {
+        History {
+            interval: history_interval(),
+            size: history_size(),
+            last: now,
+            by_topic: HashMap::new(),
+            processed: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, topic: &str, snapshot: Snapshot) {
+        if self.size == 0 {
+            return;
+        }
+        let snapshots = self.by_topic.entry(topic.to_string()).or_default();
+        snapshots.push_back(snapshot);
+        if snapshots.len() > self.size {
+            snapshots.pop_front();
+        }
+    }
+
+    // the topics without snapshot for the whole retention are forgotten
+    fn forget_before(&mut self, date: SystemTime) {
+        self.by_topic.retain(|_, snapshots| {
+            snapshots
+                .back()
+                .is_some_and(|snapshot| snapshot.date >= date)
+        });
+        let by_topic = &self.by_topic;
+        self.processed
+            .retain(|topic, _| by_topic.contains_key(topic));
+    }
+
+    // the snapshots of the topic taken in the window before `now`, oldest first
+    pub fn window(&self, topic: &str, window: Duration, now: SystemTime) -> Vec<&Snapshot> {
+        let since = now.checked_sub(window).unwrap_or(UNIX_EPOCH);
+        self.by_topic
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter(|snapshot| snapshot.date >= since)
+            .collect()
+    }
+}
+
+impl Broker {
+    // the topics served by workers or with waiting tasks, sorted, with their current counts
+    pub fn topic_snapshots(&self) -> Vec<(String, Snapshot)> {
+        let mut depths: HashMap<&str, usize> = HashMap::new();
+        self.dispatcher.tasks_to_retry.iter().for_each(|task| {
+            *depths.entry(&task.worker_topic).or_insert(0) += 1;
+        });
+        let mut topics: Vec<String> = self
+            .registry
+            .topics
+            .values()
+            .filter(|topic| !topic.workers.is_empty())
+            .map(|topic| topic.name.clone())
+            .chain(depths.keys().map(|topic| topic.to_string()))
+            .collect();
+        topics.sort();
+        topics.dedup();
+
+        let now = self.now();
+        topics
+            .into_iter()
+            .map(|topic| {
+                let snapshot = Snapshot {
+                    date: now,
+                    depth: depths.get(topic.as_str()).copied().unwrap_or(0),
+                    in_flight: self.dispatcher.tasks.count_on(&topic),
+                    workers: self
+                        .registry
+                        .topics
+                        .get(&topic)
+                        .map_or(0, |topic| topic.workers.len()),
+                    processed: self
+                        .topic_stats
+                        .get(&topic)
+                        .map_or(0, |stats| stats.processed),
+                };
+                (topic, snapshot)
+            })
+            .collect()
+    }
+
+    pub fn snapshot_topics(&mut self) {
+        let now = self.now();
+        if now.duration_since(self.history.last).unwrap_or_default() < self.history.interval {
+            return;
+        }
+        self.history.last = now;
+
+        let snapshots: Vec<(String, Snapshot, u64)> = self
+            .topic_snapshots()
+            .into_iter()
+            .map(|(topic, mut snapshot)| {
+                let processed = snapshot.processed;
+                let previous = self.history.processed.get(&topic).copied().unwrap_or(0);
+                snapshot.processed = processed.saturating_sub(previous);
+                (topic, snapshot, processed)
+            })
+            .collect();
+        for (topic, snapshot, processed) in snapshots {
+            self.history.processed.insert(topic.clone(), processed);
+            self.history.push(&topic, snapshot);
+        }
+
+        let retention = self.history.interval * self.history.size as u32;
+        if let Some(date) = now.checked_sub(retention) {
+            self.history.forget_before(date);
+        }
+    }
+
+    // a line per snapshot: `<seconds since the epoch> depth=<n> in_flight=<n> workers=<n> processed=<n>`
+    pub fn history_depth(&self, topic: &str, window: &str) -> Result<Vec<String>, String> {
+        let window =
+            cli::parse_duration(window).ok_or_else(|| format!("invalid window {}", window))?;
+        Ok(self
+            .history
+            .window(topic, window, self.now())
+            .into_iter()
+            .map(|snapshot| {
+                format!(
+                    "{} depth={} in_flight={} workers={} processed={}",
+                    snapshot
+                        .date
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    snapshot.depth,
+                    snapshot.in_flight,
+                    snapshot.workers,
+                    snapshot.processed
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, Snapshot};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn snapshots_are_a_ring_buffer_by_topic() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let snapshot = |secs, depth| Snapshot {
+            date: at(secs),
+            depth,
+            in_flight: 0,
+            workers: 1,
+            processed: 0,
+        };
+        let mut history = History::new(at(0));
+        history.size = 3;
+        for secs in 1..=4 {
+            history.push("ADD", snapshot(secs * 10, secs as usize));
+        }
+        history.push("SUB", snapshot(10, 0));
+
+        let depths = |history: &History, window| {
+            history
+                .window("ADD", Duration::from_secs(window), at(40))
+                .iter()
+                .map(|snapshot| snapshot.depth)
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(depths(&history, 3600), vec![2, 3, 4]);
+        assert_eq!(depths(&history, 10), vec![3, 4]);
+
+        history.forget_before(at(20));
+        assert!(history
+            .window("SUB", Duration::from_secs(3600), at(40))
+            .is_empty());
+        assert_eq!(depths(&history, 3600).len(), 3);
+    }
+}
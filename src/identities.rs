@@ -0,0 +1,82 @@
+use crate::log;
+use crate::transport::{Incoming, Transport};
+use crate::Broker;
+use std::collections::HashMap;
+use std::env;
+
+// any peer can claim any identity: once a client is gone, an other peer connecting with its identity would get its
+// responses
+// with `BIND_IDENTITIES=true`, an identity is bound to the address of the peer using it first (its IP, or its uid on
+// the unix socket), a peer using it from another address gets `@@IDENTITY_CONFLICT` and its message is dropped
+// the binding is forgotten with the peer, once it didn't send anything for `IDLE_TTL`
+
+pub fn bind_identities() -> bool {
+    env::var("BIND_IDENTITIES").is_ok_and(|v| v == "true")
+}
+
+// true when the identity is bound to another address, peers without address (in memory) are not bound
+fn conflicts(
+    bindings: &mut HashMap<String, String>,
+    identity: &str,
+    address: Option<&str>,
+) -> bool {
+    let address = match address {
+        Some(address) => address,
+        None => return false,
+    };
+    match bindings.get(identity) {
+        Some(bound) => bound != address,
+        None => {
+            bindings.insert(identity.to_string(), address.to_string());
+            false
+        }
+    }
+}
+
+impl Broker {
+    // the message is dropped when its peer is not the one bound to its identity
+    pub fn identity_conflict(&mut self, transport: &dyn Transport, message: &Incoming) -> bool {
+        if !self.bind_identities
+            || !conflicts(
+                &mut self.identities,
+                &message.identity,
+                message.address.as_deref(),
+            )
+        {
+            return false;
+        }
+
+        log::warn(&format!(
+            "Identity {} used from {}, dropping the message",
+            message.identity,
+            message.address.as_deref().unwrap_or("")
+        ));
+        self.emit(
+            "peer.identity_conflict",
+            &[
+                ("identity", &message.identity),
+                ("address", message.address.as_deref().unwrap_or("")),
+            ],
+        );
+        transport
+            .send(&message.identity, &["", "@@IDENTITY_CONFLICT"])
+            .ok();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::conflicts;
+    use std::collections::HashMap;
+
+    #[test]
+    fn identities_are_bound_to_the_first_address() {
+        let mut bindings = HashMap::new();
+        assert!(!conflicts(&mut bindings, "client-1", Some("10.0.0.1")));
+        assert!(!conflicts(&mut bindings, "client-1", Some("10.0.0.1")));
+        assert!(conflicts(&mut bindings, "client-1", Some("10.0.0.2")));
+        assert!(!conflicts(&mut bindings, "client-2", Some("10.0.0.2")));
+        assert!(!conflicts(&mut bindings, "client-1", None));
+    }
+}
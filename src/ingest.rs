@@ -0,0 +1,269 @@
+use crate::dispatcher::Task;
+use crate::transport::Transport;
+use crate::{log, Broker};
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// producers that don't speak ZeroMQ push their tasks to Redis lists, the broker pops them (`INGEST_REDIS`) and sends
+// them to the workers of the topic mapped to each list (`INGEST_LISTS=<list>=<topic>,...`)
+// a background thread waits on the lists (`BLPOP`), the broker takes what it popped every second
+// nobody waits for the responses of these tasks, and a task popped but not taken yet is lost if the broker dies
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// seconds a `BLPOP` waits for a task, so the thread sees the broker stopping
+const BLOCK_AS_SECS: u64 = 1;
+
+pub struct Ingest {
+    receiver: Receiver<(String, String)>,
+    stopped: Arc<AtomicBool>,
+    // response topics are unique across restarts
+    started: u128,
+    count: u64,
+}
+
+fn parse_url(url: &str) -> Result<String, String> {
+    let host = url
+        .strip_prefix("redis://")
+        .ok_or_else(|| format!("only redis:// urls are supported, not {}", url))?
+        .trim_end_matches('/');
+    Ok(match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:6379", host),
+    })
+}
+
+// `jobs=ADD,mails=MAIL>SEND` gives the topic of each list
+fn parse_lists(value: &str) -> Result<HashMap<String, String>, String> {
+    value
+        .split(',')
+        .filter(|list| !list.is_empty())
+        .map(|list| {
+            list.split_once('=')
+                .filter(|(list, topic)| !list.is_empty() && !topic.is_empty())
+                .map(|(list, topic)| (list.to_string(), topic.to_string()))
+                .ok_or_else(|| format!("bad list {}, expected <list>=<topic>", list))
+        })
+        .collect()
+}
+
+fn command(args: &[&str]) -> String {
+    args.iter()
+        .fold(format!("*{}\r\n", args.len()), |command, arg| {
+            format!("{}${}\r\n{}\r\n", command, arg.len(), arg)
+        })
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::new(ErrorKind::ConnectionAborted, "closed by Redis"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn read_bulk(reader: &mut impl BufRead) -> io::Result<String> {
+    let line = read_line(reader)?;
+    let length: usize = line
+        .strip_prefix('$')
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unexpected {}", line)))?;
+    let mut content = vec![0; length + 2];
+    reader.read_exact(&mut content)?;
+    content.truncate(length);
+    String::from_utf8(content).map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+// the list and the value popped, none when the lists stayed empty
+fn read_popped(reader: &mut impl BufRead) -> io::Result<Option<(String, String)>> {
+    let line = read_line(reader)?;
+    match line.as_str() {
+        "*-1" | "_" => Ok(None),
+        "*2" => Ok(Some((read_bulk(reader)?, read_bulk(reader)?))),
+        _ => Err(Error::other(format!("Redis answered {}", line))),
+    }
+}
+
+fn pop(
+    address: &str,
+    lists: &HashMap<String, String>,
+    sender: &Sender<(String, String)>,
+    stopped: &AtomicBool,
+) -> io::Result<()> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "can't resolve host"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT + Duration::from_secs(BLOCK_AS_SECS)))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let block = BLOCK_AS_SECS.to_string();
+    let mut args = vec!["BLPOP"];
+    args.extend(lists.keys().map(String::as_str));
+    args.push(&block);
+    let blpop = command(&args);
+
+    while !stopped.load(Ordering::Relaxed) {
+        stream.write_all(blpop.as_bytes())?;
+        if let Some((list, payload)) = read_popped(&mut reader)? {
+            let topic = lists.get(&list).cloned().unwrap_or(list);
+            if sender.send((topic, payload)).is_err() {
+                // the broker is gone
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Ingest {
+    fn start(address: String, lists: HashMap<String, String>) -> Ingest {
+        let (sender, receiver) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        thread::spawn(move || {
+            let mut backoff = Duration::from_secs(0);
+            while !thread_stopped.load(Ordering::Relaxed) {
+                match pop(&address, &lists, &sender, &thread_stopped) {
+                    Ok(()) => return,
+                    Err(error) => {
+                        backoff = (backoff * 2).max(Duration::from_secs(1)).min(MAX_BACKOFF);
+                        log::warn(&format!(
+                            "Can't pop tasks from {}, trying again in {} seconds: {}",
+                            address,
+                            backoff.as_secs(),
+                            error
+                        ));
+                        thread::sleep(backoff);
+                    }
+                }
+            }
+        });
+
+        Ingest {
+            receiver,
+            stopped,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            count: 0,
+        }
+    }
+
+    pub fn from_env() -> Option<Ingest> {
+        let url = env::var("INGEST_REDIS").ok()?;
+        let settings = parse_url(&url).and_then(|address| {
+            let lists = parse_lists(&env::var("INGEST_LISTS").unwrap_or_default())?;
+            match lists.is_empty() {
+                true => Err("INGEST_LISTS is not set".to_string()),
+                false => Ok((address, lists)),
+            }
+        });
+        let (address, lists) = settings.unwrap_or_else(|error| {
+            log::warn(&format!("Can't pop tasks from {}: {}", url, error));
+            process::exit(1);
+        });
+
+        Some(Ingest::start(address, lists))
+    }
+
+    fn response_topic(&mut self, topic_name: &str) -> String {
+        self.count += 1;
+        format!("{}>INGEST@@{}-{}", topic_name, self.started, self.count)
+    }
+}
+
+impl Broker {
+    // the tasks popped since the last tick
+    pub fn ingest_tasks(&mut self, transport: &dyn Transport) {
+        let mut ingest = match self.ingest.take() {
+            Some(ingest) => ingest,
+            None => return,
+        };
+        // nothing else is popped once the broker stops, what was popped is still sent
+        if self.is_stopping() {
+            ingest.stopped.store(true, Ordering::Relaxed);
+        }
+
+        while let Ok((topic, payload)) = ingest.receiver.try_recv() {
+            let response_topic = ingest.response_topic(&topic);
+            self.emit(
+                "task.created",
+                &[
+                    ("topic", &topic),
+                    ("responseTopic", &response_topic),
+                    ("client", "ingest"),
+                ],
+            );
+            self.mirror_task(&topic, &response_topic, &payload);
+            let worker_topic = self.route(&topic, &payload);
+            let payload = self.claim_check(&response_topic, "task", &payload);
+            self.submit(
+                transport,
+                Task::new(&worker_topic, &response_topic, &payload),
+            );
+        }
+        self.ingest = Some(ingest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command, parse_lists, Ingest};
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lists_are_mapped_to_topics() {
+        let lists = parse_lists("jobs=ADD,mails=MAIL>SEND").unwrap();
+        assert_eq!(lists.get("mails").map(String::as_str), Some("MAIL>SEND"));
+        assert!(parse_lists("jobs").is_err());
+        assert_eq!(
+            command(&["BLPOP", "jobs", "1"]),
+            "*3\r\n$5\r\nBLPOP\r\n$4\r\njobs\r\n$1\r\n1\r\n"
+        );
+    }
+
+    #[test]
+    fn popped_values_become_tasks_of_their_topic() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut line = String::new();
+            // each BLPOP is 6 lines, the first one times out
+            for answer in ["*-1\r\n", "*2\r\n$4\r\njobs\r\n$3\r\n1+1\r\n"] {
+                for _ in 0..6 {
+                    reader.read_line(&mut line).unwrap();
+                }
+                stream.write_all(answer.as_bytes()).unwrap();
+            }
+            thread::sleep(Duration::from_secs(1));
+        });
+
+        let mut lists = HashMap::new();
+        lists.insert("jobs".to_string(), "ADD".to_string());
+        let mut ingest = Ingest::start(address, lists);
+        assert_eq!(
+            ingest.receiver.recv_timeout(Duration::from_secs(5)),
+            Ok(("ADD".to_string(), "1+1".to_string()))
+        );
+        assert!(ingest.response_topic("ADD").starts_with("ADD>INGEST@@"));
+    }
+}
@@ -0,0 +1,50 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+// topic and worker names are shared by the tasks: tasks hold the same string instead of their own copy, so memory
+// follows the number of topics and workers, not the number of tasks
+// the broker runs on one thread, the interned strings too
+// an interned string no task holds anymore is forgotten by the garbage collection
+
+thread_local! {
+    static INTERNED: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+pub fn intern(value: &str) -> Rc<str> {
+    INTERNED.with(|interned| {
+        let mut interned = interned.borrow_mut();
+        if let Some(value) = interned.get(value) {
+            return value.clone();
+        }
+        let value: Rc<str> = Rc::from(value);
+        interned.insert(value.clone());
+        value
+    })
+}
+
+pub fn forget_unused() {
+    INTERNED.with(|interned| {
+        interned
+            .borrow_mut()
+            .retain(|value| Rc::strong_count(value) > 1)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forget_unused, intern, INTERNED};
+    use std::rc::Rc;
+
+    #[test]
+    fn interned_strings_are_shared_while_used() {
+        let first = intern("ORDERS");
+        let second = intern("ORDERS");
+        assert!(Rc::ptr_eq(&first, &second));
+
+        drop(first);
+        drop(second);
+        forget_unused();
+        INTERNED.with(|interned| assert!(!interned.borrow().contains("ORDERS")));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::log;
+use crate::Broker;
+use std::collections::HashMap;
+use std::env;
+
+// peers on the same host can connect through a unix socket (`IPC_PATH`), the kernel tells their uid, so local
+// workers are authenticated without keys
+
+pub fn ipc_path() -> Option<String> {
+    env::var("IPC_PATH").ok()
+}
+
+// the topics each uid can work on and send tasks to
+#[derive(Debug, Default)]
+pub struct Permissions {
+    topics_by_uid: HashMap<u32, Vec<String>>,
+}
+
+impl Permissions {
+    // `<uid>=<topic>,<topic>;<uid>=*`
+    // a bad entry is ignored, so its uid gets no permission rather than every permission
+    pub fn parse(value: &str) -> Permissions {
+        let mut permissions = Permissions::default();
+
+        for entry in value.split(';').filter(|entry| !entry.trim().is_empty()) {
+            match entry
+                .split_once('=')
+                .and_then(|(uid, topics)| Some((uid.trim().parse::<u32>().ok()?, topics)))
+            {
+                Some((uid, topics)) => {
+                    permissions
+                        .topics_by_uid
+                        .entry(uid)
+                        .or_default()
+                        .extend(topics.split(',').map(|topic| topic.trim().to_string()));
+                }
+                None => log::warn(&format!(
+                    "Ignoring the IPC permission {}, expected uid=topics",
+                    entry
+                )),
+            }
+        }
+
+        permissions
+    }
+
+    pub fn allows(&self, uid: u32, topic_name: &str) -> bool {
+        self.topics_by_uid.get(&uid).is_some_and(|topics| {
+            topics
+                .iter()
+                .any(|topic| topic == "*" || topic == topic_name)
+        })
+    }
+}
+
+pub fn ipc_permissions() -> Option<Permissions> {
+    env::var("IPC_PERMISSIONS")
+        .ok()
+        .map(|value| Permissions::parse(&value))
+}
+
+impl Broker {
+    // only local peers have a uid, the remote ones are not concerned by the permissions
+    pub fn allows_local(&self, uid: Option<u32>, topic_name: &str) -> bool {
+        match (uid, &self.ipc_permissions) {
+            (Some(uid), Some(permissions)) => permissions.allows(uid, topic_name),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Permissions;
+
+    #[test]
+    fn permissions_are_given_per_uid() {
+        let permissions = Permissions::parse("1000=ADD,SUB; 0=*;nobody=ADD;1001");
+
+        assert!(permissions.allows(1000, "ADD"));
+        assert!(permissions.allows(1000, "SUB"));
+        assert!(!permissions.allows(1000, "MUL"));
+        assert!(permissions.allows(0, "MUL"));
+        // bad entries give nothing
+        assert!(!permissions.allows(1001, "ADD"));
+        assert!(!permissions.allows(65534, "ADD"));
+    }
+}
@@ -0,0 +1,373 @@
+use crate::admin;
+use crate::json::{self, Value};
+use crate::transport::Transport;
+use crate::Broker;
+
+// admin requests starting with `{` are JSON-RPC 2.0, for tools generated from `tiny-broke protocol admin`:
+// `{"jsonrpc": "2.0", "id": 1, "method": "PEEK", "params": ["ADD", "5"]}`
+// params are the arguments of the command, or `{"args": [...], "token": "...", "timestamp": "...", "signature": "..."}`
+// with `ADMIN_USERS` and `CONTROL_SECRET`, the signature being the one of the text request (`token=<token> PEEK ADD 5`)
+// the result is `{"summary": <first line, without OK>, "lines": [<next lines>]}`, errors have the codes below
+// they run as the text commands: same roles, same audit log
+
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+// the command ran and failed (a topic without waiting tasks, a path that can't be written, ...)
+pub const COMMAND_FAILED: i32 = -32000;
+// bad signature, unknown token, or a role that doesn't allow the command
+pub const UNAUTHORIZED: i32 = -32001;
+
+pub struct ErrorCode {
+    pub code: i32,
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+pub const ERROR_CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: PARSE_ERROR,
+        name: "parse_error",
+        description: "the request is not JSON",
+    },
+    ErrorCode {
+        code: INVALID_REQUEST,
+        name: "invalid_request",
+        description: "the request is not a JSON-RPC 2.0 request",
+    },
+    ErrorCode {
+        code: METHOD_NOT_FOUND,
+        name: "method_not_found",
+        description: "the method is not an admin command",
+    },
+    ErrorCode {
+        code: INVALID_PARAMS,
+        name: "invalid_params",
+        description: "the params are not strings, or not the ones of the command",
+    },
+    ErrorCode {
+        code: COMMAND_FAILED,
+        name: "command_failed",
+        description: "the command ran and failed",
+    },
+    ErrorCode {
+        code: UNAUTHORIZED,
+        name: "unauthorized",
+        description: "bad signature, unknown token, or a role that doesn't allow the command",
+    },
+];
+
+pub struct Method {
+    pub name: &'static str,
+    // optional params are in brackets
+    pub params: &'static [&'static str],
+    pub description: &'static str,
+}
+
+pub const METHODS: &[Method] = &[
+    Method {
+        name: "EXPORT",
+        params: &["file"],
+        description: "writes the waiting tasks, declared and paused topics to a file of STATE_DIR",
+    },
+    Method {
+        name: "IMPORT",
+        params: &["file"],
+        description: "reads tasks and topics written by EXPORT, from STATE_DIR",
+    },
+    Method {
+        name: "PEEK",
+        params: &["topic", "[count]"],
+        description: "the payloads of the tasks waiting on the topic, a line each",
+    },
+    Method {
+        name: "DRAIN",
+        params: &["topic"],
+        description: "drops the tasks waiting on the topic",
+    },
+    Method {
+        name: "PAUSE",
+        params: &["topic"],
+        description: "the tasks of the topic wait until it is resumed",
+    },
+    Method {
+        name: "RESUME",
+        params: &["topic"],
+        description: "the waiting tasks of a paused topic are sent",
+    },
+    Method {
+        name: "REPLAY",
+        params: &["[topic]"],
+        description: "sends the dead tasks again",
+    },
+    Method {
+        name: "DRAIN_WORKER",
+        params: &["worker"],
+        description: "the worker gets no new task, and is shut down once its tasks are answered",
+    },
+    Method {
+        name: "SHUTDOWN",
+        params: &["worker|all"],
+        description: "asks workers to stop",
+    },
+    Method {
+        name: "RESTART",
+        params: &["worker|all"],
+        description: "asks workers to restart",
+    },
+    Method {
+        name: "CREATE_TOPIC",
+        params: &["topic", "[setting=value]..."],
+        description: "declares a topic with its settings",
+    },
+    Method {
+        name: "WORKER_WEIGHT",
+        params: &["worker", "weight"],
+        description: "the share of the tasks of its topics the worker gets",
+    },
+    Method {
+        name: "TOPIC_WEIGHT",
+        params: &["topic", "weight"],
+        description: "the share of the workers the topic gets",
+    },
+    Method {
+        name: "PEER",
+        params: &["identity"],
+        description: "what the broker knows of a peer",
+    },
+    Method {
+        name: "DLQ",
+        params: &["[topic]"],
+        description: "the dead tasks, a line each",
+    },
+    Method {
+        name: "WORKERS",
+        params: &[],
+        description: "the workers and their stats, a line each",
+    },
+    Method {
+        name: "TOPICS",
+        params: &[],
+        description: "the topics and their counts, a line each",
+    },
+    Method {
+        name: "GRAPH",
+        params: &["[dot|mermaid]"],
+        description: "clients, topics, workers and the links between them, as DOT or Mermaid",
+    },
+    Method {
+        name: "RECOVERY",
+        params: &[],
+        description:
+            "what was recovered from STOP_STATE_FILE at startup, and the quarantined records",
+    },
+    Method {
+        name: "SLOW_WORKERS",
+        params: &[],
+        description: "the slow workers, a line each",
+    },
+    Method {
+        name: "STATS",
+        params: &[],
+        description: "counts of workers, clients, topics and tasks",
+    },
+    Method {
+        name: "ENDPOINTS",
+        params: &[],
+        description: "the endpoints the broker listens on",
+    },
+    Method {
+        name: "UNDELIVERED",
+        params: &[],
+        description: "counts of the responses waiting for their client",
+    },
+    Method {
+        name: "HISTORY_DEPTH",
+        params: &["topic", "[window]"],
+        description: "the snapshots of the topic, a line each",
+    },
+    Method {
+        name: "DEBUG",
+        params: &["off|stdout|events", "[interval=<milliseconds>]"],
+        description: "where the debug line goes",
+    },
+    Method {
+        name: "AUDIT",
+        params: &["[count|VERIFY]"],
+        description: "the last lines of the audit log, or its verification",
+    },
+];
+
+pub fn is_request(request: &str) -> bool {
+    request.trim_start().starts_with('{')
+}
+
+// the id, the text request with its token, and its signature
+type TextRequest = (Value, String, Option<String>);
+
+// the text request, or the id, the code and the message of the error
+fn text_request(request: &str) -> Result<TextRequest, (Value, i32, String)> {
+    let request = json::parse(request).map_err(|error| (Value::Null, PARSE_ERROR, error))?;
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let invalid = |code: i32, message: &str| (id.clone(), code, message.to_string());
+
+    if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(invalid(
+            INVALID_REQUEST,
+            "jsonrpc is expected to be \"2.0\"",
+        ));
+    }
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid(INVALID_REQUEST, "method is expected to be a string"))?;
+    if !METHODS.iter().any(|known| known.name == method) {
+        return Err(invalid(
+            METHOD_NOT_FOUND,
+            &format!("unknown method: {}", method),
+        ));
+    }
+
+    let params = request.get("params");
+    let args = match params {
+        None => Some(&[][..]),
+        Some(Value::Array(args)) => Some(&args[..]),
+        Some(params @ Value::Object(_)) => match params.get("args") {
+            None => Some(&[][..]),
+            Some(args) => args.as_array().map(|args| &args[..]),
+        },
+        Some(_) => None,
+    };
+    let args: Vec<&str> = args
+        .and_then(|args| args.iter().map(Value::as_str).collect())
+        .filter(|args: &Vec<&str>| args.iter().all(|arg| !arg.is_empty() && !arg.contains(' ')))
+        .ok_or_else(|| {
+            invalid(
+                INVALID_PARAMS,
+                "params are expected to be strings without spaces",
+            )
+        })?;
+    let option = |name: &str| {
+        params
+            .and_then(|params| params.get(name))
+            .and_then(Value::as_str)
+    };
+
+    let mut text = std::iter::once(method)
+        .chain(args)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if let Some(token) = option("token") {
+        text = format!("token={} {}", token, text);
+    }
+    let signature = option("signature")
+        .map(|signature| format!("{} {}", option("timestamp").unwrap_or(""), signature));
+    Ok((id, text, signature))
+}
+
+fn result(id: &Value, response: &str) -> String {
+    let mut lines = response.lines();
+    let summary = lines
+        .next()
+        .unwrap_or("")
+        .trim_start_matches("OK")
+        .trim_start();
+    let lines: Vec<String> = lines.map(json::string).collect();
+    format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{{\"summary\":{},\"lines\":[{}]}}}}",
+        id,
+        json::string(summary),
+        lines.join(",")
+    )
+}
+
+fn error(id: &Value, code: i32, message: &str) -> String {
+    format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":{},\"message\":{}}}}}",
+        id,
+        code,
+        json::string(message)
+    )
+}
+
+pub fn handle(
+    broker: &mut Broker,
+    transport: &dyn Transport,
+    identity: &str,
+    request: &str,
+    signature: Option<&str>,
+) -> String {
+    let (id, request, params_signature) = match text_request(request) {
+        Ok(request) => request,
+        Err((id, code, message)) => return error(&id, code, &message),
+    };
+    let signature = params_signature.as_deref().or(signature);
+
+    let (authorized, response) =
+        admin::handle_text(broker, transport, identity, &request, signature);
+    match response.strip_prefix("ERROR ") {
+        None => result(&id, &response),
+        Some(message) if !authorized => error(&id, UNAUTHORIZED, message),
+        Some(message) if message.starts_with("usage:") => error(&id, INVALID_PARAMS, message),
+        Some(message) => error(&id, COMMAND_FAILED, message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{result, text_request, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR};
+    use crate::embedded::BrokerHandle;
+    use crate::json::Value;
+
+    #[test]
+    fn requests_are_read_as_text_commands() {
+        assert_eq!(
+            text_request(
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "PEEK", "params": ["ADD", "5"]}"#
+            ),
+            Ok((Value::Number(1.0), "PEEK ADD 5".to_string(), None))
+        );
+        assert_eq!(
+            text_request(
+                r#"{"jsonrpc": "2.0", "id": "a", "method": "STATS", "params": {"token": "t1", "timestamp": "1", "signature": "s"}}"#
+            ),
+            Ok((
+                Value::String("a".to_string()),
+                "token=t1 STATS".to_string(),
+                Some("1 s".to_string())
+            ))
+        );
+        let code = |request| text_request(request).map_err(|(_, code, _)| code);
+        assert_eq!(code("PEEK"), Err(PARSE_ERROR));
+        assert_eq!(
+            code(r#"{"jsonrpc": "2.0", "id": 1, "method": "NOPE"}"#),
+            Err(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc": "2.0", "id": 1, "method": "PEEK", "params": ["ADD 5"]}"#),
+            Err(INVALID_PARAMS)
+        );
+    }
+
+    #[test]
+    fn responses_are_a_summary_and_lines() {
+        assert_eq!(
+            result(&Value::Number(1.0), "OK 2 tasks\n1+1\n2+2"),
+            r#"{"jsonrpc":"2.0","id":1,"result":{"summary":"2 tasks","lines":["1+1","2+2"]}}"#
+        );
+    }
+
+    #[test]
+    fn commands_run_as_text_commands() {
+        let mut broker = BrokerHandle::new();
+        assert_eq!(
+            broker.admin(r#"{"jsonrpc": "2.0", "id": 1, "method": "PEEK", "params": ["ADD"]}"#),
+            r#"{"jsonrpc":"2.0","id":1,"result":{"summary":"0 tasks","lines":[]}}"#
+        );
+        assert_eq!(
+            broker.admin(r#"{"jsonrpc": "2.0", "id": 2, "method": "PEEK"}"#),
+            r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32602,"message":"usage: PEEK <topic>"}}"#
+        );
+    }
+}
@@ -0,0 +1,794 @@
+mod accepted;
+mod admin;
+mod affinity;
+mod alerts;
+mod audit;
+mod blobs;
+mod ceilings;
+mod cli;
+mod clock;
+mod cluster;
+mod container;
+mod debug;
+mod delivery;
+mod dependencies;
+mod direct;
+mod discovery;
+mod dispatcher;
+mod dlq;
+pub mod embedded;
+#[cfg(feature = "persistence")]
+mod encryption;
+mod endpoints;
+mod envelope;
+mod epochs;
+mod events;
+mod fairness;
+mod features;
+mod flags;
+mod fleet;
+mod gc;
+mod gossip;
+mod graph;
+mod groups;
+mod headers;
+mod history;
+mod identities;
+mod ingest;
+mod intern;
+mod ipc;
+mod jsonrpc;
+mod limits;
+mod loadgen;
+mod log;
+mod mirror;
+mod names;
+mod pauses;
+mod peers;
+mod protocol;
+mod proxy;
+mod queued;
+#[cfg(feature = "quic")]
+mod quic;
+mod receipts;
+#[cfg(feature = "persistence")]
+mod recovery;
+mod redaction;
+mod registrations;
+mod registry;
+mod results;
+mod retry;
+mod roles;
+mod routing;
+mod schema;
+mod shutdown;
+mod signature;
+mod simulation;
+mod split;
+#[cfg(feature = "persistence")]
+mod state;
+mod stats;
+mod topics;
+mod transport;
+mod tuning;
+mod tunnel;
+mod undelivered;
+#[cfg(feature = "http")]
+mod web;
+mod webhook;
+mod weights;
+mod wheel;
+mod workers;
+mod workflow;
+
+use alerts::Alerts;
+use audit::Audit;
+use blobs::Blobs;
+// the JSON of the wire format, shared with embedded workers
+use broker_protocol::json;
+use clock::{Clock, SystemClock};
+use cluster::Cluster;
+use debug::Debug;
+use dispatcher::{Dispatcher, Task};
+use envelope::Envelopes;
+use events::Events;
+use ingest::Ingest;
+use ipc::Permissions;
+use mirror::Mirror;
+use registry::Registry;
+use results::{Results, Wait};
+use stats::WorkerStats;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::os::unix::io::AsRawFd;
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use topics::TopicSettings;
+use transport::{Control, Incoming, Router, Transport};
+use tuning::SocketOptions;
+use workflow::Workflow;
+use zmq::{self, SocketType};
+
+struct Broker {
+    timeout_as_secs: u64,
+    registry: Registry,
+    dispatcher: Dispatcher,
+    events: Option<Events>,
+    mirror: Option<Mirror>,
+    ingest: Option<Ingest>,
+    cluster: Option<Cluster>,
+    alerts: Alerts,
+    worker_stats: HashMap<String, WorkerStats>,
+    // processing times by worker topic, failures are not counted
+    topic_stats: HashMap<String, WorkerStats>,
+    slow_worker_factor: f64,
+    poison_threshold: usize,
+    dead_letters_capacity: usize,
+    declared_topics: HashMap<String, TopicSettings>,
+    declared_topics_only: bool,
+    // see ceilings.rs
+    max_in_flight: Option<usize>,
+    // see fairness.rs
+    fairness: fairness::Fairness,
+    fair_quantum: usize,
+    // see limits.rs
+    max_pending_tasks: Option<usize>,
+    accepted_acks: bool,
+    workflows: HashMap<String, Workflow>,
+    results: Results,
+    // payloads written to disk are encrypted with it
+    #[cfg(feature = "persistence")]
+    cipher: Option<encryption::Cipher>,
+    // the admin requests `EXPORT` and `IMPORT` only reach files in it, see state.rs
+    #[cfg(feature = "persistence")]
+    state_dir: Option<std::path::PathBuf>,
+    blobs: Blobs,
+    waits: Vec<Wait>,
+    // by client identity
+    direct_endpoints: HashMap<String, String>,
+    direct_workers: HashSet<String>,
+    draining_workers: HashSet<String>,
+    // see pauses.rs
+    paused_topics: HashSet<String>,
+    // the last registration epoch of the workers giving one, by logical name
+    worker_epochs: HashMap<String, u64>,
+    // by identity, for the workers giving one
+    logical_names: HashMap<String, String>,
+    ipc_permissions: Option<Permissions>,
+    idle_ttl_as_secs: u64,
+    client_ttl: Option<Duration>,
+    // responses waiting for their client, see undelivered.rs
+    undelivered: undelivered::Undelivered,
+    // responses sent again until their client confirms them, see receipts.rs
+    receipts: receipts::Receipts,
+    // snapshots of the topics, see history.rs
+    history: history::History,
+    last_gc: SystemTime,
+    queued_interval: Option<Duration>,
+    last_queued: SystemTime,
+    last_seen: HashMap<String, SystemTime>,
+    peer_stats: HashMap<String, peers::PeerStats>,
+    // `<socket>=<endpoint>` of the bound sockets, see endpoints.rs
+    endpoints: Vec<(String, String)>,
+    // the features each worker declared, see features.rs
+    worker_features: HashMap<String, HashSet<String>>,
+    // see weights.rs
+    worker_weights: HashMap<String, usize>,
+    // tasks tagged with a group, see groups.rs
+    groups: groups::Groups,
+    // tasks sent as parts, see split.rs
+    splits: split::Splits,
+    bind_identities: bool,
+    // address of the peer using each identity
+    identities: HashMap<String, String>,
+    control_secret: Option<signature::ControlSecret>,
+    audit: Audit,
+    redaction: redaction::Redaction,
+    admin_users: Option<roles::Users>,
+    schemas: schema::Schemas,
+    // new tasks are refused once the broker is stopping, it exits at this date at most
+    stop_deadline: Option<SystemTime>,
+    // the tasks the broker had when it was told to stop, see shutdown.rs
+    stopping: Option<shutdown::Stopping>,
+    // what was recovered from the state file at startup, see recovery.rs
+    #[cfg(feature = "persistence")]
+    recovery: Option<recovery::Recovery>,
+    debug: Debug,
+    clock: Rc<dyn Clock>,
+}
+
+impl Broker {
+    // the broker doesn't start with a storage key it can't read, rather than writing payloads in plaintext
+    fn new(clock: Rc<dyn Clock>) -> Result<Broker, String> {
+        #[cfg(feature = "persistence")]
+        let cipher = encryption::Cipher::from_env()
+            .map_err(|error| format!("can't get the storage key: {}", error))?;
+        Ok(Broker {
+            timeout_as_secs: env::var("TASK_TIMEOUT")
+                .map(|v| v.parse::<u64>().unwrap_or(60))
+                .unwrap_or(60),
+            registry: Registry::default(),
+            dispatcher: Dispatcher::default(),
+            events: None,
+            mirror: Mirror::from_env(),
+            ingest: Ingest::from_env(),
+            cluster: None,
+            alerts: Alerts::new(),
+            worker_stats: HashMap::new(),
+            topic_stats: HashMap::new(),
+            slow_worker_factor: stats::slow_worker_factor(),
+            poison_threshold: dlq::poison_threshold(),
+            dead_letters_capacity: dlq::dead_letters_capacity(),
+            declared_topics: HashMap::new(),
+            declared_topics_only: topics::declared_topics_only(),
+            max_in_flight: ceilings::max_in_flight(),
+            fairness: fairness::Fairness::default(),
+            fair_quantum: fairness::fair_quantum(),
+            max_pending_tasks: limits::max_pending_tasks(),
+            accepted_acks: accepted::accepted_acks(),
+            workflows: HashMap::new(),
+            #[cfg(feature = "persistence")]
+            results: Results::from_env().stored(cipher.clone()),
+            #[cfg(not(feature = "persistence"))]
+            results: Results::from_env(),
+            #[cfg(feature = "persistence")]
+            cipher,
+            #[cfg(feature = "persistence")]
+            state_dir: state::state_dir(),
+            blobs: Blobs::from_env(),
+            waits: vec![],
+            direct_endpoints: HashMap::new(),
+            direct_workers: HashSet::new(),
+            draining_workers: HashSet::new(),
+            paused_topics: HashSet::new(),
+            worker_epochs: HashMap::new(),
+            logical_names: HashMap::new(),
+            ipc_permissions: ipc::ipc_permissions(),
+            idle_ttl_as_secs: gc::idle_ttl_as_secs(),
+            client_ttl: gc::client_ttl(),
+            undelivered: undelivered::Undelivered::from_env(),
+            receipts: receipts::Receipts::from_env(),
+            history: history::History::new(clock.now()),
+            last_gc: clock.now(),
+            queued_interval: queued::queued_interval(),
+            last_queued: clock.now(),
+            last_seen: HashMap::new(),
+            peer_stats: HashMap::new(),
+            endpoints: vec![],
+            worker_features: HashMap::new(),
+            worker_weights: HashMap::new(),
+            groups: groups::Groups::default(),
+            splits: split::Splits::default(),
+            bind_identities: identities::bind_identities(),
+            identities: HashMap::new(),
+            control_secret: signature::control_secret_from_env(),
+            audit: Audit::from_env(),
+            redaction: redaction::Redaction::from_env(),
+            admin_users: roles::admin_users(),
+            schemas: schema::Schemas::from_env(),
+            stop_deadline: None,
+            stopping: None,
+            #[cfg(feature = "persistence")]
+            recovery: None,
+            debug: Debug::from_env(),
+            clock,
+        })
+    }
+
+    // a whole message received on the router socket
+    fn handle_message(&mut self, transport: &dyn Transport, message: &Incoming) {
+        let Incoming {
+            identity,
+            control,
+            topic,
+            response_topic,
+            payload,
+            uid,
+            ..
+        } = message;
+        if !self.record_peer_message(message) || self.identity_conflict(transport, message) {
+            return;
+        }
+        let now = self.now();
+        let first_contact = self.last_seen.insert(identity.clone(), now).is_none();
+        if let Some(client) = self.registry.clients.get_mut(identity) {
+            client.last_activity = now;
+        }
+        self.redeliver(transport, identity);
+
+        match control {
+            Some(Control::Ping) => {
+                // if identity is unknown, ask for reconnexion
+                // it happens when the broker is down and reconnect in between 2 worker pings
+                if identity.starts_with("worker")
+                    && !self.registry.clients.contains_key(identity)
+                    && !self.is_draining(identity)
+                {
+                    transport.send(identity, &["", "@@REGISTER"]).ok();
+                }
+                // same for clients: a client pinging first may have been waiting on the previous broker
+                if first_contact
+                    && identity.starts_with("client")
+                    && !self.registry.clients.contains_key(identity)
+                {
+                    transport.send(identity, &["", "@@RESUBSCRIBE"]).ok();
+                }
+                transport.send(identity, &["", "@@PONG"]).ok();
+            }
+            Some(Control::Members) => {
+                // the brokers of the cluster, so peers can bootstrap from any of them
+                let endpoints = self.cluster_endpoints().join(",");
+                transport
+                    .send(identity, &["", "@@MEMBERS", &endpoints])
+                    .ok();
+            }
+            Some(Control::Result) => self.send_result(transport, identity, response_topic),
+            Some(Control::Direct) => self.set_direct_endpoint(identity, response_topic),
+            Some(Control::Done) if !self.dispatcher.is_sent_to(response_topic, identity) => {
+                log::warn(&format!(
+                    "Worker {} said task {} is done but it wasn't sent to it, ignored",
+                    identity, response_topic
+                ));
+            }
+            Some(Control::Done) => {
+                // the worker sent the response to the client itself
+                self.complete(transport, response_topic, None, &[]);
+            }
+            Some(Control::Retry) => self.retry_task(transport, identity, response_topic, payload),
+            Some(Control::Wait) => self.wait_result(transport, identity, response_topic, payload),
+            Some(Control::Workflow) => {
+                self.handle_workflow(transport, identity, *uid, response_topic, payload)
+            }
+            Some(Control::Register)
+            | Some(Control::Unregister)
+            | Some(Control::SubscribeTopic)
+            | Some(Control::UnsubscribeTopic)
+                if !self.is_signed(message) =>
+            {
+                log::warn(&format!("Bad signature of {} from {}", topic, identity));
+                transport
+                    .send(identity, &["", "@@BAD_SIGNATURE", response_topic])
+                    .ok();
+            }
+            Some(Control::Register) => {
+                // new worker, we can retry tasks
+                if self.register(transport, identity, *uid, response_topic, payload) {
+                    self.retry_tasks(transport);
+                }
+            }
+            Some(Control::Unregister) => {
+                // the worker is leaving, it won't get new tasks but its running tasks are still answered
+                self.remove_worker(identity);
+            }
+            Some(Control::SubscribeTopic) => {
+                // the worker serves more topics, their waiting tasks can be sent
+                if self.subscribe_topics(transport, identity, *uid, response_topic, payload) {
+                    self.retry_tasks(transport);
+                }
+            }
+            Some(Control::UnsubscribeTopic) => self.unsubscribe_topics(identity, response_topic),
+            Some(Control::Subscribe) => {
+                // client waits for responses on a topic without sending a task
+                if !response_topic.is_empty() {
+                    self.add_client(false, identity, response_topic);
+                }
+            }
+            Some(Control::Unsubscribe) => self.remove_client_from_topic(identity, response_topic),
+            Some(Control::Group) => {
+                self.subscribe_group(transport, identity, response_topic, payload)
+            }
+            Some(Control::Received) => self.confirm_receipt(identity, response_topic),
+            None if response_topic.is_empty() && self.is_stale_response(identity, topic) => {
+                log::info(&format!(
+                    "Ignoring the response {} of worker {}, it restarted",
+                    topic, identity
+                ));
+            }
+            None if response_topic.is_empty() => {
+                // worker response
+                // TODO: find an other way, because a client may want to trigger an async action without waiting for acknowledgment
+                let payload = self.claim_check(topic, "response", payload);
+                let headers = headers::parse_headers(&message.headers).unwrap_or_else(|error| {
+                    log::warn(&format!(
+                        "Ignoring the headers of the response {}: {}",
+                        topic, error
+                    ));
+                    vec![]
+                });
+                self.complete(transport, topic, Some(&payload), &headers);
+            }
+            None => self.handle_task(transport, message),
+        }
+    }
+
+    // a task from a client, refused, merged with a copy already sent, or submitted
+    fn handle_task(&mut self, transport: &dyn Transport, message: &Incoming) {
+        let Incoming {
+            identity,
+            topic,
+            response_topic,
+            payload,
+            partition_key,
+            dependencies,
+            headers,
+            uid,
+            ..
+        } = message;
+
+        if let Some(rejection) = self.task_rejection(*uid, topic) {
+            transport
+                .send(identity, &["", rejection, response_topic])
+                .ok();
+            self.reject_in_group(transport, headers);
+        } else if let Some(violation) = self.schema_violation(topic, payload) {
+            self.reject_in_group(transport, headers);
+            transport
+                .send(
+                    identity,
+                    &[
+                        "",
+                        "@@ERROR",
+                        response_topic,
+                        "schema_violation",
+                        &violation,
+                    ],
+                )
+                .ok();
+        } else if let Some(limit) = self.over_pending_limit(identity, response_topic) {
+            log::warn(&format!(
+                "Client {} waits on {} tasks already, refusing {}",
+                identity, limit, response_topic
+            ));
+            self.reject_in_group(transport, headers);
+            transport
+                .send(
+                    identity,
+                    &[
+                        "",
+                        "@@ERROR",
+                        response_topic,
+                        "too_many_requests",
+                        &limit.to_string(),
+                    ],
+                )
+                .ok();
+        } else if self.is_duplicate(topic, response_topic) {
+            // the client waits for the response of the first copy
+            log::info(&format!("Task {} already sent, merging it", response_topic));
+            self.add_client(false, identity, response_topic);
+            self.acknowledge(transport, identity, response_topic);
+        } else {
+            // client ask for something
+            self.add_client(false, identity, response_topic);
+            self.emit(
+                "task.created",
+                &[
+                    ("topic", topic),
+                    ("responseTopic", response_topic),
+                    ("client", identity),
+                ],
+            );
+            self.mirror_task(topic, response_topic, payload);
+            let worker_topic = self.route(topic, payload);
+            let payload = self.claim_check(response_topic, "task", payload);
+            let mut task = Task::new(&worker_topic, response_topic, &payload);
+            if self.is_ordered(&worker_topic) && !partition_key.is_empty() {
+                task.partition_key = Some(partition_key.clone());
+            }
+            task.dependencies = dependencies::parse_dependencies(dependencies);
+            task.direct_endpoint = self.direct_endpoint(identity);
+            task.client = Some(identity.clone());
+            task.headers = headers::parse_headers(headers).unwrap_or_else(|error| {
+                log::warn(&format!(
+                    "Ignoring the headers of the task {}: {}",
+                    response_topic, error
+                ));
+                vec![]
+            });
+            self.join_group(&task);
+            if let Some(task) = self.split(transport, task) {
+                self.submit(transport, task);
+            }
+            self.acknowledge(transport, identity, response_topic);
+        }
+    }
+
+    // time based rules, run regularly even without messages
+    fn tick(&mut self, transport: &dyn Transport) {
+        self.retry_timeout_tasks(transport);
+        self.retry_delayed_tasks(transport);
+        self.release_ceilings(transport);
+        self.expire_undelivered();
+        self.resend_unconfirmed(transport);
+        self.shutdown_drained_workers(transport);
+        self.ingest_tasks(transport);
+        self.send_queue_positions(transport);
+        // dependencies may be done without response (at most once tasks)
+        self.release_dependents(transport);
+        self.check_workflows(transport);
+        self.expire_waits(transport);
+        self.check_alerts();
+        self.snapshot_topics();
+        self.collect_garbage();
+        self.cluster_round();
+    }
+}
+
+// TODO: don't use strings
+pub fn run() {
+    // subcommands are tools around the broker, the broker starts when there is none
+    let args: Vec<String> = env::args().skip(1).collect();
+    let subcommand: Option<cli::Subcommand> = match args.first().map(|arg| arg.as_str()) {
+        Some("protocol") => Some(protocol::run),
+        Some("proxy") => Some(proxy::proxy),
+        Some("replay") => Some(proxy::replay),
+        Some("loadgen") => Some(loadgen::loadgen),
+        Some("simulate") => Some(simulation::simulate),
+        Some("sign") => Some(signature::run),
+        Some("codegen") => Some(schema::codegen),
+        Some("worker-webhook") => Some(workers::webhook),
+        Some("worker-exec") => Some(workers::exec),
+        Some("tunnel") => Some(tunnel::tunnel),
+        Some("graph") => Some(graph::run),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        match subcommand(&args[1..]) {
+            Ok(output) => println!("{}", output),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(2);
+            }
+        }
+        return;
+    }
+
+    // `--container` is the profile of a broker run by an orchestrator, see container.rs
+    let container = container::is_container(&args);
+    log::set_format(container);
+    if container {
+        container::handle_stop_signals();
+    }
+    // variables of the features the broker was built without, see flags.rs
+    flags::warn_ignored_variables();
+
+    let context = zmq::Context::new();
+    let options = SocketOptions::from_env();
+    // the router errors if a worker can't be reached
+    let port = env::var("PORT")
+        .map(|v| v.parse::<u16>().unwrap_or(3000))
+        .unwrap_or(3000);
+    // a wrong `BIND_ADDRESS` or port stops the broker before it binds anything
+    let endpoint = |port: &dyn std::fmt::Display| {
+        container::endpoint(port).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(2);
+        })
+    };
+    // protocol 1 and 2 are spoken at once, see envelope.rs
+    let mut router = Envelopes::new(Router::bind(&context, &endpoint(&port), &options).unwrap());
+    // with port 0, the one the OS picked
+    let port = endpoints::port_of(&router.endpoint()).unwrap_or(port);
+    let mut bound = vec![("broker".to_string(), router.endpoint())];
+    // local peers can use a unix socket instead, they are known by their uid
+    if let Some(path) = ipc::ipc_path() {
+        router.bind_local(&context, &path, &options).unwrap();
+        bound.push(("local".to_string(), format!("ipc://{}", path)));
+    }
+    // peers can connect over QUIC too, see quic.rs
+    #[cfg(feature = "quic")]
+    if let Some(port) = quic::quic_port() {
+        let quic = container::socket_address(&port, false)
+            .and_then(|address| quic::QuicServer::from_env(&address))
+            .unwrap_or_else(|error| {
+                eprintln!("Can't serve QUIC: {}", error);
+                process::exit(2);
+            });
+        bound.push(("quic".to_string(), quic.endpoint()));
+        router.serve_quic(quic);
+    }
+
+    // the admin socket is optional, it is only opened when a port is given
+    let admin_socket = env::var("ADMIN_PORT").ok().map(|port| {
+        let admin_socket = context.socket(SocketType::REP).unwrap();
+        options.apply(&admin_socket).unwrap();
+        admin_socket.bind(&endpoint(&port)).unwrap();
+        bound.push((
+            "admin".to_string(),
+            endpoints::bound_endpoint(&admin_socket),
+        ));
+        admin_socket
+    });
+
+    // the events socket is optional too, it publishes what happens in the broker
+    let events_socket = env::var("EVENTS_PORT").ok().map(|port| {
+        let events_socket = context.socket(SocketType::PUB).unwrap();
+        options.apply(&events_socket).unwrap();
+        events_socket.bind(&endpoint(&port)).unwrap();
+        bound.push((
+            "events".to_string(),
+            endpoints::bound_endpoint(&events_socket),
+        ));
+        events_socket
+    });
+
+    // the mDNS advertisement is optional, the broker still runs when it can't be done (port 5353 taken)
+    let advertiser = if discovery::mdns() {
+        match discovery::Advertiser::bind(port).and_then(|advertiser| {
+            advertiser.announce()?;
+            Ok(advertiser)
+        }) {
+            Ok(advertiser) => {
+                log::info(&format!("Advertising {} with mDNS", advertiser.endpoint()));
+                Some(advertiser)
+            }
+            Err(error) => {
+                log::warn(&format!("Can't advertise with mDNS: {}", error));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut broker = Broker::new(Rc::new(SystemClock)).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
+    broker.events = events_socket.map(Events::start);
+    // the dashboard is optional too, it is served over HTTP, see web.rs
+    // anyone reaching it runs admin commands unless they are authenticated, it is only served to this host then
+    #[cfg(feature = "http")]
+    let web_server = env::var("HTTP_PORT").ok().map(|port| {
+        let authenticated = broker.admin_users.is_some() || broker.control_secret.is_some();
+        let address = container::socket_address(&port, !authenticated).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(2);
+        });
+        if !authenticated {
+            log::warn(
+                "Serving HTTP on the loopback only, set ADMIN_USERS or CONTROL_SECRET to serve it",
+            );
+        }
+        let web_server = web::WebServer::bind(&address).unwrap_or_else(|error| {
+            eprintln!("Can't serve HTTP on {}: {}", address, error);
+            process::exit(2);
+        });
+        bound.push(("http".to_string(), web_server.endpoint()));
+        web_server
+    });
+    // the cluster is optional too, brokers forward the tasks they have no worker for to their peers
+    broker.cluster = Cluster::from_env(&context, port, &options).unwrap();
+    if let Some(cluster) = &broker.cluster {
+        log::info(&format!("Joining the cluster as {}", cluster.name()));
+        bound.push(("cluster".to_string(), cluster.peering_endpoint()));
+    }
+    broker.set_endpoints(bound);
+    // the tasks a previous broker couldn't answer before it stopped
+    #[cfg(feature = "persistence")]
+    broker.recover_from_env(&router);
+
+    // the sockets are bound, peers can connect
+    let endpoint_file = endpoints::EndpointFile::from_env();
+    endpoint_file.write(&broker.endpoints);
+    let ready_file = container::ReadyFile::from_env(container);
+    ready_file.ready();
+    let stop_timeout = container::stop_timeout();
+
+    loop {
+        // busy peers are sent their waiting messages once the router can take them, see transport.rs
+        let flush_delay = router.flush_delay();
+        let events = match flush_delay {
+            Some(delay) if delay.is_zero() => zmq::POLLIN | zmq::POLLOUT,
+            _ => zmq::POLLIN,
+        };
+        let mut items = router.poll_items(events);
+        let router_count = items.len();
+        if let Some(admin_socket) = &admin_socket {
+            items.push(admin_socket.as_poll_item(zmq::POLLIN));
+        }
+        let cluster_index = items.len();
+        if let Some(cluster) = &broker.cluster {
+            items.extend(
+                cluster
+                    .sockets()
+                    .iter()
+                    .map(|socket| socket.as_poll_item(zmq::POLLIN)),
+            );
+        }
+        let advertiser_index = items.len();
+        if let Some(advertiser) = &advertiser {
+            items.push(zmq::PollItem::from_fd(advertiser.as_raw_fd(), zmq::POLLIN));
+        }
+        #[cfg(feature = "http")]
+        let web_index = items.len();
+        #[cfg(feature = "http")]
+        if let Some(web_server) = &web_server {
+            items.push(zmq::PollItem::from_fd(web_server.as_raw_fd(), zmq::POLLIN));
+        }
+        // wake up regularly, even without messages, to retry timed out tasks, evaluate the alert rules,
+        // and collect garbage
+        // a stop signal interrupts the poll
+        let timeout = match flush_delay {
+            Some(delay) if !delay.is_zero() => delay.as_millis().clamp(1, 1000) as i64,
+            _ => 1000,
+        };
+        match zmq::poll(&mut items, timeout) {
+            Err(zmq::Error::EINTR) => {}
+            result => {
+                result.unwrap();
+            }
+        }
+        let socket_readable = items[..router_count].iter().any(|item| item.is_readable());
+        let socket_writable = items[..router_count].iter().any(|item| item.is_writable());
+        let admin_readable = admin_socket.is_some() && items[router_count].is_readable();
+        let cluster_readable = items[cluster_index..advertiser_index]
+            .iter()
+            .any(|item| item.is_readable());
+        let advertiser_readable = advertiser.is_some()
+            && items
+                .get(advertiser_index)
+                .is_some_and(|item| item.is_readable());
+        #[cfg(feature = "http")]
+        let web_readable =
+            web_server.is_some() && items.get(web_index).is_some_and(|item| item.is_readable());
+
+        if socket_writable {
+            router
+                .flush()
+                .iter()
+                .for_each(|identity| broker.remove_worker(identity));
+        }
+
+        if cluster_readable {
+            broker.handle_cluster(&router);
+        }
+
+        if advertiser_readable {
+            if let Err(error) = advertiser.as_ref().unwrap().handle() {
+                log::warn(&format!("Can't answer a mDNS query: {}", error));
+            }
+        }
+
+        #[cfg(feature = "http")]
+        if web_readable {
+            web_server.as_ref().unwrap().handle(&mut broker, &router);
+        }
+
+        if admin_readable {
+            admin::serve(&mut broker, &router, admin_socket.as_ref().unwrap());
+        }
+
+        broker.tick(&router);
+
+        if container::stop_requested() && !broker.is_stopping() {
+            ready_file.unready();
+            broker.stop(stop_timeout);
+        }
+        if broker.is_stopped() {
+            break;
+        }
+
+        if !socket_readable {
+            continue;
+        }
+
+        match router.recv() {
+            Ok(message) => {
+                broker.handle_message(&router, &message);
+
+                if message.control != Some(Control::Ping) {
+                    broker.print_debug();
+                }
+            }
+            Err(error) => log::warn(&format!("Ignoring a message: {}", error)),
+        }
+    }
+
+    broker.shutdown_report();
+    // the sockets are closed when they are dropped, waiting messages are sent for `ZMQ_LINGER`
+    ready_file.unready();
+    endpoint_file.remove();
+    log::info("Stopped");
+}
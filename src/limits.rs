@@ -0,0 +1,45 @@
+use crate::Broker;
+use std::env;
+
+// with `MAX_PENDING_TASKS`, a client waits on that many responses at most: its next tasks are refused with
+// `@@ERROR <response topic> too_many_requests <limit>` until some are answered
+// a buggy client sending tasks in a loop can't fill the broker, a copy of a task it already waits on is not counted
+
+pub fn max_pending_tasks() -> Option<usize> {
+    env::var("MAX_PENDING_TASKS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+}
+
+impl Broker {
+    // the response topics the client waits on
+    pub fn pending_tasks(&self, identity: &str) -> usize {
+        self.registry
+            .clients
+            .get(identity)
+            .map(|client| {
+                client
+                    .topics
+                    .iter()
+                    .filter(|topic_name| {
+                        self.registry
+                            .topics
+                            .get(*topic_name)
+                            .is_some_and(|topic| topic.clients.contains(&client.name))
+                    })
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    // the limit, when the task is over it
+    pub fn over_pending_limit(&self, identity: &str, response_topic: &str) -> Option<usize> {
+        let limit = self.max_pending_tasks?;
+        let already_waiting = self
+            .registry
+            .clients_of(response_topic)
+            .iter()
+            .any(|client| client == identity);
+        (!already_waiting && self.pending_tasks(identity) >= limit).then_some(limit)
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cli;
+use std::collections::HashMap;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use zmq::{self, SocketType};
+
+// the load generator runs its own workers and client against a broker, and measures the round trips
+// every task has its own response topic (the broker completes tasks by response topic), at the start of the
+// payload so the workers know where to answer
+const USAGE: &str = "usage: tiny-broke loadgen --topic <topic> [--rate <tasks per second>] \
+                     [--payload-size <bytes, 1k, 1m>] [--duration <60s, 500ms, 2m>] [--workers <count>] \
+                     [--broker <endpoint>]";
+
+// time to wait for the last responses, once every task is sent
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn zmq_error(error: zmq::Error) -> String {
+    error.to_string()
+}
+
+fn parse_size(size: &str) -> Option<usize> {
+    let (number, unit) = match size.to_lowercase().chars().last() {
+        Some('k') => (&size[..size.len() - 1], 1024),
+        Some('m') => (&size[..size.len() - 1], 1024 * 1024),
+        _ => (size, 1),
+    };
+    number.parse::<usize>().ok().map(|number| number * unit)
+}
+
+fn worker(context: &zmq::Context, broker: &str, topic: &str, identity: &str, running: &AtomicBool) {
+    let socket = context.socket(SocketType::DEALER).unwrap();
+    socket.set_identity(identity.as_bytes()).unwrap();
+    socket.connect(broker).unwrap();
+    socket.send_multipart(["@@REGISTER", topic], 0).unwrap();
+
+    let mut last_ping = Instant::now();
+    while running.load(Ordering::Relaxed) {
+        if last_ping.elapsed() > Duration::from_secs(1) {
+            socket.send("@@PING", 0).unwrap();
+            last_ping = Instant::now();
+        }
+        if socket.poll(zmq::POLLIN, 100).unwrap_or(0) == 0 {
+            continue;
+        }
+
+        let frames = match socket.recv_multipart(0) {
+            Ok(frames) => frames,
+            Err(_) => continue,
+        };
+        match frames.get(1).map(|frame| frame.as_slice()) {
+            Some(b"@@PONG") | None => {}
+            Some(b"@@REGISTER") => socket.send_multipart(["@@REGISTER", topic], 0).unwrap(),
+            Some(payload) => {
+                let response_topic = payload.split(|c| *c == b' ').next().unwrap();
+                socket
+                    .send_multipart([response_topic, b"", payload], 0)
+                    .unwrap();
+            }
+        }
+    }
+
+    socket.send("@@UNREGISTER", 0).ok();
+}
+
+// latency percentile, in microseconds, from sorted latencies
+fn percentile(latencies: &[Duration], percent: f64) -> u128 {
+    if latencies.is_empty() {
+        return 0;
+    }
+    let index = ((latencies.len() - 1) as f64 * percent / 100.0).round() as usize;
+    latencies[index].as_micros()
+}
+
+struct Report {
+    sent: usize,
+    rejected: usize,
+    latencies: Vec<Duration>,
+    duration: Duration,
+}
+
+impl Report {
+    fn print(mut self) -> String {
+        self.latencies.sort();
+        let received = self.latencies.len();
+        let lost = self.sent - received - self.rejected;
+        let throughput = received as f64 / self.duration.as_secs_f64();
+
+        let mut lines = vec![
+            format!(
+                "sent={} received={} rejected={} lost={} throughput={:.0}/s",
+                self.sent, received, self.rejected, lost, throughput
+            ),
+            format!(
+                "latency min={}us p50={}us p90={}us p99={}us p99.9={}us max={}us",
+                percentile(&self.latencies, 0.0),
+                percentile(&self.latencies, 50.0),
+                percentile(&self.latencies, 90.0),
+                percentile(&self.latencies, 99.0),
+                percentile(&self.latencies, 99.9),
+                percentile(&self.latencies, 100.0),
+            ),
+        ];
+
+        // latency distribution, by power of two buckets
+        let mut buckets: Vec<(u128, usize)> = vec![];
+        self.latencies.iter().for_each(|latency| {
+            let bucket = latency.as_micros().max(1).next_power_of_two();
+            match buckets.last_mut() {
+                Some((last, count)) if *last == bucket => *count += 1,
+                _ => buckets.push((bucket, 1)),
+            }
+        });
+        buckets.iter().for_each(|(bucket, count)| {
+            lines.push(format!(
+                "  <= {:>8}us {:>6.2}% {}",
+                bucket,
+                *count as f64 * 100.0 / received as f64,
+                "#".repeat((*count * 50).div_ceil(received))
+            ));
+        });
+
+        lines.join("\n")
+    }
+}
+
+pub fn loadgen(args: &[String]) -> Result<String, String> {
+    let (positionals, options) = cli::options(
+        args,
+        &[
+            "topic",
+            "rate",
+            "payload-size",
+            "duration",
+            "workers",
+            "broker",
+        ],
+    )?;
+    let usage = || USAGE.to_string();
+    let topic = match (positionals.is_empty(), options.get("topic")) {
+        (true, Some(topic)) => topic.clone(),
+        _ => return Err(usage()),
+    };
+    let option = |name: &str, default: &str| options.get(name).cloned().unwrap_or(default.into());
+    let rate: f64 = option("rate", "100")
+        .parse()
+        .ok()
+        .filter(|rate| *rate > 0.0)
+        .ok_or_else(usage)?;
+    let payload_size = parse_size(&option("payload-size", "100")).ok_or_else(usage)?;
+    let duration = cli::parse_duration(&option("duration", "10s")).ok_or_else(usage)?;
+    let workers: usize = option("workers", "1")
+        .parse()
+        .ok()
+        .filter(|workers| *workers > 0)
+        .ok_or_else(usage)?;
+    let broker = option("broker", "tcp://localhost:3000");
+
+    let context = zmq::Context::new();
+    let running = Arc::new(AtomicBool::new(true));
+    let handles: Vec<thread::JoinHandle<()>> = (0..workers)
+        .map(|index| {
+            let (context, broker, topic) = (context.clone(), broker.clone(), topic.clone());
+            let running = running.clone();
+            let identity = format!("worker-loadgen-{}-{}", process::id(), index);
+            thread::spawn(move || worker(&context, &broker, &topic, &identity, &running))
+        })
+        .collect();
+    // let the workers register
+    thread::sleep(Duration::from_millis(500));
+
+    let client = context.socket(SocketType::DEALER).map_err(zmq_error)?;
+    let identity = format!("client-loadgen-{}", process::id());
+    client
+        .set_identity(identity.as_bytes())
+        .map_err(zmq_error)?;
+    client.connect(&broker).map_err(zmq_error)?;
+
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    let padding = "x".repeat(payload_size);
+    let mut pending: HashMap<String, Instant> = HashMap::new();
+    let mut report = Report {
+        sent: 0,
+        rejected: 0,
+        latencies: vec![],
+        duration: Duration::default(),
+    };
+
+    println!(
+        "sending {}/s tasks of {} bytes to {} for {}s, with {} workers",
+        rate,
+        payload_size,
+        topic,
+        duration.as_secs_f64(),
+        workers
+    );
+    let start = Instant::now();
+    let mut next_send = start;
+
+    loop {
+        let now = Instant::now();
+        let sending = now < start + duration;
+        if !sending && (pending.is_empty() || now > start + duration + DRAIN_TIMEOUT) {
+            break;
+        }
+
+        // tasks late on the schedule are sent in a burst, to keep the rate
+        while sending && next_send <= now {
+            let response_topic = format!("LOADGEN>RESPONSE@@{}-{}", process::id(), report.sent);
+            let payload = format!("{} {}", response_topic, padding);
+            client
+                .send_multipart([topic.as_str(), &response_topic, &payload], 0)
+                .map_err(zmq_error)?;
+            pending.insert(response_topic, Instant::now());
+            report.sent += 1;
+            next_send += interval;
+        }
+
+        let wait = next_send.saturating_duration_since(Instant::now());
+        if client
+            .poll(zmq::POLLIN, wait.as_millis().max(1) as i64)
+            .map_err(zmq_error)?
+            == 0
+        {
+            continue;
+        }
+
+        while let Ok(frames) = client.recv_multipart(zmq::DONTWAIT) {
+            let frames: Vec<String> = frames
+                .iter()
+                .map(|frame| String::from_utf8_lossy(frame).into_owned())
+                .collect();
+            match frames.as_slice() {
+                [_, rejection, response_topic]
+                    if rejection.starts_with("@@") && pending.remove(response_topic).is_some() =>
+                {
+                    report.rejected += 1;
+                }
+                [_, payload] => {
+                    let response_topic = payload.split(' ').next().unwrap();
+                    if let Some(sent) = pending.remove(response_topic) {
+                        report.latencies.push(sent.elapsed());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    report.duration = start.elapsed();
+
+    running.store(false, Ordering::Relaxed);
+    handles
+        .into_iter()
+        .for_each(|handle| handle.join().unwrap());
+
+    Ok(report.print())
+}
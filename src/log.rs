@@ -0,0 +1,78 @@
+use crate::json;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// what the broker tells goes to stdout, one line each
+// with `LOG_FORMAT=json` (the default of the container profile) lines are JSON objects, for log collectors:
+// `{"date": <milliseconds since the epoch>, "level": "info" or "warn", "message": ...}`
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy)]
+enum Level {
+    Info,
+    Warn,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warn => "warn",
+        }
+    }
+}
+
+pub fn set_format(container: bool) {
+    let json = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => true,
+        Ok("text") => false,
+        _ => container,
+    };
+    JSON.store(json, Ordering::Relaxed);
+}
+
+fn json_line(date: u128, level: Level, message: &str) -> String {
+    format!(
+        "{{\"date\":{},\"level\":\"{}\",\"message\":{}}}",
+        date,
+        level.name(),
+        json::string(message)
+    )
+}
+
+fn print(level: Level, message: &str) {
+    if !JSON.load(Ordering::Relaxed) {
+        println!("{}", message);
+        return;
+    }
+
+    let date = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    println!("{}", json_line(date, level, message));
+}
+
+pub fn info(message: &str) {
+    print(Level::Info, message);
+}
+
+// something went wrong, the broker goes on without it
+pub fn warn(message: &str) {
+    print(Level::Warn, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_line, Level};
+
+    #[test]
+    fn json_lines_escape_the_message() {
+        assert_eq!(
+            json_line(3, Level::Warn, "Can't write \"a\"\n"),
+            r#"{"date":3,"level":"warn","message":"Can't write \"a\"\n"}"#
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{json, log, Broker};
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// tasks and responses of the topics declared with `mirror=tasks|responses|all` are copied to NATS (`MIRROR_URL`),
+// for archival and analytics, without slowing down the broker: a background thread publishes them in batches, and
+// keeps them (up to `MIRROR_BUFFER`) while NATS can't be reached, trying again with an exponential backoff
+// records are published on `<MIRROR_SUBJECT>.tasks` and `<MIRROR_SUBJECT>.responses`, as JSON objects:
+// `{"topic": ..., "responseTopic": ..., "payload": ..., "date": <milliseconds since the epoch>}`
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mirrored {
+    #[default]
+    Nothing,
+    Tasks,
+    Responses,
+    All,
+}
+
+impl Mirrored {
+    pub fn parse(value: &str) -> Result<Mirrored, String> {
+        match value {
+            "tasks" => Ok(Mirrored::Tasks),
+            "responses" => Ok(Mirrored::Responses),
+            "all" => Ok(Mirrored::All),
+            _ => Err(format!(
+                "unknown mirror {}, expected tasks, responses or all",
+                value
+            )),
+        }
+    }
+
+    fn tasks(self) -> bool {
+        self == Mirrored::Tasks || self == Mirrored::All
+    }
+
+    fn responses(self) -> bool {
+        self == Mirrored::Responses || self == Mirrored::All
+    }
+}
+
+struct Record {
+    subject: String,
+    content: String,
+}
+
+pub struct Mirror {
+    sender: Sender<Record>,
+    subject: String,
+}
+
+struct Settings {
+    address: String,
+    batch: usize,
+    interval: Duration,
+    buffer: usize,
+}
+
+fn parse_url(url: &str) -> Result<String, String> {
+    let host = url
+        .strip_prefix("nats://")
+        .ok_or_else(|| format!("only nats:// urls are supported, not {}", url))?
+        .trim_end_matches('/');
+    Ok(match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:4222", host),
+    })
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .map(|v| v.parse::<u64>().unwrap_or(default))
+        .unwrap_or(default)
+}
+
+fn publication(record: &Record) -> String {
+    format!(
+        "PUB {} {}\r\n{}\r\n",
+        record.subject,
+        record.content.len(),
+        record.content
+    )
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "can't resolve host"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    // the server says hello with its `INFO` first
+    let mut info = [0; 4096];
+    if stream.read(&mut info)? == 0 {
+        return Err(Error::new(ErrorKind::ConnectionAborted, "closed by NATS"));
+    }
+    stream.write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")?;
+    Ok(stream)
+}
+
+// NATS closes the connections that don't answer its pings
+fn answer_pings(stream: &mut TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let mut received = [0; 4096];
+    let read = match stream.read(&mut received) {
+        Ok(0) => return Err(Error::new(ErrorKind::ConnectionAborted, "closed by NATS")),
+        Ok(read) => read,
+        Err(error)
+            if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut =>
+        {
+            0
+        }
+        Err(error) => return Err(error),
+    };
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    if String::from_utf8_lossy(&received[..read]).contains("PING") {
+        stream.write_all(b"PONG\r\n")?;
+    }
+    Ok(())
+}
+
+struct Publisher {
+    settings: Settings,
+    stream: Option<TcpStream>,
+    buffer: VecDeque<Record>,
+    dropped: usize,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl Publisher {
+    fn keep(&mut self, record: Record) {
+        if self.buffer.len() >= self.settings.buffer {
+            self.buffer.pop_front();
+            self.dropped += 1;
+        }
+        self.buffer.push_back(record);
+    }
+
+    fn publish(&mut self) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(connect(&self.settings.address)?);
+        }
+        let stream = self.stream.as_mut().unwrap();
+        answer_pings(stream)?;
+
+        while !self.buffer.is_empty() {
+            let count = self.buffer.len().min(self.settings.batch);
+            let batch: String = self.buffer.iter().take(count).map(publication).collect();
+            stream.write_all(batch.as_bytes())?;
+            self.buffer.drain(..count);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if Instant::now() < self.retry_at {
+            return;
+        }
+        if self.dropped > 0 {
+            log::warn(&format!(
+                "Mirror buffer is full, {} records were dropped",
+                self.dropped
+            ));
+            self.dropped = 0;
+        }
+
+        match self.publish() {
+            Ok(()) => self.backoff = Duration::from_secs(0),
+            Err(error) => {
+                self.stream = None;
+                self.backoff = (self.backoff * 2)
+                    .max(Duration::from_secs(1))
+                    .min(MAX_BACKOFF);
+                self.retry_at = Instant::now() + self.backoff;
+                log::warn(&format!(
+                    "Can't mirror to {}, trying again in {} seconds: {}",
+                    self.settings.address,
+                    self.backoff.as_secs(),
+                    error
+                ));
+            }
+        }
+    }
+
+    // records are published once a batch is full, or every interval
+    fn run(mut self, receiver: Receiver<Record>) {
+        let mut last_flush = Instant::now();
+        loop {
+            match receiver.recv_timeout(self.settings.interval) {
+                Ok(record) => self.keep(record),
+                Err(RecvTimeoutError::Timeout) => {}
+                // the broker is gone
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.retry_at = Instant::now();
+                    self.flush();
+                    return;
+                }
+            }
+            while let Ok(record) = receiver.try_recv() {
+                self.keep(record);
+            }
+
+            if self.buffer.len() >= self.settings.batch
+                || last_flush.elapsed() >= self.settings.interval
+            {
+                self.flush();
+                last_flush = Instant::now();
+            }
+        }
+    }
+}
+
+impl Mirror {
+    fn start(settings: Settings, subject: String) -> Mirror {
+        let (sender, receiver) = mpsc::channel::<Record>();
+        let publisher = Publisher {
+            settings,
+            stream: None,
+            buffer: VecDeque::new(),
+            dropped: 0,
+            backoff: Duration::from_secs(0),
+            retry_at: Instant::now(),
+        };
+        thread::spawn(move || publisher.run(receiver));
+
+        Mirror { sender, subject }
+    }
+
+    pub fn from_env() -> Option<Mirror> {
+        let url = env::var("MIRROR_URL").ok()?;
+        let address = parse_url(&url).unwrap_or_else(|error| {
+            log::warn(&format!("Can't mirror to {}: {}", url, error));
+            process::exit(1);
+        });
+        let settings = Settings {
+            address,
+            batch: env_number("MIRROR_BATCH", 100).max(1) as usize,
+            interval: Duration::from_millis(env_number("MIRROR_INTERVAL", 1000).max(1)),
+            buffer: env_number("MIRROR_BUFFER", 10000).max(1) as usize,
+        };
+        let subject = env::var("MIRROR_SUBJECT").unwrap_or_else(|_| "tiny-broke".to_string());
+
+        Some(Mirror::start(settings, subject))
+    }
+
+    fn send(&self, kind: &str, topic_name: &str, response_topic: &str, payload: &str) {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let content = format!(
+            "{{\"topic\":{},\"responseTopic\":{},\"payload\":{},\"date\":{}}}",
+            json::string(topic_name),
+            json::string(response_topic),
+            json::string(payload),
+            date
+        );
+        self.sender
+            .send(Record {
+                subject: format!("{}.{}", self.subject, kind),
+                content,
+            })
+            .ok();
+    }
+}
+
+impl Broker {
+    fn mirrored(&self, topic_name: &str) -> Option<(&Mirror, Mirrored)> {
+        let mirror = self.mirror.as_ref()?;
+        let mirrored = self.declared_topics.get(topic_name)?.mirror;
+        Some((mirror, mirrored))
+    }
+
+    pub fn mirror_task(&self, topic_name: &str, response_topic: &str, payload: &str) {
+        if let Some((mirror, mirrored)) = self.mirrored(topic_name) {
+            if mirrored.tasks() {
+                mirror.send("tasks", topic_name, response_topic, payload);
+            }
+        }
+    }
+
+    pub fn mirror_response(&self, topic_name: &str, response_topic: &str, payload: &str) {
+        if let Some((mirror, mirrored)) = self.mirrored(topic_name) {
+            if mirrored.responses() {
+                mirror.send("responses", topic_name, response_topic, payload);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_url, Mirror, Settings};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn only_nats_urls_are_supported() {
+        assert_eq!(parse_url("nats://localhost").unwrap(), "localhost:4222");
+        assert_eq!(parse_url("nats://nats:4223/").unwrap(), "nats:4223");
+        assert!(parse_url("kafka://localhost:9092").is_err());
+    }
+
+    #[test]
+    fn records_are_published_in_batches() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"INFO {}\r\n").unwrap();
+            BufReader::new(stream)
+                .lines()
+                .take(5)
+                .collect::<Result<Vec<String>, _>>()
+                .unwrap()
+        });
+
+        let mirror = Mirror::start(
+            Settings {
+                address,
+                batch: 2,
+                interval: Duration::from_millis(10),
+                buffer: 10,
+            },
+            "broker".to_string(),
+        );
+        mirror.send("tasks", "ADD", "ADD>1", "1+1");
+        mirror.send("responses", "ADD", "ADD>1", "2");
+
+        let lines = server.join().unwrap();
+        assert!(lines[0].starts_with("CONNECT "));
+        assert!(lines[1].starts_with("PUB broker.tasks "));
+        assert!(lines[2]
+            .starts_with(r#"{"topic":"ADD","responseTopic":"ADD>1","payload":"1+1","date":"#));
+        assert!(lines[3].starts_with("PUB broker.responses "));
+    }
+}
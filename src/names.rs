@@ -0,0 +1,69 @@
+use crate::Broker;
+
+// workers may give a logical name when they register (`name=<name>` option), unique to each worker but the same when
+// its socket identity changes (a new process, a new socket):
+// - its stats aggregate under the name (WORKERS, SLOW_WORKERS), and are kept when the worker leaves, to go on when it
+//   comes back
+// - registration epochs are the ones of the name (see epochs.rs): a new identity registering with a greater epoch
+//   supersedes the previous identity of the name
+// - admin commands naming a worker (DRAIN_WORKER, SHUTDOWN, RESTART) accept it
+// workers without a name are known by their identity
+
+pub fn parse_name(options: &str) -> Option<&str> {
+    options
+        .split(' ')
+        .find_map(|option| option.strip_prefix("name="))
+        .filter(|name| !name.is_empty())
+}
+
+impl Broker {
+    pub fn set_logical_name(&mut self, identity: &str, options: &str) {
+        if let Some(name) = parse_name(options) {
+            self.logical_names
+                .insert(identity.to_string(), name.to_string());
+        }
+    }
+
+    pub fn logical_name<'a>(&'a self, identity: &'a str) -> &'a str {
+        self.logical_names
+            .get(identity)
+            .map_or(identity, String::as_str)
+    }
+
+    // the other registered identities of the worker
+    pub fn previous_identities(&self, identity: &str) -> Vec<String> {
+        let name = match self.logical_names.get(identity) {
+            Some(name) => name,
+            None => return vec![],
+        };
+        self.logical_names
+            .iter()
+            .filter(|(other, other_name)| *other != identity && *other_name == name)
+            .map(|(other, _)| other.clone())
+            .collect()
+    }
+
+    // the identity of a registered worker, from its identity or its logical name
+    pub fn identity_of(&self, worker_name: &str) -> String {
+        if self.registry.clients.contains_key(worker_name) {
+            return worker_name.to_string();
+        }
+        self.logical_names
+            .iter()
+            .find(|(identity, name)| {
+                *name == worker_name && self.registry.clients.contains_key(*identity)
+            })
+            .map_or_else(|| worker_name.to_string(), |(identity, _)| identity.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_name;
+
+    #[test]
+    fn names_are_read_from_the_options() {
+        assert_eq!(parse_name("epoch=1 name=users-0"), Some("users-0"));
+        assert_eq!(parse_name("direct name="), None);
+    }
+}
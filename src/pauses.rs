@@ -0,0 +1,37 @@
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+
+// a paused topic keeps its tasks: they wait as if there was no worker (and count in its `max_queue`), its running
+// tasks are still answered, `PAUSE <topic>` and `RESUME <topic>` admin commands
+// pauses are lost when the broker restarts, unless they are exported (`EXPORT`, `STOP_STATE_FILE`) with the tasks
+
+impl Broker {
+    pub fn is_paused(&self, topic_name: &str) -> bool {
+        self.paused_topics.contains(topic_name)
+    }
+
+    pub fn pause_topic(&mut self, topic_name: &str) -> Result<(), String> {
+        if !self.paused_topics.insert(topic_name.to_string()) {
+            return Err(format!("{} is already paused", topic_name));
+        }
+        log::info(&format!("Pausing {}", topic_name));
+        self.emit("topic.paused", &[("topic", topic_name)]);
+        Ok(())
+    }
+
+    // the waiting tasks of the topic are sent
+    pub fn resume_topic(
+        &mut self,
+        transport: &dyn Transport,
+        topic_name: &str,
+    ) -> Result<(), String> {
+        if !self.paused_topics.remove(topic_name) {
+            return Err(format!("{} is not paused", topic_name));
+        }
+        log::info(&format!("Resuming {}", topic_name));
+        self.emit("topic.resumed", &[("topic", topic_name)]);
+        self.retry_tasks(transport);
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use crate::transport::Incoming;
+use crate::{log, Broker};
+use std::time::SystemTime;
+
+// protocol counters of each peer, so a misbehaving peer is diagnosed from the admin socket (`PEER <identity>`)
+// without capturing its traffic: its messages and frames, the malformed ones (too many frames, not UTF-8) with the
+// last error, and its last message
+// they are forgotten with the peer, once it didn't send anything for `IDLE_TTL`
+
+#[derive(Debug, Clone)]
+pub struct PeerStats {
+    pub messages: u64,
+    pub frames: u64,
+    pub malformed: u64,
+    pub last_error: Option<String>,
+    // the topic (or control) of its last message
+    pub last_topic: String,
+    pub last_activity: SystemTime,
+}
+
+impl Broker {
+    // false when the message is malformed, it is then dropped
+    pub fn record_peer_message(&mut self, message: &Incoming) -> bool {
+        let now = self.now();
+        let stats = self
+            .peer_stats
+            .entry(message.identity.clone())
+            .or_insert_with(|| PeerStats {
+                messages: 0,
+                frames: 0,
+                malformed: 0,
+                last_error: None,
+                last_topic: String::new(),
+                last_activity: now,
+            });
+        stats.messages += 1;
+        stats.frames += message.frame_count as u64;
+        stats.last_activity = now;
+        stats.last_topic = message.topic.clone();
+
+        match &message.malformed {
+            Some(error) => {
+                stats.malformed += 1;
+                stats.last_error = Some(error.clone());
+                log::warn(&format!(
+                    "Ignoring a message of {}: {}",
+                    message.identity, error
+                ));
+                false
+            }
+            None => true,
+        }
+    }
+
+    // one line: the counters, the last activity, and what the broker knows of the peer
+    pub fn peer(&self, identity: &str) -> Result<String, String> {
+        let stats = self
+            .peer_stats
+            .get(identity)
+            .ok_or_else(|| format!("unknown peer {}", identity))?;
+        let client = self.registry.clients.get(identity);
+        let role = match client {
+            Some(client) if client.is_worker => "worker",
+            Some(_) => "client",
+            None => "unregistered",
+        };
+        let topics = client
+            .map(|client| client.topics.join(","))
+            .unwrap_or_default();
+
+        let mut line = format!(
+            "{} {} messages={} frames={} malformed={} idle={}ms last_topic={} topics={}",
+            identity,
+            role,
+            stats.messages,
+            stats.frames,
+            stats.malformed,
+            self.elapsed(stats.last_activity).as_millis(),
+            stats.last_topic,
+            topics
+        );
+        if let Some(features) = self.worker_features.get(identity) {
+            let mut features: Vec<&str> = features.iter().map(String::as_str).collect();
+            features.sort_unstable();
+            line.push_str(&format!(" features={}", features.join(",")));
+        }
+        if let Some(address) = self.identities.get(identity) {
+            line.push_str(&format!(" address={}", address));
+        }
+        if let Some(error) = &stats.last_error {
+            line.push_str(&format!(" last_error={}", error));
+        }
+        Ok(line)
+    }
+}
@@ -0,0 +1,781 @@
+use crate::json;
+use crate::jsonrpc;
+use crate::roles::Role;
+
+// the frame protocol, as spoken on the ROUTER socket
+// peers are DEALER sockets: their identity (starting with `worker` or `client`) is added by zmq,
+// so it is not part of the frames listed here
+// this is the reference `tiny-broke protocol describe` prints, and the interop clients are generated from
+pub const VERSION: u32 = 1;
+
+pub struct Frame {
+    pub name: &'static str,
+    // fixed value of the frame, `None` when it is free
+    pub value: Option<&'static str>,
+    pub description: &'static str,
+}
+
+pub struct Message {
+    pub name: &'static str,
+    // `peer>broker` or `broker>peer`
+    pub direction: &'static str,
+    pub frames: &'static [Frame],
+    pub description: &'static str,
+}
+
+const fn fixed(name: &'static str, value: &'static str, description: &'static str) -> Frame {
+    Frame {
+        name,
+        value: Some(value),
+        description,
+    }
+}
+
+const fn free(name: &'static str, description: &'static str) -> Frame {
+    Frame {
+        name,
+        value: None,
+        description,
+    }
+}
+
+const EMPTY: Frame = fixed("delimiter", "", "empty frame");
+
+pub const MESSAGES: &[Message] = &[
+    Message {
+        name: "ping",
+        direction: "peer>broker",
+        frames: &[fixed("topic", "@@PING", "heart beat")],
+        description: "sent regularly by every peer, the broker answers with a pong",
+    },
+    Message {
+        name: "register",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@REGISTER", "registration"),
+            free("worker_topic", "topic the worker handles"),
+        ],
+        description: "a worker registers to a topic",
+    },
+    Message {
+        name: "register_topics",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@REGISTER", "registration"),
+            free(
+                "worker_topics",
+                "a line per topic: `<topic> [options]`, the options of a line only apply to its topic",
+            ),
+            free("options", "space separated, for all the topics, see register_with_options"),
+        ],
+        description: "a worker registers to all its topics at once, the registration is taken or refused as a whole",
+    },
+    Message {
+        name: "unregister",
+        direction: "peer>broker",
+        frames: &[fixed("topic", "@@UNREGISTER", "unregistration")],
+        description: "a worker leaves, its running tasks can still be answered",
+    },
+    Message {
+        name: "subscribe_topic",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@SUBSCRIBE_TOPIC", "more topics"),
+            free("worker_topics", "a line per topic: `<topic> [options]`, like register_topics"),
+            free("options", "space separated, for all the topics: `direct`, `labels=`, `weight=`"),
+        ],
+        description: "a registered worker serves more topics, without registering again",
+    },
+    Message {
+        name: "unsubscribe_topic",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@UNSUBSCRIBE_TOPIC", "less topics"),
+            free("worker_topics", "a line per topic"),
+        ],
+        description: "a worker stops serving topics (and their labelled topics), its running tasks can still be answered",
+    },
+    Message {
+        name: "signed_register",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@REGISTER", "registration"),
+            free("worker_topic", "topic the worker handles"),
+            free("options", "`direct`, `labels=<label>,<label>` (space separated), or empty"),
+            EMPTY,
+            EMPTY,
+            free(
+                "headers",
+                "`x-signature`, hexadecimal HMAC-SHA256 of `<identity>\\n@@REGISTER\\n<worker_topic>\\n<options>\\n<timestamp>` with CONTROL_SECRET, and `x-signed-at`, the timestamp in milliseconds since the epoch",
+            ),
+        ],
+        description: "a worker registers to a topic, on a broker with CONTROL_SECRET",
+    },
+    Message {
+        name: "signed_unregister",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@UNREGISTER", "unregistration"),
+            EMPTY,
+            EMPTY,
+            EMPTY,
+            EMPTY,
+            free(
+                "headers",
+                "`x-signature`, hexadecimal HMAC-SHA256 of `<identity>\\n@@UNREGISTER\\n\\n\\n<timestamp>` with CONTROL_SECRET, and `x-signed-at`, the timestamp in milliseconds since the epoch",
+            ),
+        ],
+        description: "a worker leaves, on a broker with CONTROL_SECRET",
+    },
+    Message {
+        name: "subscribe",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@SUBSCRIBE", "subscription"),
+            free("response_topic", "response topic to wait on"),
+        ],
+        description: "a client waits for responses on a topic without sending a task",
+    },
+    Message {
+        name: "group",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@GROUP", "group subscription"),
+            free("group", "the `group` header of the tasks"),
+            free("size", "number of tasks of the group, or empty for the ones sent before"),
+        ],
+        description: "a client waits for the tasks of a group to be answered or failed",
+    },
+    Message {
+        name: "received",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@RECEIVED", "receipt"),
+            free("response_topic", "of the task sent with the `receipt` header"),
+        ],
+        description: "a client confirms a response, which isn't sent again",
+    },
+    Message {
+        name: "group_done",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@GROUP_DONE", "group completion"),
+            free("group", "the group subscribed to"),
+            free("succeeded", "number of tasks answered"),
+            free("failed", "number of tasks that won't be answered"),
+        ],
+        description: "the tasks of the group are answered or failed",
+    },
+    Message {
+        name: "unsubscribe",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@UNSUBSCRIBE", "unsubscription"),
+            free("response_topic", "response topic to stop waiting on"),
+        ],
+        description: "a client stops waiting for responses on a topic",
+    },
+    Message {
+        name: "task",
+        direction: "peer>broker",
+        frames: &[
+            free("worker_topic", "topic of the workers to send the task to"),
+            free("response_topic", "topic the response is sent back to, not empty"),
+            free("payload", "task content"),
+        ],
+        description: "a client asks for something",
+    },
+    Message {
+        name: "ordered_task",
+        direction: "peer>broker",
+        frames: &[
+            free("worker_topic", "topic of the workers to send the task to"),
+            free("response_topic", "topic the response is sent back to, not empty"),
+            free("payload", "task content"),
+            free("partition_key", "tasks with the same key are sent one at a time, in order"),
+        ],
+        description: "a client asks for something, after the previous tasks of the partition on an ordered topic",
+    },
+    Message {
+        name: "dependent_task",
+        direction: "peer>broker",
+        frames: &[
+            free("worker_topic", "topic of the workers to send the task to"),
+            free("response_topic", "topic the response is sent back to, not empty"),
+            free("payload", "task content"),
+            free("partition_key", "tasks with the same key are sent one at a time, in order, empty if unordered"),
+            free("dependencies", "comma separated response topics of the tasks to answer first"),
+        ],
+        description: "a client asks for something, once other tasks are answered",
+    },
+    Message {
+        name: "task_with_headers",
+        direction: "peer>broker",
+        frames: &[
+            free("worker_topic", "topic of the workers to send the task to"),
+            free("response_topic", "topic the response is sent back to, not empty"),
+            free("payload", "task content"),
+            free("partition_key", "tasks with the same key are sent one at a time, in order, empty if unordered"),
+            free("dependencies", "comma separated response topics of the tasks to answer first, may be empty"),
+            free("headers", "JSON object of strings, given to the worker and back with the response"),
+        ],
+        description: "a client asks for something, with headers (auth tokens, trace ids, ...)",
+    },
+    Message {
+        name: "response",
+        direction: "peer>broker",
+        frames: &[
+            free("response_topic", "response topic of the task"),
+            EMPTY,
+            free("payload", "response content"),
+        ],
+        description: "a worker answers a task",
+    },
+    Message {
+        name: "response_with_headers",
+        direction: "peer>broker",
+        frames: &[
+            free("response_topic", "response topic of the task"),
+            EMPTY,
+            free("payload", "response content"),
+            EMPTY,
+            EMPTY,
+            free("headers", "JSON object of strings, added to the headers of the task"),
+        ],
+        description: "a worker answers a task, with headers",
+    },
+    Message {
+        name: "register_direct",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@REGISTER", "registration"),
+            free("worker_topic", "topic the worker handles"),
+            fixed("options", "direct", "the worker can send responses straight to the clients"),
+        ],
+        description: "a worker registers to a topic, and gets the direct endpoint of the clients with their tasks",
+    },
+    Message {
+        name: "register_with_options",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@REGISTER", "registration"),
+            free("worker_topic", "topic the worker handles"),
+            free(
+                "options",
+                "space separated: `direct`, `labels=<label>,<label>`, `epoch=<number>`, `name=<name>`, `features=<feature>,<feature>`",
+            ),
+        ],
+        description: "a worker registers to a topic, with labels the routing rules of the topic can send tasks to, the epoch of its process and its logical name",
+    },
+    Message {
+        name: "direct",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@DIRECT", "direct responses"),
+            free("endpoint", "endpoint of a ROUTER socket bound by the client, empty to stop"),
+        ],
+        description: "a client asks for the responses of its next tasks to be sent by the workers straight to this endpoint",
+    },
+    Message {
+        name: "done",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@DONE", "completion"),
+            free("response_topic", "response topic of the task"),
+        ],
+        description: "a worker sent the response of a task straight to its client, the task is done",
+    },
+    Message {
+        name: "retry",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@RETRY", "retry request"),
+            free("response_topic", "response topic of the task"),
+            free("reason", "why the task can't be processed now, for the logs"),
+        ],
+        description: "a worker can't process a task now, it is sent again after the backoff of its topic",
+    },
+    Message {
+        name: "members",
+        direction: "peer>broker",
+        frames: &[fixed("topic", "@@MEMBERS", "membership request")],
+        description: "asks for the brokers of the cluster, to bootstrap from any of them",
+    },
+    Message {
+        name: "workflow",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@WORKFLOW", "workflow submission"),
+            free("workflow_id", "id of the workflow, chosen by the client"),
+            free("dag", "JSON object with the nodes (id, topic, payload) and the edges (pairs of node ids)"),
+        ],
+        description: "a client sends a DAG of tasks, a node is sent once the nodes it waits for are answered",
+    },
+    Message {
+        name: "workflow_query",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@WORKFLOW", "workflow query"),
+            free("workflow_id", "id of the workflow"),
+        ],
+        description: "asks for the status of a workflow",
+    },
+    Message {
+        name: "result",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@RESULT", "result request"),
+            free("response_topic", "response topic of the task"),
+        ],
+        description: "asks for the response of a task answered in the last RESULTS_TTL seconds",
+    },
+    Message {
+        name: "wait",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@WAIT", "result request"),
+            free("response_topic", "response topic of the task"),
+            free("timeout", "milliseconds to wait for the response, 30 seconds if empty"),
+        ],
+        description: "asks for the response of a task, answered as soon as it comes or once the timeout expires",
+    },
+    Message {
+        name: "pong",
+        direction: "broker>peer",
+        frames: &[EMPTY, fixed("topic", "@@PONG", "heart beat")],
+        description: "answer to a ping",
+    },
+    Message {
+        name: "register_again",
+        direction: "broker>peer",
+        frames: &[EMPTY, fixed("topic", "@@REGISTER", "registration request")],
+        description: "the broker does not know the pinging worker, it has to register again",
+    },
+    Message {
+        name: "shutdown",
+        direction: "broker>peer",
+        frames: &[EMPTY, fixed("topic", "@@SHUTDOWN", "shutdown request")],
+        description: "the worker was drained (DRAIN_WORKER admin command) and has no task anymore, or an admin asked it to stop (SHUTDOWN admin command), it answers its running tasks and exits",
+    },
+    Message {
+        name: "restart",
+        direction: "broker>peer",
+        frames: &[EMPTY, fixed("topic", "@@RESTART", "restart request")],
+        description: "an admin asked the worker to restart (RESTART admin command), it gets no new task, answers its running tasks and connects again",
+    },
+    Message {
+        name: "resubscribe",
+        direction: "broker>peer",
+        frames: &[EMPTY, fixed("topic", "@@RESUBSCRIBE", "subscription request")],
+        description: "the broker does not know the pinging client, it has to subscribe again and resend its unanswered tasks",
+    },
+    Message {
+        name: "no_topic",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@NO_TOPIC", "rejection"),
+            free("response_topic", "response topic of the rejected task"),
+        ],
+        description: "the task topic is not declared (DECLARED_TOPICS_ONLY)",
+    },
+    Message {
+        name: "forbidden",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@FORBIDDEN", "rejection"),
+            free(
+                "response_topic",
+                "response topic of the rejected task, or topic of the rejected registration",
+            ),
+        ],
+        description: "the uid of the client is not allowed by the topic acl (remote clients have none), or by IPC_PERMISSIONS",
+    },
+    Message {
+        name: "error",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@ERROR", "rejection"),
+            free("response_topic", "response topic of the rejected task"),
+            fixed("code", "schema_violation", "why the task is rejected"),
+            free("detail", "what doesn't match, `$.age: expected integer`"),
+        ],
+        description: "the task payload doesn't match the schema of its topic (SCHEMA_FILE)",
+    },
+    Message {
+        name: "too_many_requests",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@ERROR", "rejection"),
+            free("response_topic", "response topic of the rejected task"),
+            fixed("code", "too_many_requests", "why the task is rejected"),
+            free("detail", "the number of responses a client may wait on"),
+        ],
+        description: "the client already waits on MAX_PENDING_TASKS responses",
+    },
+    Message {
+        name: "stopping",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@STOPPING", "rejection"),
+            free("response_topic", "response topic of the rejected task"),
+        ],
+        description: "the broker is stopping (SIGTERM in the container profile), the task can be sent to another broker",
+    },
+    Message {
+        name: "identity_conflict",
+        direction: "broker>peer",
+        frames: &[EMPTY, fixed("topic", "@@IDENTITY_CONFLICT", "rejection")],
+        description: "the identity is bound to a peer at another address (BIND_IDENTITIES), the message is dropped",
+    },
+    Message {
+        name: "bad_signature",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@BAD_SIGNATURE", "rejection"),
+            free("worker_topic", "topic of the refused registration, empty for an unregistration"),
+        ],
+        description: "a registration or unregistration is not signed with CONTROL_SECRET, it is ignored",
+    },
+    Message {
+        name: "accepted",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@ACCEPTED", "acknowledgment"),
+            free("response_topic", "response topic of the accepted task"),
+        ],
+        description: "the task is validated and queued, sent with ACCEPTED_ACKS before a worker gets it",
+    },
+    Message {
+        name: "queued",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@QUEUED", "queue position"),
+            free("response_topic", "response topic of the waiting task"),
+            free("position", "1 for the next task of the topic sent to a worker"),
+            free("eta", "milliseconds to process the task once a worker is there, or unknown"),
+        ],
+        description: "the task waits for a worker, sent every QUEUED_INTERVAL seconds",
+    },
+    Message {
+        name: "envelope",
+        direction: "peer>broker",
+        frames: &[
+            fixed("topic", "@@ENVELOPE", "protocol 2"),
+            free(
+                "envelope",
+                "JSON object: `version` (SemVer, `2.0`), and the frames of protocol 1 as strings: `topic`, `response_topic`, `payload`, `partition_key`, `dependencies`, and `headers` (object)",
+            ),
+        ],
+        description: "any message of protocol 1 in an envelope, the broker then answers the peer in envelopes",
+    },
+    Message {
+        name: "envelope_answer",
+        direction: "broker>peer",
+        frames: &[
+            fixed("topic", "@@ENVELOPE", "protocol 2"),
+            free("envelope", "JSON object: `version` (`2.0`) and `frames`, the frames of protocol 1 as an array of strings"),
+        ],
+        description: "any message of protocol 1 to a peer that sent an envelope",
+    },
+    Message {
+        name: "split_failed",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@ERROR", "failure"),
+            free("response_topic", "response topic of the task"),
+            fixed("code", "split_failed", "the task of a topic declared with a split won't be answered"),
+            free("detail", "the part that won't be answered, or the error of the split or merge script"),
+        ],
+        description: "a task sent as parts fails, see `split=` in CREATE_TOPIC",
+    },
+    Message {
+        name: "unsupported_protocol",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@ERROR", "rejection"),
+            fixed("response_topic", "", "empty"),
+            fixed("code", "unsupported_protocol", "the major version of the message isn't in PROTOCOL_VERSIONS"),
+            free("detail", "the versions the broker speaks"),
+        ],
+        description: "the message is refused, always in frames",
+    },
+    Message {
+        name: "features",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@FEATURES", "features of the broker"),
+            free("features", "space separated: `headers`, `direct`, `retry`, `restart`, `topics`, `epochs`, `names`, and `blobs`, `accepted`, `queued` when enabled"),
+        ],
+        description: "the answer to a registration declaring `features=`, the broker only uses with the worker the features it declared",
+    },
+    Message {
+        name: "queue_full",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@QUEUE_FULL", "rejection"),
+            free("response_topic", "response topic of the rejected task"),
+        ],
+        description: "no worker is available and the topic queue is full",
+    },
+    Message {
+        name: "dependency_failed",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@DEPENDENCY_FAILED", "rejection"),
+            free("response_topic", "response topic of the dropped task"),
+        ],
+        description: "a task the task depends on won't be answered (dead letter queue, queue full, drained)",
+    },
+    Message {
+        name: "members_list",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@MEMBERS", "membership"),
+            free(
+                "endpoints",
+                "comma separated endpoints of the live brokers, this one first, empty outside a cluster",
+            ),
+        ],
+        description: "the answer to a membership request",
+    },
+    Message {
+        name: "workflow_status",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@WORKFLOW", "workflow status"),
+            free("workflow_id", "id of the workflow"),
+            free("status", "JSON object with the status of the workflow and of its nodes, with their responses"),
+        ],
+        description: "sent on submission, on query, and to the submitting client once the workflow is over",
+    },
+    Message {
+        name: "result_found",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@RESULT", "result"),
+            free("response_topic", "response topic of the task"),
+            free("payload", "response content"),
+        ],
+        description: "the response of the task",
+    },
+    Message {
+        name: "no_result",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            fixed("topic", "@@NO_RESULT", "no result"),
+            free("response_topic", "response topic of the task"),
+        ],
+        description: "the task is not answered yet (or before the wait timeout), unknown, or its response expired",
+    },
+    Message {
+        name: "delivery",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            free(
+                "payload",
+                "task content, or response content, `@@BLOB <url>` when it was written to the blob store",
+            ),
+        ],
+        description: "a task sent to a worker, or a response sent to a client",
+    },
+    Message {
+        name: "direct_delivery",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            free("payload", "task content"),
+            free("endpoint", "where to send the response, as `[response_topic, \"\", payload]`"),
+        ],
+        description: "a task sent to a worker registered with `direct`, whose client gave a direct endpoint",
+    },
+    Message {
+        name: "task_delivery_with_headers",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            free("payload", "task content"),
+            free("endpoint", "direct endpoint of the client, empty if there is none"),
+            free(
+                "headers",
+                "JSON object of strings, the headers of the task, with the broker headers its topic asks for",
+            ),
+        ],
+        description: "a task with headers, or on a topic declared with `headers=`, sent to a worker",
+    },
+    Message {
+        name: "response_delivery_with_headers",
+        direction: "broker>peer",
+        frames: &[
+            EMPTY,
+            free("payload", "response content"),
+            free(
+                "headers",
+                "JSON object of strings, the headers of the task and of the response, with x-broker-retry and x-broker-queue-time",
+            ),
+        ],
+        description: "the response of a task with headers",
+    },
+];
+
+fn frame_json(frame: &Frame) -> String {
+    format!(
+        "{{\"name\":{},\"value\":{},\"description\":{}}}",
+        json::string(frame.name),
+        frame.value.map_or("null".to_string(), json::string),
+        json::string(frame.description)
+    )
+}
+
+fn message_json(message: &Message) -> String {
+    let frames: Vec<String> = message.frames.iter().map(frame_json).collect();
+    format!(
+        "{{\"name\":{},\"direction\":{},\"frames\":[{}],\"description\":{}}}",
+        json::string(message.name),
+        json::string(message.direction),
+        frames.join(","),
+        json::string(message.description)
+    )
+}
+
+fn message_text(message: &Message) -> String {
+    let frames: Vec<String> = message
+        .frames
+        .iter()
+        .map(|frame| match frame.value {
+            Some(value) => format!("{:?}", value),
+            None => format!("<{}>", frame.name),
+        })
+        .collect();
+    format!(
+        "{} {} [{}]\n    {}",
+        message.direction,
+        message.name,
+        frames.join(", "),
+        message.description
+    )
+}
+
+pub fn describe(format: &str) -> Result<String, String> {
+    match format {
+        "json" => {
+            let messages: Vec<String> = MESSAGES.iter().map(message_json).collect();
+            Ok(format!(
+                "{{\"version\":{},\"messages\":[{}]}}",
+                VERSION,
+                messages.join(",")
+            ))
+        }
+        "text" => {
+            let messages: Vec<String> = MESSAGES.iter().map(message_text).collect();
+            Ok(format!(
+                "tiny-broke protocol {}\n{}",
+                VERSION,
+                messages.join("\n")
+            ))
+        }
+        _ => Err(format!("unknown format: {}", format)),
+    }
+}
+
+// the JSON-RPC methods of the admin socket (see jsonrpc.rs), with the role they need and their error codes
+pub fn describe_admin(format: &str) -> Result<String, String> {
+    let role = |name: &str| match Role::of_command(name) {
+        Role::Observer => "observer",
+        Role::Operator => "operator",
+        Role::Admin => "admin",
+    };
+    match format {
+        "json" => {
+            let methods: Vec<String> = jsonrpc::METHODS
+                .iter()
+                .map(|method| {
+                    let params: Vec<String> = method
+                        .params
+                        .iter()
+                        .map(|param| json::string(param))
+                        .collect();
+                    format!(
+                        "{{\"name\":{},\"params\":[{}],\"role\":{},\"description\":{}}}",
+                        json::string(method.name),
+                        params.join(","),
+                        json::string(role(method.name)),
+                        json::string(method.description)
+                    )
+                })
+                .collect();
+            let errors: Vec<String> = jsonrpc::ERROR_CODES
+                .iter()
+                .map(|error| {
+                    format!(
+                        "{{\"code\":{},\"name\":{},\"description\":{}}}",
+                        error.code,
+                        json::string(error.name),
+                        json::string(error.description)
+                    )
+                })
+                .collect();
+            Ok(format!(
+                "{{\"version\":{},\"methods\":[{}],\"errors\":[{}]}}",
+                VERSION,
+                methods.join(","),
+                errors.join(",")
+            ))
+        }
+        "text" => {
+            let methods = jsonrpc::METHODS.iter().map(|method| {
+                format!(
+                    "{} {} ({})\n    {}",
+                    method.name,
+                    method.params.join(" "),
+                    role(method.name),
+                    method.description
+                )
+            });
+            let errors = jsonrpc::ERROR_CODES.iter().map(|error| {
+                format!(
+                    "error {} {}\n    {}",
+                    error.code, error.name, error.description
+                )
+            });
+            Ok(format!(
+                "tiny-broke admin protocol {}\n{}",
+                VERSION,
+                methods.chain(errors).collect::<Vec<String>>().join("\n")
+            ))
+        }
+        _ => Err(format!("unknown format: {}", format)),
+    }
+}
+
+// `tiny-broke protocol describe [--format json|text]`
+// `tiny-broke protocol admin [--format json|text]`
+pub fn run(args: &[String]) -> Result<String, String> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    match args.as_slice() {
+        ["describe"] => describe("text"),
+        ["describe", "--format", format] => describe(format),
+        ["admin"] => describe_admin("text"),
+        ["admin", "--format", format] => describe_admin(format),
+        _ => Err("usage: tiny-broke protocol describe|admin [--format json|text]".to_string()),
+    }
+}
@@ -0,0 +1,236 @@
+use crate::cli;
+use crate::json;
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zmq::{self, SocketType};
+
+// the recording is a JSON line per message, in both directions:
+// `{"time":<microseconds since epoch>,"direction":"peer>broker","identity":"...","frames":["...",...]}`
+const PROXY_USAGE: &str =
+    "usage: tiny-broke proxy --record <file.jsonl> [--listen <endpoint>] [--broker <endpoint>]";
+const REPLAY_USAGE: &str =
+    "usage: tiny-broke replay <file.jsonl> [--broker <endpoint>] [--speed <factor>]";
+
+fn zmq_error(error: zmq::Error) -> String {
+    error.to_string()
+}
+
+fn record_line(direction: &str, identity: &[u8], frames: &[Vec<u8>]) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros();
+    let frames: Vec<String> = frames
+        .iter()
+        .map(|frame| json::string(&String::from_utf8_lossy(frame)))
+        .collect();
+
+    format!(
+        "{{\"time\":{},\"direction\":{},\"identity\":{},\"frames\":[{}]}}",
+        time,
+        json::string(direction),
+        json::string(&String::from_utf8_lossy(identity)),
+        frames.join(",")
+    )
+}
+
+// peers connect to the proxy instead of the broker
+// every peer gets its own connection to the broker, with the same identity, so the broker can't tell the difference
+pub fn proxy(args: &[String]) -> Result<String, String> {
+    let (positionals, options) = cli::options(args, &["record", "listen", "broker"])?;
+    let record = match (positionals.is_empty(), options.get("record")) {
+        (true, Some(record)) => record,
+        _ => return Err(PROXY_USAGE.to_string()),
+    };
+    let listen = options
+        .get("listen")
+        .map_or("tcp://0.0.0.0:3001", |listen| listen);
+    let broker = options
+        .get("broker")
+        .map_or("tcp://localhost:3000", |broker| broker);
+
+    let file =
+        File::create(record).map_err(|error| format!("can't create {}: {}", record, error))?;
+    let mut recorder = LineWriter::new(file);
+
+    let context = zmq::Context::new();
+    let frontend = context.socket(SocketType::ROUTER).map_err(zmq_error)?;
+    frontend.bind(listen).map_err(zmq_error)?;
+    println!("proxying {} to {}, recording in {}", listen, broker, record);
+
+    let mut backends: Vec<(Vec<u8>, zmq::Socket)> = vec![];
+
+    loop {
+        let readables: Vec<bool> = {
+            let mut items = vec![frontend.as_poll_item(zmq::POLLIN)];
+            items.extend(
+                backends
+                    .iter()
+                    .map(|(_, backend)| backend.as_poll_item(zmq::POLLIN)),
+            );
+            zmq::poll(&mut items, -1).map_err(zmq_error)?;
+            items.iter().map(|item| item.is_readable()).collect()
+        };
+
+        if readables[0] {
+            let frames = frontend.recv_multipart(0).map_err(zmq_error)?;
+            let (identity, frames) = frames.split_first().unwrap();
+
+            if !backends.iter().any(|(known, _)| known == identity) {
+                let backend = context.socket(SocketType::DEALER).map_err(zmq_error)?;
+                backend.set_identity(identity).map_err(zmq_error)?;
+                backend.connect(broker).map_err(zmq_error)?;
+                backends.push((identity.clone(), backend));
+            }
+            let (_, backend) = backends
+                .iter()
+                .find(|(known, _)| known == identity)
+                .unwrap();
+
+            writeln!(recorder, "{}", record_line("peer>broker", identity, frames))
+                .map_err(|error| error.to_string())?;
+            backend.send_multipart(frames, 0).map_err(zmq_error)?;
+        }
+
+        for (index, _) in readables.iter().enumerate().skip(1).filter(|(_, r)| **r) {
+            let (identity, backend) = &backends[index - 1];
+            let frames = backend.recv_multipart(0).map_err(zmq_error)?;
+
+            writeln!(
+                recorder,
+                "{}",
+                record_line("broker>peer", identity, &frames)
+            )
+            .map_err(|error| error.to_string())?;
+            frontend
+                .send_multipart(std::iter::once(identity).chain(frames.iter()), 0)
+                .map_err(zmq_error)?;
+        }
+    }
+}
+
+struct Recorded {
+    time: f64,
+    identity: String,
+    frames: Vec<String>,
+}
+
+fn parse_recording(path: &str) -> Result<Vec<Recorded>, String> {
+    let content =
+        fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    let mut recorded = vec![];
+
+    for (index, line) in content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+    {
+        let invalid = |reason: &str| format!("line {}: {}", index + 1, reason);
+        let value = json::parse(line).map_err(|error| invalid(&error))?;
+
+        // only what the peers sent is replayed, the broker answers by itself
+        if value.get("direction").and_then(|d| d.as_str()) != Some("peer>broker") {
+            continue;
+        }
+
+        let time = value.get("time").and_then(|time| time.as_f64());
+        let identity = value.get("identity").and_then(|identity| identity.as_str());
+        let frames: Option<Vec<String>> = value
+            .get("frames")
+            .and_then(|frames| frames.as_array())
+            .and_then(|frames| {
+                frames
+                    .iter()
+                    .map(|frame| frame.as_str().map(|frame| frame.to_string()))
+                    .collect()
+            });
+
+        match (time, identity, frames) {
+            (Some(time), Some(identity), Some(frames)) => recorded.push(Recorded {
+                time,
+                identity: identity.to_string(),
+                frames,
+            }),
+            _ => return Err(invalid("bad record")),
+        }
+    }
+
+    Ok(recorded)
+}
+
+// sends the recorded peer messages again, with their identities and their timing
+// `--speed 2` replays twice as fast, `--speed 0` as fast as possible (load testing)
+pub fn replay(args: &[String]) -> Result<String, String> {
+    let (positionals, options) = cli::options(args, &["broker", "speed"])?;
+    let path = match positionals.as_slice() {
+        [path] => path,
+        _ => return Err(REPLAY_USAGE.to_string()),
+    };
+    let broker = options
+        .get("broker")
+        .map_or("tcp://localhost:3000", |broker| broker);
+    let speed: f64 = match options.get("speed").map(|speed| speed.parse()) {
+        None => 1.0,
+        Some(Ok(speed)) if speed >= 0.0 => speed,
+        Some(_) => return Err(REPLAY_USAGE.to_string()),
+    };
+
+    let recorded = parse_recording(path)?;
+    let first_time = recorded.first().map_or(0.0, |record| record.time);
+
+    let context = zmq::Context::new();
+    let mut peers: Vec<(String, zmq::Socket)> = vec![];
+    let mut received = 0;
+    let start = Instant::now();
+
+    // answers are read and counted, so the peers queues don't fill up
+    let mut receive = |peers: &[(String, zmq::Socket)]| {
+        peers.iter().for_each(|(_, peer)| {
+            while peer.recv_multipart(zmq::DONTWAIT).is_ok() {
+                received += 1;
+            }
+        });
+    };
+
+    for record in &recorded {
+        if speed > 0.0 {
+            let offset = Duration::from_micros(((record.time - first_time) / speed) as u64);
+            if let Some(wait) = offset.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        if !peers
+            .iter()
+            .any(|(identity, _)| identity == &record.identity)
+        {
+            let peer = context.socket(SocketType::DEALER).map_err(zmq_error)?;
+            peer.set_identity(record.identity.as_bytes())
+                .map_err(zmq_error)?;
+            peer.connect(broker).map_err(zmq_error)?;
+            peers.push((record.identity.clone(), peer));
+        }
+        let (_, peer) = peers
+            .iter()
+            .find(|(identity, _)| identity == &record.identity)
+            .unwrap();
+        peer.send_multipart(&record.frames, 0).map_err(zmq_error)?;
+
+        receive(&peers);
+    }
+
+    // last answers
+    let duration = start.elapsed();
+    thread::sleep(Duration::from_secs(1));
+    receive(&peers);
+
+    Ok(format!(
+        "{} messages replayed to {} in {}ms, {} messages received",
+        recorded.len(),
+        broker,
+        duration.as_millis(),
+        received
+    ))
+}
@@ -0,0 +1,86 @@
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::HashMap;
+use std::env;
+use std::rc::Rc;
+use std::time::Duration;
+
+// clients of the tasks waiting for a worker get `@@QUEUED <response topic> <position> <eta>` every
+// `QUEUED_INTERVAL` seconds, so applications can tell how long the wait may be:
+// - the position is 1 for the next task of the topic sent to a worker
+// - the eta is the milliseconds to process the tasks before this one (and this one) once a worker is there, from the
+//   average processing time of the topic and its number of workers, `unknown` when no task of the topic was answered
+
+pub fn queued_interval() -> Option<Duration> {
+    let seconds = env::var("QUEUED_INTERVAL")
+        .map(|v| v.parse::<u64>().unwrap_or(5))
+        .unwrap_or(5);
+    match seconds {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    }
+}
+
+fn eta(position: usize, average_as_micros: Option<u128>, workers: usize) -> String {
+    match average_as_micros {
+        Some(average) => {
+            let micros = average * position as u128 / workers.max(1) as u128;
+            (micros / 1000).to_string()
+        }
+        None => "unknown".to_string(),
+    }
+}
+
+impl Broker {
+    pub fn send_queue_positions(&mut self, transport: &dyn Transport) {
+        let interval = match self.queued_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.elapsed(self.last_queued) < interval {
+            return;
+        }
+        self.last_queued = self.now();
+
+        let mut positions: HashMap<Rc<str>, usize> = HashMap::new();
+        for task in &self.dispatcher.tasks_to_retry {
+            let position = positions.entry(task.worker_topic.clone()).or_insert(0);
+            *position += 1;
+
+            let average = self
+                .topic_stats
+                .get(&*task.worker_topic)
+                .filter(|stats| stats.processed > 0)
+                .map(|stats| stats.average_as_micros());
+            let workers = self
+                .registry
+                .topics
+                .get(&*task.worker_topic)
+                .map_or(0, |topic| topic.workers.len());
+            let eta = eta(*position, average, workers);
+            let position = position.to_string();
+
+            for identity in self.registry.clients_of(&task.response_topic) {
+                transport
+                    .send(
+                        &identity,
+                        &["", "@@QUEUED", &task.response_topic, &position, &eta],
+                    )
+                    .ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::eta;
+
+    #[test]
+    fn eta_grows_with_the_position() {
+        assert_eq!(eta(3, Some(2000), 1), "6");
+        assert_eq!(eta(3, Some(2000), 2), "3");
+        assert_eq!(eta(1, Some(2000), 0), "2");
+        assert_eq!(eta(1, None, 1), "unknown");
+    }
+}
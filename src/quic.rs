@@ -0,0 +1,336 @@
+use crate::log;
+use crate::transport::{outbox_size, Incoming, Unreachable};
+use quinn::rustls::pki_types::pem::PemObject;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::env;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use tokio::sync::mpsc as channel;
+
+// with `QUIC_PORT`, peers can connect over QUIC (UDP) next to the ROUTER socket: TLS with `QUIC_CERT` and `QUIC_KEY`,
+// no head-of-line blocking between peers, and connections surviving a change of address (a worker on a mobile or
+// unreliable network)
+// a peer opens a bidirectional stream on its connection, its first message is its identity, then it sends and
+// receives the same frames as on the ROUTER socket
+// a message is the number of frames, then each frame after its length, all of them as big-endian u32
+// the connections are served by a tokio runtime on its own thread: the broker reads the messages in its loop, woken up
+// by a byte on a unix socket for each of them, and its messages wait for each peer in a queue of `OUTBOX_SIZE`
+// messages, a peer with a full queue being considered gone, as with the ROUTER socket
+
+const MAX_FRAMES: usize = 16;
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+pub fn quic_port() -> Option<String> {
+    env::var("QUIC_PORT").ok()
+}
+
+// the certificate chain and the private key, PEM files
+fn server_config() -> Result<quinn::ServerConfig, String> {
+    let read = |variable: &str| {
+        env::var(variable).map_err(|_| format!("QUIC_PORT needs {}, a PEM file", variable))
+    };
+    let (cert, key) = (read("QUIC_CERT")?, read("QUIC_KEY")?);
+    let chain = CertificateDer::pem_file_iter(&cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| format!("can't read {}: {}", cert, error))?;
+    let key = PrivateKeyDer::from_pem_file(&key)
+        .map_err(|error| format!("can't read {}: {}", key, error))?;
+    quinn::ServerConfig::with_single_cert(chain, key).map_err(|error| error.to_string())
+}
+
+// the identity and the frames of a message, with the address of its peer
+type Received = (Vec<Vec<u8>>, String);
+
+// the queues of the connected peers
+type Peers = Arc<Mutex<HashMap<String, channel::Sender<Vec<String>>>>>;
+
+pub struct QuicServer {
+    endpoint: String,
+    incoming: mpsc::Receiver<Received>,
+    peers: Peers,
+    // a byte for each message waiting
+    wake_up: UnixStream,
+}
+
+impl QuicServer {
+    pub fn from_env(address: &str) -> Result<QuicServer, String> {
+        QuicServer::bind(address, server_config()?)
+    }
+
+    // `<address>:<port>`
+    pub fn bind(address: &str, config: quinn::ServerConfig) -> Result<QuicServer, String> {
+        let address: SocketAddr = address
+            .parse()
+            .map_err(|_| format!("{} is not an address", address))?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|error| error.to_string())?;
+        let endpoint = {
+            let _runtime = runtime.enter();
+            quinn::Endpoint::server(config, address).map_err(|error| error.to_string())?
+        };
+        let local_address = endpoint.local_addr().map_err(|error| error.to_string())?;
+        let (wake_up, waker) = UnixStream::pair().map_err(|error| error.to_string())?;
+        let (sender, incoming) = mpsc::channel();
+        let peers = Peers::default();
+        let connections = Connections {
+            incoming: sender,
+            peers: peers.clone(),
+            waker: Arc::new(waker),
+        };
+        thread::spawn(move || runtime.block_on(accept(endpoint, connections)));
+
+        Ok(QuicServer {
+            endpoint: format!("quic://{}", local_address),
+            incoming,
+            peers,
+            wake_up,
+        })
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    pub fn is_connected(&self, identity: &str) -> bool {
+        self.peers.lock().unwrap().contains_key(identity)
+    }
+
+    // without waiting: a peer with a full queue can't be reached
+    pub fn send(&self, identity: &str, frames: &[&str]) -> Result<(), Unreachable> {
+        let frames = frames.iter().map(|frame| frame.to_string()).collect();
+        match self.peers.lock().unwrap().get(identity) {
+            Some(queue) if queue.try_send(frames).is_ok() => Ok(()),
+            _ => Err(Unreachable(identity.to_string())),
+        }
+    }
+
+    // the next message, once the unix socket is readable
+    pub fn recv(&self) -> Result<Incoming, String> {
+        let mut byte = [0];
+        (&self.wake_up)
+            .read_exact(&mut byte)
+            .map_err(|error| error.to_string())?;
+        let (frames, address) = self
+            .incoming
+            .recv()
+            .map_err(|_| "the QUIC endpoint is stopped".to_string())?;
+        let mut message = Incoming::parse(frames)?;
+        message.address = Some(address);
+        Ok(message)
+    }
+}
+
+impl AsRawFd for QuicServer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wake_up.as_raw_fd()
+    }
+}
+
+// what the connections share with the broker
+#[derive(Clone)]
+struct Connections {
+    incoming: mpsc::Sender<Received>,
+    peers: Peers,
+    waker: Arc<UnixStream>,
+}
+
+async fn accept(endpoint: quinn::Endpoint, connections: Connections) {
+    while let Some(incoming) = endpoint.accept().await {
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            let address = incoming.remote_address();
+            if let Err(error) = serve(incoming, connections).await {
+                log::warn(&format!("QUIC connection of {} closed: {}", address, error));
+            }
+        });
+    }
+}
+
+async fn serve(incoming: quinn::Incoming, connections: Connections) -> Result<(), String> {
+    let connection = incoming.await.map_err(|error| error.to_string())?;
+    let address = connection.remote_address().to_string();
+    let (mut send, mut recv) = connection
+        .accept_bi()
+        .await
+        .map_err(|error| error.to_string())?;
+    let identity = match read_message(&mut recv).await?.as_deref() {
+        Some([identity]) if !identity.is_empty() => identity.clone(),
+        _ => return Err("the first message is not an identity".to_string()),
+    };
+
+    // a new connection of the peer replaces the previous one
+    let (queue, mut outgoing) = channel::channel(outbox_size().max(1));
+    let name = String::from_utf8_lossy(&identity).into_owned();
+    connections
+        .peers
+        .lock()
+        .unwrap()
+        .insert(name.clone(), queue.clone());
+    tokio::spawn(async move {
+        while let Some(frames) = outgoing.recv().await {
+            if send.write_all(&encode(&frames)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let read = async {
+        while let Some(frames) = read_message(&mut recv).await? {
+            let mut message = vec![identity.clone()];
+            message.extend(frames);
+            connections
+                .incoming
+                .send((message, address.clone()))
+                .map_err(|_| "the broker is stopped".to_string())?;
+            // blocks when the broker is behind, which holds the peers back
+            (&*connections.waker)
+                .write_all(&[1])
+                .map_err(|error| error.to_string())?;
+        }
+        Ok(())
+    };
+    let result = read.await;
+
+    let mut peers = connections.peers.lock().unwrap();
+    if peers
+        .get(&name)
+        .is_some_and(|other| other.same_channel(&queue))
+    {
+        peers.remove(&name);
+    }
+    result
+}
+
+fn encode(frames: &[String]) -> Vec<u8> {
+    let mut message = (frames.len() as u32).to_be_bytes().to_vec();
+    for frame in frames {
+        message.extend((frame.len() as u32).to_be_bytes());
+        message.extend(frame.as_bytes());
+    }
+    message
+}
+
+async fn read_u32(recv: &mut quinn::RecvStream) -> Result<usize, quinn::ReadExactError> {
+    let mut bytes = [0; 4];
+    recv.read_exact(&mut bytes).await?;
+    Ok(u32::from_be_bytes(bytes) as usize)
+}
+
+// `None` once the peer finished the stream, or closed its connection, between two messages
+async fn read_message(recv: &mut quinn::RecvStream) -> Result<Option<Vec<Vec<u8>>>, String> {
+    let count = match read_u32(recv).await {
+        Ok(count) => count,
+        Err(quinn::ReadExactError::FinishedEarly(0))
+        | Err(quinn::ReadExactError::ReadError(quinn::ReadError::ConnectionLost(_))) => {
+            return Ok(None)
+        }
+        Err(error) => return Err(error.to_string()),
+    };
+    if count == 0 || count > MAX_FRAMES {
+        return Err(format!("{} frames in a message", count));
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    for _ in 0..count {
+        let length = read_u32(recv).await.map_err(|error| error.to_string())?;
+        if length > MAX_FRAME {
+            return Err(format!("a frame of {} bytes", length));
+        }
+        let mut frame = vec![0; length];
+        recv.read_exact(&mut frame)
+            .await
+            .map_err(|error| error.to_string())?;
+        frames.push(frame);
+    }
+    Ok(Some(frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, read_message, QuicServer};
+    use crate::transport::Control;
+    use quinn::rustls::pki_types::pem::PemObject;
+    use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use quinn::rustls::RootCertStore;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    const CERT: &str = include_str!("../testdata/localhost.pem");
+    const KEY: &str = include_str!("../testdata/localhost.key");
+
+    fn frames(frames: &[&str]) -> Vec<String> {
+        frames.iter().map(|frame| frame.to_string()).collect()
+    }
+
+    // registers as worker-1, gives back the first message it receives, and closes its connection
+    async fn worker(address: SocketAddr, cert: CertificateDer<'static>) -> Vec<Vec<u8>> {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(
+            quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap(),
+        );
+        let connection = client.connect(address, "localhost").unwrap().await.unwrap();
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(&encode(&frames(&["worker-1"])))
+            .await
+            .unwrap();
+        send.write_all(&encode(&frames(&["@@REGISTER", "ADD"])))
+            .await
+            .unwrap();
+
+        let received = read_message(&mut recv).await.unwrap().unwrap();
+        connection.close(0u32.into(), b"done");
+        client.wait_idle().await;
+        received
+    }
+
+    #[test]
+    fn peers_are_known_by_the_identity_of_their_stream() {
+        let cert = CertificateDer::from_pem_slice(CERT.as_bytes()).unwrap();
+        let key = PrivateKeyDer::from_pem_slice(KEY.as_bytes()).unwrap();
+        let config = quinn::ServerConfig::with_single_cert(vec![cert.clone()], key).unwrap();
+        let server = QuicServer::bind("127.0.0.1:0", config).unwrap();
+        let address = server
+            .endpoint()
+            .trim_start_matches("quic://")
+            .parse()
+            .unwrap();
+        let worker = thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(worker(address, cert))
+        });
+
+        let message = server.recv().unwrap();
+        assert_eq!(message.identity, "worker-1");
+        assert_eq!(message.control, Some(Control::Register));
+        assert_eq!(message.response_topic, "ADD");
+        assert!(message.address.unwrap().starts_with("127.0.0.1:"));
+        assert!(server.is_connected("worker-1"));
+
+        server.send("worker-1", &["", "1+1"]).unwrap();
+        assert!(server.send("worker-2", &["", "1+1"]).is_err());
+        assert_eq!(worker.join().unwrap(), vec![b"".to_vec(), b"1+1".to_vec()]);
+
+        // gone with its connection
+        for _ in 0..100 {
+            if !server.is_connected("worker-1") {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(server.send("worker-1", &["", "1+1"]).is_err());
+    }
+}
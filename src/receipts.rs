@@ -0,0 +1,170 @@
+use crate::headers::Headers;
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, SystemTime};
+
+// tasks sent with the `receipt` header (`{"receipt": "true"}`) are answered at least once: the client confirms the
+// response with `@@RECEIVED <response topic>`, until then the broker sends it again every `RECEIPT_INTERVAL`
+// a response not confirmed within `UNDELIVERED_TTL` is dropped with a `response.dropped` event (reason `unconfirmed`)
+// clients may get a response twice, they are expected to ignore the copies of the responses they already confirmed
+
+pub fn receipt_interval() -> Duration {
+    Duration::from_secs(
+        env::var("RECEIPT_INTERVAL")
+            .map(|v| v.parse::<u64>().unwrap_or(5))
+            .unwrap_or(5),
+    )
+}
+
+pub fn wants_receipt(headers: &Headers) -> bool {
+    headers
+        .iter()
+        .any(|(name, value)| name == "receipt" && value == "true")
+}
+
+// client and response topic
+type Key = (String, String);
+
+struct Unconfirmed {
+    frames: Vec<String>,
+    first_sent: SystemTime,
+    last_sent: SystemTime,
+}
+
+pub struct Receipts {
+    interval: Duration,
+    unconfirmed: HashMap<Key, Unconfirmed>,
+    pub resent: u64,
+}
+
+impl Receipts {
+    pub fn from_env() -> Receipts {
+        Receipts {
+            interval: receipt_interval(),
+            unconfirmed: HashMap::new(),
+            resent: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.unconfirmed.len()
+    }
+
+    fn insert(&mut self, identity: &str, response_topic: &str, frames: &[&str], now: SystemTime) {
+        self.unconfirmed.insert(
+            (identity.to_string(), response_topic.to_string()),
+            Unconfirmed {
+                frames: frames.iter().map(|frame| frame.to_string()).collect(),
+                first_sent: now,
+                last_sent: now,
+            },
+        );
+    }
+
+    fn confirm(&mut self, identity: &str, response_topic: &str) -> bool {
+        self.unconfirmed
+            .remove(&(identity.to_string(), response_topic.to_string()))
+            .is_some()
+    }
+
+    // the responses to send again, and the ones waiting for longer than the ttl, which are removed
+    fn due(&mut self, now: SystemTime, ttl: Duration) -> (Vec<(Key, Vec<String>)>, Vec<Key>) {
+        let interval = self.interval;
+        let mut expired = vec![];
+        self.unconfirmed.retain(|key, unconfirmed| {
+            let keep = now
+                .duration_since(unconfirmed.first_sent)
+                .unwrap_or_default()
+                < ttl;
+            if !keep {
+                expired.push(key.clone());
+            }
+            keep
+        });
+        let mut due = vec![];
+        for (key, unconfirmed) in self.unconfirmed.iter_mut() {
+            if now
+                .duration_since(unconfirmed.last_sent)
+                .unwrap_or_default()
+                >= interval
+            {
+                unconfirmed.last_sent = now;
+                due.push((key.clone(), unconfirmed.frames.clone()));
+            }
+        }
+        due.sort();
+        expired.sort();
+        (due, expired)
+    }
+}
+
+impl Broker {
+    // the response was sent (or kept, see undelivered.rs), it is sent again until the client confirms it
+    pub fn await_receipt(&mut self, identity: &str, response_topic: &str, frames: &[&str]) {
+        let now = self.now();
+        self.receipts.insert(identity, response_topic, frames, now);
+    }
+
+    pub fn confirm_receipt(&mut self, identity: &str, response_topic: &str) {
+        if !self.receipts.confirm(identity, response_topic) {
+            log::info(&format!(
+                "Client {} confirmed {}, which doesn't wait for a receipt",
+                identity, response_topic
+            ));
+        }
+    }
+
+    pub fn resend_unconfirmed(&mut self, transport: &dyn Transport) {
+        let now = self.now();
+        let ttl = self.undelivered.ttl();
+        let (due, expired) = self.receipts.due(now, ttl);
+        for (identity, response_topic) in expired {
+            self.drop_response(&identity, &response_topic, "unconfirmed");
+        }
+        for ((identity, response_topic), frames) in due {
+            log::info(&format!(
+                "Client {} didn't confirm {}, sending it again",
+                identity, response_topic
+            ));
+            let frames: Vec<&str> = frames.iter().map(String::as_str).collect();
+            self.receipts.resent += 1;
+            // a failed send is tried again on the next interval
+            transport.send(&identity, &frames).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Receipts;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn responses_are_sent_again_until_confirmed() {
+        let mut receipts = Receipts::from_env();
+        receipts.interval = Duration::from_secs(5);
+        let ttl = Duration::from_secs(60);
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        receipts.insert("client-1", "R1", &["", "1"], at(0));
+        receipts.insert("client-1", "R2", &["", "2"], at(0));
+        assert!(receipts.due(at(4), ttl).0.is_empty());
+
+        let (due, expired) = receipts.due(at(5), ttl);
+        assert_eq!(due.len(), 2);
+        assert!(expired.is_empty());
+        assert!(receipts.due(at(6), ttl).0.is_empty());
+
+        assert!(receipts.confirm("client-1", "R1"));
+        assert!(!receipts.confirm("client-1", "R1"));
+        assert_eq!(receipts.due(at(10), ttl).0.len(), 1);
+
+        let (due, expired) = receipts.due(at(60), ttl);
+        assert!(due.is_empty());
+        assert_eq!(expired, vec![("client-1".to_string(), "R2".to_string())]);
+        assert_eq!(receipts.len(), 0);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::log;
+use crate::state::{self, Record};
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// a broker starting with the `STOP_STATE_FILE` of a previous one recovers its tasks, record by record (unlike
+// `IMPORT`, which refuses a corrupted file):
+// - corrupt records (and tasks without a topic) are quarantined: written to `<file>.quarantine`, a state file to
+//   fix by hand and `IMPORT`
+// - duplicated tasks (same response topic) are dropped, and clients waiting for a task that isn't there
+// - orphaned tasks, that no client waits for, are sent anyway: their responses can be kept with `RESULTS_TTL`
+// the state file is removed once recovered, the report is logged and given by the `RECOVERY` admin command, with
+// the recovered topics no worker has registered to yet
+
+pub struct Recovery {
+    path: String,
+    tasks: usize,
+    dead: usize,
+    clients: usize,
+    orphaned: usize,
+    duplicates: usize,
+    dropped_clients: usize,
+    // `line <number>: <reason>`
+    quarantined: Vec<String>,
+    topics: BTreeSet<String>,
+}
+
+impl Recovery {
+    fn new(path: &str) -> Recovery {
+        Recovery {
+            path: path.to_string(),
+            tasks: 0,
+            dead: 0,
+            clients: 0,
+            orphaned: 0,
+            duplicates: 0,
+            dropped_clients: 0,
+            quarantined: vec![],
+            topics: BTreeSet::new(),
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} tasks={} dead={} clients={} orphaned={} duplicates={} dropped_clients={} quarantined={}",
+            self.path,
+            self.tasks,
+            self.dead,
+            self.clients,
+            self.orphaned,
+            self.duplicates,
+            self.dropped_clients,
+            self.quarantined.len()
+        )
+    }
+}
+
+fn quarantine_path(path: &str) -> String {
+    format!("{}.quarantine", path)
+}
+
+impl Broker {
+    pub fn recover_from_env(&mut self, transport: &dyn Transport) {
+        if let Ok(path) = env::var("STOP_STATE_FILE") {
+            if !path.is_empty() && Path::new(&path).exists() {
+                self.recover_state(transport, &path);
+            }
+        }
+    }
+
+    pub fn recover_state(&mut self, transport: &dyn Transport, path: &str) {
+        let mut recovery = Recovery::new(path);
+        let (version, lines) = match state::read_state(path) {
+            Ok(state) => state,
+            // nothing to repair, the whole file is put aside
+            Err(error) => {
+                recovery
+                    .quarantined
+                    .push(format!("{}, the whole file", error));
+                if let Err(error) = fs::rename(path, quarantine_path(path)) {
+                    log::warn(&format!(
+                        "Can't quarantine the state file {}: {}",
+                        path, error
+                    ));
+                }
+                self.report_recovery(recovery);
+                return;
+            }
+        };
+
+        let mut clients = vec![];
+        let mut tasks = vec![];
+        let mut dead_letters = vec![];
+        let mut topics = vec![];
+        let mut quarantined = vec![];
+        let mut response_topics: HashSet<String> = self
+            .dispatcher
+            .pending()
+            .map(|task| task.response_topic.clone())
+            .collect();
+
+        for (line_number, line) in &lines {
+            match state::parse_record(version, *line_number, line, self.cipher.as_ref()) {
+                Ok(Record::Client(identity, topic)) => clients.push((identity, topic)),
+                Ok(Record::Task(task) | Record::Dead(task))
+                    if task.worker_topic.is_empty() || task.response_topic.is_empty() =>
+                {
+                    recovery
+                        .quarantined
+                        .push(format!("line {}: task without a topic", line_number));
+                    quarantined.push(line.as_str());
+                }
+                Ok(Record::Task(task)) => match response_topics.insert(task.response_topic.clone())
+                {
+                    true => tasks.push(task),
+                    false => recovery.duplicates += 1,
+                },
+                Ok(Record::Dead(task)) => dead_letters.push(task),
+                Ok(record) => topics.push(record),
+                Err(error) => {
+                    recovery.quarantined.push(error.to_string());
+                    quarantined.push(line.as_str());
+                }
+            }
+        }
+
+        let waited: HashSet<&str> = clients.iter().map(|(_, topic)| topic.as_str()).collect();
+        recovery.orphaned = tasks
+            .iter()
+            .filter(|task| !waited.contains(task.response_topic.as_str()))
+            .count();
+        for (identity, topic) in &clients {
+            match response_topics.contains(topic) {
+                true => {
+                    self.add_client(false, identity, topic);
+                    recovery.clients += 1;
+                }
+                false => recovery.dropped_clients += 1,
+            }
+        }
+
+        if !quarantined.is_empty() {
+            let content = format!("{}\n{}\n", state::header(version), quarantined.join("\n"));
+            if let Err(error) = fs::write(quarantine_path(path), content) {
+                log::warn(&format!(
+                    "Can't write the quarantined records of {}: {}",
+                    path, error
+                ));
+            }
+        }
+        // recovered once, a broker stopping without pending tasks doesn't write it again
+        if let Err(error) = fs::remove_file(path) {
+            log::warn(&format!("Can't remove the state file {}: {}", path, error));
+        }
+
+        recovery.tasks = tasks.len();
+        recovery.dead = dead_letters.len();
+        recovery.topics = tasks
+            .iter()
+            .map(|task| task.worker_topic.to_string())
+            .collect();
+        self.dispatcher.dead_letters.extend(dead_letters);
+        self.report_recovery(recovery);
+        self.restore_topics(topics);
+        self.restore_tasks(transport, tasks);
+    }
+
+    fn report_recovery(&mut self, recovery: Recovery) {
+        log::info(&format!("Recovered {}", recovery.summary()));
+        recovery
+            .quarantined
+            .iter()
+            .for_each(|reason| log::warn(&format!("Quarantined {}", reason)));
+        self.recovery = Some(recovery);
+    }
+
+    // the report, with the recovered topics still without workers, and a line per quarantined record
+    pub fn recovery_lines(&self) -> Result<String, String> {
+        let recovery = self
+            .recovery
+            .as_ref()
+            .ok_or_else(|| "no state was recovered at startup".to_string())?;
+        let without_workers: Vec<&str> = recovery
+            .topics
+            .iter()
+            .filter(|topic| {
+                self.registry
+                    .topics
+                    .get(topic.as_str())
+                    .is_none_or(|registered| registered.workers.is_empty())
+            })
+            .map(String::as_str)
+            .collect();
+
+        let mut lines = vec![format!(
+            "{} without_workers={}",
+            recovery.summary(),
+            without_workers.join(",")
+        )];
+        lines.extend(recovery.quarantined.iter().cloned());
+        Ok(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedded::BrokerHandle;
+    use std::fs;
+
+    #[test]
+    fn corrupt_records_are_quarantined_and_the_rest_recovered() {
+        let path = std::env::temp_dir().join("tiny-broke-recovery-test");
+        let path = path.to_str().unwrap();
+        fs::write(
+            path,
+            [
+                "tiny-broke-state 1",
+                "client\tclient-1\tADD>1",
+                "client\tclient-1\tADD>9",
+                "task\tADD\tADD>1\t0\t1+1",
+                "task\tADD\tADD>1\t0\t1+1",
+                "task\tSUB\tSUB>1\t0\t2-1",
+                "task\tADD\tADD>2\tnope\t2+2",
+                "unknown\trecord",
+                "",
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let mut broker = BrokerHandle::new();
+        broker.register_worker("worker-1", "ADD");
+        broker.broker.recover_state(&broker.transport, path);
+
+        assert_eq!(
+            broker.admin("RECOVERY"),
+            format!(
+                "OK {} tasks=2 dead=0 clients=1 orphaned=1 duplicates=1 dropped_clients=1 quarantined=2 without_workers=SUB\nline 7: retry is not a number\nline 8: bad unknown record",
+                path
+            )
+        );
+        assert_eq!(
+            broker.receive("worker-1"),
+            Some(vec!["".to_string(), "1+1".to_string()])
+        );
+        assert!(!std::path::Path::new(path).exists());
+        let quarantine = format!("{}.quarantine", path);
+        assert_eq!(
+            fs::read_to_string(&quarantine).unwrap(),
+            "tiny-broke-state 1\ntask\tADD\tADD>2\tnope\t2+2\nunknown\trecord\n"
+        );
+        fs::remove_file(quarantine).unwrap();
+    }
+}
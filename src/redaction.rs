@@ -0,0 +1,145 @@
+use std::env;
+
+// payloads may hold secrets: they are redacted before they show in operational tooling (`PEEK`, `DLQ` and the audit
+// log), not in what peers get
+// - `REDACT_FIELDS`: comma separated names of JSON fields whose values are replaced by `"***"`
+// - `REDACT_DIGITS`: runs of at least this many digits (card numbers, phone numbers) are replaced by `***`
+
+const MASK: &str = "***";
+
+#[derive(Debug, Default)]
+pub struct Redaction {
+    fields: Vec<String>,
+    digits: Option<usize>,
+}
+
+// the end of the JSON value starting at `start`: a string with its escapes, or anything up to a delimiter
+fn value_end(text: &str, start: usize) -> usize {
+    let bytes = text.as_bytes();
+    if bytes.get(start) != Some(&b'"') {
+        return text[start..]
+            .find(|c: char| c == ',' || c == '}' || c == ']' || c.is_whitespace())
+            .map_or(text.len(), |end| start + end);
+    }
+
+    let mut index = start + 1;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' => index += 2,
+            b'"' => return index + 1,
+            _ => index += 1,
+        }
+    }
+    text.len()
+}
+
+impl Redaction {
+    pub fn new(fields: Vec<String>, digits: Option<usize>) -> Redaction {
+        Redaction { fields, digits }
+    }
+
+    pub fn from_env() -> Redaction {
+        Redaction::new(
+            env::var("REDACT_FIELDS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .filter(|field| !field.is_empty())
+                .collect(),
+            env::var("REDACT_DIGITS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|digits| *digits > 0),
+        )
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for field in &self.fields {
+            redacted = redact_field(&redacted, field);
+        }
+        match self.digits {
+            Some(digits) => redact_digits(&redacted, digits),
+            None => redacted,
+        }
+    }
+}
+
+// `"<field>": <value>`, wherever it is nested
+fn redact_field(text: &str, field: &str) -> String {
+    let key = format!("\"{}\"", field);
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(found) = rest.find(&key) {
+        let after_key = found + key.len();
+        let value_start = rest[after_key..]
+            .find(|c: char| !c.is_whitespace())
+            .map(|offset| after_key + offset)
+            .filter(|colon| rest[*colon..].starts_with(':'))
+            .and_then(|colon| {
+                rest[colon + 1..]
+                    .find(|c: char| !c.is_whitespace())
+                    .map(|offset| colon + 1 + offset)
+            });
+        match value_start {
+            // objects and arrays are left, their own fields can be redacted
+            Some(start) if !rest[start..].starts_with(['{', '[']) => {
+                redacted.push_str(&rest[..start]);
+                redacted.push_str(&format!("\"{}\"", MASK));
+                rest = &rest[value_end(rest, start)..];
+            }
+            _ => {
+                redacted.push_str(&rest[..after_key]);
+                rest = &rest[after_key..];
+            }
+        }
+    }
+
+    redacted.push_str(rest);
+    redacted
+}
+
+fn redact_digits(text: &str, digits: usize) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut run = String::new();
+
+    let flush = |redacted: &mut String, run: &mut String| {
+        match run.len() >= digits {
+            true => redacted.push_str(MASK),
+            false => redacted.push_str(run),
+        }
+        run.clear();
+    };
+
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            run.push(c);
+        } else {
+            flush(&mut redacted, &mut run);
+            redacted.push(c);
+        }
+    }
+    flush(&mut redacted, &mut run);
+
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redaction;
+
+    #[test]
+    fn fields_and_digits_are_masked() {
+        let redaction = Redaction::new(vec!["password".to_string(), "pin".to_string()], Some(12));
+        assert_eq!(
+            redaction.redact(r#"{"user": "jo", "password": "a \"b\"", "card": "4111 111111111111", "auth": {"pin": 1234}}"#),
+            r#"{"user": "jo", "password": "***", "card": "4111 ***", "auth": {"pin": "***"}}"#
+        );
+        assert_eq!(redaction.redact("1+1"), "1+1");
+        assert_eq!(
+            Redaction::default().redact(r#"{"password":"x"}"#),
+            r#"{"password":"x"}"#
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::log;
+use crate::routing;
+use crate::transport::Transport;
+use crate::Broker;
+
+// a worker registers to all its topics at once with a line per topic in the topic frame: `<topic> [options]`,
+// the options of a line (`labels=`, `direct`, `weight=`) only apply to its topic, the ones of the options frame to all of them
+// the registration is taken or refused as a whole, the worker never gets tasks of only part of its topics
+
+pub fn parse<'a>(topics: &'a str, options: &'a str) -> Vec<(&'a str, String)> {
+    topics
+        .lines()
+        .filter_map(|line| {
+            let (topic, line_options) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match topic.is_empty() {
+                true => None,
+                false => Some((
+                    topic,
+                    format!("{} {}", options, line_options).trim().to_string(),
+                )),
+            }
+        })
+        .collect()
+}
+
+impl Broker {
+    // `false` when a topic is forbidden to the peer, which is told with `@@FORBIDDEN <topic>`
+    pub fn register(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        uid: Option<u32>,
+        topics: &str,
+        options: &str,
+    ) -> bool {
+        let registrations = parse(topics, options);
+        if let Some((topic, _)) = registrations
+            .iter()
+            .find(|(topic, _)| !self.allows_local(uid, topic))
+        {
+            transport.send(identity, &["", "@@FORBIDDEN", topic]).ok();
+            return false;
+        }
+        if !self.register_epoch(transport, identity, options) {
+            return false;
+        }
+
+        self.add_worker_topics(identity, &registrations);
+        self.record_features(transport, identity, options);
+        self.record_weight(identity, options);
+        true
+    }
+
+    fn add_worker_topics(&mut self, identity: &str, registrations: &[(&str, String)]) {
+        for (topic, options) in registrations {
+            self.add_client(true, identity, topic);
+            for label in routing::labels(options) {
+                self.add_client(true, identity, &routing::labelled_topic(topic, label));
+            }
+            if options.split(' ').any(|option| option == "direct") {
+                self.direct_workers.insert(identity.to_string());
+            }
+        }
+    }
+
+    // a registered worker serves more topics without registering again (`@@SUBSCRIBE_TOPIC`, lines and options like
+    // a registration), its epoch and features are kept
+    pub fn subscribe_topics(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        uid: Option<u32>,
+        topics: &str,
+        options: &str,
+    ) -> bool {
+        let is_worker = self
+            .registry
+            .clients
+            .get(identity)
+            .is_some_and(|client| client.is_worker);
+        if !is_worker {
+            log::warn(&format!(
+                "{} subscribed to topics without being registered",
+                identity
+            ));
+            transport.send(identity, &["", "@@REGISTER"]).ok();
+            return false;
+        }
+        let registrations = parse(topics, options);
+        if let Some((topic, _)) = registrations
+            .iter()
+            .find(|(topic, _)| !self.allows_local(uid, topic))
+        {
+            transport.send(identity, &["", "@@FORBIDDEN", topic]).ok();
+            return false;
+        }
+
+        log::info(&format!(
+            "Worker {} subscribed to {}",
+            identity,
+            topics.replace('\n', ", ")
+        ));
+        self.add_worker_topics(identity, &registrations);
+        self.record_weight(identity, options);
+        true
+    }
+
+    // the worker stops serving topics (`@@UNSUBSCRIBE_TOPIC`, a line per topic), their labelled topics included, its
+    // running tasks are still answered (and sent again to the other workers of the topic if they time out)
+    // a worker without topic left is gone, like with `@@UNREGISTER`
+    pub fn unsubscribe_topics(&mut self, identity: &str, topics: &str) {
+        let topics: Vec<&str> = topics
+            .lines()
+            .map(str::trim)
+            .filter(|topic| !topic.is_empty())
+            .collect();
+        let worker_topics: Vec<String> = match self.registry.clients.get(identity) {
+            Some(client) if client.is_worker => client.topics.clone(),
+            _ => return,
+        };
+        let (removed, kept): (Vec<String>, Vec<String>) =
+            worker_topics.into_iter().partition(|worker_topic| {
+                topics.iter().any(|topic| {
+                    worker_topic == topic
+                        || worker_topic.starts_with(&routing::labelled_topic(topic, ""))
+                })
+            });
+
+        log::info(&format!(
+            "Worker {} unsubscribed from {}",
+            identity,
+            topics.join(", ")
+        ));
+        if kept.is_empty() {
+            self.remove_worker(identity);
+        } else {
+            removed
+                .iter()
+                .for_each(|topic| self.registry.remove_worker_from_topic(identity, topic));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn topics_are_given_a_line_each() {
+        assert_eq!(
+            parse("ADD", "epoch=1"),
+            vec![("ADD", "epoch=1".to_string())]
+        );
+        assert_eq!(
+            parse("ADD labels=gpu\n\nSUB\r\n", "epoch=1"),
+            vec![
+                ("ADD", "epoch=1 labels=gpu".to_string()),
+                ("SUB", "epoch=1".to_string())
+            ]
+        );
+        assert!(parse("", "").is_empty());
+    }
+}
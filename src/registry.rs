@@ -0,0 +1,342 @@
+use crate::Broker;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+// who is connected, and on which topics: workers on the topics they handle, clients on the response topics
+// they wait on
+// it knows nothing about tasks nor sockets
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+    pub is_worker: bool,
+    pub topics: Vec<String>,
+    // its last message
+    pub last_activity: SystemTime,
+}
+
+impl Client {
+    fn new(name: &str, is_worker: bool, now: SystemTime) -> Client {
+        Client {
+            is_worker,
+            name: name.to_string(),
+            topics: vec![],
+            last_activity: now,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Topic {
+    pub name: String,
+    pub workers: Vec<String>,
+    next_worker_index: usize,
+    // smooth weighted round-robin, when the workers don't all have the same weight
+    current_weights: HashMap<String, i64>,
+    pub clients: Vec<String>,
+    pub last_activity: SystemTime,
+}
+
+impl Topic {
+    fn new(name: &str, now: SystemTime) -> Topic {
+        Topic {
+            name: name.to_string(),
+            workers: vec![],
+            next_worker_index: 0,
+            current_weights: HashMap::new(),
+            clients: vec![],
+            last_activity: now,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    pub clients: HashMap<String, Client>,
+    pub topics: HashMap<String, Topic>,
+}
+
+impl Registry {
+    // false when the peer was already on the topic (the same client asked twice or subscribed)
+    pub fn add(
+        &mut self,
+        is_worker: bool,
+        identity: &str,
+        topic_name: &str,
+        now: SystemTime,
+    ) -> bool {
+        let client = self
+            .clients
+            .entry(identity.to_string())
+            .or_insert_with(|| Client::new(identity, is_worker, now));
+        if client.topics.iter().any(|name| name == topic_name) {
+            return false;
+        }
+        client.topics.push(topic_name.to_string());
+
+        let topic = self
+            .topics
+            .entry(topic_name.to_string())
+            .or_insert_with(|| Topic::new(topic_name, now));
+        topic.last_activity = now;
+        if is_worker {
+            topic.workers.push(identity.to_string());
+        } else {
+            topic.clients.push(identity.to_string());
+        }
+
+        true
+    }
+
+    // round-robin, skipped workers are only picked when there is no other worker
+    // workers with a weight (1 by default) get that many tasks for one of a worker of weight 1, interleaved
+    pub fn next_worker(
+        &mut self,
+        topic_name: &str,
+        skipped: &[String],
+        weights: &HashMap<String, usize>,
+        now: SystemTime,
+    ) -> Option<String> {
+        let topic = self.topics.get_mut(topic_name)?;
+        topic.last_activity = now;
+        let weight = |worker_name: &String| weights.get(worker_name).copied().unwrap_or(1) as i64;
+        if topic
+            .workers
+            .iter()
+            .any(|worker_name| weight(worker_name) != 1)
+        {
+            let candidates: Vec<&String> = topic
+                .workers
+                .iter()
+                .filter(|worker_name| !skipped.contains(worker_name))
+                .collect();
+            let total: i64 = candidates
+                .iter()
+                .map(|worker_name| weight(worker_name))
+                .sum();
+            let mut picked: Option<(&String, i64)> = None;
+            for worker_name in candidates {
+                let current = topic
+                    .current_weights
+                    .entry(worker_name.clone())
+                    .or_default();
+                *current += weight(worker_name);
+                if picked.is_none_or(|(_, best)| *current > best) {
+                    picked = Some((worker_name, *current));
+                }
+            }
+            if let Some((worker_name, _)) = picked {
+                *topic.current_weights.get_mut(worker_name)? -= total;
+                return Some(worker_name.clone());
+            }
+        }
+        let mut skipped_worker_name = None;
+
+        for _ in 0..topic.workers.len() {
+            if topic.next_worker_index >= topic.workers.len() {
+                topic.next_worker_index = 0;
+            }
+            let worker_name = &topic.workers[topic.next_worker_index];
+            topic.next_worker_index += 1;
+
+            if !skipped.contains(worker_name) {
+                return Some(worker_name.clone());
+            }
+            skipped_worker_name.get_or_insert_with(|| worker_name.clone());
+        }
+
+        skipped_worker_name
+    }
+
+    pub fn clients_of(&self, topic_name: &str) -> Vec<String> {
+        self.topics
+            .get(topic_name)
+            .map(|topic| topic.clients.clone())
+            .unwrap_or_default()
+    }
+
+    // the client stops waiting on the topic
+    // the client and the topic are removed once nobody uses them
+    pub fn remove_client_from_topic(&mut self, identity: &str, topic_name: &str) {
+        if let Some(client) = self.clients.get_mut(identity) {
+            client.topics.retain(|name| name != topic_name);
+            if client.topics.is_empty() {
+                self.clients.remove(identity);
+            }
+        }
+
+        if let Some(topic) = self.topics.get_mut(topic_name) {
+            topic.clients.retain(|name| name != identity);
+        }
+        self.remove_if_unused(topic_name);
+    }
+
+    pub fn remove_if_unused(&mut self, topic_name: &str) {
+        if self
+            .topics
+            .get(topic_name)
+            .is_some_and(|topic| topic.clients.is_empty() && topic.workers.is_empty())
+        {
+            self.topics.remove(topic_name);
+        }
+    }
+
+    // the worker stops serving the topic, it keeps its other topics
+    pub fn remove_worker_from_topic(&mut self, identity: &str, topic_name: &str) {
+        if let Some(client) = self.clients.get_mut(identity) {
+            client.topics.retain(|name| name != topic_name);
+        }
+        if let Some(topic) = self.topics.get_mut(topic_name) {
+            topic.workers.retain(|name| name != identity);
+            topic.current_weights.remove(identity);
+        }
+        self.remove_if_unused(topic_name);
+    }
+
+    // false when the worker is unknown
+    pub fn remove_worker(&mut self, worker_name: &str) -> bool {
+        let worker = match self.clients.remove(worker_name) {
+            Some(worker) => worker,
+            None => return false,
+        };
+
+        worker.topics.iter().for_each(|topic| {
+            if let Some(topic) = self.topics.get_mut(topic) {
+                topic.workers.retain(|name| name != worker_name);
+                topic.current_weights.remove(worker_name);
+            }
+        });
+
+        true
+    }
+}
+
+impl Broker {
+    // avoided workers are only picked when there is no other worker, slow workers get a reduced share (see stats.rs)
+    pub fn get_next_worker_name(&mut self, topic_name: &str, avoided: &[String]) -> Option<String> {
+        let now = self.now();
+        match self.slow_worker_weights(topic_name) {
+            Some(weights) => self
+                .registry
+                .next_worker(topic_name, avoided, &weights, now),
+            None => self
+                .registry
+                .next_worker(topic_name, avoided, &self.worker_weights, now),
+        }
+    }
+
+    pub fn add_client(&mut self, is_worker: bool, identity: &str, topic_name: &str) {
+        let now = self.now();
+        if self.registry.add(is_worker, identity, topic_name, now) && is_worker {
+            self.emit(
+                "worker.joined",
+                &[("worker", identity), ("topic", topic_name)],
+            );
+        }
+    }
+
+    pub fn remove_client_from_topic(&mut self, identity: &str, topic_name: &str) {
+        self.registry.remove_client_from_topic(identity, topic_name);
+    }
+
+    pub fn remove_worker(&mut self, worker_name: &str) {
+        if self.registry.remove_worker(worker_name) {
+            // the stats and the epoch of a logical name are kept for its next identity
+            if self.logical_names.remove(worker_name).is_none() {
+                self.worker_stats.remove(worker_name);
+                self.worker_epochs.remove(worker_name);
+            }
+            self.direct_workers.remove(worker_name);
+            self.worker_features.remove(worker_name);
+            self.worker_weights.remove(worker_name);
+            self.emit("worker.lost", &[("worker", worker_name)]);
+        }
+    }
+
+    // nobody will answer on this topic, its clients stop waiting
+    pub fn abandon_response_topic(&mut self, topic_name: &str) {
+        self.registry
+            .clients_of(topic_name)
+            .iter()
+            .for_each(|identity| {
+                self.registry.remove_client_from_topic(identity, topic_name);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+    use std::collections::HashMap;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn next_worker_is_round_robin_and_skips_slow_workers() {
+        let mut registry = Registry::default();
+        ["worker-1", "worker-2", "worker-3"]
+            .iter()
+            .for_each(|worker| {
+                registry.add(true, worker, "ADD", UNIX_EPOCH);
+            });
+        let slow = vec!["worker-2".to_string()];
+        let weights = HashMap::new();
+
+        let picked: Vec<String> = (0..4)
+            .filter_map(|_| registry.next_worker("ADD", &slow, &weights, UNIX_EPOCH))
+            .collect();
+        assert_eq!(picked, vec!["worker-1", "worker-3", "worker-1", "worker-3"]);
+
+        // a slow worker is better than no worker
+        registry.remove_worker("worker-1");
+        registry.remove_worker("worker-3");
+        assert_eq!(
+            registry
+                .next_worker("ADD", &slow, &weights, UNIX_EPOCH)
+                .as_deref(),
+            Some("worker-2")
+        );
+        assert_eq!(
+            registry.next_worker("SUB", &slow, &weights, UNIX_EPOCH),
+            None
+        );
+    }
+
+    #[test]
+    fn weighted_workers_get_more_tasks_interleaved() {
+        let mut registry = Registry::default();
+        ["worker-1", "worker-2", "worker-3"]
+            .iter()
+            .for_each(|worker| {
+                registry.add(true, worker, "ADD", UNIX_EPOCH);
+            });
+        let weights = HashMap::from([("worker-1".to_string(), 3)]);
+
+        let picked: Vec<String> = (0..5)
+            .filter_map(|_| registry.next_worker("ADD", &[], &weights, UNIX_EPOCH))
+            .collect();
+        assert_eq!(
+            picked,
+            vec!["worker-1", "worker-2", "worker-1", "worker-3", "worker-1"]
+        );
+
+        // skipped workers don't get a share
+        let slow = vec!["worker-1".to_string()];
+        let picked: Vec<String> = (0..2)
+            .filter_map(|_| registry.next_worker("ADD", &slow, &weights, UNIX_EPOCH))
+            .collect();
+        assert_eq!(picked, vec!["worker-2", "worker-3"]);
+    }
+
+    #[test]
+    fn clients_and_topics_are_removed_once_unused() {
+        let mut registry = Registry::default();
+        assert!(registry.add(false, "client-1", "ADD>RESPONSE", UNIX_EPOCH));
+        assert!(!registry.add(false, "client-1", "ADD>RESPONSE", UNIX_EPOCH));
+        assert_eq!(registry.clients_of("ADD>RESPONSE"), vec!["client-1"]);
+
+        registry.remove_client_from_topic("client-1", "ADD>RESPONSE");
+        assert!(registry.clients.is_empty());
+        assert!(registry.topics.is_empty());
+        assert!(!registry.remove_worker("client-1"));
+    }
+}
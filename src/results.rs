@@ -0,0 +1,313 @@
+#[cfg(feature = "persistence")]
+use crate::encryption::{self, Cipher};
+#[cfg(feature = "persistence")]
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+#[cfg(feature = "persistence")]
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+// responses are kept for `RESULTS_TTL`, so clients can fetch them later with `@@RESULT <response topic>` instead
+// of waiting on the response topic
+// the oldest ones go first when they take more than `RESULTS_MAX_SIZE` bytes
+// with `RESULTS_DIR`, they are written there too, one file per response, and loaded back on start, encrypted with a
+// storage key (see encryption.rs)
+// `@@WAIT <response topic> <timeout>` is answered as soon as the response comes, or with `@@NO_RESULT` once the
+// timeout (milliseconds) expires, for clients that can't wait on a response topic
+
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+
+pub fn results_ttl_as_secs() -> u64 {
+    env::var("RESULTS_TTL")
+        .map(|v| v.parse::<u64>().unwrap_or(0))
+        .unwrap_or(0)
+}
+
+pub fn results_max_size() -> usize {
+    env::var("RESULTS_MAX_SIZE")
+        .map(|v| v.parse::<usize>().unwrap_or(10_000_000))
+        .unwrap_or(10_000_000)
+}
+
+// the file name is the hexadecimal response topic, response topics are free text
+pub fn file_name(response_topic: &str) -> String {
+    response_topic
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(feature = "persistence")]
+fn response_topic_of(file_name: &str) -> Option<String> {
+    let bytes = (0..file_name.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(file_name.get(index..index + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+// what the encrypted content of a file is, see encryption.rs
+#[cfg(feature = "persistence")]
+fn record(response_topic: &str) -> String {
+    format!("result\t{}", response_topic)
+}
+
+// a client waiting for a response
+pub struct Wait {
+    identity: String,
+    response_topic: String,
+    deadline: SystemTime,
+}
+
+pub struct Results {
+    ttl: Duration,
+    max_size: usize,
+    size: usize,
+    dir: Option<PathBuf>,
+    #[cfg(feature = "persistence")]
+    cipher: Option<Cipher>,
+    // oldest first
+    order: VecDeque<String>,
+    results: HashMap<String, (String, SystemTime)>,
+}
+
+impl Results {
+    pub fn new(ttl: Duration, max_size: usize) -> Results {
+        Results {
+            ttl,
+            max_size,
+            size: 0,
+            dir: None,
+            #[cfg(feature = "persistence")]
+            cipher: None,
+            order: VecDeque::new(),
+            results: HashMap::new(),
+        }
+    }
+
+    pub fn from_env() -> Results {
+        Results::new(
+            Duration::from_secs(results_ttl_as_secs()),
+            results_max_size(),
+        )
+    }
+
+    // with `RESULTS_DIR`, the results are written there, and the ones written before are loaded
+    #[cfg(feature = "persistence")]
+    pub fn stored(mut self, cipher: Option<Cipher>) -> Results {
+        self.dir = env::var("RESULTS_DIR").ok().map(PathBuf::from);
+        self.cipher = cipher;
+        if let Err(error) = self.load() {
+            log::warn(&format!("Can't load the results: {}", error));
+        }
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ttl > Duration::from_secs(0)
+    }
+
+    #[cfg(feature = "persistence")]
+    fn load(&mut self) -> io::Result<()> {
+        let dir = match &self.dir {
+            Some(dir) if self.is_enabled() => dir.clone(),
+            _ => return Ok(()),
+        };
+        fs::create_dir_all(&dir)?;
+
+        let mut loaded = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let response_topic = match entry.file_name().to_str().and_then(response_topic_of) {
+                Some(response_topic) => response_topic,
+                None => continue,
+            };
+            let date = entry.metadata()?.modified()?;
+            let content = fs::read_to_string(entry.path())?;
+            match encryption::open(self.cipher.as_ref(), &record(&response_topic), &content) {
+                Ok(payload) => loaded.push((date, response_topic, payload)),
+                Err(error) => log::warn(&format!(
+                    "Can't load the result of {}: {}",
+                    response_topic, error
+                )),
+            }
+        }
+
+        loaded.sort_by_key(|(date, _, _)| *date);
+        loaded
+            .into_iter()
+            .for_each(|(date, response_topic, payload)| self.keep(&response_topic, payload, date));
+        Ok(())
+    }
+
+    fn keep(&mut self, response_topic: &str, payload: String, date: SystemTime) {
+        self.remove(response_topic);
+        self.size += payload.len();
+        self.order.push_back(response_topic.to_string());
+        self.results
+            .insert(response_topic.to_string(), (payload, date));
+
+        while self.size > self.max_size {
+            match self.order.front().cloned() {
+                Some(oldest) => self.remove(&oldest),
+                None => break,
+            }
+        }
+    }
+
+    pub fn insert(&mut self, response_topic: &str, payload: &str, now: SystemTime) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        #[cfg(feature = "persistence")]
+        self.write(response_topic, payload);
+        self.keep(response_topic, payload.to_string(), now);
+    }
+
+    #[cfg(feature = "persistence")]
+    fn write(&self, response_topic: &str, payload: &str) {
+        if let Some(dir) = &self.dir {
+            let written = encryption::seal(self.cipher.as_ref(), &record(response_topic), payload)
+                .and_then(|content| {
+                    fs::write(dir.join(file_name(response_topic)), content)
+                        .map_err(|error| error.to_string())
+                });
+            if let Err(error) = written {
+                log::warn(&format!(
+                    "Can't write the result of {}: {}",
+                    response_topic, error
+                ));
+            }
+        }
+    }
+
+    pub fn get(&self, response_topic: &str) -> Option<&str> {
+        self.results
+            .get(response_topic)
+            .map(|(payload, _)| payload.as_str())
+    }
+
+    fn remove(&mut self, response_topic: &str) {
+        let (payload, _) = match self.results.remove(response_topic) {
+            Some(result) => result,
+            None => return,
+        };
+        self.size -= payload.len();
+        self.order.retain(|kept| kept != response_topic);
+
+        if let Some(dir) = &self.dir {
+            fs::remove_file(dir.join(file_name(response_topic))).ok();
+        }
+    }
+
+    pub fn expire(&mut self, now: SystemTime) {
+        while let Some(oldest) = self.order.front().cloned() {
+            let date = self.results[&oldest].1;
+            if now.duration_since(date).unwrap_or_default() < self.ttl {
+                break;
+            }
+            self.remove(&oldest);
+        }
+    }
+}
+
+impl Broker {
+    pub fn send_result(&self, transport: &dyn Transport, identity: &str, response_topic: &str) {
+        match self.results.get(response_topic) {
+            Some(payload) => transport.send(identity, &["", "@@RESULT", response_topic, payload]),
+            None => transport.send(identity, &["", "@@NO_RESULT", response_topic]),
+        }
+        .ok();
+    }
+
+    // answered now if the response is known
+    pub fn wait_result(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        response_topic: &str,
+        timeout_as_millis: &str,
+    ) {
+        if self.results.get(response_topic).is_some() {
+            self.send_result(transport, identity, response_topic);
+            return;
+        }
+
+        let timeout = timeout_as_millis
+            .parse()
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_WAIT);
+        self.waits.push(Wait {
+            identity: identity.to_string(),
+            response_topic: response_topic.to_string(),
+            deadline: self.now() + timeout,
+        });
+    }
+
+    // the clients waiting for this response get it, even if responses are not kept
+    pub fn end_waits(&mut self, transport: &dyn Transport, response_topic: &str, payload: &str) {
+        self.waits.retain(|wait| {
+            if wait.response_topic != response_topic {
+                return true;
+            }
+            transport
+                .send(&wait.identity, &["", "@@RESULT", response_topic, payload])
+                .ok();
+            false
+        });
+    }
+
+    pub fn expire_waits(&mut self, transport: &dyn Transport) {
+        let now = self.now();
+        self.waits.retain(|wait| {
+            if wait.deadline > now {
+                return true;
+            }
+            transport
+                .send(&wait.identity, &["", "@@NO_RESULT", &wait.response_topic])
+                .ok();
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Results;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn results_are_bounded_by_size_and_ttl() {
+        let mut results = Results::new(Duration::from_secs(60), 10);
+        results.insert("R1", "1234", UNIX_EPOCH);
+        results.insert("R2", "5678", UNIX_EPOCH + Duration::from_secs(10));
+        assert_eq!(results.get("R1"), Some("1234"));
+
+        // the oldest goes first
+        results.insert("R3", "90", UNIX_EPOCH + Duration::from_secs(20));
+        results.insert("R4", "1", UNIX_EPOCH + Duration::from_secs(20));
+        assert_eq!(results.get("R1"), None);
+        assert_eq!(results.get("R2"), Some("5678"));
+
+        results.expire(UNIX_EPOCH + Duration::from_secs(70));
+        assert_eq!(results.get("R2"), None);
+        assert_eq!(results.get("R3"), Some("90"));
+    }
+
+    #[test]
+    #[cfg(feature = "persistence")]
+    fn file_names_are_hexadecimal_response_topics() {
+        use super::{file_name, response_topic_of};
+
+        assert_eq!(file_name("A/b"), "412f62");
+        assert_eq!(response_topic_of("412f62").as_deref(), Some("A/b"));
+        assert_eq!(response_topic_of("4"), None);
+        assert_eq!(response_topic_of("zz"), None);
+    }
+}
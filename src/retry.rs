@@ -0,0 +1,329 @@
+use crate::cli;
+use crate::dlq::Failure;
+use crate::log;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+use std::time::{Duration, SystemTime};
+
+// what happens to the tasks of a topic that fail, set when it is declared:
+// - `retries=<count>`: failures (on any worker) before the task is moved to the dead letter queue, instead of failing
+//   on `POISON_THRESHOLD` workers
+// - `backoff=<none|fixed:<duration>|exponential:<duration>>`: time to wait before sending a failed task again, the
+//   exponential one doubles with each failure, up to an hour
+// - `retry_on=<reason>,...`: the failures that are retried, the others move the task to the dead letter queue at
+//   once: `unreachable` (the task can't be sent to the worker), `timeout`, and `retry` (the worker answered
+//   `@@RETRY`) and `restart` (the worker registered again with a new epoch, see epochs.rs), all of them by default
+// - `on_retry=<backoff|elsewhere|requeue>`: what a `@@RETRY` does, the task waits for the backoff (default), is sent
+//   at once to another worker (the one that asked only gets it back when there is no other), or goes behind the
+//   tasks waiting for a worker
+
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+// `retries=` above it is refused, a task failing that much is a poison message anyway
+pub const MAX_RETRIES: usize = 10_000;
+const REASONS: &[&str] = &["unreachable", "timeout", "retry", "restart"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backoff {
+    #[default]
+    None,
+    Fixed(Duration),
+    Exponential(Duration),
+}
+
+impl Backoff {
+    pub fn parse(value: &str) -> Result<Backoff, String> {
+        let bad_backoff = || {
+            format!(
+                "unknown backoff {}, expected none, fixed:<duration> or exponential:<duration>",
+                value
+            )
+        };
+        let (kind, delay) = match value.split_once(':') {
+            Some((kind, delay)) => (kind, cli::parse_duration(delay).ok_or_else(bad_backoff)?),
+            None if value == "none" => return Ok(Backoff::None),
+            None => return Err(bad_backoff()),
+        };
+        match kind {
+            "fixed" => Ok(Backoff::Fixed(delay)),
+            "exponential" => Ok(Backoff::Exponential(delay)),
+            _ => Err(bad_backoff()),
+        }
+    }
+
+    // before the next try, after this many failures
+    fn delay(self, failures: usize) -> Duration {
+        match self {
+            Backoff::None => Duration::from_secs(0),
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential(delay) => {
+                let exponent = failures.saturating_sub(1).min(31) as u32;
+                delay.saturating_mul(2u32.pow(exponent)).min(MAX_BACKOFF)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OnRetry {
+    #[default]
+    Backoff,
+    Elsewhere,
+    Requeue,
+}
+
+impl OnRetry {
+    pub fn parse(value: &str) -> Result<OnRetry, String> {
+        match value {
+            "backoff" => Ok(OnRetry::Backoff),
+            "elsewhere" => Ok(OnRetry::Elsewhere),
+            "requeue" => Ok(OnRetry::Requeue),
+            _ => Err(format!(
+                "unknown on_retry {}, expected backoff, elsewhere or requeue",
+                value
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OnRetry::Backoff => "backoff",
+            OnRetry::Elsewhere => "elsewhere",
+            OnRetry::Requeue => "requeue",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    pub retries: Option<usize>,
+    pub backoff: Backoff,
+    // all the reasons when not given
+    pub retry_on: Option<Vec<String>>,
+    pub on_retry: OnRetry,
+}
+
+pub fn parse_retries(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(retries) if retries <= MAX_RETRIES => Ok(retries),
+        Ok(_) => Err(format!("retries is above {}", MAX_RETRIES)),
+        Err(_) => Err("retries is not a number".to_string()),
+    }
+}
+
+pub fn parse_reasons(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .filter(|reason| !reason.is_empty())
+        .map(|reason| match REASONS.contains(&reason) {
+            true => Ok(reason.to_string()),
+            false => Err(format!(
+                "unknown reason {}, expected {}",
+                reason,
+                REASONS.join(", ")
+            )),
+        })
+        .collect()
+}
+
+impl RetryPolicy {
+    pub fn retries_on(&self, reason: &str) -> bool {
+        self.retry_on
+            .as_ref()
+            .is_none_or(|reasons| reasons.iter().any(|retried| retried == reason))
+    }
+}
+
+impl Broker {
+    pub fn retry_policy(&self, topic_name: &str) -> RetryPolicy {
+        self.declared_topics
+            .get(topic_name)
+            .map(|settings| settings.retry.clone())
+            .unwrap_or_default()
+    }
+
+    // the task, if it can be sent again now, otherwise it waits for its backoff
+    pub fn delay_retry(&mut self, task: Task) -> Option<Task> {
+        let delay = self
+            .retry_policy(&task.worker_topic)
+            .backoff
+            .delay(task.failures.len());
+        if task.failures.is_empty() || delay.is_zero() || self.is_poison(&task) {
+            return Some(task);
+        }
+
+        log::info(&format!(
+            "Task {} failed, sending it again in {}ms",
+            task.worker_topic,
+            delay.as_millis()
+        ));
+        let retry_at = self.now() + delay;
+        self.dispatcher.delayed.push((retry_at, task));
+        None
+    }
+
+    // the tasks whose backoff is over
+    pub fn retry_delayed_tasks(&mut self, transport: &dyn Transport) {
+        let now = self.now();
+        let (due, delayed): (Vec<(SystemTime, Task)>, _) = self
+            .dispatcher
+            .delayed
+            .drain(..)
+            .partition(|(retry_at, _)| *retry_at <= now);
+        self.dispatcher.delayed = delayed;
+        for (_, task) in due {
+            self.send_task_and_retry(transport, task);
+        }
+    }
+
+    // the workers that asked to retry the task, on topics sending it elsewhere
+    pub fn avoided_workers(&self, task: &Task) -> Vec<String> {
+        if self.retry_policy(&task.worker_topic).on_retry != OnRetry::Elsewhere {
+            return vec![];
+        }
+        task.failures
+            .iter()
+            .filter(|failure| failure.reason == "retry")
+            .map(|failure| failure.worker_name.clone())
+            .collect()
+    }
+
+    // the worker can't process the task now (`@@RETRY`), it is sent again as its topic says
+    pub fn retry_task(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        response_topic: &str,
+        reason: &str,
+    ) {
+        if !self.dispatcher.is_sent_to(response_topic, identity) {
+            log::warn(&format!(
+                "Worker {} asked to retry task {} which wasn't sent to it, ignored",
+                identity, response_topic
+            ));
+            return;
+        }
+        for mut task in self.dispatcher.tasks.complete_by(response_topic, identity) {
+            let on_retry = self.retry_policy(&task.worker_topic).on_retry;
+            log::info(&format!(
+                "Worker {} asked to retry task {} ({}): {}",
+                identity,
+                task.worker_topic,
+                on_retry.name(),
+                reason
+            ));
+            self.emit(
+                "task.nacked",
+                &[
+                    ("topic", &task.worker_topic),
+                    ("responseTopic", &task.response_topic),
+                    ("worker", identity),
+                    ("reason", reason),
+                    ("onRetry", on_retry.name()),
+                ],
+            );
+            task.failures.push(Failure::new(identity, "retry"));
+
+            match on_retry {
+                OnRetry::Backoff => {
+                    if let Some(task) = self.delay_retry(task) {
+                        self.send_task_and_retry(transport, task);
+                    }
+                }
+                OnRetry::Elsewhere => self.send_task_and_retry(transport, task),
+                OnRetry::Requeue if self.is_poison(&task) => {
+                    self.send_task_and_retry(transport, task)
+                }
+                OnRetry::Requeue => {
+                    self.dispatcher.tasks_to_retry.push(task);
+                    self.retry_tasks(transport);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_reasons, parse_retries, Backoff, OnRetry, RetryPolicy, MAX_RETRIES};
+    use crate::embedded::BrokerHandle;
+    use std::time::Duration;
+
+    #[test]
+    fn tasks_are_retried_past_255_times() {
+        assert!(parse_retries(&(MAX_RETRIES + 1).to_string()).is_err());
+        assert!(parse_retries("-1").is_err());
+
+        let mut broker = BrokerHandle::new();
+        assert!(broker
+            .admin("CREATE_TOPIC ADD retries=1000")
+            .starts_with("OK"));
+        broker.register_worker("worker-1", "ADD");
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        for _ in 0..300 {
+            assert_eq!(
+                broker.receive("worker-1"),
+                Some(vec!["".to_string(), "1+1".to_string()])
+            );
+            broker
+                .send("worker-1", &["@@RETRY", "ADD>1", "busy"])
+                .unwrap();
+        }
+        broker.respond("worker-1", "ADD>1", "2");
+        assert_eq!(
+            broker.receive("client-1"),
+            Some(vec!["".to_string(), "2".to_string()])
+        );
+    }
+
+    #[test]
+    fn only_the_assigned_worker_can_ask_to_retry() {
+        let mut broker = BrokerHandle::new();
+        broker.register_worker("worker-1", "ADD");
+        broker.register_worker("worker-2", "SUB");
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        assert!(broker.receive("worker-1").is_some());
+
+        broker
+            .send("worker-2", &["@@RETRY", "ADD>1", "busy"])
+            .unwrap();
+        assert_eq!(broker.receive("worker-1"), None);
+        assert!(broker.broker.dispatcher.is_sent_to("ADD>1", "worker-1"));
+
+        broker.respond("worker-1", "ADD>1", "2");
+        assert_eq!(
+            broker.receive("client-1"),
+            Some(vec!["".to_string(), "2".to_string()])
+        );
+    }
+
+    #[test]
+    fn backoffs_grow_with_failures() {
+        let exponential = Backoff::parse("exponential:500ms").unwrap();
+        let delays: Vec<u128> = (1..5)
+            .map(|failures| exponential.delay(failures).as_millis())
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 4000]);
+        assert_eq!(exponential.delay(100), Duration::from_secs(3600));
+        assert_eq!(
+            Backoff::parse("fixed:2s").unwrap().delay(5),
+            Duration::from_secs(2)
+        );
+        assert_eq!(Backoff::parse("none").unwrap(), Backoff::None);
+        assert!(Backoff::parse("linear:1s").is_err());
+        assert!(Backoff::parse("fixed").is_err());
+    }
+
+    #[test]
+    fn only_the_given_reasons_are_retried() {
+        let policy = RetryPolicy {
+            retry_on: Some(parse_reasons("timeout,retry").unwrap()),
+            ..RetryPolicy::default()
+        };
+        assert!(policy.retries_on("timeout"));
+        assert!(!policy.retries_on("unreachable"));
+        assert!(RetryPolicy::default().retries_on("unreachable"));
+        assert!(parse_reasons("timeout,crash").is_err());
+        assert_eq!(OnRetry::parse("requeue").unwrap(), OnRetry::Requeue);
+        assert!(OnRetry::parse("later").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::log;
+use crate::signature::same;
+use crate::Broker;
+use std::env;
+
+// with `ADMIN_USERS`, admin requests start with the token of a user, `token=<token> <command> ...`, and the role of
+// the user tells the commands it can run, so dashboards can read stats without being able to drain queues:
+// - observer: PEEK, DLQ, WORKERS, TOPICS, SLOW_WORKERS, STATS, PEER, AUDIT, ENDPOINTS, UNDELIVERED, HISTORY_DEPTH,
+//   GRAPH, RECOVERY
+// - operator: the observer commands, DRAIN, DRAIN_WORKER, SHUTDOWN, RESTART, DEBUG, EXPORT, WORKER_WEIGHT,
+//   TOPIC_WEIGHT, PAUSE, RESUME, REPLAY
+// - admin: every command, CREATE_TOPIC (settings and acl) and IMPORT included
+// the user name goes to the audit log with the address of the admin client
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Role {
+    Observer,
+    Operator,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Result<Role, String> {
+        match value {
+            "observer" => Ok(Role::Observer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!(
+                "unknown role {}, expected observer, operator or admin",
+                value
+            )),
+        }
+    }
+
+    // the role a command needs, unknown commands need the admin one
+    pub fn of_command(command: &str) -> Role {
+        match command {
+            "PEEK" | "DLQ" | "WORKERS" | "TOPICS" | "SLOW_WORKERS" | "STATS" | "PEER" | "AUDIT"
+            | "ENDPOINTS" | "UNDELIVERED" | "HISTORY_DEPTH" | "GRAPH" | "RECOVERY" => {
+                Role::Observer
+            }
+            "DRAIN" | "DRAIN_WORKER" | "SHUTDOWN" | "RESTART" | "DEBUG" | "EXPORT"
+            | "WORKER_WEIGHT" | "TOPIC_WEIGHT" | "PAUSE" | "RESUME" | "REPLAY" => Role::Operator,
+            _ => Role::Admin,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct User {
+    name: String,
+    role: Role,
+    token: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Users {
+    users: Vec<User>,
+}
+
+impl Users {
+    // `<name>:<role>:<token>;<name>:<role>:<token>`
+    // a bad entry is ignored, so its token gives no access rather than every access
+    pub fn parse(value: &str) -> Users {
+        let mut users = Users::default();
+
+        for entry in value.split(';').filter(|entry| !entry.trim().is_empty()) {
+            let mut fields = entry.trim().splitn(3, ':');
+            match (fields.next(), fields.next().map(Role::parse), fields.next()) {
+                (Some(name), Some(Ok(role)), Some(token)) if !token.is_empty() => {
+                    users.users.push(User {
+                        name: name.to_string(),
+                        role,
+                        token: token.to_string(),
+                    })
+                }
+                _ => log::warn(&format!(
+                    "Ignoring the admin user {}, expected name:role:token",
+                    entry.split(':').next().unwrap_or("")
+                )),
+            }
+        }
+
+        users
+    }
+
+    // the user name, and the request without its token
+    fn authorize<'a>(&self, request: &'a str) -> Result<(String, &'a str), String> {
+        let (token, request) = request
+            .strip_prefix("token=")
+            .map(|rest| rest.split_once(' ').unwrap_or((rest, "")))
+            .ok_or("a token is expected, token=<token> <command>")?;
+        let user = self
+            .users
+            .iter()
+            .find(|user| same(user.token.as_bytes(), token.as_bytes()))
+            .ok_or("unknown token")?;
+
+        let command = request.split_whitespace().next().unwrap_or("");
+        if user.role < Role::of_command(command) {
+            return Err(format!("{} can't run {}", user.name, command));
+        }
+        Ok((user.name.clone(), request))
+    }
+}
+
+// what the audit log keeps of a request
+pub fn without_token(request: &str) -> &str {
+    match request.strip_prefix("token=") {
+        Some(rest) => rest.split_once(' ').map_or("", |(_, request)| request),
+        None => request,
+    }
+}
+
+pub fn admin_users() -> Option<Users> {
+    env::var("ADMIN_USERS")
+        .ok()
+        .map(|value| Users::parse(&value))
+}
+
+impl Broker {
+    // the user running the request (none without users), and the request without its token
+    pub fn authorized_request<'a>(
+        &self,
+        request: &'a str,
+    ) -> Result<(Option<String>, &'a str), String> {
+        match &self.admin_users {
+            Some(users) => users
+                .authorize(request)
+                .map(|(name, request)| (Some(name), request)),
+            None => Ok((None, request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Users;
+
+    #[test]
+    fn roles_tell_the_commands_users_can_run() {
+        let users =
+            Users::parse("dashboard:observer:t1; ops:operator:t2;root:admin:t3;bad:root:t4");
+        assert_eq!(
+            users.authorize("token=t1 STATS"),
+            Ok(("dashboard".to_string(), "STATS"))
+        );
+        assert!(users.authorize("token=t1 DRAIN ADD").is_err());
+        assert_eq!(
+            users.authorize("token=t2 DRAIN ADD"),
+            Ok(("ops".to_string(), "DRAIN ADD"))
+        );
+        assert!(users.authorize("token=t2 CREATE_TOPIC ADD").is_err());
+        assert!(users.authorize("token=t3 CREATE_TOPIC ADD").is_ok());
+        assert!(users.authorize("token=t4 STATS").is_err());
+        assert!(users.authorize("STATS").is_err());
+    }
+}
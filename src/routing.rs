@@ -0,0 +1,208 @@
+use crate::json::{self, Value};
+use crate::Broker;
+
+// routing rules send the tasks of a topic elsewhere depending on their payload, they are topic settings:
+// `CREATE_TOPIC ADD route=$.country=de:ADD_EU route=$.country=fr:@eu`
+// a rule is `<path>=<value>:<target>`, the first rule whose path gives the value routes the task:
+// - to another topic, its workers get the task (its own rules don't apply)
+// - to the workers of the topic with a label when the target is `@<label>`, workers get labels when they register
+//   (`labels=eu,gpu` options), a labelled worker is also a worker of `<topic>@<label>`, which can be declared too
+// paths are JSONPath-style, `$.order.items[0].country`, tasks whose payload is not JSON are not routed
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Topic(String),
+    Label(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    path: Vec<Step>,
+    value: String,
+    target: Target,
+}
+
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let rest = path
+        .strip_prefix('$')
+        .ok_or_else(|| format!("bad path {}, it starts with $", path))?;
+    let mut steps = vec![];
+    let mut chars = rest.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                let mut field = String::new();
+                while let Some(c) = chars.peek().filter(|c| **c != '.' && **c != '[') {
+                    field.push(*c);
+                    chars.next();
+                }
+                if field.is_empty() {
+                    return Err(format!("bad path {}, a field has no name", path));
+                }
+                steps.push(Step::Field(field));
+            }
+            '[' => {
+                let index: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                let index = index
+                    .parse()
+                    .map_err(|_| format!("bad path {}, {} is not an index", path, index))?;
+                steps.push(Step::Index(index));
+            }
+            _ => return Err(format!("bad path {}, expected . or [ before {}", path, c)),
+        }
+    }
+
+    Ok(steps)
+}
+
+fn at<'a>(value: &'a Value, path: &[Step]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, step| match step {
+        Step::Field(field) => value.get(field),
+        Step::Index(index) => value.as_array()?.get(*index),
+    })
+}
+
+// the value of the rule is compared to the text of strings, numbers and booleans
+fn matches(value: &Value, expected: &str) -> bool {
+    match value {
+        Value::String(value) => value == expected,
+        Value::Number(value) => expected
+            .parse::<f64>()
+            .is_ok_and(|expected| expected == *value),
+        Value::Bool(value) => value.to_string() == expected,
+        Value::Null => expected == "null",
+        _ => false,
+    }
+}
+
+impl Route {
+    pub fn parse(rule: &str) -> Result<Route, String> {
+        let bad_rule = || format!("bad route {}, expected <path>=<value>:<target>", rule);
+        let (path, rest) = rule.split_once('=').ok_or_else(bad_rule)?;
+        let (value, target) = rest.rsplit_once(':').ok_or_else(bad_rule)?;
+        let target = match target.strip_prefix('@') {
+            Some(label) if !label.is_empty() => Target::Label(label.to_string()),
+            None if !target.is_empty() => Target::Topic(target.to_string()),
+            _ => return Err(bad_rule()),
+        };
+
+        Ok(Route {
+            path: parse_path(path)?,
+            value: value.to_string(),
+            target,
+        })
+    }
+
+    fn applies_to(&self, payload: &Value) -> bool {
+        at(payload, &self.path).is_some_and(|value| matches(value, &self.value))
+    }
+
+    // the topic whose workers get the tasks routed from the topic
+    pub fn target_topic(&self, topic_name: &str) -> String {
+        match &self.target {
+            Target::Topic(target) => target.clone(),
+            Target::Label(label) => labelled_topic(topic_name, label),
+        }
+    }
+
+    // `<path>=<value>`, as given
+    pub fn condition(&self) -> String {
+        let path: String = self
+            .path
+            .iter()
+            .map(|step| match step {
+                Step::Field(field) => format!(".{}", field),
+                Step::Index(index) => format!("[{}]", index),
+            })
+            .collect();
+        format!("${}={}", path, self.value)
+    }
+}
+
+// the topic of the workers of a label
+pub fn labelled_topic(topic_name: &str, label: &str) -> String {
+    format!("{}@{}", topic_name, label)
+}
+
+// `labels=eu,gpu` in the registration options
+pub fn labels(options: &str) -> Vec<&str> {
+    options
+        .split(' ')
+        .filter_map(|option| option.strip_prefix("labels="))
+        .flat_map(|labels| labels.split(','))
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+impl Broker {
+    // the topic whose workers get the task
+    pub fn route(&self, topic_name: &str, payload: &str) -> String {
+        let routes = match self.declared_topics.get(topic_name) {
+            Some(settings) if !settings.routes.is_empty() => &settings.routes,
+            _ => return topic_name.to_string(),
+        };
+        let payload = match json::parse(payload) {
+            Ok(payload) => payload,
+            Err(_) => return topic_name.to_string(),
+        };
+
+        match routes.iter().find(|route| route.applies_to(&payload)) {
+            Some(Route {
+                target: Target::Topic(target),
+                ..
+            }) => target.clone(),
+            Some(Route {
+                target: Target::Label(label),
+                ..
+            }) => labelled_topic(topic_name, label),
+            None => topic_name.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{labels, Route, Target};
+    use crate::json;
+
+    #[test]
+    fn routes_match_the_value_at_their_path() {
+        let route = Route::parse("$.order.items[1].country=de:@eu").unwrap();
+        assert_eq!(route.target, Target::Label("eu".to_string()));
+        assert_eq!(route.condition(), "$.order.items[1].country=de");
+        assert_eq!(route.target_topic("ADD"), "ADD@eu");
+
+        let payload = |country: &str| {
+            json::parse(&format!(
+                r#"{{"order":{{"items":[{{}},{{"country":{}}}]}}}}"#,
+                country
+            ))
+            .unwrap()
+        };
+        assert!(route.applies_to(&payload("\"de\"")));
+        assert!(!route.applies_to(&payload("\"fr\"")));
+        assert!(!route.applies_to(&json::parse("{}").unwrap()));
+
+        let route = Route::parse("$.priority=1:ADD>URGENT").unwrap();
+        assert_eq!(route.target, Target::Topic("ADD>URGENT".to_string()));
+        assert!(route.applies_to(&json::parse(r#"{"priority":1.0}"#).unwrap()));
+
+        assert!(Route::parse("$.country=de").is_err());
+        assert!(Route::parse("country=de:EU").is_err());
+        assert!(Route::parse("$.items[x]=de:EU").is_err());
+        assert!(Route::parse("$.country=de:@").is_err());
+    }
+
+    #[test]
+    fn labels_are_read_from_the_registration_options() {
+        assert_eq!(labels("direct labels=eu,gpu"), vec!["eu", "gpu"]);
+        assert!(labels("direct").is_empty());
+    }
+}
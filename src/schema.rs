@@ -0,0 +1,424 @@
+use crate::json::{self, Value};
+use crate::log;
+use crate::Broker;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+
+// with `SCHEMA_FILE`, the payloads of the tasks sent to the topics it lists are checked against their JSON Schema:
+// `{"<topic>": <schema>, ...}`
+// a task whose payload doesn't match is refused with `@@ERROR` followed by its response topic, `schema_violation`
+// and what doesn't match, its payload has to be JSON
+// the keywords understood are `type`, `enum`, `properties`, `required`, `additionalProperties` (false),
+// `items`, `minimum`, `maximum`, `minLength`, `maxLength`, `minItems` and `maxItems`, the others are ignored
+// `tiny-broke codegen <schema file>` prints the Rust structs of these payloads, for the SDK users
+
+#[derive(Debug, Default)]
+pub struct Schemas {
+    topics: HashMap<String, Value>,
+}
+
+fn parse_schemas(content: &str) -> Result<Vec<(String, Value)>, String> {
+    match json::parse(content)? {
+        Value::Object(topics) => Ok(topics),
+        _ => Err("the schema file is not an object of topics".to_string()),
+    }
+}
+
+impl Schemas {
+    pub fn parse(content: &str) -> Result<Schemas, String> {
+        Ok(Schemas {
+            topics: parse_schemas(content)?.into_iter().collect(),
+        })
+    }
+
+    // the broker doesn't start with a schema file it can't read, rather than accepting any payload
+    pub fn from_env() -> Schemas {
+        let path = match env::var("SCHEMA_FILE") {
+            Ok(path) => path,
+            Err(_) => return Schemas::default(),
+        };
+        fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|content| Schemas::parse(&content))
+            .unwrap_or_else(|error| {
+                log::warn(&format!("Can't read the schema file {}: {}", path, error));
+                process::exit(1);
+            })
+    }
+
+    pub fn check(&self, topic_name: &str, payload: &str) -> Result<(), String> {
+        let schema = match self.topics.get(topic_name) {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        let payload = json::parse(payload).map_err(|_| "the payload is not JSON".to_string())?;
+        validate(schema, &payload, "$")
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.fract() == 0.0 => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    let actual = type_name(value);
+    actual == expected || (expected == "number" && actual == "integer")
+}
+
+fn limit(schema: &Value, keyword: &str) -> Option<f64> {
+    schema.get(keyword).and_then(Value::as_f64)
+}
+
+fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    match schema.get("type") {
+        Some(Value::String(expected)) if !has_type(value, expected) => {
+            return Err(format!("{}: expected {}", path, expected));
+        }
+        Some(Value::Array(types))
+            if !types
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|expected| has_type(value, expected)) =>
+        {
+            let names: Vec<&str> = types.iter().filter_map(Value::as_str).collect();
+            return Err(format!("{}: expected {}", path, names.join(" or ")));
+        }
+        _ => {}
+    }
+
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            return Err(format!("{}: not one of the allowed values", path));
+        }
+    }
+
+    match value {
+        Value::Number(number) => {
+            if limit(schema, "minimum").is_some_and(|minimum| *number < minimum) {
+                return Err(format!("{}: below the minimum", path));
+            }
+            if limit(schema, "maximum").is_some_and(|maximum| *number > maximum) {
+                return Err(format!("{}: above the maximum", path));
+            }
+        }
+        Value::String(string) => {
+            let length = string.chars().count() as f64;
+            if limit(schema, "minLength").is_some_and(|minimum| length < minimum) {
+                return Err(format!("{}: too short", path));
+            }
+            if limit(schema, "maxLength").is_some_and(|maximum| length > maximum) {
+                return Err(format!("{}: too long", path));
+            }
+        }
+        Value::Array(items) => {
+            let length = items.len() as f64;
+            if limit(schema, "minItems").is_some_and(|minimum| length < minimum) {
+                return Err(format!("{}: too few items", path));
+            }
+            if limit(schema, "maxItems").is_some_and(|maximum| length > maximum) {
+                return Err(format!("{}: too many items", path));
+            }
+            if let Some(items_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate(items_schema, item, &format!("{}[{}]", path, index))?;
+                }
+            }
+        }
+        Value::Object(fields) => {
+            let properties = match schema.get("properties") {
+                Some(Value::Object(properties)) => properties.as_slice(),
+                _ => &[],
+            };
+            for required in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if value.get(required).is_none() {
+                    return Err(format!("{}.{}: missing", path, required));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = format!("{}.{}", path, name);
+                match properties.iter().find(|(property, _)| property == name) {
+                    Some((_, property_schema)) => validate(property_schema, field, &field_path)?,
+                    None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                        return Err(format!("{}: not allowed", field_path));
+                    }
+                    None => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+impl Broker {
+    pub fn schema_violation(&self, topic_name: &str, payload: &str) -> Option<String> {
+        self.schemas.check(topic_name, payload).err()
+    }
+}
+
+// `USER>GET_TOKEN` gives `UserGetToken`
+fn pascal_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let word = word.to_ascii_lowercase();
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+// `userId` gives `user_id`
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut previous = ' ';
+    for c in name.chars() {
+        // `userID` gives `user_id`
+        if c.is_ascii_uppercase() && (previous.is_ascii_lowercase() || previous.is_ascii_digit()) {
+            snake.push('_');
+        }
+        previous = c;
+        match c.is_ascii_alphanumeric() {
+            true => snake.push(c.to_ascii_lowercase()),
+            false if !snake.ends_with('_') => snake.push('_'),
+            false => {}
+        }
+    }
+    let snake = snake.trim_matches('_').to_string();
+    match snake.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("_{}", snake),
+        None => "_".to_string(),
+        Some(_) => match snake.as_str() {
+            "type" | "ref" | "match" | "move" | "self" | "struct" | "enum" | "fn" | "impl"
+            | "mod" | "use" | "loop" | "where" | "async" => format!("r#{}", snake),
+            _ => snake,
+        },
+    }
+}
+
+// the Rust type of a schema, the structs it needs are added to `structs`
+fn rust_type(schema: &Value, name: &str, structs: &mut Vec<String>) -> String {
+    let schema_type = match schema.get("type") {
+        Some(Value::String(schema_type)) => schema_type.as_str(),
+        // `["string", "null"]`
+        Some(Value::Array(types)) => {
+            let types: Vec<&str> = types.iter().filter_map(Value::as_str).collect();
+            match types.as_slice() {
+                [schema_type, "null"] | ["null", schema_type] => {
+                    let mut schema = schema.clone();
+                    if let Value::Object(fields) = &mut schema {
+                        fields.retain(|(key, _)| key != "type");
+                        fields.push(("type".to_string(), Value::String(schema_type.to_string())));
+                    }
+                    return format!("Option<{}>", rust_type(&schema, name, structs));
+                }
+                _ => "",
+            }
+        }
+        _ => "",
+    };
+
+    match schema_type {
+        "string" => "String".to_string(),
+        "integer" => "i64".to_string(),
+        "number" => "f64".to_string(),
+        "boolean" => "bool".to_string(),
+        "array" => match schema.get("items") {
+            Some(items) => format!(
+                "Vec<{}>",
+                rust_type(items, &format!("{}Item", name), structs)
+            ),
+            None => "Vec<serde_json::Value>".to_string(),
+        },
+        "object" if matches!(schema.get("properties"), Some(Value::Object(_))) => {
+            struct_code(schema, name, structs);
+            name.to_string()
+        }
+        _ => "serde_json::Value".to_string(),
+    }
+}
+
+fn struct_code(schema: &Value, name: &str, structs: &mut Vec<String>) {
+    let properties = match schema.get("properties") {
+        Some(Value::Object(properties)) => properties.clone(),
+        _ => vec![],
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+
+    let mut code = format!(
+        "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\npub struct {} {{\n",
+        name
+    );
+    for (property, property_schema) in &properties {
+        let field = snake_case(property);
+        let mut field_type = rust_type(
+            property_schema,
+            &format!("{}{}", name, pascal_case(property)),
+            structs,
+        );
+        if !required.contains(&property.as_str()) && !field_type.starts_with("Option<") {
+            field_type = format!("Option<{}>", field_type);
+        }
+        if field.trim_start_matches("r#") != property {
+            code.push_str(&format!(
+                "    #[serde(rename = {})]\n",
+                json::string(property)
+            ));
+        }
+        if field_type.starts_with("Option<") {
+            code.push_str("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n");
+        }
+        code.push_str(&format!("    pub {}: {},\n", field, field_type));
+    }
+    code.push('}');
+    structs.push(code);
+}
+
+// `tiny-broke codegen <schema file>`
+pub fn codegen(args: &[String]) -> Result<String, String> {
+    let path = match args {
+        [path] => path,
+        _ => return Err("usage: tiny-broke codegen <schema file>".to_string()),
+    };
+    let content =
+        fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+
+    let mut code = vec![format!(
+        "// generated by `tiny-broke codegen {}`, payloads of the tasks\nuse serde::{{Deserialize, Serialize}};",
+        path
+    )];
+    for (topic, schema) in parse_schemas(&content)? {
+        let name = pascal_case(&topic);
+        let constant = snake_case(&name).to_ascii_uppercase();
+        code.push(format!(
+            "pub const {}: &str = {};",
+            constant,
+            json::string(&topic)
+        ));
+
+        let mut structs = vec![];
+        let payload_type = rust_type(&schema, &name, &mut structs);
+        if payload_type != name {
+            structs.push(format!("pub type {} = {};", name, payload_type));
+        }
+        code.extend(structs);
+    }
+
+    Ok(code.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{codegen, Schemas};
+
+    const SCHEMAS: &str = r#"{
+        "USER>SIGNUP": {
+            "type": "object",
+            "required": ["email", "age"],
+            "additionalProperties": false,
+            "properties": {
+                "email": {"type": "string", "minLength": 3},
+                "age": {"type": "integer", "minimum": 18},
+                "plan": {"enum": ["free", "pro"]},
+                "tags": {"type": "array", "items": {"type": "string"}, "maxItems": 2},
+                "address": {"type": "object", "properties": {"zipCode": {"type": "string"}}}
+            }
+        }
+    }"#;
+
+    #[test]
+    fn payloads_are_checked_against_their_schema() {
+        let schemas = Schemas::parse(SCHEMAS).unwrap();
+        assert_eq!(
+            schemas.check(
+                "USER>SIGNUP",
+                r#"{"email": "a@b", "age": 20, "tags": ["x"]}"#
+            ),
+            Ok(())
+        );
+        assert_eq!(schemas.check("OTHER", "not json"), Ok(()));
+        assert_eq!(
+            schemas.check("USER>SIGNUP", "not json"),
+            Err("the payload is not JSON".to_string())
+        );
+        assert_eq!(
+            schemas.check("USER>SIGNUP", r#"{"email": "a@b"}"#),
+            Err("$.age: missing".to_string())
+        );
+        assert_eq!(
+            schemas.check("USER>SIGNUP", r#"{"email": "a@b", "age": 17.5}"#),
+            Err("$.age: expected integer".to_string())
+        );
+        assert_eq!(
+            schemas.check("USER>SIGNUP", r#"{"email": "a@b", "age": 17}"#),
+            Err("$.age: below the minimum".to_string())
+        );
+        assert_eq!(
+            schemas.check(
+                "USER>SIGNUP",
+                r#"{"email": "a@b", "age": 20, "tags": ["x", 1]}"#
+            ),
+            Err("$.tags[1]: expected string".to_string())
+        );
+        assert_eq!(
+            schemas.check(
+                "USER>SIGNUP",
+                r#"{"email": "a@b", "age": 20, "plan": "gold"}"#
+            ),
+            Err("$.plan: not one of the allowed values".to_string())
+        );
+        assert_eq!(
+            schemas.check(
+                "USER>SIGNUP",
+                r#"{"email": "a@b", "age": 20, "admin": true}"#
+            ),
+            Err("$.admin: not allowed".to_string())
+        );
+    }
+
+    #[test]
+    fn structs_are_generated_from_the_schemas() {
+        let path = std::env::temp_dir().join("tiny-broke-schema-test.json");
+        std::fs::write(&path, SCHEMAS).unwrap();
+        let code = codegen(&[path.to_string_lossy().into_owned()]).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(super::snake_case("userID"), "user_id");
+        assert!(code.contains("pub const USER_SIGNUP: &str = \"USER>SIGNUP\";"));
+        assert!(
+            code.contains("pub struct UserSignup {\n    pub email: String,\n    pub age: i64,\n")
+        );
+        assert!(code.contains("    pub plan: Option<serde_json::Value>,\n"));
+        assert!(code.contains("    pub tags: Option<Vec<String>>,\n"));
+        assert!(code.contains("    pub address: Option<UserSignupAddress>,\n"));
+        assert!(code.contains(
+            "pub struct UserSignupAddress {\n    #[serde(rename = \"zipCode\")]\n    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub zip_code: Option<String>,\n}"
+        ));
+    }
+}
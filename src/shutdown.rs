@@ -0,0 +1,163 @@
+use crate::json;
+use crate::log;
+use crate::Broker;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// when a stopping broker exits, it reports what became of the tasks it had when it was told to stop, so operators
+// can check that no work was lost during a maintenance:
+// - drained: answered (or failed) while the broker was stopping
+// - persisted: still pending when it exited, written to `STOP_STATE_FILE` (the `EXPORT` format), for `IMPORT`
+// - abandoned: still pending when it exited, without `STOP_STATE_FILE` or when it couldn't be written
+// the report is a JSON line, logged and written to `SHUTDOWN_REPORT`
+
+pub struct Stopping {
+    started: SystemTime,
+    // pending tasks by topic
+    tasks: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Counts {
+    drained: usize,
+    persisted: usize,
+    abandoned: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.drained += other.drained;
+        self.persisted += other.persisted;
+        self.abandoned += other.abandoned;
+    }
+
+    fn json(&self) -> String {
+        format!(
+            "\"drained\":{},\"persisted\":{},\"abandoned\":{}",
+            self.drained, self.persisted, self.abandoned
+        )
+    }
+}
+
+impl Broker {
+    fn pending_by_topic(&self) -> BTreeMap<String, usize> {
+        let mut tasks = BTreeMap::new();
+        for task in self.dispatcher.pending() {
+            *tasks.entry(task.worker_topic.to_string()).or_insert(0) += 1;
+        }
+        tasks
+    }
+
+    pub fn count_stopping_tasks(&mut self) {
+        self.stopping = Some(Stopping {
+            started: self.now(),
+            tasks: self.pending_by_topic(),
+        });
+    }
+
+    // the state file the pending tasks were written to
+    #[cfg(feature = "persistence")]
+    fn persist_pending(&self) -> Option<String> {
+        let path = env::var("STOP_STATE_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())?;
+        match self.export_state(&path) {
+            Ok(_) => Some(path),
+            Err(error) => {
+                log::warn(&format!("Can't write the state file {}: {}", path, error));
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "persistence"))]
+    fn persist_pending(&self) -> Option<String> {
+        None
+    }
+
+    // the tasks still pending are exported, the report is logged and written, once the broker is stopped
+    pub fn shutdown_report(&mut self) -> String {
+        let remaining = self.pending_by_topic();
+        let state_file = match remaining.is_empty() {
+            true => None,
+            false => self.persist_pending(),
+        };
+
+        let (started, at_stop) = match self.stopping.take() {
+            Some(stopping) => (stopping.started, stopping.tasks),
+            None => (self.now(), BTreeMap::new()),
+        };
+        let mut topics: BTreeMap<&str, Counts> = BTreeMap::new();
+        for (topic, count) in &at_stop {
+            topics.entry(topic).or_default().drained =
+                count.saturating_sub(remaining.get(topic).copied().unwrap_or(0));
+        }
+        for (topic, count) in &remaining {
+            let counts = topics.entry(topic).or_default();
+            match state_file {
+                Some(_) => counts.persisted = *count,
+                None => counts.abandoned = *count,
+            }
+        }
+        let mut total = Counts::default();
+        topics.values().for_each(|counts| total.add(counts));
+
+        let date = self.now();
+        let report = format!(
+            "{{\"date\":{},\"waited\":{},{},\"dead_letters\":{},\"state_file\":{},\"topics\":{{{}}}}}",
+            date.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            date.duration_since(started).unwrap_or_default().as_millis(),
+            total.json(),
+            self.dispatcher.dead_letters.len(),
+            state_file.as_deref().map_or("null".to_string(), json::string),
+            topics
+                .iter()
+                .map(|(topic, counts)| format!("{}:{{{}}}", json::string(topic), counts.json()))
+                .collect::<Vec<String>>()
+                .join(",")
+        );
+
+        log::info(&format!("Shutdown report: {}", report));
+        if let Ok(path) = env::var("SHUTDOWN_REPORT") {
+            if let Err(error) = fs::write(&path, format!("{}\n", report)) {
+                log::warn(&format!(
+                    "Can't write the shutdown report {}: {}",
+                    path, error
+                ));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedded::BrokerHandle;
+    use std::time::Duration;
+
+    #[test]
+    fn the_report_tells_drained_and_abandoned_tasks() {
+        let mut broker = BrokerHandle::new();
+        broker.register_worker("worker-1", "ADD");
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        broker.send_task("client-1", "ADD", "ADD>2", "2+2");
+        broker.send_task("client-1", "SUB", "SUB>1", "2-1");
+
+        broker.stop(Duration::from_secs(10));
+        broker.respond("worker-1", "ADD>1", "2");
+        broker.advance(Duration::from_secs(10));
+        assert!(broker.is_stopped());
+
+        assert_eq!(
+            broker.broker.shutdown_report(),
+            concat!(
+                "{\"date\":1500000010,\"waited\":10000,\"drained\":1,\"persisted\":0,\"abandoned\":2,",
+                "\"dead_letters\":0,\"state_file\":null,\"topics\":{",
+                "\"ADD\":{\"drained\":1,\"persisted\":0,\"abandoned\":1},",
+                "\"SUB\":{\"drained\":0,\"persisted\":0,\"abandoned\":1}}}"
+            )
+        );
+    }
+}
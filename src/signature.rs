@@ -0,0 +1,316 @@
+use crate::headers::{headers_frame, parse_headers};
+use crate::transport::Incoming;
+use crate::Broker;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// with `CONTROL_SECRET`, control messages have to be signed with this shared secret, so a peer without it can't
+// unregister workers, nor an admin client pause or drain topics, even without CURVE
+// the signature is the hexadecimal HMAC-SHA256 of the message followed by a line with its timestamp (milliseconds
+// since the epoch), it is refused when the timestamp is more than `SIGNATURE_WINDOW` away from the clock of the
+// broker, or when it was already used, so a captured message can't be replayed:
+// - `@@REGISTER`, `@@UNREGISTER`, `@@SUBSCRIBE_TOPIC` and `@@UNSUBSCRIBE_TOPIC`:
+//   `<identity>\n<control>\n<worker topic>\n<options>\n<timestamp>` (empty lines for the frames not given), the
+//   signature and the timestamp are the `x-signature` and `x-signed-at` headers
+// - admin requests: `<request>\n<timestamp>`, `<timestamp> <signature>` being the second frame of the request (the
+//   `X-Signature` header over HTTP, the `timestamp` and `signature` params with JSON-RPC)
+// `tiny-broke sign <message>` prints the timestamp and the signature of a message with the `CONTROL_SECRET` of its
+// environment
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hmac_sha256(secret: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+pub fn sign(secret: &str, message: &str) -> String {
+    hex(&hmac_sha256(secret.as_bytes(), message.as_bytes()))
+}
+
+// compared in constant time, so a signature or a token can't be guessed byte after byte
+pub fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn verify(secret: &str, message: &str, signature: &str) -> bool {
+    same(sign(secret, message).as_bytes(), signature.as_bytes())
+}
+
+fn millis(date: SystemTime) -> u128 {
+    date.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+// `<timestamp> <signature>` of a message, signed now
+pub fn timestamped(secret: &str, message: &str, now: SystemTime) -> String {
+    let timestamp = millis(now);
+    format!(
+        "{} {}",
+        timestamp,
+        sign(secret, &format!("{}\n{}", message, timestamp))
+    )
+}
+
+// the frames of a control message sent by a peer, with its signature headers when there is a secret
+pub fn control_frames(
+    secret: Option<&str>,
+    identity: &str,
+    control: &str,
+    worker_topic: &str,
+    options: &str,
+    now: SystemTime,
+) -> Vec<String> {
+    let mut frames = vec![
+        control.to_string(),
+        worker_topic.to_string(),
+        options.to_string(),
+    ];
+    if let Some(secret) = secret {
+        let message = format!("{}\n{}\n{}\n{}", identity, control, worker_topic, options);
+        let (timestamp, signature) = timestamped(secret, &message, now)
+            .split_once(' ')
+            .map(|(timestamp, signature)| (timestamp.to_string(), signature.to_string()))
+            .unwrap_or_default();
+        frames.extend([
+            String::new(),
+            String::new(),
+            headers_frame(&[
+                ("x-signature".to_string(), signature),
+                ("x-signed-at".to_string(), timestamp),
+            ]),
+        ]);
+    }
+    frames
+}
+
+pub struct ControlSecret {
+    secret: String,
+    // milliseconds
+    window: u128,
+    // the signatures used, by timestamp, forgotten once out of the window
+    used: HashMap<String, u128>,
+}
+
+pub fn control_secret() -> Option<String> {
+    env::var("CONTROL_SECRET").ok().filter(|v| !v.is_empty())
+}
+
+pub fn control_secret_from_env() -> Option<ControlSecret> {
+    let window_as_secs = env::var("SIGNATURE_WINDOW")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    control_secret().map(|secret| ControlSecret::new(&secret, window_as_secs))
+}
+
+impl ControlSecret {
+    pub fn new(secret: &str, window_as_secs: u64) -> ControlSecret {
+        ControlSecret {
+            secret: secret.to_string(),
+            window: window_as_secs as u128 * 1000,
+            used: HashMap::new(),
+        }
+    }
+
+    fn verify(
+        &mut self,
+        message: &str,
+        timestamp: &str,
+        signature: &str,
+        now: u128,
+    ) -> Result<(), String> {
+        let timestamp_as_millis: u128 = timestamp
+            .parse()
+            .map_err(|_| "the timestamp is not a number".to_string())?;
+        if now.abs_diff(timestamp_as_millis) > self.window {
+            return Err("stale signature".to_string());
+        }
+        if !verify(
+            &self.secret,
+            &format!("{}\n{}", message, timestamp),
+            signature,
+        ) {
+            return Err("bad signature".to_string());
+        }
+
+        let window = self.window;
+        self.used.retain(|_, used| now.abs_diff(*used) <= window);
+        match self.used.insert(signature.to_string(), timestamp_as_millis) {
+            Some(_) => Err("signature already used".to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+// `tiny-broke sign <message>`
+pub fn run(args: &[String]) -> Result<String, String> {
+    let secret = control_secret().ok_or("CONTROL_SECRET is not set")?;
+    match args {
+        [message] => Ok(timestamped(
+            &secret,
+            &message.replace("\\n", "\n"),
+            SystemTime::now(),
+        )),
+        _ => Err("usage: tiny-broke sign <message>, `\\n` separating the lines".to_string()),
+    }
+}
+
+impl Broker {
+    // control messages of workers are signed when there is a secret
+    pub fn is_signed(&mut self, message: &Incoming) -> bool {
+        let now = millis(self.now());
+        let secret = match &mut self.control_secret {
+            Some(secret) => secret,
+            None => return true,
+        };
+        let headers = parse_headers(&message.headers).unwrap_or_default();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map_or("", |(_, value)| value.as_str())
+        };
+        let signed = format!(
+            "{}\n{}\n{}\n{}",
+            message.identity, message.topic, message.response_topic, message.payload
+        );
+        secret
+            .verify(&signed, header("x-signed-at"), header("x-signature"), now)
+            .is_ok()
+    }
+
+    // the admin request is signed when there is a secret, `signature` being `<timestamp> <signature>`
+    pub fn signed_request(&mut self, request: &str, signature: Option<&str>) -> Result<(), String> {
+        let now = millis(self.now());
+        let secret = match &mut self.control_secret {
+            Some(secret) => secret,
+            None => return Ok(()),
+        };
+        let (timestamp, signature) = signature
+            .and_then(|signature| signature.trim().split_once(' '))
+            .ok_or("the request is not signed")?;
+        secret.verify(request, timestamp, signature, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{control_frames, hex, sha256, sign, timestamped, verify, ControlSecret};
+    use crate::embedded::BrokerHandle;
+    use std::time::Duration;
+
+    #[test]
+    fn sha256_and_hmac_match_the_test_vectors() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert!(verify(
+            "Jefe",
+            "what do ya want for nothing?",
+            &sign("Jefe", "what do ya want for nothing?")
+        ));
+        assert!(!verify(
+            "Jefe",
+            "what do ya want for something?",
+            &sign("Jefe", "what do ya want for nothing?")
+        ));
+        assert!(!verify("Jefe", "", ""));
+    }
+
+    fn signed_broker() -> BrokerHandle {
+        let mut broker = BrokerHandle::new();
+        broker.broker.control_secret = Some(ControlSecret::new("secret", 30));
+        broker
+    }
+
+    #[test]
+    fn registrations_are_signed_once() {
+        let mut broker = signed_broker();
+        broker.register_worker("worker-1", "ADD");
+        assert!(broker.workers("ADD").is_empty());
+
+        let frames = control_frames(
+            Some("secret"),
+            "worker-1",
+            "@@REGISTER",
+            "ADD",
+            "",
+            broker.now(),
+        );
+        let frames: Vec<&str> = frames.iter().map(String::as_str).collect();
+        broker.send("worker-1", &frames).unwrap();
+        assert_eq!(broker.workers("ADD"), vec!["worker-1".to_string()]);
+
+        // replayed by another peer, or by the same one
+        while broker.receive("worker-1").is_some() {}
+        for identity in ["worker-2", "worker-1"] {
+            broker.send(identity, &frames).unwrap();
+            assert_eq!(broker.receive(identity).unwrap()[1], "@@BAD_SIGNATURE");
+        }
+        assert_eq!(broker.workers("ADD"), vec!["worker-1".to_string()]);
+        let unregister = control_frames(
+            Some("secret"),
+            "worker-1",
+            "@@UNREGISTER",
+            "",
+            "",
+            broker.now() - Duration::from_secs(31),
+        );
+        let unregister: Vec<&str> = unregister.iter().map(String::as_str).collect();
+        broker.send("worker-1", &unregister).unwrap();
+        assert_eq!(broker.workers("ADD"), vec!["worker-1".to_string()]);
+    }
+
+    #[test]
+    fn admin_requests_are_signed_with_a_timestamp() {
+        let mut broker = signed_broker();
+        assert_eq!(broker.admin("STATS"), "ERROR the request is not signed");
+
+        let signature = timestamped("secret", "PAUSE ADD", broker.now());
+        assert_eq!(
+            broker.signed_admin("PAUSE ADD", &signature),
+            "OK ADD paused"
+        );
+        assert_eq!(
+            broker.signed_admin("PAUSE ADD", &signature),
+            "ERROR signature already used"
+        );
+        assert_eq!(
+            broker.signed_admin("RESUME ADD", &signature),
+            "ERROR bad signature"
+        );
+
+        let stale = timestamped("secret", "RESUME ADD", broker.now());
+        broker.advance(Duration::from_secs(31));
+        assert_eq!(
+            broker.signed_admin("RESUME ADD", &stale),
+            "ERROR stale signature"
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use crate::embedded::BrokerHandle;
+use crate::{cli, container};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::time::Duration;
+
+// a simulation drives a broker with scripted peers and a virtual time, one step per line:
+// - `set <NAME> <value>`: configuration (environment variable), before any other step, reset after the script
+// - `send <identity> <frame>...`: the peer sends a message, the broker handles it
+// - `advance <duration>`: the virtual time moves forward (`500ms`, `30s`, `2m`), then the time based rules run
+// - `expect <identity> <frame>...`: the next message the peer received
+// - `expect-nothing <identity>`: the peer has no message waiting
+// - `disconnect <identity>`: the peer goes away, messages sent to it fail
+// - `admin <command>...`: an admin command, which has to succeed
+// - `stop`: the broker got a stop signal (SIGTERM in the container profile)
+// - `expect-stopped`, `expect-running`: the broker can exit, or still has tasks to answer
+// frames are separated by spaces, double quotes allow empty frames and spaces (`""`, `"a b"`)
+// `#` starts a comment line
+const USAGE: &str = "usage: tiny-broke simulate <script>...";
+
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    // `\n` is a new line, `\"` a quote
+                    Some('\\') => match chars.next() {
+                        Some('n') => token.push('\n'),
+                        c => token.extend(c),
+                    },
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+        } else {
+            token.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+// the broker is embedded (see embedded.rs): peers share its in-memory transport, a message is received as soon as
+// it is sent
+struct Simulation {
+    broker: Option<BrokerHandle>,
+    // the values the `set` steps replaced
+    previous_env: Vec<(String, Option<OsString>)>,
+}
+
+impl Simulation {
+    fn new() -> Simulation {
+        Simulation {
+            broker: None,
+            previous_env: vec![],
+        }
+    }
+
+    // created on the first step that needs it, so `set` steps are taken into account
+    fn broker(&mut self) -> &mut BrokerHandle {
+        self.broker.get_or_insert_with(BrokerHandle::new)
+    }
+
+    fn send(&mut self, identity: &str, frames: &[String]) -> Result<(), String> {
+        let frames: Vec<&str> = frames.iter().map(|frame| frame.as_str()).collect();
+        self.broker().send(identity, &frames)
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        self.broker().advance(duration);
+    }
+
+    fn receive(&mut self, identity: &str) -> Option<Vec<String>> {
+        self.broker().receive(identity)
+    }
+
+    fn expect(&mut self, identity: &str, frames: &[String]) -> Result<(), String> {
+        match self.receive(identity) {
+            None => Err(format!(
+                "{} received nothing, expected {:?}",
+                identity, frames
+            )),
+            Some(received) if received != frames => Err(format!(
+                "{} received {:?}, expected {:?}",
+                identity, received, frames
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn expect_nothing(&mut self, identity: &str) -> Result<(), String> {
+        match self.receive(identity) {
+            None => Ok(()),
+            Some(received) => Err(format!(
+                "{} received {:?}, expected nothing",
+                identity, received
+            )),
+        }
+    }
+
+    fn admin(&mut self, request: &str) -> Result<(), String> {
+        let response = self.broker().admin(request);
+        if response.starts_with("OK") {
+            Ok(())
+        } else {
+            Err(response)
+        }
+    }
+
+    fn step(&mut self, tokens: &[String]) -> Result<(), String> {
+        let tokens: Vec<&str> = tokens.iter().map(|token| token.as_str()).collect();
+        // a broker that can't start fails the first step after the `set` ones
+        if self.broker.is_none() && tokens.first() != Some(&"set") {
+            self.broker = Some(BrokerHandle::try_new()?);
+        }
+
+        match tokens.as_slice() {
+            ["set", name, value] => {
+                if self.broker.is_some() {
+                    return Err("`set` steps come first".to_string());
+                }
+                self.previous_env
+                    .push((name.to_string(), env::var_os(name)));
+                env::set_var(name, value);
+                Ok(())
+            }
+            ["send", identity, ..] => self.send(identity, &owned(&tokens[2..])),
+            ["advance", duration] => match cli::parse_duration(duration) {
+                Some(duration) => {
+                    self.advance(duration);
+                    Ok(())
+                }
+                None => Err(format!("bad duration: {}", duration)),
+            },
+            ["expect", identity, ..] => self.expect(identity, &owned(&tokens[2..])),
+            ["expect-nothing", identity] => self.expect_nothing(identity),
+            ["disconnect", identity] => {
+                self.broker().disconnect(identity);
+                Ok(())
+            }
+            ["admin", ..] => self.admin(&tokens[1..].join(" ")),
+            ["stop"] => {
+                self.broker().stop(container::stop_timeout());
+                Ok(())
+            }
+            ["expect-stopped"] => match self.broker().is_stopped() {
+                true => Ok(()),
+                false => Err("the broker is running, expected it stopped".to_string()),
+            },
+            ["expect-running"] => match self.broker().is_stopped() {
+                true => Err("the broker is stopped, expected it running".to_string()),
+                false => Ok(()),
+            },
+            _ => Err(format!("unknown step: {}", tokens.join(" "))),
+        }
+    }
+}
+
+fn owned(tokens: &[&str]) -> Vec<String> {
+    tokens.iter().map(|token| token.to_string()).collect()
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        for (name, value) in self.previous_env.drain(..).rev() {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+}
+
+fn run_script(path: &str) -> Result<usize, String> {
+    let content =
+        fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    let mut simulation = Simulation::new();
+    let mut steps = 0;
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        tokenize(line)
+            .and_then(|tokens| simulation.step(&tokens))
+            .map_err(|error| format!("{}:{}: {}", path, index + 1, error))?;
+        steps += 1;
+    }
+
+    Ok(steps)
+}
+
+// every script runs on its own broker, with the configuration it sets
+pub fn simulate(args: &[String]) -> Result<String, String> {
+    if args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut lines = vec![];
+    for path in args {
+        let steps = run_script(path)?;
+        lines.push(format!("{}: {} steps passed", path, steps));
+    }
+
+    Ok(lines.join("\n"))
+}
+
+// state machine tests: random sequences of peer events, with the broker invariants checked after every event
+// a failure is shrunk by proptest to the shortest sequence it finds, and given with it
+#[cfg(test)]
+mod tests {
+    use super::Simulation;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+
+    const WORKERS: u64 = 3;
+    const CLIENTS: u64 = 3;
+    const TOPICS: [&str; 2] = ["A", "B"];
+    const CASES: u32 = 100;
+    const EVENTS: usize = 60;
+
+    #[derive(Debug, Clone)]
+    enum Event {
+        Register { worker: u64, topic: usize },
+        Request { client: u64, topic: usize },
+        Respond { worker: u64 },
+        Unregister { worker: u64 },
+        Disconnect { worker: u64 },
+        Advance { secs: u64 },
+    }
+
+    fn event() -> impl Strategy<Value = Event> {
+        let worker = || 0..WORKERS;
+        let topic = || 0..TOPICS.len();
+        prop_oneof![
+            2 => (worker(), topic()).prop_map(|(worker, topic)| Event::Register { worker, topic }),
+            3 => (0..CLIENTS, topic()).prop_map(|(client, topic)| Event::Request { client, topic }),
+            2 => worker().prop_map(|worker| Event::Respond { worker }),
+            1 => worker().prop_map(|worker| Event::Unregister { worker }),
+            1 => worker().prop_map(|worker| Event::Disconnect { worker }),
+            1 => (0..40u64).prop_map(|secs| Event::Advance { secs }),
+        ]
+    }
+
+    // what the peers know
+    #[derive(Default)]
+    struct Model {
+        requests: usize,
+        // response topics sent and not answered yet
+        unanswered: HashSet<String>,
+        // response topics of the tasks received by each worker, not answered yet
+        running: HashMap<String, Vec<String>>,
+        unregistered: HashSet<String>,
+        disconnected: HashSet<String>,
+    }
+
+    fn frames(frames: &[&str]) -> Vec<String> {
+        frames.iter().map(|frame| frame.to_string()).collect()
+    }
+
+    fn apply(simulation: &mut Simulation, model: &mut Model, event: &Event) -> Result<(), String> {
+        match *event {
+            Event::Register { worker, topic } => {
+                let worker = format!("worker-{}", worker);
+                if model.disconnected.contains(&worker) {
+                    return Ok(());
+                }
+                model.unregistered.remove(&worker);
+                simulation.send(&worker, &frames(&["@@REGISTER", TOPICS[topic]]))
+            }
+            Event::Request { client, topic } => {
+                let client = format!("client-{}", client);
+                let response_topic = format!("R{}", model.requests);
+                model.requests += 1;
+                model.unanswered.insert(response_topic.clone());
+                simulation.send(
+                    &client,
+                    &frames(&[TOPICS[topic], &response_topic, &response_topic]),
+                )
+            }
+            Event::Respond { worker } => {
+                let worker = format!("worker-{}", worker);
+                let running = model.running.entry(worker.clone()).or_default();
+                if running.is_empty() || model.disconnected.contains(&worker) {
+                    return Ok(());
+                }
+                // workers answer with the response topic as payload, so clients know what is answered
+                let response_topic = running.remove(0);
+                simulation.send(&worker, &frames(&[&response_topic, "", &response_topic]))
+            }
+            Event::Unregister { worker } => {
+                let worker = format!("worker-{}", worker);
+                if model.disconnected.contains(&worker) {
+                    return Ok(());
+                }
+                model.unregistered.insert(worker.clone());
+                simulation.send(&worker, &frames(&["@@UNREGISTER"]))
+            }
+            Event::Disconnect { worker } => {
+                let worker = format!("worker-{}", worker);
+                simulation.broker().disconnect(&worker);
+                model.running.remove(&worker);
+                model.disconnected.insert(worker);
+                Ok(())
+            }
+            Event::Advance { secs } => {
+                simulation.advance(Duration::from_secs(secs));
+                Ok(())
+            }
+        }
+    }
+
+    // reads what the peers received
+    fn receive(simulation: &mut Simulation, model: &mut Model) -> Result<(), String> {
+        for client in 0..CLIENTS {
+            let client = format!("client-{}", client);
+            while let Some(received) = simulation.receive(&client) {
+                // positions in the queue are not answers
+                if received[1] == "@@QUEUED" {
+                    continue;
+                }
+                let response_topic = &received[1];
+                if !model.unanswered.remove(response_topic) {
+                    return Err(format!("{} answered twice, or never asked", response_topic));
+                }
+            }
+        }
+
+        for worker in 0..WORKERS {
+            let worker = format!("worker-{}", worker);
+            if model.disconnected.contains(&worker) {
+                continue;
+            }
+            while let Some(received) = simulation.receive(&worker) {
+                // no dispatch to removed workers
+                if model.unregistered.contains(&worker) {
+                    return Err(format!("{} received {:?} after leaving", worker, received));
+                }
+                model
+                    .running
+                    .entry(worker.clone())
+                    .or_default()
+                    .push(received[1].clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_invariants(simulation: &mut Simulation, model: &Model) -> Result<(), String> {
+        let broker = &simulation.broker().broker;
+
+        // no task lost: an unanswered task is running, waiting for a worker, or in the dead letter queue
+        let known: HashSet<&str> = broker
+            .dispatcher
+            .pending()
+            .chain(broker.dispatcher.dead_letters.iter())
+            .map(|task| task.response_topic.as_str())
+            .collect();
+        if let Some(lost) = model
+            .unanswered
+            .iter()
+            .find(|topic| !known.contains(topic.as_str()))
+        {
+            return Err(format!("task {} is lost", lost));
+        }
+
+        // clients and topics point to each other
+        for client in broker.registry.clients.values() {
+            for topic_name in &client.topics {
+                let topic = broker.registry.topics.get(topic_name).ok_or_else(|| {
+                    format!("{} is on the unknown topic {}", client.name, topic_name)
+                })?;
+                let members = if client.is_worker {
+                    &topic.workers
+                } else {
+                    &topic.clients
+                };
+                if !members.contains(&client.name) {
+                    return Err(format!(
+                        "{} is not in the topic {}",
+                        client.name, topic_name
+                    ));
+                }
+            }
+        }
+        for topic in broker.registry.topics.values() {
+            for name in topic.workers.iter().chain(topic.clients.iter()) {
+                if !broker
+                    .registry
+                    .clients
+                    .get(name)
+                    .is_some_and(|client| client.topics.contains(&topic.name))
+                {
+                    return Err(format!(
+                        "{} in {} is not a known peer of it",
+                        name, topic.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run(events: &[Event]) -> Result<(), String> {
+        let mut simulation = Simulation::new();
+        let mut model = Model::default();
+
+        for event in events {
+            apply(&mut simulation, &mut model, event)?;
+            receive(&mut simulation, &mut model)?;
+            check_invariants(&mut simulation, &model)?;
+        }
+
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+        #[test]
+        fn broker_invariants_hold(events in vec(event(), 1..=EVENTS)) {
+            run(&events).map_err(TestCaseError::fail)?;
+        }
+    }
+}
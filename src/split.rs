@@ -0,0 +1,314 @@
+use crate::json::{self, Value};
+use crate::log;
+use crate::transport::Transport;
+use crate::workers;
+use crate::{Broker, Task};
+use std::collections::HashMap;
+
+// topics declared with `split=<splitter>` fan a task out into parts, sent to their workers as tasks of the topic
+// (response topics `<response topic>/part-<index>`), the broker merges their responses (`merge=<merger>`) into the
+// response of the task:
+// - `lines:<count>`: parts of `count` lines, merged with new lines
+// - `bytes:<count>`: parts of `count` bytes (rounded to characters), merged as is
+// - `script:<command>`: the command gets the payload on its stdin and writes the parts as a JSON array of strings
+// - `merge=concat` (default) or `merge=script:<command>`: the command gets the responses of the parts as a JSON array
+//   of strings on its stdin, and writes the response
+// a task with a single part is sent as is, a part that won't be answered fails the task: its clients get
+// `@@ERROR <response topic> split_failed <detail>`
+// the scripts run in the broker loop, they have to be quick
+// the scripts run as the broker: topics are only declared with them when `ADMIN_USERS` is set, by an admin user
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Splitter {
+    Lines(usize),
+    Bytes(usize),
+    Script(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Merger {
+    #[default]
+    Concat,
+    Script(String),
+}
+
+fn count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("{} is not a positive number", value)),
+    }
+}
+
+impl Splitter {
+    pub fn parse(value: &str) -> Result<Splitter, String> {
+        match value.split_once(':') {
+            Some(("lines", lines)) => Ok(Splitter::Lines(count(lines)?)),
+            Some(("bytes", bytes)) => Ok(Splitter::Bytes(count(bytes)?)),
+            Some(("script", command)) if !command.is_empty() => {
+                Ok(Splitter::Script(command.to_string()))
+            }
+            _ => Err(format!(
+                "unknown split {}, expected lines:<count>, bytes:<count> or script:<command>",
+                value
+            )),
+        }
+    }
+
+    pub fn split(&self, payload: &str) -> Result<Vec<String>, String> {
+        match self {
+            Splitter::Lines(count) => Ok(payload
+                .lines()
+                .collect::<Vec<_>>()
+                .chunks(*count)
+                .map(|lines| lines.join("\n"))
+                .collect()),
+            Splitter::Bytes(count) => {
+                let mut parts = vec![];
+                let mut part = String::new();
+                for c in payload.chars() {
+                    if !part.is_empty() && part.len() + c.len_utf8() > *count {
+                        parts.push(std::mem::take(&mut part));
+                    }
+                    part.push(c);
+                }
+                if !part.is_empty() {
+                    parts.push(part);
+                }
+                Ok(parts)
+            }
+            Splitter::Script(command) => strings(&workers::run_command(command, payload)?),
+        }
+    }
+}
+
+impl Merger {
+    pub fn parse(value: &str) -> Result<Merger, String> {
+        match value.split_once(':') {
+            None if value == "concat" => Ok(Merger::Concat),
+            Some(("script", command)) if !command.is_empty() => {
+                Ok(Merger::Script(command.to_string()))
+            }
+            _ => Err(format!(
+                "unknown merge {}, expected concat or script:<command>",
+                value
+            )),
+        }
+    }
+
+    fn merge(&self, splitter: &Splitter, responses: &[String]) -> Result<String, String> {
+        match (self, splitter) {
+            (Merger::Concat, Splitter::Lines(_)) => Ok(responses.join("\n")),
+            (Merger::Concat, _) => Ok(responses.concat()),
+            (Merger::Script(command), _) => {
+                let responses: Vec<String> = responses.iter().map(|r| json::string(r)).collect();
+                workers::run_command(command, &format!("[{}]", responses.join(",")))
+            }
+        }
+    }
+}
+
+// a JSON array of strings
+fn strings(content: &str) -> Result<Vec<String>, String> {
+    match json::parse(content.trim())? {
+        Value::Array(values) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| "the parts are expected to be strings".to_string())
+            })
+            .collect(),
+        _ => Err("the parts are expected to be a JSON array".to_string()),
+    }
+}
+
+#[derive(Debug)]
+pub struct SplitTask {
+    splitter: Splitter,
+    merger: Merger,
+    responses: Vec<Option<String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Splits {
+    // by response topic of the task
+    tasks: HashMap<String, SplitTask>,
+    // response topic and index of the task of each part
+    parts: HashMap<String, (String, usize)>,
+}
+
+fn part_topic(response_topic: &str, index: usize) -> String {
+    format!("{}/part-{}", response_topic, index)
+}
+
+impl Broker {
+    // script splitters and mergers run commands, they are refused when anyone reaching the admin socket could declare
+    // them
+    pub fn allows_scripts(&self, split: Option<&Splitter>, merge: &Merger) -> Result<(), String> {
+        let scripted =
+            matches!(split, Some(Splitter::Script(_))) || matches!(merge, Merger::Script(_));
+        match scripted && self.admin_users.is_none() {
+            true => Err("script splitters and mergers need ADMIN_USERS".to_string()),
+            false => Ok(()),
+        }
+    }
+
+    // `None` once the task is sent as parts, or refused
+    pub fn split(&mut self, transport: &dyn Transport, task: Task) -> Option<Task> {
+        let settings = self.declared_topics.get(&*task.worker_topic);
+        let (splitter, merger) = match settings.and_then(|s| Some((s.split.clone()?, &s.merge))) {
+            Some((splitter, merger)) => (splitter, merger.clone()),
+            None => return Some(task),
+        };
+        // the task is already split, its client waits for the response with the first one
+        if self.splits.tasks.contains_key(&task.response_topic) {
+            return None;
+        }
+        let parts = match splitter.split(&task.payload) {
+            Ok(parts) if parts.len() <= 1 => return Some(task),
+            Ok(parts) => parts,
+            Err(error) => {
+                self.fail_split_task(transport, &task.response_topic, &error);
+                return None;
+            }
+        };
+
+        log::info(&format!(
+            "Splitting task {} into {} parts",
+            task.response_topic,
+            parts.len()
+        ));
+        self.splits.tasks.insert(
+            task.response_topic.clone(),
+            SplitTask {
+                splitter,
+                merger,
+                responses: vec![None; parts.len()],
+            },
+        );
+        for (index, payload) in parts.into_iter().enumerate() {
+            let mut part = task.clone();
+            part.response_topic = part_topic(&task.response_topic, index);
+            part.payload = payload;
+            // the broker merges the responses
+            part.direct_endpoint = None;
+            self.splits.parts.insert(
+                part.response_topic.clone(),
+                (task.response_topic.clone(), index),
+            );
+            self.submit(transport, part);
+        }
+        None
+    }
+
+    // the response of a part, the task is answered once all its parts are
+    pub fn merge_part(&mut self, transport: &dyn Transport, part_topic: &str, payload: &str) {
+        let (response_topic, index) = match self.splits.parts.remove(part_topic) {
+            Some(part) => part,
+            None => return,
+        };
+        let split_task = match self.splits.tasks.get_mut(&response_topic) {
+            Some(split_task) => split_task,
+            None => return,
+        };
+        split_task.responses[index] = Some(payload.to_string());
+        if split_task.responses.iter().any(Option::is_none) {
+            return;
+        }
+
+        let split_task = self.splits.tasks.remove(&response_topic).unwrap();
+        let responses: Vec<String> = split_task.responses.into_iter().flatten().collect();
+        match split_task.merger.merge(&split_task.splitter, &responses) {
+            Ok(response) => self.send_response(transport, &response_topic, &response),
+            Err(error) => self.fail_split_task(transport, &response_topic, &error),
+        }
+    }
+
+    // a part won't be answered, nor its task
+    pub fn fail_part(&mut self, transport: &dyn Transport, part_topic: &str) {
+        if let Some((response_topic, index)) = self.splits.parts.remove(part_topic) {
+            let parts = match self.splits.tasks.remove(&response_topic) {
+                Some(split_task) => split_task.responses.len(),
+                None => return,
+            };
+            self.splits
+                .parts
+                .retain(|_, (other, _)| *other != response_topic);
+            let detail = format!("part {} of {} won't be answered", index, parts);
+            self.fail_split_task(transport, &response_topic, &detail);
+        }
+    }
+
+    fn fail_split_task(&mut self, transport: &dyn Transport, response_topic: &str, detail: &str) {
+        log::warn(&format!("Task {} failed: {}", response_topic, detail));
+        self.registry
+            .clients_of(response_topic)
+            .iter()
+            .for_each(|identity| {
+                transport
+                    .send(
+                        identity,
+                        &["", "@@ERROR", response_topic, "split_failed", detail],
+                    )
+                    .ok();
+            });
+        self.abandon_response_topic(response_topic);
+        self.fail_dependents(transport, response_topic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strings, Merger, Splitter};
+    use crate::embedded::BrokerHandle;
+
+    #[test]
+    fn payloads_are_split_by_lines_or_bytes() {
+        let lines = Splitter::parse("lines:2").unwrap();
+        assert_eq!(lines.split("a\nb\nc").unwrap(), vec!["a\nb", "c"]);
+        assert_eq!(
+            Merger::Concat.merge(&lines, &["A\nB".into(), "C".into()]),
+            Ok("A\nB\nC".to_string())
+        );
+
+        let bytes = Splitter::parse("bytes:3").unwrap();
+        assert_eq!(bytes.split("abcdéf").unwrap(), vec!["abc", "dé", "f"]);
+        assert_eq!(
+            Merger::Concat.merge(&bytes, &["ab".into(), "c".into()]),
+            Ok("abc".to_string())
+        );
+
+        assert!(Splitter::parse("lines:0").is_err());
+        assert!(Splitter::parse("words:2").is_err());
+        assert!(Merger::parse("sum").is_err());
+    }
+
+    #[test]
+    fn scripts_split_and_merge_with_json_arrays() {
+        let script = Splitter::Script("echo '[\"a\",\"b\"]'".to_string());
+        assert_eq!(script.split("").unwrap(), vec!["a", "b"]);
+        let merger = Merger::Script("cat".to_string());
+        assert_eq!(
+            merger.merge(&script, &["a".into(), "b\"".into()]),
+            Ok(r#"["a","b\""]"#.to_string())
+        );
+        assert!(strings("{}").is_err());
+    }
+
+    #[test]
+    fn scripts_are_refused_without_admin_users() {
+        let mut broker = BrokerHandle::new();
+        assert_eq!(
+            broker.admin("CREATE_TOPIC ADD split=script:cat"),
+            "ERROR script splitters and mergers need ADMIN_USERS"
+        );
+        assert_eq!(
+            broker.admin("CREATE_TOPIC ADD merge=script:cat"),
+            "ERROR script splitters and mergers need ADMIN_USERS"
+        );
+        assert!(broker
+            .admin("CREATE_TOPIC ADD split=lines:2")
+            .starts_with("OK"));
+    }
+}
@@ -0,0 +1,447 @@
+use crate::dependencies::parse_dependencies;
+use crate::dlq::Failure;
+use crate::encryption::{self, Cipher};
+use crate::headers::{headers_frame, parse_headers};
+use crate::topics::TopicSettings;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+use std::env;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+// the state file is line based, one record per line, fields separated by tabs:
+// - `client <identity> <response topic>`: a client waiting on a response topic
+// - `task <worker topic> <response topic> <retry> <payload> <partition key> <dependencies> <headers> <client>
+//   [<worker> <reason>]...`: a task not answered yet, with its failures, empty fields when it has none, the
+//   dependencies separated by commas and the headers as their frame
+// - `dead ...`: a task in the dead letter queue, same fields as `task`
+// - `topic <topic> [<setting>]...`: a topic declared with `CREATE_TOPIC`, with its arguments
+// - `paused <topic>`: a paused topic
+// workers are not exported, they register again when they ping the new broker
+// payloads are encrypted with a storage key (see encryption.rs)
+// files of version 1, whose tasks stop after their payload, are still read
+pub const VERSION: u8 = 2;
+
+pub fn header(version: u8) -> String {
+    format!("tiny-broke-state {}", version)
+}
+
+// the admin socket doesn't tell who is asking, so `EXPORT` and `IMPORT` are given file names in `STATE_DIR` and
+// refused without it, `STOP_STATE_FILE` is set by the operator and can be anywhere
+pub fn state_dir() -> Option<PathBuf> {
+    env::var("STATE_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+fn invalid(line_number: usize, reason: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line_number, reason),
+    )
+}
+
+fn task_line(kind: &str, task: &Task, cipher: Option<&Cipher>) -> io::Result<String> {
+    let record = format!("{}\t{}\t{}", kind, task.worker_topic, task.response_topic);
+    let payload = encryption::seal(cipher, &record, &task.payload).map_err(Error::other)?;
+    let headers = match task.headers.is_empty() {
+        true => String::new(),
+        false => headers_frame(&task.headers),
+    };
+    let mut line = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        kind,
+        escape(&task.worker_topic),
+        escape(&task.response_topic),
+        task.retry,
+        escape(&payload),
+        escape(task.partition_key.as_deref().unwrap_or("")),
+        escape(&task.dependencies.join(",")),
+        escape(&headers),
+        escape(task.client.as_deref().unwrap_or("")),
+    );
+    task.failures.iter().for_each(|failure| {
+        line.push_str(&format!(
+            "\t{}\t{}",
+            escape(&failure.worker_name),
+            escape(&failure.reason)
+        ));
+    });
+
+    Ok(line)
+}
+
+fn parse_task(
+    version: u8,
+    line_number: usize,
+    fields: &[String],
+    cipher: Option<&Cipher>,
+) -> io::Result<Task> {
+    // the fields before the failures
+    let count = match version {
+        1 => 5,
+        _ => 9,
+    };
+    if fields.len() < count || fields.len().is_multiple_of(2) {
+        return Err(invalid(line_number, &format!("bad {} record", fields[0])));
+    }
+
+    let record = format!("{}\t{}\t{}", fields[0], fields[1], fields[2]);
+    let payload = encryption::open(cipher, &record, &fields[4])
+        .map_err(|error| invalid(line_number, &error))?;
+    let mut task = Task::new(&fields[1], &fields[2], &payload);
+    task.retry = fields[3]
+        .parse()
+        .map_err(|_| invalid(line_number, "retry is not a number"))?;
+    if version > 1 {
+        let optional = |field: &String| Some(field.clone()).filter(|field| !field.is_empty());
+        task.partition_key = optional(&fields[5]);
+        task.dependencies = parse_dependencies(&fields[6]);
+        task.headers = parse_headers(&fields[7]).map_err(|error| invalid(line_number, &error))?;
+        task.client = optional(&fields[8]);
+    }
+    task.failures = fields[count..]
+        .chunks(2)
+        .map(|failure| Failure::new(&failure[0], &failure[1]))
+        .collect();
+
+    Ok(task)
+}
+
+pub enum Record {
+    // identity, response topic
+    Client(String, String),
+    Task(Task),
+    Dead(Task),
+    // name, settings
+    Topic(String, TopicSettings),
+    Paused(String),
+}
+
+// the version of a state file, and its records with their line numbers, empty lines skipped
+pub fn read_state(path: impl AsRef<Path>) -> io::Result<(u8, Vec<(usize, String)>)> {
+    let content = fs::read_to_string(path)?;
+    let mut lines = content.lines();
+
+    let version = lines
+        .next()
+        .and_then(|line| line.strip_prefix("tiny-broke-state "))
+        .and_then(|version| version.parse().ok())
+        .filter(|version| (1..=VERSION).contains(version))
+        .ok_or_else(|| invalid(1, "not a tiny-broke state file"))?;
+
+    Ok((
+        version,
+        lines
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| (index + 2, line.to_string()))
+            .collect(),
+    ))
+}
+
+pub fn parse_record(
+    version: u8,
+    line_number: usize,
+    line: &str,
+    cipher: Option<&Cipher>,
+) -> io::Result<Record> {
+    let fields: Vec<String> = line.split('\t').map(unescape).collect();
+
+    match (fields[0].as_str(), fields.len()) {
+        ("client", 3) => Ok(Record::Client(fields[1].clone(), fields[2].clone())),
+        ("topic", count) if count > 1 => {
+            TopicSettings::parse(fields[2..].iter().map(String::as_str))
+                .map(|settings| Record::Topic(fields[1].clone(), settings))
+                .map_err(|error| invalid(line_number, &error))
+        }
+        ("paused", 2) => Ok(Record::Paused(fields[1].clone())),
+        ("task", _) => Ok(Record::Task(parse_task(
+            version,
+            line_number,
+            &fields,
+            cipher,
+        )?)),
+        ("dead", _) => Ok(Record::Dead(parse_task(
+            version,
+            line_number,
+            &fields,
+            cipher,
+        )?)),
+        (kind, _) => Err(invalid(line_number, &format!("bad {} record", kind))),
+    }
+}
+
+impl Broker {
+    // the file name of an admin request, in `STATE_DIR`
+    pub fn state_path(&self, name: &str) -> Result<PathBuf, String> {
+        let dir = self
+            .state_dir
+            .as_ref()
+            .ok_or_else(|| "STATE_DIR is not set".to_string())?;
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(file)), None) => Ok(dir.join(file)),
+            _ => Err(format!("{} is not a file name", name)),
+        }
+    }
+
+    // in flight tasks are exported with the waiting ones, since their workers won't follow the broker
+    pub fn export_state(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let mut lines = vec![header(VERSION)];
+
+        let mut topics: Vec<(&String, &TopicSettings)> = self.declared_topics.iter().collect();
+        topics.sort_by_key(|(name, _)| *name);
+        topics.iter().for_each(|(name, settings)| {
+            let mut line = format!("topic\t{}", escape(name));
+            settings
+                .declaration
+                .iter()
+                .for_each(|arg| line.push_str(&format!("\t{}", escape(arg))));
+            lines.push(line);
+        });
+        let mut paused: Vec<&String> = self.paused_topics.iter().collect();
+        paused.sort();
+        paused
+            .iter()
+            .for_each(|name| lines.push(format!("paused\t{}", escape(name))));
+
+        self.registry
+            .clients
+            .values()
+            .filter(|client| !client.is_worker)
+            .for_each(|client| {
+                client.topics.iter().for_each(|topic| {
+                    lines.push(format!(
+                        "client\t{}\t{}",
+                        escape(&client.name),
+                        escape(topic)
+                    ));
+                });
+            });
+
+        let tasks: Vec<&Task> = self.dispatcher.pending().collect();
+        for task in &tasks {
+            lines.push(task_line("task", task, self.cipher.as_ref())?);
+        }
+        for task in &self.dispatcher.dead_letters {
+            lines.push(task_line("dead", task, self.cipher.as_ref())?);
+        }
+
+        lines.push(String::new());
+        fs::write(path, lines.join("\n"))?;
+
+        Ok(tasks.len())
+    }
+
+    // the whole file is parsed before touching the broker, so a corrupted file is not half imported
+    pub fn import_state(
+        &mut self,
+        transport: &dyn Transport,
+        path: impl AsRef<Path>,
+    ) -> io::Result<usize> {
+        let mut clients = vec![];
+        let mut tasks = vec![];
+        let mut dead_letters = vec![];
+        let mut topics = vec![];
+
+        let (version, lines) = read_state(path)?;
+        for (line_number, line) in lines {
+            match parse_record(version, line_number, &line, self.cipher.as_ref())? {
+                Record::Client(identity, topic) => clients.push((identity, topic)),
+                Record::Task(task) => tasks.push(task),
+                Record::Dead(task) => dead_letters.push(task),
+                record => topics.push(record),
+            }
+        }
+
+        self.restore_topics(topics);
+        clients.iter().for_each(|(identity, topic)| {
+            self.add_client(false, identity, topic);
+        });
+
+        self.dispatcher.dead_letters.extend(dead_letters);
+
+        let count = tasks.len();
+        self.restore_tasks(transport, tasks);
+
+        Ok(count)
+    }
+
+    // the declared and paused topics, before their tasks are sent
+    pub fn restore_topics(&mut self, records: Vec<Record>) {
+        records.into_iter().for_each(|record| match record {
+            Record::Topic(name, settings) => {
+                self.declared_topics.insert(name, settings);
+            }
+            Record::Paused(name) => {
+                self.paused_topics.insert(name);
+            }
+            _ => {}
+        });
+    }
+
+    // the tasks wait for their dependencies again, the others are sent in the order of their partition
+    pub fn restore_tasks(&mut self, transport: &dyn Transport, tasks: Vec<Task>) {
+        self.dispatcher.blocked.extend(tasks);
+        self.release_dependents(transport);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_record, task_line, Record, VERSION};
+    use crate::dlq::Failure;
+    use crate::embedded::BrokerHandle;
+    use crate::Task;
+
+    #[test]
+    fn tasks_keep_their_fields() {
+        let mut task = Task::new("ADD", "ADD>2", "2+2\t");
+        task.retry = 3;
+        task.partition_key = Some("user\n1".to_string());
+        task.dependencies = vec!["ADD>0".to_string(), "ADD>1".to_string()];
+        task.headers = vec![("trace".to_string(), "a\tb".to_string())];
+        task.client = Some("client-1".to_string());
+        task.failures = vec![Failure::new("worker-1", "timeout")];
+
+        let line = task_line("task", &task, None).unwrap();
+        let read = match parse_record(VERSION, 2, &line, None).unwrap() {
+            Record::Task(read) => read,
+            _ => panic!("not a task: {}", line),
+        };
+        assert_eq!(read.worker_topic, task.worker_topic);
+        assert_eq!(read.response_topic, task.response_topic);
+        assert_eq!(read.payload, task.payload);
+        assert_eq!(read.retry, 3);
+        assert_eq!(read.partition_key, task.partition_key);
+        assert_eq!(read.dependencies, task.dependencies);
+        assert_eq!(read.headers, task.headers);
+        assert_eq!(read.client, task.client);
+        assert_eq!(read.failures[0].worker_name, "worker-1");
+        assert_eq!(read.failures[0].reason, "timeout");
+
+        let empty = task_line("task", &Task::new("ADD", "ADD>3", "3+3"), None).unwrap();
+        match parse_record(VERSION, 3, &empty, None).unwrap() {
+            Record::Task(read) => {
+                assert_eq!(read.partition_key, None);
+                assert!(read.dependencies.is_empty() && read.headers.is_empty());
+                assert_eq!(read.client, None);
+            }
+            _ => panic!("not a task: {}", empty),
+        }
+        assert!(parse_record(1, 4, "task\tADD\tADD>4\t0\t4+4", None).is_ok());
+    }
+
+    #[test]
+    fn imported_tasks_wait_for_their_dependencies() {
+        let mut broker = BrokerHandle::new();
+        broker.broker.state_dir = Some(std::env::temp_dir());
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        broker
+            .send("client-1", &["ADD", "ADD>2", "2+2", "", "ADD>1"])
+            .unwrap();
+        assert!(broker
+            .admin("EXPORT tiny-broke-dependencies-test")
+            .starts_with("OK 2 tasks"));
+
+        let mut imported = BrokerHandle::new();
+        imported.broker.state_dir = Some(std::env::temp_dir());
+        imported.register_worker("worker-1", "ADD");
+        assert!(imported
+            .admin("IMPORT tiny-broke-dependencies-test")
+            .starts_with("OK"));
+        std::fs::remove_file(std::env::temp_dir().join("tiny-broke-dependencies-test")).unwrap();
+        assert_eq!(
+            imported.receive("worker-1"),
+            Some(vec!["".to_string(), "1+1".to_string()])
+        );
+        assert_eq!(imported.receive("worker-1"), None);
+        imported.respond("worker-1", "ADD>1", "2");
+        assert_eq!(
+            imported.receive("worker-1"),
+            Some(vec!["".to_string(), "2+2".to_string()])
+        );
+    }
+
+    #[test]
+    fn declared_and_paused_topics_are_exported() {
+        let mut broker = BrokerHandle::new();
+        broker.broker.state_dir = Some(std::env::temp_dir());
+        broker.admin("CREATE_TOPIC ADD max_queue=2");
+        broker.admin("CREATE_TOPIC SUB acl=1000");
+        broker.admin("PAUSE ADD");
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        broker.admin("EXPORT tiny-broke-topics-test");
+
+        let mut imported = BrokerHandle::new();
+        imported.broker.state_dir = Some(std::env::temp_dir());
+        imported.register_worker("worker-1", "ADD");
+        assert_eq!(
+            imported.admin("IMPORT tiny-broke-topics-test"),
+            "OK 1 tasks imported from tiny-broke-topics-test"
+        );
+        std::fs::remove_file(std::env::temp_dir().join("tiny-broke-topics-test")).unwrap();
+        let settings = &imported.broker.declared_topics["ADD"];
+        assert_eq!(settings.max_queue, Some(2));
+        assert!(!imported.broker.declared_topics["SUB"].allows(None));
+        assert!(imported.broker.is_paused("ADD"));
+        assert_eq!(imported.receive("worker-1"), None);
+        imported.admin("RESUME ADD");
+        assert_eq!(
+            imported.receive("worker-1"),
+            Some(vec!["".to_string(), "1+1".to_string()])
+        );
+    }
+
+    #[test]
+    fn state_files_are_confined_to_the_state_dir() {
+        let mut broker = BrokerHandle::new();
+        assert_eq!(
+            broker.admin("EXPORT state"),
+            "ERROR can't export to state: STATE_DIR is not set"
+        );
+        broker.broker.state_dir = Some(std::env::temp_dir());
+        for name in ["/etc/passwd", "../state", "dir/state", ".."] {
+            assert_eq!(
+                broker.admin(&format!("IMPORT {}", name)),
+                format!(
+                    "ERROR can't import from {}: {} is not a file name",
+                    name, name
+                )
+            );
+        }
+    }
+}
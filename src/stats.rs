@@ -0,0 +1,237 @@
+use crate::Broker;
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+pub struct WorkerStats {
+    pub processed: u64,
+    pub failures: u64,
+    pub total_processing_time: Duration,
+    // the average of the last tasks mostly, it is what slow workers are found on: a worker that got faster stops
+    // being slow after a few tasks
+    pub recent_as_micros: u128,
+}
+
+// slow workers get one task for this many of a worker of the same weight, their recent average goes on
+const SLOW_WORKER_SHARE: usize = 10;
+
+impl WorkerStats {
+    fn record(&mut self, processing_time: Duration) {
+        self.processed += 1;
+        self.total_processing_time += processing_time;
+        self.recent_as_micros = match self.processed {
+            1 => processing_time.as_micros(),
+            _ => (self.recent_as_micros * 3 + processing_time.as_micros()) / 4,
+        };
+    }
+
+    pub fn average_as_micros(&self) -> u128 {
+        if self.processed == 0 {
+            return 0;
+        }
+
+        self.total_processing_time.as_micros() / u128::from(self.processed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SlowWorker {
+    pub name: String,
+    pub average_as_micros: u128,
+    pub median_as_micros: u128,
+}
+
+// a worker is slow when its average processing time is `SLOW_WORKER_FACTOR` times the median of the other workers of
+// its topic, the factor can be fractional (`1.5`)
+pub fn slow_worker_factor() -> f64 {
+    env::var("SLOW_WORKER_FACTOR")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|factor| factor.is_finite() && *factor > 0.0)
+        .unwrap_or(3.0)
+}
+
+// each worker is compared to the others, with two workers the median is the average of the other one
+pub fn slow_among(averages: &[(&str, u128)], factor: f64) -> Vec<SlowWorker> {
+    if averages.len() < 2 {
+        return vec![];
+    }
+
+    let mut sorted: Vec<u128> = averages.iter().map(|(_, average)| *average).collect();
+    sorted.sort_unstable();
+    averages
+        .iter()
+        .filter_map(|(name, average)| {
+            let median = median_without(&sorted, sorted.binary_search(average).ok()?);
+            (*average as f64 > median as f64 * factor).then(|| SlowWorker {
+                name: name.to_string(),
+                average_as_micros: *average,
+                median_as_micros: median,
+            })
+        })
+        .collect()
+}
+
+// the median of the sorted averages, without the one at `skipped`
+fn median_without(sorted: &[u128], skipped: usize) -> u128 {
+    let at = |index: usize| match index < skipped {
+        true => sorted[index],
+        false => sorted[index + 1],
+    };
+    let count = sorted.len() - 1;
+    let middle = count / 2;
+    match count.is_multiple_of(2) {
+        true => (at(middle - 1) + at(middle)) / 2,
+        false => at(middle),
+    }
+}
+
+// the name is only copied for the first record of a worker or topic
+fn stats_of<'a>(stats: &'a mut HashMap<String, WorkerStats>, name: &str) -> &'a mut WorkerStats {
+    if !stats.contains_key(name) {
+        stats.insert(name.to_string(), WorkerStats::default());
+    }
+    stats.get_mut(name).unwrap()
+}
+
+impl Broker {
+    // the stats of a worker are the ones of its logical name
+    fn worker_stats_of(&mut self, worker_name: &str) -> &mut WorkerStats {
+        let worker_name = self
+            .logical_names
+            .get(worker_name)
+            .map_or(worker_name, String::as_str);
+        stats_of(&mut self.worker_stats, worker_name)
+    }
+
+    pub fn record_processed(
+        &mut self,
+        worker_name: &str,
+        topic_name: &str,
+        processing_time: Duration,
+    ) {
+        self.worker_stats_of(worker_name).record(processing_time);
+        stats_of(&mut self.topic_stats, topic_name).record(processing_time);
+    }
+
+    pub fn record_failure(&mut self, worker_name: &str) {
+        self.worker_stats_of(worker_name).failures += 1;
+    }
+
+    // only workers that already processed tasks are compared
+    pub fn slow_workers(&self, topic_name: &str) -> Vec<SlowWorker> {
+        let topic = match self.registry.topics.get(topic_name) {
+            Some(topic) => topic,
+            None => return vec![],
+        };
+
+        let averages: Vec<(&str, u128)> = topic
+            .workers
+            .iter()
+            .filter_map(|name| {
+                self.worker_stats
+                    .get(self.logical_name(name))
+                    .filter(|stats| stats.processed > 0)
+                    .map(|stats| (name.as_str(), stats.recent_as_micros))
+            })
+            .collect();
+
+        slow_among(&averages, self.slow_worker_factor)
+    }
+
+    // the weights of the workers of the topic, with the share of slow workers reduced
+    // `None` without slow workers, the weights of the workers are used as is
+    pub fn slow_worker_weights(&self, topic_name: &str) -> Option<HashMap<String, usize>> {
+        let slow = self.slow_workers(topic_name);
+        if slow.is_empty() {
+            return None;
+        }
+
+        let topic = self.registry.topics.get(topic_name)?;
+        let weights = topic
+            .workers
+            .iter()
+            .map(|name| {
+                let weight = self.worker_weight(name);
+                match slow.iter().any(|slow| &slow.name == name) {
+                    true => (name.clone(), weight),
+                    false => (name.clone(), weight * SLOW_WORKER_SHARE),
+                }
+            })
+            .collect();
+        Some(weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::slow_among;
+    use crate::embedded::BrokerHandle;
+    use std::time::Duration;
+
+    fn slow(averages: &[(&str, u128)], factor: f64) -> Vec<(String, u128)> {
+        slow_among(averages, factor)
+            .into_iter()
+            .map(|slow| (slow.name, slow.median_as_micros))
+            .collect()
+    }
+
+    #[test]
+    fn slow_workers_are_compared_to_the_other_workers() {
+        // two workers: the median is the other worker
+        let two = [("worker-1", 100), ("worker-2", 400)];
+        assert_eq!(slow(&two, 3.0), vec![("worker-2".to_string(), 100)]);
+        assert!(slow(&two, 4.0).is_empty());
+        assert_eq!(
+            slow(&[("worker-1", 100), ("worker-2", 160)], 1.5),
+            vec![("worker-2".to_string(), 100)]
+        );
+
+        let three = [("worker-1", 100), ("worker-2", 120), ("worker-3", 500)];
+        assert_eq!(slow(&three, 3.0), vec![("worker-3".to_string(), 110)]);
+        assert!(slow(&three, 5.0).is_empty());
+
+        let four = [
+            ("worker-1", 100),
+            ("worker-2", 110),
+            ("worker-3", 120),
+            ("worker-4", 400),
+        ];
+        assert_eq!(slow(&four, 3.0), vec![("worker-4".to_string(), 110)]);
+        let four = [
+            ("worker-1", 100),
+            ("worker-2", 100),
+            ("worker-3", 400),
+            ("worker-4", 400),
+        ];
+        assert_eq!(
+            slow(&four, 3.0),
+            vec![("worker-3".to_string(), 100), ("worker-4".to_string(), 100)]
+        );
+
+        assert!(slow(&[("worker-1", 100)], 3.0).is_empty());
+    }
+
+    #[test]
+    fn slow_workers_keep_a_share_and_recover() {
+        let mut handle = BrokerHandle::new();
+        handle.register_worker("worker-1", "ADD");
+        handle.register_worker("worker-2", "ADD");
+        let broker = &mut handle.broker;
+        broker.record_processed("worker-1", "ADD", Duration::from_micros(100));
+        broker.record_processed("worker-2", "ADD", Duration::from_micros(1000));
+
+        let picked: Vec<String> = (0..11)
+            .filter_map(|_| broker.get_next_worker_name("ADD", &[]))
+            .collect();
+        assert_eq!(picked.iter().filter(|name| *name == "worker-2").count(), 1);
+
+        // a few fast tasks, the worker is not slow anymore
+        (0..6).for_each(|_| {
+            broker.record_processed("worker-2", "ADD", Duration::from_micros(100));
+        });
+        assert!(broker.slow_workers("ADD").is_empty());
+        assert!(broker.slow_worker_weights("ADD").is_none());
+    }
+}
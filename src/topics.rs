@@ -0,0 +1,189 @@
+use crate::delivery::Delivery;
+use crate::headers::BrokerHeader;
+use crate::mirror::Mirrored;
+use crate::retry::{self, Backoff, OnRetry, RetryPolicy};
+use crate::routing::Route;
+use crate::split::{Merger, Splitter};
+use crate::weights;
+use crate::Broker;
+use std::env;
+
+// settings of a topic declared with the `CREATE_TOPIC` admin command
+#[derive(Debug, Clone, Default)]
+pub struct TopicSettings {
+    pub ttl_as_secs: Option<u64>,
+    pub max_queue: Option<usize>,
+    // uids of the local peers allowed to send tasks to the topic
+    pub acl: Option<Vec<u32>>,
+    pub delivery: Delivery,
+    pub ordered: bool,
+    // broker headers given to the workers with the tasks
+    pub headers: Vec<BrokerHeader>,
+    // in order, see routing.rs
+    pub routes: Vec<Route>,
+    // copied to the mirror, see mirror.rs
+    pub mirror: Mirrored,
+    // see retry.rs
+    pub retry: RetryPolicy,
+    // see ceilings.rs
+    pub max_in_flight: Option<usize>,
+    // see fairness.rs
+    pub weight: Option<usize>,
+    // see split.rs
+    pub split: Option<Splitter>,
+    pub merge: Merger,
+    // the arguments it was declared with, written by `EXPORT`
+    pub declaration: Vec<String>,
+}
+
+impl TopicSettings {
+    // settings are given as `name=value` arguments: `ttl=<seconds>`, `max_queue=<count>`, `max_in_flight=<count>`, `weight=<count>`, `acl=<uid>,<uid>`,
+    // `delivery=<mode>`, `ordered=<true|false>`, `headers=<name>,<name>`, `route=<path>=<value>:<target>` (repeated),
+    // `mirror=<tasks|responses|all>`, `retries=<count>`, `backoff=<mode>`, `retry_on=<reason>,<reason>`,
+    // `on_retry=<backoff|elsewhere|requeue>`, `split=<lines:<count>|bytes:<count>|script:<command>>`,
+    // `merge=<concat|script:<command>>`
+    pub fn parse<'a>(args: impl Iterator<Item = &'a str>) -> Result<TopicSettings, String> {
+        let mut settings = TopicSettings::default();
+
+        for arg in args {
+            settings.declaration.push(arg.to_string());
+            let (name, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("bad setting {}, expected name=value", arg))?;
+
+            match name {
+                "ttl" => {
+                    settings.ttl_as_secs = Some(value.parse().map_err(|_| "ttl is not a number")?)
+                }
+                "max_queue" => {
+                    settings.max_queue =
+                        Some(value.parse().map_err(|_| "max_queue is not a number")?)
+                }
+                "max_in_flight" => {
+                    settings.max_in_flight =
+                        Some(value.parse().map_err(|_| "max_in_flight is not a number")?)
+                }
+                "weight" => settings.weight = Some(weights::parse_weight(value)?),
+                "acl" => settings.acl = Some(parse_acl(value)?),
+                "delivery" => settings.delivery = Delivery::parse(value)?,
+                "ordered" => {
+                    settings.ordered = value.parse().map_err(|_| "ordered is not a boolean")?
+                }
+                "headers" => {
+                    settings.headers = value
+                        .split(',')
+                        .filter(|header| !header.is_empty())
+                        .map(BrokerHeader::parse)
+                        .collect::<Result<Vec<BrokerHeader>, String>>()?
+                }
+                "route" => settings.routes.push(Route::parse(value)?),
+                "mirror" => settings.mirror = Mirrored::parse(value)?,
+                "retries" => settings.retry.retries = Some(retry::parse_retries(value)?),
+                "backoff" => settings.retry.backoff = Backoff::parse(value)?,
+                "retry_on" => settings.retry.retry_on = Some(retry::parse_reasons(value)?),
+                "on_retry" => settings.retry.on_retry = OnRetry::parse(value)?,
+                "split" => settings.split = Some(Splitter::parse(value)?),
+                "merge" => settings.merge = Merger::parse(value)?,
+                _ => return Err(format!("unknown setting {}", name)),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    // identities are chosen by the peers, the acl is checked against the uid the kernel gives for the peers of the
+    // unix socket (see ipc.rs): remote peers can't send tasks to a topic with an acl
+    pub fn allows(&self, uid: Option<u32>) -> bool {
+        self.acl
+            .as_ref()
+            .is_none_or(|acl| uid.is_some_and(|uid| acl.contains(&uid)))
+    }
+}
+
+fn parse_acl(value: &str) -> Result<Vec<u32>, String> {
+    value
+        .split(',')
+        .map(|uid| {
+            uid.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("acl takes uids, {} is not one", uid))
+        })
+        .collect()
+}
+
+pub fn declared_topics_only() -> bool {
+    env::var("DECLARED_TOPICS_ONLY").is_ok_and(|v| v == "true")
+}
+
+impl Broker {
+    // the control message to send back to the client when its task is refused
+    pub fn task_rejection(&self, uid: Option<u32>, topic_name: &str) -> Option<&'static str> {
+        match self.declared_topics.get(topic_name) {
+            _ if self.is_stopping() => Some("@@STOPPING"),
+            _ if !self.allows_local(uid, topic_name) => Some("@@FORBIDDEN"),
+            None if self.declared_topics_only => Some("@@NO_TOPIC"),
+            Some(settings) if !settings.allows(uid) => Some("@@FORBIDDEN"),
+            _ => None,
+        }
+    }
+
+    pub fn task_timeout_as_secs(&self, topic_name: &str) -> u64 {
+        self.declared_topics
+            .get(topic_name)
+            .and_then(|settings| settings.ttl_as_secs)
+            .unwrap_or(self.timeout_as_secs)
+    }
+
+    pub fn is_ordered(&self, topic_name: &str) -> bool {
+        self.declared_topics
+            .get(topic_name)
+            .is_some_and(|settings| settings.ordered)
+    }
+
+    pub fn is_queue_full(&self, topic_name: &str) -> bool {
+        let max_queue = match self
+            .declared_topics
+            .get(topic_name)
+            .and_then(|settings| settings.max_queue)
+        {
+            Some(max_queue) => max_queue,
+            None => return false,
+        };
+
+        self.dispatcher
+            .tasks_to_retry
+            .iter()
+            .filter(|task| &*task.worker_topic == topic_name)
+            .count()
+            >= max_queue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopicSettings;
+    use crate::embedded::BrokerHandle;
+
+    #[test]
+    fn acls_allow_local_uids() {
+        let settings = TopicSettings::parse("acl=1000,1001".split_whitespace()).unwrap();
+        assert!(settings.allows(Some(1001)));
+        assert!(!settings.allows(Some(0)));
+        assert!(!settings.allows(None));
+        assert!(TopicSettings::default().allows(None));
+        assert!(TopicSettings::parse("acl=client-".split_whitespace()).is_err());
+
+        // whatever their identity, remote peers have no uid
+        let mut broker = BrokerHandle::new();
+        broker.admin("CREATE_TOPIC ADD acl=1000");
+        broker.send_task("client-1", "ADD", "ADD>1", "1+1");
+        assert_eq!(
+            broker.receive("client-1"),
+            Some(vec![
+                "".to_string(),
+                "@@FORBIDDEN".to_string(),
+                "ADD>1".to_string()
+            ])
+        );
+    }
+}
@@ -0,0 +1,623 @@
+use crate::endpoints;
+#[cfg(feature = "quic")]
+use crate::quic::QuicServer;
+use crate::tuning::SocketOptions;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fmt;
+#[cfg(feature = "quic")]
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+// the framing of the ROUTER socket: the broker only deals with messages, the transport with sockets
+// the transport is a trait, so the broker can be driven without zmq: the zmq router in production, queues in
+// memory for simulations and tests
+
+#[derive(Debug)]
+pub struct Unreachable(pub String);
+
+impl fmt::Display for Unreachable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} can't be reached", self.0)
+    }
+}
+
+pub trait Transport {
+    // frames after the identity, without waiting if the peer can't be reached
+    fn send(&self, identity: &str, frames: &[&str]) -> Result<(), Unreachable>;
+    // true when a message can be received before the timeout
+    fn poll(&self, timeout: Duration) -> Result<bool, String>;
+    // the next message, waits for it
+    fn recv(&self) -> Result<Incoming, String>;
+}
+
+fn zmq_error(error: zmq::Error) -> String {
+    error.to_string()
+}
+
+pub struct Router {
+    socket: zmq::Socket,
+    // the unix socket for local peers, they are answered on it
+    local: Option<zmq::Socket>,
+    local_peers: RefCell<HashSet<String>>,
+    outbox: RefCell<Outbox>,
+    // the peers connected over QUIC, they are answered on their connection
+    #[cfg(feature = "quic")]
+    quic: Option<QuicServer>,
+}
+
+pub fn outbox_size() -> usize {
+    env::var("OUTBOX_SIZE")
+        .map(|v| v.parse::<usize>().unwrap_or(1000))
+        .unwrap_or(1000)
+}
+
+// sends a message to a peer, without waiting
+type SendFrames<'a> = dyn Fn(&str, &[String]) -> Result<(), zmq::Error> + 'a;
+
+// messages for the peers whose pipe is full (`EAGAIN` from the mandatory router): the peer is busy, not gone, its
+// messages wait here until it can take them again
+// a peer with waiting messages gets the next ones behind them, so they stay in order
+// a peer with too many waiting messages is considered gone
+// the router is writable as soon as one peer can take messages, so a flush that sent nothing is only tried again
+// after a delay, doubled each time, not to spin while a peer stays busy
+pub struct Outbox {
+    capacity: usize,
+    queues: HashMap<String, VecDeque<Vec<String>>>,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+const MIN_FLUSH_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_FLUSH_BACKOFF: Duration = Duration::from_millis(100);
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Outbox {
+        Outbox {
+            capacity,
+            queues: HashMap::new(),
+            backoff: Duration::ZERO,
+            retry_at: None,
+        }
+    }
+
+    // `None` without waiting messages, zero when they can be tried now
+    fn flush_delay(&self, now: Instant) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.retry_at.map_or(Duration::ZERO, |retry_at| {
+            retry_at.saturating_duration_since(now)
+        }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    fn is_waiting(&self, identity: &str) -> bool {
+        self.queues.contains_key(identity)
+    }
+
+    // false when the peer has too many waiting messages
+    fn push(&mut self, identity: &str, frames: &[&str]) -> bool {
+        let queue = self.queues.entry(identity.to_string()).or_default();
+        if queue.len() >= self.capacity {
+            self.queues.remove(identity);
+            return false;
+        }
+        queue.push_back(frames.iter().map(|frame| frame.to_string()).collect());
+        true
+    }
+
+    // sends the waiting messages, a peer busy again keeps the rest of its messages
+    // the peers that are gone are given back, their messages are dropped
+    fn flush(&mut self, send: &SendFrames, now: Instant) -> Vec<String> {
+        let mut gone = vec![];
+        let mut sent = 0;
+        self.queues.retain(|identity, queue| {
+            while let Some(frames) = queue.front() {
+                match send(identity, frames) {
+                    Ok(()) => {
+                        queue.pop_front();
+                        sent += 1;
+                    }
+                    Err(zmq::Error::EAGAIN) => return true,
+                    Err(_) => {
+                        gone.push(identity.clone());
+                        return false;
+                    }
+                }
+            }
+            false
+        });
+
+        (self.backoff, self.retry_at) = match sent {
+            0 if !self.queues.is_empty() => {
+                let backoff = (self.backoff * 2).clamp(MIN_FLUSH_BACKOFF, MAX_FLUSH_BACKOFF);
+                (backoff, Some(now + backoff))
+            }
+            _ => (Duration::ZERO, None),
+        };
+        gone
+    }
+}
+
+// the router is set as mandatory, so sending to an unknown peer fails
+fn bind_router(
+    context: &zmq::Context,
+    endpoint: &str,
+    options: &SocketOptions,
+) -> Result<zmq::Socket, zmq::Error> {
+    let socket = context.socket(zmq::ROUTER)?;
+    options.apply(&socket)?;
+    socket.set_router_mandatory(true)?;
+    socket.bind(endpoint)?;
+    Ok(socket)
+}
+
+// on a unix socket, libzmq gives the peer credentials in the address: `<host>:<uid>:<gid>:<pid>`
+fn uid_of(peer_address: &str) -> Option<u32> {
+    peer_address.rsplit(':').nth(2)?.parse().ok()
+}
+
+impl Router {
+    pub fn bind(
+        context: &zmq::Context,
+        endpoint: &str,
+        options: &SocketOptions,
+    ) -> Result<Router, zmq::Error> {
+        Ok(Router {
+            socket: bind_router(context, endpoint, options)?,
+            local: None,
+            local_peers: RefCell::new(HashSet::new()),
+            outbox: RefCell::new(Outbox::new(outbox_size())),
+            #[cfg(feature = "quic")]
+            quic: None,
+        })
+    }
+
+    pub fn bind_local(
+        &mut self,
+        context: &zmq::Context,
+        path: &str,
+        options: &SocketOptions,
+    ) -> Result<(), zmq::Error> {
+        self.local = Some(bind_router(context, &format!("ipc://{}", path), options)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "quic")]
+    pub fn serve_quic(&mut self, quic: QuicServer) {
+        self.quic = Some(quic);
+    }
+
+    // the endpoint the OS gave, with port 0
+    pub fn endpoint(&self) -> String {
+        endpoints::bound_endpoint(&self.socket)
+    }
+
+    fn sockets(&self) -> Vec<&zmq::Socket> {
+        let mut sockets = vec![&self.socket];
+        sockets.extend(self.local.as_ref());
+        sockets
+    }
+
+    // to be polled along other sockets, the sockets first, then the QUIC messages, only for reading
+    pub fn poll_items(&self, events: zmq::PollEvents) -> Vec<zmq::PollItem<'_>> {
+        #[allow(unused_mut)]
+        let mut items: Vec<zmq::PollItem> = self
+            .sockets()
+            .iter()
+            .map(|socket| socket.as_poll_item(events))
+            .collect();
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &self.quic {
+            items.push(zmq::PollItem::from_fd(quic.as_raw_fd(), zmq::POLLIN));
+        }
+        items
+    }
+
+    fn socket_of(&self, identity: &str) -> &zmq::Socket {
+        match &self.local {
+            Some(local) if self.local_peers.borrow().contains(identity) => local,
+            _ => &self.socket,
+        }
+    }
+
+    // when to poll the sockets for writing too: `None` without waiting messages, zero when they can be tried now
+    pub fn flush_delay(&self) -> Option<Duration> {
+        self.outbox.borrow().flush_delay(Instant::now())
+    }
+
+    // sends the messages of the outbox the peers can take now, gives back the peers that are gone
+    pub fn flush(&self) -> Vec<String> {
+        self.outbox.borrow_mut().flush(
+            &|identity, frames| {
+                let mut parts = vec![identity];
+                parts.extend(frames.iter().map(String::as_str));
+                self.socket_of(identity)
+                    .send_multipart(parts, zmq::DONTWAIT)
+            },
+            Instant::now(),
+        )
+    }
+}
+
+impl Transport for Router {
+    fn send(&self, identity: &str, frames: &[&str]) -> Result<(), Unreachable> {
+        #[cfg(feature = "quic")]
+        if let Some(quic) = self
+            .quic
+            .as_ref()
+            .filter(|quic| quic.is_connected(identity))
+        {
+            return quic.send(identity, frames);
+        }
+        let unreachable = || Unreachable(identity.to_string());
+        if self.outbox.borrow().is_waiting(identity) {
+            return match self.outbox.borrow_mut().push(identity, frames) {
+                true => Ok(()),
+                false => Err(unreachable()),
+            };
+        }
+
+        let mut parts = vec![identity];
+        parts.extend_from_slice(frames);
+        match self
+            .socket_of(identity)
+            .send_multipart(parts, zmq::DONTWAIT)
+        {
+            Ok(()) => Ok(()),
+            // the peer is busy
+            Err(zmq::Error::EAGAIN) if self.outbox.borrow_mut().push(identity, frames) => Ok(()),
+            Err(_) => Err(unreachable()),
+        }
+    }
+
+    fn poll(&self, timeout: Duration) -> Result<bool, String> {
+        let mut items = self.poll_items(zmq::POLLIN);
+        zmq::poll(&mut items, timeout.as_millis() as i64)
+            .map(|count| count > 0)
+            .map_err(zmq_error)
+    }
+
+    fn recv(&self) -> Result<Incoming, String> {
+        let sockets = self.sockets();
+        let mut items = self.poll_items(zmq::POLLIN);
+        zmq::poll(&mut items, -1).map_err(zmq_error)?;
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &self.quic {
+            if !items[..sockets.len()].iter().any(|item| item.is_readable()) {
+                return quic.recv();
+            }
+        }
+        let is_local = sockets.len() > 1 && items[1].is_readable() && !items[0].is_readable();
+        let socket = sockets[if is_local { 1 } else { 0 }];
+
+        let mut identity = socket.recv_msg(0).map_err(zmq_error)?;
+        let uid = match is_local {
+            true => identity.gets("Peer-Address").and_then(uid_of),
+            false => None,
+        };
+        let mut frames = vec![identity.to_vec()];
+        while socket.get_rcvmore().map_err(zmq_error)? {
+            frames.push(socket.recv_bytes(0).map_err(zmq_error)?);
+        }
+
+        let mut message = Incoming::parse(frames)?;
+        message.address = match is_local {
+            true => uid.map(|uid| format!("uid {}", uid)),
+            false => identity.gets("Peer-Address").map(String::from),
+        };
+        message.uid = uid;
+        // the peer is answered where it was last seen
+        if is_local {
+            self.local_peers
+                .borrow_mut()
+                .insert(message.identity.clone());
+        } else {
+            self.local_peers.borrow_mut().remove(&message.identity);
+        }
+
+        Ok(message)
+    }
+}
+
+// peers are queues in the same thread: what they send waits for the broker, what they receive waits for them
+// like the mandatory router, a peer must be connected to receive, and disconnecting drops what it didn't read
+#[derive(Default)]
+pub struct Memory {
+    incoming: RefCell<VecDeque<Vec<Vec<u8>>>>,
+    peers: RefCell<HashMap<String, VecDeque<Vec<String>>>>,
+}
+
+impl Memory {
+    pub fn connect(&self, identity: &str) {
+        self.peers
+            .borrow_mut()
+            .entry(identity.to_string())
+            .or_default();
+    }
+
+    pub fn disconnect(&self, identity: &str) {
+        self.peers.borrow_mut().remove(identity);
+    }
+
+    // the peer sends frames to the broker
+    pub fn push(&self, identity: &str, frames: &[String]) {
+        self.connect(identity);
+
+        let mut message = vec![identity.as_bytes().to_vec()];
+        message.extend(frames.iter().map(|frame| frame.as_bytes().to_vec()));
+        self.incoming.borrow_mut().push_back(message);
+    }
+
+    // the next message the peer received
+    pub fn take(&self, identity: &str) -> Option<Vec<String>> {
+        self.peers
+            .borrow_mut()
+            .get_mut(identity)
+            .and_then(|received| received.pop_front())
+    }
+}
+
+impl Transport for Memory {
+    fn send(&self, identity: &str, frames: &[&str]) -> Result<(), Unreachable> {
+        match self.peers.borrow_mut().get_mut(identity) {
+            Some(received) => {
+                received.push_back(frames.iter().map(|frame| frame.to_string()).collect());
+                Ok(())
+            }
+            None => Err(Unreachable(identity.to_string())),
+        }
+    }
+
+    // nobody else can send in the meantime, there is no need to wait
+    fn poll(&self, _timeout: Duration) -> Result<bool, String> {
+        Ok(!self.incoming.borrow().is_empty())
+    }
+
+    fn recv(&self) -> Result<Incoming, String> {
+        let frames = self
+            .incoming
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| "nothing to receive".to_string())?;
+        Incoming::parse(frames)
+    }
+}
+
+// a message received on the router socket: the peer identity, then up to 5 frames
+// missing frames are empty, the uid is only known for local peers
+// the control messages peers send, the other messages are tasks and responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Ping,
+    Members,
+    Group,
+    Result,
+    Direct,
+    Done,
+    Retry,
+    Wait,
+    Workflow,
+    Register,
+    Unregister,
+    Subscribe,
+    Unsubscribe,
+    SubscribeTopic,
+    UnsubscribeTopic,
+    Received,
+}
+
+impl Control {
+    // matched on the bytes of the frame, before it is read as a string
+    pub fn parse(frame: &[u8]) -> Option<Control> {
+        match frame {
+            b"@@PING" => Some(Control::Ping),
+            b"@@MEMBERS" => Some(Control::Members),
+            b"@@GROUP" => Some(Control::Group),
+            b"@@RESULT" => Some(Control::Result),
+            b"@@DIRECT" => Some(Control::Direct),
+            b"@@DONE" => Some(Control::Done),
+            b"@@RETRY" => Some(Control::Retry),
+            b"@@WAIT" => Some(Control::Wait),
+            b"@@WORKFLOW" => Some(Control::Workflow),
+            b"@@REGISTER" => Some(Control::Register),
+            b"@@UNREGISTER" => Some(Control::Unregister),
+            b"@@SUBSCRIBE" => Some(Control::Subscribe),
+            b"@@UNSUBSCRIBE" => Some(Control::Unsubscribe),
+            b"@@SUBSCRIBE_TOPIC" => Some(Control::SubscribeTopic),
+            b"@@UNSUBSCRIBE_TOPIC" => Some(Control::UnsubscribeTopic),
+            b"@@RECEIVED" => Some(Control::Received),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Incoming {
+    pub identity: String,
+    // set when the topic is a control message
+    pub control: Option<Control>,
+    pub topic: String,
+    pub response_topic: String,
+    pub payload: String,
+    // tasks of ordered topics
+    pub partition_key: String,
+    // tasks waiting for others, comma separated response topics
+    pub dependencies: String,
+    // JSON object of strings
+    pub headers: String,
+    pub uid: Option<u32>,
+    // IP of the peer, or its uid on the unix socket
+    pub address: Option<String>,
+    // with the identity
+    pub frame_count: usize,
+    // why the message can't be read, its other frames are then empty
+    pub malformed: Option<String>,
+}
+
+impl Incoming {
+    // malformed messages are kept to be counted for their peer, only the identity has to be read
+    pub fn parse(frames: Vec<Vec<u8>>) -> Result<Incoming, String> {
+        let frame_count = frames.len();
+        let identity = frames
+            .first()
+            .and_then(|identity| String::from_utf8(identity.clone()).ok())
+            .ok_or_else(|| "identities are expected to be UTF-8".to_string())?;
+
+        let mut message = Incoming::read(frames).unwrap_or_else(|error| Incoming {
+            identity,
+            malformed: Some(error),
+            ..Incoming::default()
+        });
+        message.frame_count = frame_count;
+        Ok(message)
+    }
+
+    fn read(frames: Vec<Vec<u8>>) -> Result<Incoming, String> {
+        if frames.len() > 7 {
+            return Err(format!("{} frames, at most 7 are expected", frames.len()));
+        }
+
+        let control = frames.get(1).and_then(|topic| Control::parse(topic));
+        let mut frames = frames.into_iter().map(|frame| {
+            String::from_utf8(frame).map_err(|_| "frames are expected to be UTF-8".to_string())
+        });
+        let mut next = || frames.next().unwrap_or_else(|| Ok(String::new()));
+
+        Ok(Incoming {
+            identity: next()?,
+            control,
+            topic: next()?,
+            response_topic: next()?,
+            payload: next()?,
+            partition_key: next()?,
+            dependencies: next()?,
+            headers: next()?,
+            ..Incoming::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{uid_of, Control, Incoming, Memory, Outbox, Transport};
+    use std::cell::RefCell;
+    use std::time::{Duration, Instant};
+
+    fn frames(frames: &[&str]) -> Vec<Vec<u8>> {
+        frames
+            .iter()
+            .map(|frame| frame.as_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn missing_frames_are_empty() {
+        let message = Incoming::parse(frames(&["worker-1", "@@PING"])).unwrap();
+        assert_eq!(message.identity, "worker-1");
+        assert_eq!(message.control, Some(Control::Ping));
+        assert_eq!(message.topic, "@@PING");
+        assert_eq!(message.response_topic, "");
+        assert_eq!(message.payload, "");
+        assert_eq!(message.partition_key, "");
+    }
+
+    #[test]
+    fn too_many_or_binary_frames_are_refused() {
+        let message =
+            Incoming::parse(frames(&["client-1", "ADD", "R", "1+1", "key", "R0", "{}"])).unwrap();
+        assert_eq!(message.partition_key, "key");
+        assert_eq!(message.dependencies, "R0");
+        assert_eq!(message.headers, "{}");
+        assert_eq!(message.frame_count, 7);
+        let message = Incoming::parse(frames(&[
+            "client-1", "ADD", "R", "1+1", "key", "R0", "{}", "extra",
+        ]))
+        .unwrap();
+        assert_eq!(message.identity, "client-1");
+        assert!(message.malformed.is_some());
+        let message = Incoming::parse(vec![b"client-1".to_vec(), vec![0xff]]).unwrap();
+        assert!(message.malformed.is_some());
+        assert_eq!(message.topic, "");
+        assert!(Incoming::parse(vec![vec![0xff], b"ADD".to_vec()]).is_err());
+    }
+
+    #[test]
+    fn memory_peers_must_be_connected_to_receive() {
+        let memory = Memory::default();
+        assert!(memory.send("worker-1", &["", "1+1"]).is_err());
+
+        memory.push("worker-1", &["@@REGISTER".to_string(), "ADD".to_string()]);
+        assert!(memory.poll(Duration::from_secs(0)).unwrap());
+        let message = memory.recv().unwrap();
+        assert_eq!(message.identity, "worker-1");
+        assert_eq!(message.response_topic, "ADD");
+        assert!(!memory.poll(Duration::from_secs(0)).unwrap());
+
+        memory.send("worker-1", &["", "1+1"]).unwrap();
+        assert_eq!(
+            memory.take("worker-1"),
+            Some(vec!["".to_string(), "1+1".to_string()])
+        );
+        assert_eq!(memory.take("worker-1"), None);
+
+        memory.send("worker-1", &["", "2+2"]).unwrap();
+        memory.disconnect("worker-1");
+        assert_eq!(memory.take("worker-1"), None);
+        assert!(memory.send("worker-1", &["", "2+2"]).is_err());
+    }
+
+    #[test]
+    fn uid_is_read_from_the_local_peer_address() {
+        assert_eq!(uid_of("localhost:1000:100:4242"), Some(1000));
+        assert_eq!(uid_of(":0:0:1"), Some(0));
+        assert_eq!(uid_of("127.0.0.1"), None);
+    }
+
+    #[test]
+    fn busy_peers_get_their_messages_in_order_once_they_can() {
+        let mut outbox = Outbox::new(2);
+        assert!(outbox.push("busy", &["", "1"]));
+        assert!(outbox.push("busy", &["", "2"]));
+        assert!(outbox.push("gone", &["", "1"]));
+        assert!(outbox.is_waiting("busy"));
+
+        // the busy peer takes one message, then is busy again
+        let now = Instant::now();
+        let sent = RefCell::new(vec![]);
+        let send = |identity: &str, frames: &[String]| match identity {
+            "gone" => Err(zmq::Error::EHOSTUNREACH),
+            _ if sent.borrow().is_empty() => {
+                sent.borrow_mut().push(frames[1].clone());
+                Ok(())
+            }
+            _ => Err(zmq::Error::EAGAIN),
+        };
+        let gone = outbox.flush(&send, now);
+        assert_eq!(gone, vec!["gone"]);
+        assert_eq!(*sent.borrow(), vec!["1"]);
+        assert!(outbox.is_waiting("busy"));
+        assert!(!outbox.is_waiting("gone"));
+        assert_eq!(outbox.flush_delay(now), Some(Duration::ZERO));
+
+        // nothing sent, the next flush waits, longer each time
+        assert!(outbox.flush(&send, now).is_empty());
+        assert_eq!(outbox.flush_delay(now), Some(Duration::from_millis(1)));
+        outbox.flush(&send, now);
+        assert_eq!(outbox.flush_delay(now), Some(Duration::from_millis(2)));
+        assert_eq!(
+            outbox.flush_delay(now + Duration::from_millis(5)),
+            Some(Duration::ZERO)
+        );
+
+        // too many waiting messages
+        assert!(outbox.push("busy", &["", "3"]));
+        assert!(!outbox.push("busy", &["", "4"]));
+        assert!(outbox.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::container;
+use std::env;
+
+// options of the zmq sockets, from `ZMQ_<OPTION>` environment variables
+// the zmq defaults don't fit a broker: a peer's pipe is full after 1000 messages (the router then refuses to send
+// to it), and an unsent message keeps the process from exiting (infinite linger)
+// reconnection and `IMMEDIATE` only matter on connecting sockets (cluster peers)
+
+const DEFAULT_HWM: i32 = 100_000;
+const DEFAULT_LINGER_AS_MILLIS: i32 = 1000;
+const DEFAULT_TCP_KEEPALIVE_IDLE_AS_SECS: i32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketOptions {
+    pub sndhwm: i32,
+    pub rcvhwm: i32,
+    pub linger_as_millis: i32,
+    // -1 leaves it to the OS, 0 disables it, 1 enables it
+    pub tcp_keepalive: i32,
+    pub tcp_keepalive_idle_as_secs: i32,
+    pub reconnect_ivl_as_millis: Option<i32>,
+    pub reconnect_ivl_max_as_millis: Option<i32>,
+    // messages are only queued for connected peers
+    pub immediate: bool,
+    // IPv6 peers, see container.rs
+    pub ipv6: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            sndhwm: DEFAULT_HWM,
+            rcvhwm: DEFAULT_HWM,
+            linger_as_millis: DEFAULT_LINGER_AS_MILLIS,
+            tcp_keepalive: 1,
+            tcp_keepalive_idle_as_secs: DEFAULT_TCP_KEEPALIVE_IDLE_AS_SECS,
+            reconnect_ivl_as_millis: None,
+            reconnect_ivl_max_as_millis: None,
+            immediate: false,
+            ipv6: false,
+        }
+    }
+}
+
+fn var(name: &str) -> Option<i32> {
+    env::var(name).ok().and_then(|v| v.parse::<i32>().ok())
+}
+
+impl SocketOptions {
+    pub fn from_env() -> SocketOptions {
+        let defaults = SocketOptions::default();
+        SocketOptions {
+            sndhwm: var("ZMQ_SNDHWM").unwrap_or(defaults.sndhwm),
+            rcvhwm: var("ZMQ_RCVHWM").unwrap_or(defaults.rcvhwm),
+            linger_as_millis: var("ZMQ_LINGER").unwrap_or(defaults.linger_as_millis),
+            tcp_keepalive: var("ZMQ_TCP_KEEPALIVE").unwrap_or(defaults.tcp_keepalive),
+            tcp_keepalive_idle_as_secs: var("ZMQ_TCP_KEEPALIVE_IDLE")
+                .unwrap_or(defaults.tcp_keepalive_idle_as_secs),
+            reconnect_ivl_as_millis: var("ZMQ_RECONNECT_IVL"),
+            reconnect_ivl_max_as_millis: var("ZMQ_RECONNECT_IVL_MAX"),
+            immediate: env::var("ZMQ_IMMEDIATE").is_ok_and(|v| v == "true"),
+            ipv6: container::ipv6(),
+        }
+    }
+
+    // before binding or connecting, most options are not taken into account afterwards
+    pub fn apply(&self, socket: &zmq::Socket) -> Result<(), zmq::Error> {
+        socket.set_sndhwm(self.sndhwm)?;
+        socket.set_rcvhwm(self.rcvhwm)?;
+        socket.set_linger(self.linger_as_millis)?;
+        socket.set_tcp_keepalive(self.tcp_keepalive)?;
+        socket.set_tcp_keepalive_idle(self.tcp_keepalive_idle_as_secs)?;
+        if let Some(reconnect_ivl) = self.reconnect_ivl_as_millis {
+            socket.set_reconnect_ivl(reconnect_ivl)?;
+        }
+        if let Some(reconnect_ivl_max) = self.reconnect_ivl_max_as_millis {
+            socket.set_reconnect_ivl_max(reconnect_ivl_max)?;
+        }
+        socket.set_immediate(self.immediate)?;
+        socket.set_ipv6(self.ipv6)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketOptions;
+
+    #[test]
+    fn options_are_applied_to_the_socket() {
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::DEALER).unwrap();
+        let options = SocketOptions {
+            sndhwm: 10,
+            linger_as_millis: 0,
+            reconnect_ivl_as_millis: Some(250),
+            immediate: true,
+            ..SocketOptions::default()
+        };
+        options.apply(&socket).unwrap();
+
+        assert_eq!(socket.get_sndhwm().unwrap(), 10);
+        assert_eq!(socket.get_rcvhwm().unwrap(), 100_000);
+        assert_eq!(socket.get_linger().unwrap(), 0);
+        assert_eq!(socket.get_tcp_keepalive().unwrap(), 1);
+        assert_eq!(socket.get_reconnect_ivl().unwrap(), 250);
+        assert!(socket.is_immediate().unwrap());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::time::{Duration, SystemTime};
+
+// responses a client can't be sent (it is gone, or its outbox is full, see transport.rs) wait for it: they are sent
+// again with its next message (a reconnection, a ping, ...), in order
+// at most `UNDELIVERED_SIZE` responses per client wait, for `UNDELIVERED_TTL` at most, the others are dropped with a
+// `response.dropped` event, and counted (`UNDELIVERED` admin command)
+
+pub fn undelivered_size() -> usize {
+    env::var("UNDELIVERED_SIZE")
+        .map(|v| v.parse::<usize>().unwrap_or(100))
+        .unwrap_or(100)
+}
+
+pub fn undelivered_ttl() -> Duration {
+    Duration::from_secs(
+        env::var("UNDELIVERED_TTL")
+            .map(|v| v.parse::<u64>().unwrap_or(60))
+            .unwrap_or(60),
+    )
+}
+
+struct Response {
+    response_topic: String,
+    frames: Vec<String>,
+    date: SystemTime,
+}
+
+pub struct Undelivered {
+    size: usize,
+    ttl: Duration,
+    by_client: HashMap<String, VecDeque<Response>>,
+    pub redelivered: u64,
+    pub dropped: u64,
+}
+
+impl Undelivered {
+    pub fn from_env() -> Undelivered {
+        Undelivered {
+            size: undelivered_size(),
+            ttl: undelivered_ttl(),
+            by_client: HashMap::new(),
+            redelivered: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_client.values().map(VecDeque::len).sum()
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn clients(&self) -> usize {
+        self.by_client.len()
+    }
+
+    // the response pushed out of the queue of the client, if it is full
+    fn push(
+        &mut self,
+        identity: &str,
+        response_topic: &str,
+        frames: &[&str],
+        now: SystemTime,
+    ) -> Option<Response> {
+        if self.size == 0 {
+            return None;
+        }
+        let queue = self.by_client.entry(identity.to_string()).or_default();
+        queue.push_back(Response {
+            response_topic: response_topic.to_string(),
+            frames: frames.iter().map(|frame| frame.to_string()).collect(),
+            date: now,
+        });
+        match queue.len() > self.size {
+            true => queue.pop_front(),
+            false => None,
+        }
+    }
+
+    // by client, the responses waiting for longer than the ttl
+    fn expire(&mut self, now: SystemTime) -> Vec<(String, Response)> {
+        let ttl = self.ttl;
+        let mut expired = vec![];
+        self.by_client.retain(|identity, queue| {
+            while queue.front().is_some_and(|response| {
+                now.duration_since(response.date).unwrap_or_default() >= ttl
+            }) {
+                expired.extend(
+                    queue
+                        .pop_front()
+                        .map(|response| (identity.clone(), response)),
+                );
+            }
+            !queue.is_empty()
+        });
+        expired
+    }
+}
+
+impl Broker {
+    // a response the client can't be sent now waits for it
+    pub fn send_or_keep(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        response_topic: &str,
+        frames: &[&str],
+    ) {
+        if transport.send(identity, frames).is_ok() {
+            return;
+        }
+        log::warn(&format!(
+            "Client {} can't be sent the response {} now, keeping it",
+            identity, response_topic
+        ));
+        let now = self.now();
+        if let Some(dropped) = self.undelivered.push(identity, response_topic, frames, now) {
+            self.drop_response(identity, &dropped.response_topic, "full");
+        }
+    }
+
+    // the client is back, it gets its waiting responses
+    pub fn redeliver(&mut self, transport: &dyn Transport, identity: &str) {
+        let responses = match self.undelivered.by_client.remove(identity) {
+            Some(responses) => responses,
+            None => return,
+        };
+        log::info(&format!(
+            "Client {} is back, sending its waiting responses ({})",
+            identity,
+            responses.len()
+        ));
+        for response in responses {
+            let frames: Vec<&str> = response.frames.iter().map(String::as_str).collect();
+            self.undelivered.redelivered += 1;
+            self.send_or_keep(transport, identity, &response.response_topic, &frames);
+        }
+    }
+
+    pub fn expire_undelivered(&mut self) {
+        let now = self.now();
+        for (identity, response) in self.undelivered.expire(now) {
+            self.drop_response(&identity, &response.response_topic, "expired");
+        }
+    }
+
+    pub fn drop_response(&mut self, identity: &str, response_topic: &str, reason: &str) {
+        log::warn(&format!(
+            "Dropping the response {} of client {} ({})",
+            response_topic, identity, reason
+        ));
+        self.undelivered.dropped += 1;
+        self.emit(
+            "response.dropped",
+            &[
+                ("client", identity),
+                ("responseTopic", response_topic),
+                ("reason", reason),
+            ],
+        );
+    }
+
+    pub fn undelivered_line(&self) -> String {
+        format!(
+            "{} responses waiting for {} clients; {} redelivered; {} waiting for a receipt; {} resent; {} dropped",
+            self.undelivered.len(),
+            self.undelivered.clients(),
+            self.undelivered.redelivered,
+            self.receipts.len(),
+            self.receipts.resent,
+            self.undelivered.dropped
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Undelivered;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn responses_are_bounded_by_count_and_age() {
+        let mut undelivered = Undelivered::from_env();
+        undelivered.size = 2;
+        undelivered.ttl = Duration::from_secs(10);
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        assert!(undelivered
+            .push("client-1", "R1", &["", "1"], at(0))
+            .is_none());
+        assert!(undelivered
+            .push("client-1", "R2", &["", "2"], at(5))
+            .is_none());
+        let pushed_out = undelivered.push("client-1", "R3", &["", "3"], at(6));
+        assert_eq!(
+            pushed_out
+                .map(|response| response.response_topic)
+                .as_deref(),
+            Some("R1")
+        );
+        assert_eq!(undelivered.len(), 2);
+
+        let expired = undelivered.expire(at(15));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].1.response_topic, "R2");
+        assert_eq!(undelivered.expire(at(16)).len(), 1);
+        assert_eq!(undelivered.clients(), 0);
+    }
+}
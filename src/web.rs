@@ -0,0 +1,531 @@
+use crate::admin;
+use crate::jsonrpc;
+use crate::log;
+use crate::transport::Transport;
+use crate::Broker;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// with `HTTP_PORT`, the broker serves a dashboard (topics, workers, dead tasks, with pause/resume/drain/replay
+// buttons) and the admin requests over HTTP:
+// - `GET /`: the dashboard, a single page calling `/admin`
+// - `POST /admin`: an admin request in the body, text or JSON-RPC, answered as on the admin socket (same roles, same
+//   audit log, the peer address being the identity)
+// without `ADMIN_USERS` or `CONTROL_SECRET`, the admin requests aren't authenticated, it is only served on the
+// loopback
+// connections are read and written by a thread each (`MAX_CONNECTIONS` at once), for `TIMEOUT` at most, and requests
+// are bounded (`MAX_LINE`, `MAX_HEADERS`, `MAX_BODY`): the broker only answers the requests once they are read, in
+// its loop, woken up by a byte on a unix socket, so a slow client doesn't hold it
+// pages of other sites can't send requests through the browsers: the `Host` has to be the loopback or the address the
+// connection reached (no DNS rebinding), an `Origin` has to be the server's, and `POST` requests need an
+// `X-Requested-With` header or a JSON content type, which browsers don't send across sites without a preflight
+
+const DASHBOARD: &str = include_str!("../web/dashboard.html");
+const TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_CONNECTIONS: usize = 16;
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY: usize = 64 * 1024;
+
+// a request read by its connection thread, waiting for the broker to answer it
+struct Pending {
+    identity: String,
+    request: Request,
+    response: Sender<Response>,
+}
+
+pub struct WebServer {
+    endpoint: String,
+    requests: Receiver<Pending>,
+    // readable when requests are waiting
+    wake_up: UnixStream,
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    // `X-Signature`, see signature.rs
+    signature: Option<String>,
+    host: Option<String>,
+    origin: Option<String>,
+    // `X-Requested-With` or `Content-Type: application/json`
+    preflighted: bool,
+    body: String,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl WebServer {
+    // `<address>:<port>`
+    pub fn bind(address: &str) -> io::Result<WebServer> {
+        let listener = TcpListener::bind(address)?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let (wake_up, waker) = UnixStream::pair()?;
+        wake_up.set_nonblocking(true)?;
+        // a full socket already wakes the broker up
+        waker.set_nonblocking(true)?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || accept(listener, sender, waker));
+
+        Ok(WebServer {
+            endpoint,
+            requests,
+            wake_up,
+        })
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    // answers the requests read since the last time, without waiting
+    pub fn handle(&self, broker: &mut Broker, transport: &dyn Transport) {
+        let mut bytes = [0; 64];
+        while matches!((&self.wake_up).read(&mut bytes), Ok(read) if read > 0) {}
+
+        while let Ok(pending) = self.requests.try_recv() {
+            let response = respond(broker, transport, &pending.identity, &pending.request);
+            // the connection may have timed out
+            pending.response.send(response).ok();
+        }
+    }
+}
+
+impl AsRawFd for WebServer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wake_up.as_raw_fd()
+    }
+}
+
+// the connection threads running, decremented when they end
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn accept(listener: TcpListener, requests: Sender<Pending>, waker: UnixStream) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                log::warn(&format!("Can't accept an HTTP connection: {}", error));
+                continue;
+            }
+        };
+        // the connection is closed
+        if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::Relaxed);
+            continue;
+        }
+        let connection = Connection(connections.clone());
+        let (requests, waker) = match waker.try_clone() {
+            Ok(waker) => (requests.clone(), waker),
+            Err(error) => {
+                log::warn(&format!("Can't accept an HTTP connection: {}", error));
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            let _connection = connection;
+            let identity = stream
+                .peer_addr()
+                .map(|address| address.to_string())
+                .unwrap_or_default();
+            if let Err(error) = serve(stream, &identity, &requests, &waker) {
+                log::warn(&format!(
+                    "Can't answer the HTTP request of {}: {}",
+                    identity, error
+                ));
+            }
+        });
+    }
+}
+
+// a connection that can't be read or written after its deadline, however many reads and writes are done: the socket
+// timeouts only apply to each of them
+struct Deadline {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Deadline {
+    fn time_left(&self) -> io::Result<Duration> {
+        match self.deadline.saturating_duration_since(Instant::now()) {
+            left if left.is_zero() => {
+                Err(Error::new(ErrorKind::TimedOut, "the request took too long"))
+            }
+            left => Ok(left),
+        }
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.time_left()?))?;
+        self.stream.read(buffer)
+    }
+}
+
+impl Write for Deadline {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.time_left()?))?;
+        self.stream.write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    identity: &str,
+    requests: &Sender<Pending>,
+    mut waker: &UnixStream,
+) -> io::Result<()> {
+    let mut stream = Deadline {
+        stream,
+        deadline: Instant::now() + TIMEOUT,
+    };
+
+    let response = match read_request(&mut stream) {
+        Ok(request) if cross_site(&request, stream.stream.local_addr()?) => Response {
+            status: "403 Forbidden",
+            content_type: "text/plain",
+            body: "cross-site request".to_string(),
+        },
+        Ok(request) => {
+            let (sender, response) = mpsc::channel();
+            requests
+                .send(Pending {
+                    identity: identity.to_string(),
+                    request,
+                    response: sender,
+                })
+                .map_err(|_| Error::new(ErrorKind::BrokenPipe, "the broker is stopped"))?;
+            waker.write_all(&[1]).ok();
+            response
+                .recv_timeout(TIMEOUT)
+                .map_err(|_| Error::new(ErrorKind::TimedOut, "the broker didn't answer"))?
+        }
+        Err(error) => Response {
+            status: "400 Bad Request",
+            content_type: "text/plain",
+            body: error.to_string(),
+        },
+    };
+    stream.deadline = Instant::now() + TIMEOUT;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )
+}
+
+// a line of at most `MAX_LINE` bytes, 0 at the end of the stream
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    line.clear();
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(Error::new(ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read)
+}
+
+fn read_request(stream: &mut impl Read) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(Error::new(ErrorKind::InvalidData, "bad request line")),
+    };
+
+    let mut content_length = 0;
+    let mut signature = None;
+    let mut host = None;
+    let mut origin = None;
+    let mut preflighted = false;
+    for headers in 0.. {
+        if read_line(&mut reader, &mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if headers == MAX_HEADERS {
+            return Err(Error::new(ErrorKind::InvalidData, "too many headers"));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "bad content length"))?;
+            }
+            if name.eq_ignore_ascii_case("x-signature") {
+                signature = Some(value.trim().to_string());
+            }
+            if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+            if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            }
+            if name.eq_ignore_ascii_case("x-requested-with")
+                || name.eq_ignore_ascii_case("content-type")
+                    && value
+                        .trim()
+                        .to_ascii_lowercase()
+                        .starts_with("application/json")
+            {
+                preflighted = true;
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(Error::new(ErrorKind::InvalidData, "body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "the body is not UTF-8"))?;
+    Ok(Request {
+        method,
+        path,
+        signature,
+        host,
+        origin,
+        preflighted,
+        body,
+    })
+}
+
+// whether a page of another site could have made the browser send this request, `local` being the address the
+// connection reached
+fn cross_site(request: &Request, local: SocketAddr) -> bool {
+    let host = match &request.host {
+        Some(host) => host,
+        None => return true,
+    };
+    // without its port
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host
+            .rsplit_once(':')
+            .map_or(host.as_str(), |(name, _)| name),
+    };
+    let ours = name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip == local.ip());
+    let same_origin = match &request.origin {
+        Some(origin) => origin.eq_ignore_ascii_case(&format!("http://{}", host)),
+        None => true,
+    };
+    !ours || !same_origin || request.method != "GET" && !request.preflighted
+}
+
+fn respond(
+    broker: &mut Broker,
+    transport: &dyn Transport,
+    identity: &str,
+    request: &Request,
+) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => Response {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: DASHBOARD.to_string(),
+        },
+        ("POST", "/admin") => Response {
+            status: "200 OK",
+            content_type: match jsonrpc::is_request(&request.body) {
+                true => "application/json",
+                false => "text/plain; charset=utf-8",
+            },
+            body: admin::handle(
+                broker,
+                transport,
+                identity,
+                request.body.trim(),
+                request.signature.as_deref(),
+            ),
+        },
+        (_, "/") | (_, "/admin") => Response {
+            status: "405 Method Not Allowed",
+            content_type: "text/plain",
+            body: "method not allowed".to_string(),
+        },
+        _ => Response {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body: "not found".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cross_site, read_request, respond, Deadline, Request, WebServer, MAX_HEADERS, MAX_LINE,
+    };
+    use crate::embedded::BrokerHandle;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn admin_requests_are_posted() {
+        let raw = "POST /admin HTTP/1.1\r\nHost: localhost:8080\r\nX-Signature: 1 ab\r\nX-Requested-With: curl\r\nContent-Length: 11\r\n\r\nPEEK ADD 5\n";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "POST".to_string(),
+                path: "/admin".to_string(),
+                signature: Some("1 ab".to_string()),
+                host: Some("localhost:8080".to_string()),
+                origin: None,
+                preflighted: true,
+                body: "PEEK ADD 5\n".to_string(),
+            }
+        );
+
+        let mut broker = BrokerHandle::new();
+        let response = respond(&mut broker.broker, &broker.transport, "web", &request);
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.body, "OK 0 tasks");
+
+        let request = read_request(&mut "GET /nope HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        let response = respond(&mut broker.broker, &broker.transport, "web", &request);
+        assert_eq!(response.status, "404 Not Found");
+    }
+
+    #[test]
+    fn cross_site_requests_are_refused() {
+        let local = "192.168.1.2:8080".parse().unwrap();
+        let refused = |headers: &str| {
+            let raw = format!("POST /admin HTTP/1.1\r\n{}\r\n", headers);
+            cross_site(&read_request(&mut raw.as_bytes()).unwrap(), local)
+        };
+        assert!(!refused(
+            "Host: localhost:8080\r\nX-Requested-With: curl\r\n"
+        ));
+        assert!(!refused(
+            "Host: 127.0.0.1\r\nContent-Type: application/json\r\n"
+        ));
+        assert!(!refused("Host: [::1]:8080\r\nX-Requested-With: curl\r\n"));
+        assert!(!refused(
+            "Host: 192.168.1.2:8080\r\nOrigin: http://192.168.1.2:8080\r\nContent-Type: application/json\r\n"
+        ));
+
+        // DNS rebinding
+        assert!(refused(
+            "Host: evil.example:8080\r\nX-Requested-With: curl\r\n"
+        ));
+        assert!(refused("X-Requested-With: curl\r\n"));
+        assert!(refused(
+            "Host: localhost:8080\r\nOrigin: http://evil.example\r\nContent-Type: application/json\r\n"
+        ));
+        // a form, without a preflight
+        assert!(refused(
+            "Host: localhost:8080\r\nContent-Type: text/plain\r\n"
+        ));
+
+        let raw = "GET / HTTP/1.1\r\nHost: localhost:8080\r\n\r\n";
+        assert!(!cross_site(
+            &read_request(&mut raw.as_bytes()).unwrap(),
+            local
+        ));
+    }
+
+    #[test]
+    fn requests_are_bounded() {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        assert!(read_request(&mut long_line.as_bytes()).is_err());
+
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(read_request(&mut many_headers.as_bytes()).is_err());
+        let headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS));
+        assert!(read_request(&mut headers.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn slow_clients_are_cut_at_the_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // a header line every 20ms, for ever
+        thread::spawn(move || {
+            let mut client = TcpStream::connect(address).unwrap();
+            client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+            while client.write_all(b"X: y\r\n").is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let mut stream = Deadline {
+            stream,
+            deadline: start + Duration::from_millis(200),
+        };
+        let error = read_request(&mut stream).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::TimedOut | ErrorKind::WouldBlock
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn slow_clients_dont_hold_the_broker() {
+        let mut broker = BrokerHandle::new();
+        let server = WebServer::bind("127.0.0.1:0").unwrap();
+        let address = server.endpoint().trim_start_matches("http://").to_string();
+
+        // connected, but never sending its request
+        let _slow = TcpStream::connect(&address).unwrap();
+        let client = thread::spawn(move || {
+            let mut client = TcpStream::connect(&address).unwrap();
+            client
+                .write_all(b"POST /admin HTTP/1.1\r\nHost: localhost\r\nX-Requested-With: test\r\nContent-Length: 5\r\n\r\nSTATS")
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let start = Instant::now();
+        while !client.is_finished() && start.elapsed() < Duration::from_secs(1) {
+            server.handle(&mut broker.broker, &broker.transport);
+            thread::sleep(Duration::from_millis(5));
+        }
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("\r\n\r\nOK "), "{}", response);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
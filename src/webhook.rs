@@ -0,0 +1,98 @@
+use crate::log;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// only plain `http://host[:port][/path]` urls are supported
+fn parse_url(url: &str) -> io::Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "only http:// urls are supported"))?;
+
+    let (host, path) = match rest.find('/') {
+        Some(position) => (&rest[..position], &rest[position..]),
+        None => (rest, "/"),
+    };
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    Ok((host, path.to_string()))
+}
+
+// the body of a response, whose chunks are joined when it is sent in chunks
+fn response_body(response: &str) -> String {
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((response, ""));
+    if !head.to_lowercase().contains("transfer-encoding: chunked") {
+        return body.to_string();
+    }
+
+    let mut content = String::new();
+    let mut rest = body;
+    while let Some((size, chunk)) = rest.split_once("\r\n") {
+        let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+        if size == 0 || chunk.len() < size {
+            break;
+        }
+        content.push_str(&chunk[..size]);
+        rest = chunk[size..].trim_start_matches("\r\n");
+    }
+    content
+}
+
+fn request(method: &str, url: &str, content_type: &str, body: &str) -> io::Result<String> {
+    let (host, path) = parse_url(url)?;
+    let address = host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "can't resolve host"))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        host,
+        content_type,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.split_whitespace().nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        return Err(Error::other(format!("{} responded {}", url, status)));
+    }
+
+    Ok(response_body(&response))
+}
+
+// the body of the response
+pub fn post(url: &str, body: &str) -> io::Result<String> {
+    request("POST", url, "application/json", body)
+}
+
+// S3-compatible stores take an object with a `PUT` on its url
+pub fn put(url: &str, body: &str) -> io::Result<()> {
+    request("PUT", url, "application/octet-stream", body).map(|_| ())
+}
+
+// the broker doesn't wait for the webhook to respond
+pub fn post_in_background(url: &str, body: String) {
+    let url = url.to_string();
+    thread::spawn(move || {
+        if let Err(error) = post(&url, &body) {
+            log::warn(&format!("Can't call webhook {}: {}", url, error));
+        }
+    });
+}
@@ -0,0 +1,58 @@
+use crate::Broker;
+
+// a worker registered with `weight=<count>` gets that many tasks of its topics for one of a worker of weight 1 (faster
+// machines get more tasks), a topic declared with `weight=<count>` gets that many shares of its workers when the tasks
+// waiting for a worker are sent (see fairness.rs)
+// both can be changed at runtime with the `WORKER_WEIGHT` and `TOPIC_WEIGHT` admin commands
+
+pub fn parse_weight(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(weight) if weight > 0 => Ok(weight),
+        _ => Err("weight is not a positive number".to_string()),
+    }
+}
+
+impl Broker {
+    pub fn record_weight(&mut self, identity: &str, options: &str) {
+        let weight = options
+            .split(' ')
+            .filter_map(|option| option.strip_prefix("weight="))
+            .find_map(|value| parse_weight(value).ok());
+        if let Some(weight) = weight {
+            self.worker_weights.insert(identity.to_string(), weight);
+        }
+    }
+
+    pub fn worker_weight(&self, identity: &str) -> usize {
+        self.worker_weights.get(identity).copied().unwrap_or(1)
+    }
+
+    pub fn set_worker_weight(&mut self, worker_name: &str, weight: &str) -> Result<usize, String> {
+        let weight = parse_weight(weight)?;
+        let identity = self.identity_of(worker_name);
+        let is_worker = self
+            .registry
+            .clients
+            .get(&identity)
+            .is_some_and(|client| client.is_worker);
+        if !is_worker {
+            return Err(format!("unknown worker {}", worker_name));
+        }
+        self.worker_weights.insert(identity, weight);
+        Ok(weight)
+    }
+
+    pub fn set_topic_weight(&mut self, topic_name: &str, weight: &str) -> Result<usize, String> {
+        let weight = parse_weight(weight)?;
+        match self.declared_topics.get_mut(topic_name) {
+            Some(settings) => {
+                settings.weight = Some(weight);
+                Ok(weight)
+            }
+            None => Err(format!(
+                "topic {} is not declared, see CREATE_TOPIC",
+                topic_name
+            )),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// a hashed timing wheel: a deadline goes in the bucket of its second, modulo the size of the wheel
+// expiring looks at the buckets of the seconds elapsed since the last time, so its cost follows the number of
+// deadlines of these seconds (deadlines one or more turns later share the buckets), not the number of deadlines
+// timeouts are in seconds and the broker ticks every second: one or two buckets are looked at per tick
+
+const BUCKETS: usize = 512;
+
+fn as_secs(date: SystemTime) -> u64 {
+    date.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct Wheel {
+    buckets: Vec<Vec<(usize, SystemTime)>>,
+    // the second expired last, its bucket is looked at again as it may have later deadlines of the same second
+    current: Option<u64>,
+}
+
+impl Default for Wheel {
+    fn default() -> Wheel {
+        Wheel {
+            buckets: vec![vec![]; BUCKETS],
+            current: None,
+        }
+    }
+}
+
+impl Wheel {
+    fn bucket(secs: u64) -> usize {
+        (secs % BUCKETS as u64) as usize
+    }
+
+    pub fn insert(&mut self, id: usize, deadline: SystemTime) {
+        let secs = as_secs(deadline);
+        if self.current.is_none_or(|current| secs < current) {
+            self.current = Some(secs);
+        }
+        self.buckets[Wheel::bucket(secs)].push((id, deadline));
+    }
+
+    pub fn remove(&mut self, id: usize, deadline: SystemTime) {
+        self.buckets[Wheel::bucket(as_secs(deadline))]
+            .retain(|(other, other_deadline)| *other != id || *other_deadline != deadline);
+    }
+
+    // the ids whose deadline is passed, they are removed from the wheel
+    pub fn expire(&mut self, now: SystemTime) -> Vec<usize> {
+        let now_as_secs = as_secs(now);
+        let current = match self.current {
+            Some(current) if current <= now_as_secs => current,
+            _ => return vec![],
+        };
+
+        // after a full turn, every bucket was looked at
+        let mut expired = vec![];
+        for secs in current..=now_as_secs.min(current + BUCKETS as u64 - 1) {
+            let bucket = &mut self.buckets[Wheel::bucket(secs)];
+            bucket.retain(|(id, deadline)| {
+                if *deadline > now {
+                    return true;
+                }
+                expired.push(*id);
+                false
+            });
+        }
+        self.current = Some(now_as_secs);
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Wheel, BUCKETS};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn deadlines_expire_once_passed() {
+        let at = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+        let mut wheel = Wheel::default();
+        wheel.insert(1, at(10_500));
+        wheel.insert(2, at(10_900));
+        wheel.insert(3, at(20_000));
+        // a turn later, in the same bucket
+        wheel.insert(4, at(10_000 + BUCKETS as u64 * 1000));
+        wheel.insert(5, at(30_000));
+        wheel.remove(5, at(30_000));
+
+        assert_eq!(wheel.expire(at(10_600)), vec![1]);
+        assert_eq!(wheel.expire(at(10_950)), vec![2]);
+        assert_eq!(wheel.expire(at(40_000)), vec![3]);
+        assert!(wheel
+            .expire(at(10_000 + BUCKETS as u64 * 1000 - 1))
+            .is_empty());
+        assert_eq!(wheel.expire(at(10_000 + BUCKETS as u64 * 1000)), vec![4]);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::json::{self, Value};
+use crate::{cli, container, log, signature, webhook};
+use std::io::Write;
+use std::process::{self, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zmq::{self, SocketType};
+
+// workers shipped with the broker, for small deployments: they speak the protocol of the SDKs, tasks are
+// `{"type": ..., "returnsType": ..., "payload": ...}` and are answered with `{"type": ..., "payload": ..., "error": ...}`
+// - `worker-webhook` posts the payload of the tasks to an url, the body of the response is the response
+// - `worker-exec` runs a command with the payload on its stdin, its stdout is the response, it fails when the
+//   command exits with an error
+// they run until SIGTERM or SIGINT, then unregister
+// with `CONTROL_SECRET`, they sign their registrations, see signature.rs
+const WEBHOOK_USAGE: &str =
+    "usage: tiny-broke worker-webhook --topic <topic> --url <http url> [--broker <endpoint>] [--name <identity>]";
+const EXEC_USAGE: &str =
+    "usage: tiny-broke worker-exec --topic <topic> --command <shell command> [--broker <endpoint>] [--name <identity>]";
+
+fn zmq_error(error: zmq::Error) -> String {
+    error.to_string()
+}
+
+// responses that are not JSON are sent as strings
+fn value(content: &str) -> Value {
+    json::parse(content.trim()).unwrap_or_else(|_| Value::String(content.to_string()))
+}
+
+// the response topic and the response of a task, none when the task doesn't say where to answer
+fn answer(
+    task: &str,
+    process: &dyn Fn(&str) -> Result<String, String>,
+) -> Option<(String, String)> {
+    let task = json::parse(task).ok();
+    let returns_type = task
+        .as_ref()
+        .and_then(|task| task.get("returnsType"))
+        .and_then(Value::as_str)
+        .map(String::from);
+    let payload = task
+        .as_ref()
+        .and_then(|task| task.get("payload"))
+        .cloned()
+        .unwrap_or(Value::Null);
+
+    let (payload, error) = match process(&payload.to_string()) {
+        Ok(response) => (value(&response), Value::Null),
+        Err(error) => (Value::Null, Value::String(error)),
+    };
+    let returns_type = match returns_type {
+        Some(returns_type) => returns_type,
+        None => {
+            log::warn("Task without returnsType, processed without response");
+            return None;
+        }
+    };
+    let response = Value::Object(vec![
+        ("type".to_string(), Value::String(returns_type.clone())),
+        ("payload".to_string(), payload),
+        ("error".to_string(), error),
+    ]);
+    Some((returns_type, response.to_string()))
+}
+
+fn serve(
+    broker: &str,
+    topic: &str,
+    identity: &str,
+    process: &dyn Fn(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    container::handle_stop_signals();
+    let context = zmq::Context::new();
+    let socket = context.socket(SocketType::DEALER).map_err(zmq_error)?;
+    socket
+        .set_identity(identity.as_bytes())
+        .map_err(zmq_error)?;
+    socket.set_linger(1000).map_err(zmq_error)?;
+    socket.connect(broker).map_err(zmq_error)?;
+    // a restarted worker supersedes its previous registration, see epochs.rs
+    let epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let options = format!("epoch={}", epoch);
+    let secret = signature::control_secret();
+    let control = |control: &str, topic: &str, options: &str| {
+        signature::control_frames(
+            secret.as_deref(),
+            identity,
+            control,
+            topic,
+            options,
+            SystemTime::now(),
+        )
+        .into_iter()
+        .map(String::into_bytes)
+        .collect::<Vec<Vec<u8>>>()
+    };
+    socket
+        .send_multipart(control("@@REGISTER", topic, &options), 0)
+        .map_err(zmq_error)?;
+    log::info(&format!("{} works on {} from {}", identity, topic, broker));
+
+    let mut last_ping = Instant::now();
+    while !container::stop_requested() {
+        if last_ping.elapsed() > Duration::from_secs(1) {
+            socket.send("@@PING", 0).map_err(zmq_error)?;
+            last_ping = Instant::now();
+        }
+        // a stop signal interrupts the poll
+        if socket.poll(zmq::POLLIN, 100).unwrap_or(0) == 0 {
+            continue;
+        }
+
+        let frames = socket.recv_multipart(0).map_err(zmq_error)?;
+        match frames.get(1).map(|frame| String::from_utf8_lossy(frame)) {
+            None => {}
+            Some(control) if control == "@@PONG" => {}
+            Some(registration) if registration == "@@REGISTER" => socket
+                .send_multipart(control("@@REGISTER", topic, &options), 0)
+                .map_err(zmq_error)?,
+            Some(task) => {
+                if let Some((returns_type, response)) = answer(&task, process) {
+                    socket
+                        .send_multipart([returns_type.as_str(), "", response.as_str()], 0)
+                        .map_err(zmq_error)?;
+                }
+            }
+        }
+    }
+
+    socket
+        .send_multipart(control("@@UNREGISTER", "", ""), 0)
+        .ok();
+    Ok(format!("{} stopped", identity))
+}
+
+// the topic, the url or command, the broker and the identity of the worker
+fn settings(
+    args: &[String],
+    kind: &str,
+    target: &str,
+    usage: &str,
+) -> Result<(String, String, String, String), String> {
+    let (positionals, options) = cli::options(args, &["topic", target, "broker", "name"])?;
+    let (topic, target) = match (
+        positionals.is_empty(),
+        options.get("topic"),
+        options.get(target),
+    ) {
+        (true, Some(topic), Some(target)) => (topic.clone(), target.clone()),
+        _ => return Err(usage.to_string()),
+    };
+    let broker = options
+        .get("broker")
+        .cloned()
+        .unwrap_or_else(|| "tcp://localhost:3000".to_string());
+    let name = options
+        .get("name")
+        .cloned()
+        .unwrap_or_else(|| format!("worker-{}-{}", kind, process::id()));
+    Ok((topic, target, broker, name))
+}
+
+pub fn webhook(args: &[String]) -> Result<String, String> {
+    let (topic, url, broker, name) = settings(args, "webhook", "url", WEBHOOK_USAGE)?;
+    serve(&broker, &topic, &name, &|payload: &str| {
+        webhook::post(&url, payload).map_err(|error| error.to_string())
+    })
+}
+
+pub fn run_command(command: &str, payload: &str) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| error.to_string())?;
+    // the command may not read its stdin
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload.as_bytes()).ok();
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|error| error.to_string())?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+pub fn exec(args: &[String]) -> Result<String, String> {
+    let (topic, command, broker, name) = settings(args, "exec", "command", EXEC_USAGE)?;
+    serve(&broker, &topic, &name, &|payload: &str| {
+        run_command(&command, payload)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{answer, run_command};
+
+    #[test]
+    fn tasks_are_answered_on_their_returns_type() {
+        let task = r#"{"type":"ADD","returnsType":"ADD>1","payload":{"a":1,"b":[true,null]}}"#;
+        let (returns_type, response) =
+            answer(task, &|payload: &str| Ok(payload.to_string())).unwrap();
+        assert_eq!(returns_type, "ADD>1");
+        assert_eq!(
+            response,
+            r#"{"type":"ADD>1","payload":{"a":1,"b":[true,null]},"error":null}"#
+        );
+
+        let (_, response) = answer(task, &|_: &str| Err("down".to_string())).unwrap();
+        assert_eq!(
+            response,
+            r#"{"type":"ADD>1","payload":null,"error":"down"}"#
+        );
+        assert!(answer("1+1", &|_: &str| Ok(String::new())).is_none());
+    }
+
+    #[test]
+    fn commands_get_the_payload_on_their_stdin() {
+        assert_eq!(run_command("tr a b", "aa").unwrap(), "bb");
+        assert!(run_command("echo oops >&2; exit 3", "")
+            .unwrap_err()
+            .ends_with("oops"));
+    }
+}
@@ -0,0 +1,393 @@
+use crate::json::{self, Value};
+use crate::log;
+use crate::transport::Transport;
+use crate::{Broker, Task};
+use std::time::{Duration, SystemTime};
+
+// a workflow is a DAG of tasks sent at once: `@@WORKFLOW <id> <dag>`, the DAG being a JSON object
+// `{"nodes": [{"id": "a", "topic": "ADD", "payload": "1+1"}, ...], "edges": [["a", "b"], ...]}`
+// an edge `[a, b]` makes `b` wait for the response of `a`, which replaces `{{a}}` in the payload of `b`
+// nodes are tasks with dependencies, their response topic is `<workflow id>/<node id>`
+// the status is sent back on submission, on `@@WORKFLOW <id>`, and once the workflow is over
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub id: String,
+    pub topic: String,
+    pub payload: String,
+    pub after: Vec<String>,
+    pub result: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Workflow {
+    pub id: String,
+    pub client: String,
+    // in topological order
+    pub nodes: Vec<Node>,
+    pub finished: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Running => "running",
+            Status::Completed => "completed",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn event(self) -> &'static str {
+        match self {
+            Status::Running => "workflow.running",
+            Status::Completed => "workflow.completed",
+            Status::Failed => "workflow.failed",
+        }
+    }
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a str, String> {
+    value
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("a node has no {}", name))
+}
+
+impl Workflow {
+    pub fn parse(id: &str, client: &str, content: &str) -> Result<Workflow, String> {
+        let dag = json::parse(content)?;
+        let mut nodes = dag
+            .get("nodes")
+            .and_then(Value::as_array)
+            .ok_or("nodes are missing")?
+            .iter()
+            .map(|node| {
+                Ok(Node {
+                    id: field(node, "id")?.to_string(),
+                    topic: field(node, "topic")?.to_string(),
+                    payload: field(node, "payload")?.to_string(),
+                    after: vec![],
+                    result: None,
+                })
+            })
+            .collect::<Result<Vec<Node>, String>>()?;
+
+        for edge in dag
+            .get("edges")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+        {
+            let (from, to) = match edge.as_array().map(Vec::as_slice) {
+                Some([Value::String(from), Value::String(to)]) => (from, to),
+                _ => return Err("an edge is not a pair of node ids".to_string()),
+            };
+            if !nodes.iter().any(|node| &node.id == from) {
+                return Err(format!("unknown node {}", from));
+            }
+            nodes
+                .iter_mut()
+                .find(|node| &node.id == to)
+                .ok_or_else(|| format!("unknown node {}", to))?
+                .after
+                .push(from.clone());
+        }
+
+        // nodes are sent after the nodes they wait for, so these are known by the broker
+        let mut ordered: Vec<Node> = vec![];
+        while !nodes.is_empty() {
+            let ready = nodes
+                .iter()
+                .position(|node| {
+                    node.after
+                        .iter()
+                        .all(|id| ordered.iter().any(|node| &node.id == id))
+                })
+                .ok_or("the nodes have a cycle")?;
+            let node = nodes.remove(ready);
+            if ordered.iter().any(|other| other.id == node.id) {
+                return Err(format!("duplicate node {}", node.id));
+            }
+            ordered.push(node);
+        }
+
+        Ok(Workflow {
+            id: id.to_string(),
+            client: client.to_string(),
+            nodes: ordered,
+            finished: None,
+        })
+    }
+
+    pub fn response_topic(&self, node: &Node) -> String {
+        format!("{}/{}", self.id, node.id)
+    }
+
+    fn node_status(&self, node: &Node, is_pending: &dyn Fn(&str) -> bool) -> Status {
+        match &node.result {
+            Some(_) => Status::Completed,
+            None if is_pending(&self.response_topic(node)) => Status::Running,
+            None => Status::Failed,
+        }
+    }
+
+    pub fn status(&self, is_pending: &dyn Fn(&str) -> bool) -> Status {
+        let statuses: Vec<Status> = self
+            .nodes
+            .iter()
+            .map(|node| self.node_status(node, is_pending))
+            .collect();
+
+        if statuses.contains(&Status::Failed) {
+            Status::Failed
+        } else if statuses.contains(&Status::Running) {
+            Status::Running
+        } else {
+            Status::Completed
+        }
+    }
+
+    // `{"id": ..., "status": ..., "nodes": {"a": {"status": ..., "result": ...}}}`
+    pub fn status_json(&self, is_pending: &dyn Fn(&str) -> bool) -> String {
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let result = node
+                    .result
+                    .as_deref()
+                    .map_or("null".to_string(), json::string);
+                format!(
+                    "{}:{{\"status\":{},\"result\":{}}}",
+                    json::string(&node.id),
+                    json::string(self.node_status(node, is_pending).name()),
+                    result
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"id\":{},\"status\":{},\"nodes\":{{{}}}}}",
+            json::string(&self.id),
+            json::string(self.status(is_pending).name()),
+            nodes.join(",")
+        )
+    }
+}
+
+impl Broker {
+    fn workflow_status(&self, workflow: &Workflow) -> String {
+        workflow.status_json(&|response_topic| self.is_pending(response_topic))
+    }
+
+    fn send_workflow_status(&self, transport: &dyn Transport, identity: &str, id: &str) {
+        let status = match self.workflows.get(id) {
+            Some(workflow) => self.workflow_status(workflow),
+            None => format!("{{\"id\":{},\"status\":\"unknown\"}}", json::string(id)),
+        };
+        transport
+            .send(identity, &["", "@@WORKFLOW", id, &status])
+            .ok();
+    }
+
+    // submits the workflow, or sends its status
+    // a workflow submitted again is not sent twice
+    pub fn handle_workflow(
+        &mut self,
+        transport: &dyn Transport,
+        identity: &str,
+        uid: Option<u32>,
+        id: &str,
+        content: &str,
+    ) {
+        if content.is_empty() || self.workflows.contains_key(id) {
+            self.send_workflow_status(transport, identity, id);
+            return;
+        }
+
+        let workflow = Workflow::parse(id, identity, content).and_then(|workflow| {
+            match workflow
+                .nodes
+                .iter()
+                .find_map(|node| self.task_rejection(uid, &node.topic))
+            {
+                Some(rejection) => Err(format!("a node is refused with {}", rejection)),
+                None => Ok(workflow),
+            }
+        });
+        let workflow = match workflow {
+            Ok(workflow) => workflow,
+            Err(error) => {
+                let status = format!(
+                    "{{\"id\":{},\"status\":\"invalid\",\"error\":{}}}",
+                    json::string(id),
+                    json::string(&error)
+                );
+                transport
+                    .send(identity, &["", "@@WORKFLOW", id, &status])
+                    .ok();
+                return;
+            }
+        };
+
+        self.emit(
+            "workflow.submitted",
+            &[
+                ("workflow", id),
+                ("client", identity),
+                ("nodes", &workflow.nodes.len().to_string()),
+            ],
+        );
+        let tasks: Vec<Task> = workflow
+            .nodes
+            .iter()
+            .map(|node| {
+                let mut task =
+                    Task::new(&node.topic, &workflow.response_topic(node), &node.payload);
+                task.dependencies = node
+                    .after
+                    .iter()
+                    .map(|id| format!("{}/{}", workflow.id, id))
+                    .collect();
+                task.client = Some(workflow.client.clone());
+                task
+            })
+            .collect();
+        self.workflows.insert(id.to_string(), workflow);
+        tasks
+            .into_iter()
+            .for_each(|task| self.submit(transport, task));
+
+        self.send_workflow_status(transport, identity, id);
+    }
+
+    // the response of a node, kept for the nodes waiting for it
+    pub fn record_workflow_result(&mut self, response_topic: &str, payload: &str) {
+        let (id, node_id) = match response_topic.rsplit_once('/') {
+            Some(ids) => ids,
+            None => return,
+        };
+
+        if let Some(node) = self
+            .workflows
+            .get_mut(id)
+            .and_then(|workflow| workflow.nodes.iter_mut().find(|node| node.id == node_id))
+        {
+            node.result = Some(payload.to_string());
+        }
+    }
+
+    // the responses of the nodes it waited for replace their placeholders in the payload of a node
+    pub fn fill_results(&self, task: &mut Task) {
+        let (workflow, node) =
+            match task
+                .response_topic
+                .rsplit_once('/')
+                .and_then(|(id, node_id)| {
+                    let workflow = self.workflows.get(id)?;
+                    Some((
+                        workflow,
+                        workflow.nodes.iter().find(|node| node.id == node_id)?,
+                    ))
+                }) {
+                Some(found) => found,
+                None => return,
+            };
+
+        node.after.iter().for_each(|id| {
+            let result = workflow
+                .nodes
+                .iter()
+                .find(|other| &other.id == id)
+                .and_then(|other| other.result.as_deref())
+                .unwrap_or("");
+            task.payload = task.payload.replace(&format!("{{{{{}}}}}", id), result);
+        });
+    }
+
+    // the client gets the status of its workflow once it is over, finished workflows are forgotten after `IDLE_TTL`
+    pub fn check_workflows(&mut self, transport: &dyn Transport) {
+        let now = self.now();
+        let idle_ttl = Duration::from_secs(self.idle_ttl_as_secs);
+        self.workflows.retain(|_, workflow| {
+            workflow
+                .finished
+                .is_none_or(|finished| now.duration_since(finished).unwrap_or_default() < idle_ttl)
+        });
+
+        let finished: Vec<(String, Status)> = self
+            .workflows
+            .values()
+            .filter(|workflow| workflow.finished.is_none())
+            .map(|workflow| {
+                let status = workflow.status(&|response_topic| self.is_pending(response_topic));
+                (workflow.id.clone(), status)
+            })
+            .filter(|(_, status)| *status != Status::Running)
+            .collect();
+
+        finished.iter().for_each(|(id, status)| {
+            let client = match self.workflows.get_mut(id) {
+                Some(workflow) => {
+                    workflow.finished = Some(now);
+                    workflow.client.clone()
+                }
+                None => return,
+            };
+            log::info(&format!("Workflow {} {}", id, status.name()));
+            self.emit(status.event(), &[("workflow", id)]);
+            self.send_workflow_status(transport, &client, id);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Status, Workflow};
+
+    #[test]
+    fn nodes_are_ordered_after_the_nodes_they_wait_for() {
+        let workflow = Workflow::parse(
+            "w",
+            "client-1",
+            r#"{"nodes": [
+                {"id": "c", "topic": "ADD", "payload": "{{a}}+{{b}}"},
+                {"id": "a", "topic": "ADD", "payload": "1+1"},
+                {"id": "b", "topic": "ADD", "payload": "2+2"}
+            ], "edges": [["a", "c"], ["b", "c"]]}"#,
+        )
+        .unwrap();
+        let ids: Vec<&str> = workflow.nodes.iter().map(|node| node.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert_eq!(workflow.nodes[2].after, vec!["a", "b"]);
+        assert_eq!(workflow.response_topic(&workflow.nodes[0]), "w/a");
+        assert_eq!(workflow.status(&|_| true), Status::Running);
+        assert_eq!(workflow.status(&|topic| topic != "w/b"), Status::Failed);
+    }
+
+    #[test]
+    fn bad_dags_are_refused() {
+        let node = |id: &str| format!(r#"{{"id": "{}", "topic": "ADD", "payload": ""}}"#, id);
+        let dag = |nodes: &[String], edges: &str| {
+            format!(r#"{{"nodes": [{}], "edges": {}}}"#, nodes.join(","), edges)
+        };
+
+        assert!(Workflow::parse(
+            "w",
+            "c",
+            &dag(&[node("a"), node("b")], r#"[["a", "b"], ["b", "a"]]"#)
+        )
+        .is_err());
+        assert!(Workflow::parse("w", "c", &dag(&[node("a")], r#"[["a", "z"]]"#)).is_err());
+        assert!(Workflow::parse("w", "c", &dag(&[node("a"), node("a")], "[]")).is_err());
+        assert!(Workflow::parse("w", "c", r#"{"nodes": [{"id": "a"}]}"#).is_err());
+        assert!(Workflow::parse("w", "c", &dag(&[node("a")], "[]")).is_ok());
+    }
+}